@@ -0,0 +1,241 @@
+//! A server committing every letter typed as its uppercase, other keys go back to the client.
+
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{ConnectionExt, KeyPressEvent};
+use xim::{
+    x11rb::X11rbServer, ConnectInfo, Server, ServerError, ServerHandler, UserInputContext,
+    XimConnections,
+};
+use xim_parser::InputStyle;
+
+/// Keyboard mapping from `GetKeyboardMapping`, only the first keysym of each keycode is used.
+#[derive(Default)]
+struct Handler {
+    min_keycode: u8,
+    keysyms_per_keycode: u8,
+    keysyms: Vec<u32>,
+}
+
+impl Handler {
+    fn letter(&self, keycode: u8) -> Option<char> {
+        let index = usize::from(keycode.checked_sub(self.min_keycode)?)
+            * usize::from(self.keysyms_per_keycode);
+        // latin letters have the keysyms of their ASCII code
+        let c = char::from_u32(*self.keysyms.get(index)?)?;
+
+        if c.is_ascii_alphabetic() {
+            Some(c.to_ascii_uppercase())
+        } else {
+            None
+        }
+    }
+}
+
+impl<S: Server<XEvent = KeyPressEvent>> ServerHandler<S> for Handler {
+    type InputContextData = ();
+    type InputStyleArray = [InputStyle; 2];
+
+    fn new_ic_data(
+        &mut self,
+        _server: &mut S,
+        _style: InputStyle,
+    ) -> Result<Self::InputContextData, ServerError> {
+        Ok(())
+    }
+
+    fn input_styles(&self) -> Self::InputStyleArray {
+        [
+            InputStyle::PREEDIT_NOTHING | InputStyle::STATUS_NOTHING,
+            InputStyle::PREEDIT_POSITION | InputStyle::STATUS_NOTHING,
+        ]
+    }
+
+    fn filter_events(&self) -> u32 {
+        1
+    }
+
+    fn handle_connect(&mut self, _server: &mut S, info: &ConnectInfo) -> Result<(), ServerError> {
+        log::info!("Connected! {:?}", info);
+        Ok(())
+    }
+
+    fn handle_create_ic(
+        &mut self,
+        server: &mut S,
+        user_ic: &mut UserInputContext<Self::InputContextData>,
+    ) -> Result<(), ServerError> {
+        server.set_event_mask(&user_ic.ic, 1, 0)
+    }
+
+    fn handle_forward_event(
+        &mut self,
+        server: &mut S,
+        user_ic: &mut UserInputContext<Self::InputContextData>,
+        xev: &S::XEvent,
+    ) -> Result<bool, ServerError> {
+        match self.letter(xev.detail) {
+            Some(c) => {
+                server.commit(&user_ic.ic, c.encode_utf8(&mut [0; 4]))?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn handle_destroy_ic(
+        &mut self,
+        _server: &mut S,
+        _user_ic: UserInputContext<Self::InputContextData>,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    fn handle_reset_ic(
+        &mut self,
+        _server: &mut S,
+        _user_ic: &mut UserInputContext<Self::InputContextData>,
+    ) -> Result<String, ServerError> {
+        Ok(String::new())
+    }
+
+    fn handle_set_ic_values(
+        &mut self,
+        _server: &mut S,
+        _user_ic: &mut UserInputContext<Self::InputContextData>,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    fn handle_set_focus(
+        &mut self,
+        _server: &mut S,
+        _user_ic: &mut UserInputContext<Self::InputContextData>,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    fn handle_unset_focus(
+        &mut self,
+        _server: &mut S,
+        _user_ic: &mut UserInputContext<Self::InputContextData>,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    pretty_env_logger::init_custom_env("XIM_RS_LOG");
+
+    let (conn, screen_num) = x11rb::rust_connection::RustConnection::connect(None)?;
+    let setup = conn.setup();
+    let min_keycode = setup.min_keycode;
+    let mapping = conn
+        .get_keyboard_mapping(min_keycode, setup.max_keycode - min_keycode + 1)?
+        .reply()?;
+
+    let mut server = X11rbServer::init(&conn, screen_num, "uppercase", xim::ALL_LOCALES)?;
+    let mut connections = XimConnections::new();
+    let mut handler = Handler {
+        min_keycode,
+        keysyms_per_keycode: mapping.keysyms_per_keycode,
+        keysyms: mapping.keysyms,
+    };
+
+    loop {
+        let e = conn.wait_for_event()?;
+        server.filter_event(&e, &mut connections, &mut handler)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Handler;
+    use core::num::NonZeroU16;
+    use x11rb::protocol::xproto::KeyPressEvent;
+    use xim::{InputContext, ServerCore, ServerError, ServerHandler, UserInputContext};
+    use xim_parser::{CommitData, Request, XEvent};
+
+    #[derive(Default)]
+    struct MockServer {
+        sent: Vec<Request>,
+    }
+
+    impl ServerCore for MockServer {
+        type XEvent = KeyPressEvent;
+
+        fn serialize_event(&self, ev: &KeyPressEvent) -> Result<XEvent, ServerError> {
+            Ok(XEvent {
+                response_type: ev.response_type,
+                detail: ev.detail,
+                sequence: ev.sequence,
+                time: ev.time,
+                root: ev.root,
+                event: ev.event,
+                child: ev.child,
+                root_x: ev.root_x,
+                root_y: ev.root_y,
+                event_x: ev.event_x,
+                event_y: ev.event_y,
+                state: ev.state.into(),
+                same_screen: ev.same_screen,
+            })
+        }
+
+        fn deserialize_event(&self, ev: &XEvent) -> KeyPressEvent {
+            KeyPressEvent {
+                detail: ev.detail,
+                ..Default::default()
+            }
+        }
+
+        fn send_req(&mut self, _client_win: u32, req: Request) -> Result<(), ServerError> {
+            self.sent.push(req);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn uppercase_letters() {
+        // keycodes 8, 9 and 10 type `a`, `Z` and `1`
+        let mut handler = Handler {
+            min_keycode: 8,
+            keysyms_per_keycode: 2,
+            keysyms: vec![0x61, 0x41, 0x5a, 0x5a, 0x31, 0x21],
+        };
+        let mut server = MockServer::default();
+        let mut user_ic = UserInputContext::new(
+            InputContext::new(
+                1,
+                NonZeroU16::new(1).unwrap(),
+                NonZeroU16::new(1).unwrap(),
+                "en_US".into(),
+            ),
+            (),
+        );
+
+        let mut committed = String::new();
+        for (detail, consumed) in [(8, true), (9, true), (10, false), (200, false)] {
+            let xev = KeyPressEvent {
+                detail,
+                ..Default::default()
+            };
+            assert_eq!(
+                handler
+                    .handle_forward_event(&mut server, &mut user_ic, &xev)
+                    .unwrap(),
+                consumed
+            );
+        }
+
+        for req in server.sent.drain(..) {
+            match req {
+                Request::Commit {
+                    data: CommitData::Chars { commited, .. },
+                    ..
+                } => committed.push_str(&xim_ctext::compound_text_to_utf8(&commited).unwrap()),
+                other => panic!("Unexpected request: {:?}", other),
+            }
+        }
+        assert_eq!(committed, "AZ");
+    }
+}
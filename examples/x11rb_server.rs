@@ -1,6 +1,7 @@
 use x11rb::connection::Connection;
 use xim::{
-    x11rb::X11rbServer, Server, ServerError, ServerHandler, UserInputContext, XimConnections,
+    x11rb::X11rbServer, ConnectInfo, Inspect, Server, ServerError, ServerHandler, UserInputContext,
+    XimConnections,
 };
 use xim_parser::InputStyle;
 
@@ -35,8 +36,8 @@ impl<S: Server<XEvent = x11rb::protocol::xproto::KeyPressEvent>> ServerHandler<S
         1
     }
 
-    fn handle_connect(&mut self, _server: &mut S) -> Result<(), ServerError> {
-        log::info!("Connected!");
+    fn handle_connect(&mut self, _server: &mut S, info: &ConnectInfo) -> Result<(), ServerError> {
+        log::info!("Connected! {:?}", info);
         Ok(())
     }
 
@@ -109,7 +110,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     pretty_env_logger::init_custom_env("XIM_RS_LOG");
 
     let (conn, screen_num) = x11rb::rust_connection::RustConnection::connect(None)?;
-    let mut server = X11rbServer::init(&conn, screen_num, "test_server", xim::ALL_LOCALES)?;
+    let mut server = X11rbServer::init(&conn, screen_num, "test_server", xim::ALL_LOCALES)?
+        .map_transport(|transport| {
+            Inspect::wrap(transport, |direction, win, bytes: &[u8]| {
+                log::trace!("{:?} {:02x?} with {}", direction, bytes, win)
+            })
+        });
     let mut connections = XimConnections::new();
     let mut handler = Handler::default();
 
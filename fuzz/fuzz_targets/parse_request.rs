@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use xim_parser::{Endian, Request, XimWrite};
+
+fuzz_target!(|data: &[u8]| {
+    for endian in [Endian::Little, Endian::Big] {
+        let req = match xim_parser::read_with_endian::<Request>(data, endian) {
+            Ok(req) => req,
+            Err(_) => continue,
+        };
+        // `Connect` is read in the byte order it declares
+        let endian = match req {
+            Request::Connect { endian, .. } => endian,
+            _ => endian,
+        };
+
+        let mut out = vec![0; req.size()];
+        xim_parser::write_with_endian(&req, &mut out, endian);
+        let reparsed = xim_parser::read_with_endian::<Request>(&out, endian)
+            .expect("re-serialized request doesn't parse");
+
+        assert_eq!(req, reparsed);
+    }
+});
@@ -20,6 +20,8 @@ pub enum ClientError {
     UnsupportedTransport,
     InvalidReply,
     NoXimServer,
+    /// The server sent a commit or preedit string that isn't valid compound text.
+    InvalidText(xim_ctext::DecodeError),
     #[cfg(feature = "std")]
     Other(alloc::boxed::Box<dyn std::error::Error + Send + Sync>),
 }
@@ -40,6 +42,7 @@ impl fmt::Display for ClientError {
             ClientError::UnsupportedTransport => write!(f, "Server Transport is not supported"),
             ClientError::InvalidReply => write!(f, "Invalid reply from server"),
             ClientError::NoXimServer => write!(f, "Can't connect xim server"),
+            ClientError::InvalidText(e) => write!(f, "Can't decode server text: {}", e),
             #[cfg(feature = "std")]
             ClientError::Other(e) => write!(f, "Other error: {}", e),
         }
@@ -173,7 +176,8 @@ pub fn handle_request<C: ClientCore>(
                     client,
                     input_method_id,
                     input_context_id,
-                    &xim_ctext::compound_text_to_utf8(&commited).expect("Encoding Error"),
+                    &xim_ctext::compound_text_to_utf8(&commited)
+                        .map_err(ClientError::InvalidText)?,
                 )?;
 
                 if syncronous {
@@ -219,7 +223,8 @@ pub fn handle_request<C: ClientCore>(
             status,
             feedbacks,
         } => {
-            let preedit_string = xim_ctext::compound_text_to_utf8(&preedit_string).unwrap();
+            let preedit_string = xim_ctext::compound_text_to_utf8(&preedit_string)
+                .map_err(ClientError::InvalidText)?;
             handler.handle_preedit_draw(
                 client,
                 input_method_id,
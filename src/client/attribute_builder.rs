@@ -10,10 +10,7 @@ pub struct NestedListBuilder<'a> {
 impl<'a> NestedListBuilder<'a> {
     pub fn push<V: XimWrite>(self, name: AttributeName, value: V) -> Self {
         if let Some(id) = self.id_map.get(&name).copied() {
-            let attr = Attribute {
-                id,
-                value: xim_parser::write_to_vec(value),
-            };
+            let attr = Attribute::new(id, value);
             xim_parser::write_extend_vec(attr, self.out);
         }
 
@@ -36,10 +33,7 @@ impl<'a> AttributeBuilder<'a> {
 
     pub fn push<V: XimWrite>(mut self, name: AttributeName, value: V) -> Self {
         if let Some(id) = self.id_map.get(&name).copied() {
-            self.out.push(Attribute {
-                id,
-                value: xim_parser::write_to_vec(value),
-            });
+            self.out.push(Attribute::new(id, value));
         }
 
         self
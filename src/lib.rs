@@ -17,6 +17,11 @@
 //! - A wrapper around [`x11-dl`](x11dl-library), the standard X11 library. See the [`xlib`]
 //!   module for more information (requires the `xlib-client` feature).
 //!
+//! Internal maps hash with `ahash` by default. Disabling the `ahash` feature switches them to
+//! the standard library's randomly seeded `RandomState`, which is more resistant to collisions
+//! crafted from untrusted window ids. Without `ahash` or `std` they use SipHash with fixed
+//! keys.
+//!
 //! [x11rb-library]: https://crates.io/crates/x11rb
 //! [x11dl-library]: https://crates.io/crates/x11-dl
 
@@ -32,8 +37,10 @@ extern crate std;
 
 #[cfg(feature = "client")]
 mod client;
+mod locale;
 #[cfg(feature = "server")]
 mod server;
+mod transport;
 
 #[cfg(any(feature = "x11rb-server", feature = "x11rb-client"))]
 pub mod x11rb;
@@ -42,16 +49,28 @@ pub mod xlib;
 
 #[cfg(feature = "client")]
 pub use crate::client::{Client, ClientError, ClientHandler};
+pub use crate::locale::{encode_supported_locales, parse_supported_locales, LocaleModifiers};
+pub use crate::transport::{Direction, Inspect, Transport};
 
 #[cfg(feature = "server")]
 pub const ALL_LOCALES: &str = include_str!("./all_locales.txt");
 
 #[cfg(feature = "server")]
 pub use crate::server::{
-    InputContext, InputMethod, Server, ServerCore, ServerError, ServerHandler, UserInputContext,
-    XimConnection, XimConnections,
+    server_atom_name, ClientRequest, ConnectInfo, DisconnectReason, DuplicateIcPolicy, Encoding,
+    EventResult, IcDefaults, InputContext, InputContextBuilder, InputMethod, KeyEvent,
+    KeyEventKind, PendingOperation, Server, ServerCore, ServerError, ServerHandler, StatusState,
+    UserInputContext, XimConnection, XimConnections, IC_LOCALE_EXTENSION, PREEDIT_STATE_EXTENSION,
 };
+#[cfg(feature = "ahash")]
 pub type AHashMap<K, V> = hashbrown::HashMap<K, V, ahash::RandomState>;
+#[cfg(all(not(feature = "ahash"), feature = "std"))]
+pub type AHashMap<K, V> = hashbrown::HashMap<K, V, std::collections::hash_map::RandomState>;
+// without a source of random keys in no_std, SipHash with fixed keys is all there is
+#[cfg(not(any(feature = "ahash", feature = "std")))]
+#[allow(deprecated)]
+pub type AHashMap<K, V> =
+    hashbrown::HashMap<K, V, core::hash::BuildHasherDefault<core::hash::SipHasher>>;
 pub use xim_parser::*;
 
 #[allow(non_snake_case, dead_code)]
@@ -61,6 +80,7 @@ struct Atoms<Atom> {
     TRANSPORT: Atom,
     XIM_XCONNECT: Atom,
     XIM_PROTOCOL: Atom,
+    XIM_MOREDATA: Atom,
 }
 
 impl<Atom> Atoms<Atom> {
@@ -75,6 +95,7 @@ impl<Atom> Atoms<Atom> {
             TRANSPORT: f("TRANSPORT")?,
             XIM_XCONNECT: f("_XIM_XCONNECT")?,
             XIM_PROTOCOL: f("_XIM_PROTOCOL")?,
+            XIM_MOREDATA: f("_XIM_MOREDATA")?,
         })
     }
 
@@ -89,6 +110,7 @@ impl<Atom> Atoms<Atom> {
             TRANSPORT: f("TRANSPORT\0")?,
             XIM_XCONNECT: f("_XIM_XCONNECT\0")?,
             XIM_PROTOCOL: f("_XIM_PROTOCOL\0")?,
+            XIM_MOREDATA: f("_XIM_MOREDATA\0")?,
         })
     }
 }
@@ -0,0 +1,121 @@
+use alloc::vec::Vec;
+
+/// Components of a locale modifier string like `XMODIFIERS` or an `XIM_SERVERS` atom name.
+///
+/// Modifiers are `@key=value` pairs, e.g. `@im=myime` picks the input method a client connects
+/// to and `@server=myime` is how a server advertises itself. Unknown keys and pairs without a
+/// value are ignored.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LocaleModifiers<'a> {
+    /// The input method requested with `@im=`.
+    pub im: Option<&'a str>,
+    /// The server name advertised with `@server=`.
+    pub server: Option<&'a str>,
+}
+
+impl<'a> LocaleModifiers<'a> {
+    /// Parse the modifiers in `s`; missing or malformed components are left as `None`.
+    pub fn parse(s: &'a str) -> Self {
+        let mut modifiers = Self::default();
+
+        for component in s.split('@') {
+            let (key, value) = match component.split_once('=') {
+                Some((key, value)) if !value.is_empty() => (key, value),
+                _ => continue,
+            };
+
+            match key {
+                "im" => modifiers.im = Some(value),
+                "server" => modifiers.server = Some(value),
+                _ => {}
+            }
+        }
+
+        modifiers
+    }
+}
+
+/// The value of the `LOCALES` selection a server answers with: `@locale=` then the locales
+/// separated by commas.
+pub fn encode_supported_locales(locales: &[&str]) -> Vec<u8> {
+    let mut out = b"@locale=".to_vec();
+
+    for (i, locale) in locales.iter().enumerate() {
+        if i > 0 {
+            out.push(b',');
+        }
+        out.extend_from_slice(locale.as_bytes());
+    }
+
+    out
+}
+
+/// Parse the `LOCALES` selection of a server, see [`encode_supported_locales`].
+///
+/// Returns `None` if it doesn't start with `@locale=` or isn't UTF-8. A trailing NUL is ignored.
+pub fn parse_supported_locales(data: &[u8]) -> Option<Vec<&str>> {
+    let data = data.strip_suffix(b"\0").unwrap_or(data);
+    let locales = core::str::from_utf8(data.strip_prefix(b"@locale=")?).ok()?;
+
+    Some(locales.split(',').filter(|l| !l.is_empty()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_supported_locales, parse_supported_locales, LocaleModifiers};
+    use alloc::vec;
+
+    #[test]
+    fn parse_both() {
+        assert_eq!(
+            LocaleModifiers::parse("@im=foo@server=bar"),
+            LocaleModifiers {
+                im: Some("foo"),
+                server: Some("bar"),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_im() {
+        assert_eq!(
+            LocaleModifiers::parse("@im=foo"),
+            LocaleModifiers {
+                im: Some("foo"),
+                server: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_empty() {
+        assert_eq!(LocaleModifiers::parse(""), LocaleModifiers::default());
+        assert_eq!(
+            LocaleModifiers::parse("@im=@server"),
+            LocaleModifiers::default()
+        );
+    }
+
+    #[test]
+    fn supported_locales_single() {
+        let data = encode_supported_locales(&["ko_KR"]);
+        assert_eq!(data, b"@locale=ko_KR");
+        assert_eq!(parse_supported_locales(&data), Some(vec!["ko_KR"]));
+    }
+
+    #[test]
+    fn supported_locales_list() {
+        let data = encode_supported_locales(&["en", "ja_JP", "C"]);
+        assert_eq!(data, b"@locale=en,ja_JP,C");
+        assert_eq!(
+            parse_supported_locales(&data),
+            Some(vec!["en", "ja_JP", "C"])
+        );
+
+        assert_eq!(
+            parse_supported_locales(b"@locale=en,ko\0"),
+            Some(vec!["en", "ko"])
+        );
+        assert_eq!(parse_supported_locales(b"@transport=X/"), None);
+    }
+}
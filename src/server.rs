@@ -1,19 +1,67 @@
+/// Build a request for an input context, filling `input_method_id` and `input_context_id`
+/// from [`InputContext::address`].
+macro_rules! ic_request {
+    ($ic:expr, $name:ident { $($field:ident $(: $value:expr)?),* $(,)? }) => {{
+        let (input_method_id, input_context_id) = $ic.address();
+        Request::$name {
+            input_method_id: input_method_id.get(),
+            input_context_id: input_context_id.get(),
+            $($field $(: $value)?,)*
+        }
+    }};
+}
+
 mod connection;
+#[cfg(test)]
+mod tests;
 
 use alloc::string::String;
 use alloc::vec;
 use alloc::vec::Vec;
 use core::fmt;
 use core::num::NonZeroU16;
+use core::time::Duration;
 
 use xim_parser::{
-    CommitData, ErrorCode, ErrorFlag, Feedback, InputStyle, PreeditDrawStatus, Request,
+    CaretDirection, CaretStyle, CommitData, Endian, ErrorCode, ErrorFlag, Extension, Feedback,
+    ForwardEventFlag, InputStyle, Point, PreeditDrawStatus, Request, StatusContent,
+    StatusTextContent, XEventBytes,
 };
 
 pub use self::connection::{
-    InputContext, InputMethod, UserInputContext, XimConnection, XimConnections,
+    InputContext, InputContextBuilder, InputMethod, UserInputContext, XimConnection, XimConnections,
 };
 
+/// What the client sent in its `Connect` request.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConnectInfo {
+    /// Byte order the client speaks, the connection reads and writes in it afterwards.
+    pub endian: Endian,
+    pub client_major_protocol_version: u16,
+    pub client_minor_protocol_version: u16,
+    pub client_auth_protocol_names: Vec<String>,
+}
+
+/// Attributes of a new input context before the ones the client sends in `CreateIc` are
+/// applied, see [`ServerHandler::ic_defaults`].
+///
+/// A default spot location doesn't count as sent by the client, see
+/// [`InputContext::spot_received`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IcDefaults {
+    pub input_style: InputStyle,
+    pub preedit_spot: Point,
+}
+
+impl Default for IcDefaults {
+    fn default() -> Self {
+        Self {
+            input_style: InputStyle::empty(),
+            preedit_spot: Point { x: 0, y: 0 },
+        }
+    }
+}
+
 #[derive(Debug)]
 #[non_exhaustive]
 pub enum ServerError {
@@ -21,6 +69,18 @@ pub enum ServerError {
     ReadProtocol(xim_parser::ReadError),
     XimError(xim_parser::ErrorCode, String),
     InvalidReply,
+    /// Server name is empty or contains characters reserved by the locale modifiers.
+    InvalidServerName(String),
+    /// Preedit was drawn for an OverTheSpot input context before the client sent its spot location.
+    SpotNotReceived,
+    /// Every input method or input context id of the connection is in use.
+    IdExhausted,
+    /// Text was committed before the input method negotiated an encoding, see
+    /// [`ServerCore::require_negotiated_encoding`].
+    EncodingNotNegotiated,
+    /// An event had to be sent back to the client, but the server core doesn't implement
+    /// [`ServerCore::serialize_event`].
+    EventNotSerializable,
     Internal(String),
     #[cfg(feature = "std")]
     Other(alloc::boxed::Box<dyn std::error::Error + Send + Sync>),
@@ -41,6 +101,13 @@ impl fmt::Display for ServerError {
                 write!(f, "Client send error code: {:?}, detail: {}", e, d)
             }
             ServerError::InvalidReply => write!(f, "Invalid reply from client"),
+            ServerError::InvalidServerName(name) => write!(f, "Invalid server name: {:?}", name),
+            ServerError::SpotNotReceived => {
+                write!(f, "Preedit spot location is not received yet")
+            }
+            ServerError::IdExhausted => write!(f, "No free input method or context id"),
+            ServerError::EncodingNotNegotiated => write!(f, "Encoding is not negotiated yet"),
+            ServerError::EventNotSerializable => write!(f, "Can't serialize the event"),
             ServerError::Internal(e) => write!(f, "Internal error: {}", e),
             #[cfg(feature = "std")]
             ServerError::Other(e) => write!(f, "Other error: {}", e),
@@ -51,21 +118,348 @@ impl fmt::Display for ServerError {
 #[cfg(feature = "std")]
 impl std::error::Error for ServerError {}
 
+/// Name of the extension freezing and thawing the preedit of an input context.
+///
+/// A handler listing it in [`ServerHandler::extensions`] gets its requests as
+/// [`ServerHandler::handle_preedit_state`] instead of [`ServerHandler::handle_extension`]. The
+/// body is the input method id and input context id, then a
+/// [`PreeditStateFlag`](xim_parser::PreeditStateFlag) with `DISABLE` freezing the preedit and
+/// `ENABLE` thawing it.
+pub const PREEDIT_STATE_EXTENSION: &str = "XIM_EXT_PREEDIT_STATE";
+
+/// Name of the extension letting an input context use another locale than its input method.
+///
+/// A handler listing it in [`ServerHandler::extensions`] advertises the `locale` input context
+/// attribute in `OpenReply`, see [`InputContext::locale`]. It isn't part of XIM, stock clients
+/// never see it otherwise.
+pub const IC_LOCALE_EXTENSION: &str = "XIM_EXT_IC_LOCALE";
+
+/// Build the `@server=<name>` atom name a server is advertised with in `XIM_SERVERS`.
+///
+/// Clients pick the server by matching `name` against `@im=<name>` in `XMODIFIERS`, so it
+/// can't be empty or contain `@`, `=` or whitespace.
+pub fn server_atom_name(name: &str) -> Result<String, ServerError> {
+    if name.is_empty() || name.contains(|c: char| c == '@' || c == '=' || c.is_whitespace()) {
+        return Err(ServerError::InvalidServerName(name.into()));
+    }
+
+    Ok(alloc::format!("@server={}", name))
+}
+
+/// Encoding of the text sent to the client, negotiated with `EncodingNegotiation`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Encoding {
+    #[default]
+    CompoundText,
+    Utf8,
+}
+
+impl Encoding {
+    /// The encoding with the name a client offers in `EncodingNegotiation`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        if name.starts_with("COMPOUND_TEXT") {
+            Some(Encoding::CompoundText)
+        } else if name == "UTF-8" {
+            Some(Encoding::Utf8)
+        } else {
+            None
+        }
+    }
+
+    /// Encode `s` for a `Commit` or `PreeditDraw`, an empty `s` is empty in every encoding.
+    pub fn encode(self, s: &str) -> Vec<u8> {
+        match self {
+            // rather than an empty UTF-8 segment, so the string length is 0
+            Encoding::CompoundText if s.is_empty() => Vec::new(),
+            Encoding::CompoundText => xim_ctext::utf8_to_compound_text(s),
+            Encoding::Utf8 => s.as_bytes().to_vec(),
+        }
+    }
+
+    /// Decode a string the client sent in this encoding, malformed text is an error.
+    pub fn decode(self, bytes: &[u8]) -> Result<String, xim_ctext::DecodeError> {
+        match self {
+            Encoding::CompoundText => xim_ctext::compound_text_to_utf8(bytes),
+            Encoding::Utf8 => Ok(String::from_utf8(bytes.to_vec())?),
+        }
+    }
+}
+
+/// Why a connection went away, see [`ServerHandler::handle_disconnect_ic`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DisconnectReason {
+    /// The client sent `Disconnect` or the server shut down.
+    Clean,
+    /// Talking to the client failed.
+    TransportError,
+    /// The client window was destroyed without disconnecting.
+    WindowDestroyed,
+}
+
+/// A request from the client as [`ServerHandler::before_dispatch`] sees it.
+#[derive(Clone, Copy, Debug)]
+pub enum ClientRequest<'a> {
+    Request(&'a Request),
+    /// A request at the opcodes of a negotiated extension, `body` is the request after its header.
+    Extension {
+        extension: &'a Extension,
+        body: &'a [u8],
+    },
+}
+
+impl<'a> ClientRequest<'a> {
+    /// The name of the request, or of the extension.
+    pub fn name(&self) -> &'a str {
+        match self {
+            ClientRequest::Request(req) => req.name(),
+            ClientRequest::Extension { extension, .. } => &extension.name,
+        }
+    }
+}
+
+/// Where an input context is in the `StatusStart`, `StatusDraw`*, `StatusDone` lifecycle.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum StatusState {
+    /// No `StatusStart` was sent, or the status was finished with `StatusDone`.
+    #[default]
+    Idle,
+    /// `StatusStart` was sent, `StatusDone` is sent on the next focus loss.
+    Started,
+}
+
+/// What `CreateIc` does when the input method already has an input context for the same client
+/// window.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DuplicateIcPolicy {
+    /// Create another input context.
+    #[default]
+    Allow,
+    /// Apply the attributes to the existing input context and reply with its id.
+    Reuse,
+    /// Reply with a `BadClientWindow` error.
+    Error,
+}
+
+/// What to send back to the client for an event it forwarded to the server.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EventResult<E> {
+    /// The event is consumed and doesn't go back to the client.
+    Consumed,
+    /// The original event goes back to the client.
+    Forward,
+    /// This event goes back to the client instead of the original one.
+    Replace(E),
+}
+
+/// A request sent to the client that waits for its reply, see
+/// [`XimConnection::add_pending`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PendingOperation {
+    /// `Sync`, answered with `SyncReply`.
+    Sync,
+    /// `PreeditStart`, answered with `PreeditStartReply`.
+    PreeditStart,
+}
+
+/// Whether a [`KeyEvent`] is a key press or a key release.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeyEventKind {
+    Press,
+    Release,
+}
+
+/// The key of an event the client forwarded, without the event type of the transport, see
+/// [`ServerCore::decode_key_event`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct KeyEvent {
+    pub keycode: u8,
+    /// Modifier and button mask.
+    pub state: u16,
+    pub time: u32,
+    pub kind: KeyEventKind,
+}
+
 pub trait ServerHandler<S: Server> {
     type InputStyleArray: AsRef<[InputStyle]>;
     type InputContextData;
 
+    /// Create the user data of a new input context, `input_style` is the one the client asked for.
     fn new_ic_data(
         &mut self,
         server: &mut S,
         input_style: InputStyle,
     ) -> Result<Self::InputContextData, ServerError>;
 
+    /// Like [`new_ic_data`](Self::new_ic_data) with the locale of the input method as well.
+    ///
+    /// This is what `CreateIc` calls, after the requested attributes are applied. Defaults to
+    /// [`new_ic_data`](Self::new_ic_data).
+    fn new_ic_data_with_locale(
+        &mut self,
+        server: &mut S,
+        _locale: &str,
+        input_style: InputStyle,
+    ) -> Result<Self::InputContextData, ServerError> {
+        self.new_ic_data(server, input_style)
+    }
+
+    /// Styles the server supports, asked again on every `GetImValues` so a change is seen by
+    /// the next client asking.
     fn input_styles(&self) -> Self::InputStyleArray;
     fn filter_events(&self) -> u32;
 
-    fn handle_connect(&mut self, server: &mut S) -> Result<(), ServerError>;
+    fn handle_connect(&mut self, server: &mut S, info: &ConnectInfo) -> Result<(), ServerError>;
 
+    /// Pick one of the authentication protocols a client offers in `Connect`, only asked when
+    /// it offers any.
+    ///
+    /// Returning `None` rejects the client with `BadProtocol` instead of `ConnectReply`. No
+    /// authentication exchange follows, picking one only acknowledges it. Defaults to the first.
+    fn negotiate_auth(&self, _names: &[String]) -> Option<usize> {
+        Some(0)
+    }
+
+    /// Whether the client can't parse nested attribute lists, see
+    /// [`XimConnection::set_flat_attributes`]. Defaults to `false`.
+    fn flat_attributes(&self, _info: &ConnectInfo) -> bool {
+        false
+    }
+
+    /// Extensions answered to `QueryExtension`, with their negotiated opcodes.
+    ///
+    /// Only the ones the client asks for are replied, in the order it asked. Defaults to none.
+    fn extensions(&self) -> Vec<Extension> {
+        Vec::new()
+    }
+
+    /// Pick one of the encoding names a client offers in `EncodingNegotiation`.
+    ///
+    /// Returning `None` falls back to COMPOUND_TEXT in the detailed data, or rejects the
+    /// negotiation without it. A name other than COMPOUND_TEXT or UTF-8 can't be encoded and is
+    /// treated like `None`. Defaults to COMPOUND_TEXT first, then UTF-8.
+    ///
+    /// An empty list asks for the default: `Some(0)` answers it with COMPOUND_TEXT at index 0,
+    /// which the default does.
+    fn select_encoding(&self, offered: &[String]) -> Option<usize> {
+        if offered.is_empty() {
+            return Some(0);
+        }
+
+        offered
+            .iter()
+            .position(|e| Encoding::from_name(e) == Some(Encoding::CompoundText))
+            .or_else(|| offered.iter().position(|e| e == "UTF-8"))
+    }
+
+    /// Called after `EncodingNegotiationReply` with the encoding commits and preedits of the
+    /// input method use from now on. Not called when the negotiation is rejected.
+    fn handle_encoding_negotiated(
+        &mut self,
+        _server: &mut S,
+        _input_method_id: u16,
+        _encoding: Encoding,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    /// What to do when a client creates a second input context for the same client window.
+    ///
+    /// With [`DuplicateIcPolicy::Reuse`] the existing one is passed to
+    /// [`handle_set_ic_values`](Self::handle_set_ic_values). Defaults to
+    /// [`DuplicateIcPolicy::Allow`].
+    fn duplicate_ic_policy(&self) -> DuplicateIcPolicy {
+        DuplicateIcPolicy::Allow
+    }
+
+    /// Attributes every new input context starts with. Defaults to an empty style and the spot
+    /// at `(0, 0)`.
+    fn ic_defaults(&self) -> IcDefaults {
+        IcDefaults::default()
+    }
+
+    /// Keep a spot location the client sent inside its window of `size`.
+    ///
+    /// Only called when [`ServerCore::window_size`] knows the geometry of the focus window, or
+    /// the client window without one. Defaults to clamping the spot to the window bounds.
+    fn clamp_preedit_spot(&self, spot: Point, size: (u16, u16)) -> Point {
+        let clamp =
+            |v: i16, len: u16| v.clamp(0, len.saturating_sub(1).min(i16::MAX as u16) as i16);
+
+        Point {
+            x: clamp(spot.x, size.0),
+            y: clamp(spot.y, size.1),
+        }
+    }
+
+    /// Least time between two events of an input context passed to
+    /// [`handle_forward_event`](Self::handle_forward_event), measured with the timestamps of
+    /// the events.
+    ///
+    /// Events arriving sooner aren't processed, they go back to the client unless
+    /// [`passthrough`](Self::passthrough) is `false`. Defaults to `None`, every event is
+    /// processed.
+    fn forward_event_budget(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Whether events the handler doesn't consume are sent back to the client.
+    ///
+    /// When `false` they are dropped, a synchronous `ForwardEvent` is still answered with
+    /// `SyncReply`. Defaults to `true`.
+    fn passthrough(&self) -> bool {
+        true
+    }
+
+    /// Called for a request at the opcodes of a negotiated extension, `body` is the request
+    /// after its header. Defaults to ignoring it.
+    fn handle_extension(
+        &mut self,
+        _server: &mut S,
+        extension: &Extension,
+        _body: &[u8],
+    ) -> Result<(), ServerError> {
+        log::warn!("Ignore unhandled extension request: {}", extension.name);
+        Ok(())
+    }
+
+    /// Called after the client froze or thawed the preedit with the
+    /// [`PREEDIT_STATE_EXTENSION`], see [`InputContext::preedit_frozen`].
+    fn handle_preedit_state(
+        &mut self,
+        _server: &mut S,
+        _user_ic: &mut UserInputContext<Self::InputContextData>,
+        _frozen: bool,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    /// Called after an input method is removed, either by `Close` or automatically when
+    /// [`auto_close_empty_im`](Self::auto_close_empty_im) is enabled.
+    fn handle_close(&mut self, _server: &mut S, _input_method_id: u16) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    /// Close an input method once its last input context is destroyed.
+    ///
+    /// Some clients never send `Close`, which leaks the input method until disconnect.
+    /// Defaults to `false`.
+    fn auto_close_empty_im(&self) -> bool {
+        false
+    }
+
+    /// Called before every request from the client is dispatched, extension requests included.
+    ///
+    /// `sequence` numbers the requests of the connection from 1, see
+    /// [`XimConnection::sequence`].
+    fn before_dispatch(&mut self, _sequence: u64, _req: ClientRequest) {}
+
+    /// Called after every request from the client is dispatched, with the name and result of it.
+    ///
+    /// The request was moved into the dispatch, [`before_dispatch`](Self::before_dispatch)
+    /// saw it with the same `sequence`.
+    fn after_dispatch(&mut self, _sequence: u64, _name: &str, _result: &Result<(), ServerError>) {}
+
+    /// Called once the input context is created, with the requested attributes already applied.
     fn handle_create_ic(
         &mut self,
         server: &mut S,
@@ -77,6 +471,18 @@ pub trait ServerHandler<S: Server> {
         server: &mut S,
         user_ic: UserInputContext<Self::InputContextData>,
     ) -> Result<(), ServerError>;
+    /// Called for every input context left when its connection goes away, before
+    /// [`handle_close`](Self::handle_close) of its input method. Defaults to
+    /// [`handle_destroy_ic`](Self::handle_destroy_ic).
+    fn handle_disconnect_ic(
+        &mut self,
+        server: &mut S,
+        user_ic: UserInputContext<Self::InputContextData>,
+        _reason: DisconnectReason,
+    ) -> Result<(), ServerError> {
+        self.handle_destroy_ic(server, user_ic)
+    }
+
     fn handle_reset_ic(
         &mut self,
         server: &mut S,
@@ -101,6 +507,49 @@ pub trait ServerHandler<S: Server> {
         user_ic: &mut UserInputContext<Self::InputContextData>,
     ) -> Result<(), ServerError>;
 
+    /// Called after `TriggerNotifyReply` when the client pressed the on key at `index` of its
+    /// trigger keys.
+    fn handle_trigger_on(
+        &mut self,
+        _server: &mut S,
+        _user_ic: &mut UserInputContext<Self::InputContextData>,
+        _index: u32,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    /// Called after `TriggerNotifyReply` when the client pressed the off key at `index` of its
+    /// trigger keys.
+    fn handle_trigger_off(
+        &mut self,
+        _server: &mut S,
+        _user_ic: &mut UserInputContext<Self::InputContextData>,
+        _index: u32,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    /// Called when the client didn't answer `operation` before its deadline, see
+    /// [`XimConnections::expire_pending`].
+    fn handle_pending_timeout(
+        &mut self,
+        _server: &mut S,
+        _user_ic: &mut UserInputContext<Self::InputContextData>,
+        _operation: PendingOperation,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    /// Called when the client answers a [`Server::preedit_caret`] with the new caret position.
+    fn handle_caret(
+        &mut self,
+        _server: &mut S,
+        _user_ic: &mut UserInputContext<Self::InputContextData>,
+        _position: i32,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
     /// return `false` when event back to client
     /// if return `true` it consumed and don't back to client
     fn handle_forward_event(
@@ -109,11 +558,30 @@ pub trait ServerHandler<S: Server> {
         user_ic: &mut UserInputContext<Self::InputContextData>,
         xev: &S::XEvent,
     ) -> Result<bool, ServerError>;
+
+    /// Like [`handle_forward_event`](Self::handle_forward_event), but can replace the event sent
+    /// back to the client.
+    ///
+    /// This is what `ForwardEvent` calls. Defaults to [`EventResult::Consumed`] or
+    /// [`EventResult::Forward`] according to [`handle_forward_event`](Self::handle_forward_event).
+    fn handle_forward_event_result(
+        &mut self,
+        server: &mut S,
+        user_ic: &mut UserInputContext<Self::InputContextData>,
+        xev: &S::XEvent,
+    ) -> Result<EventResult<S::XEvent>, ServerError> {
+        if self.handle_forward_event(server, user_ic, xev)? {
+            Ok(EventResult::Consumed)
+        } else {
+            Ok(EventResult::Forward)
+        }
+    }
 }
 
 pub trait Server {
     type XEvent;
 
+    /// Send an error to the client, the `ErrorFlag` validity bits are set for the given ids.
     fn error(
         &mut self,
         client_win: u32,
@@ -123,8 +591,35 @@ pub trait Server {
         user_ic_id: Option<NonZeroU16>,
     ) -> Result<(), ServerError>;
 
+    /// Fails with [`ServerError::SpotNotReceived`] when `ic` uses OverTheSpot and the client
+    /// hasn't sent its spot location yet. The preedit is cut to
+    /// [`InputContext::preedit_limit`], with a limit of 0 nothing is drawn. Nothing is drawn
+    /// either while the preedit is [frozen](InputContext::preedit_frozen).
     fn preedit_draw(&mut self, ic: &mut InputContext, s: &str) -> Result<(), ServerError>;
+    /// Move the caret of a preedit the client draws, it answers with `PreeditCaretReply`
+    /// which is routed to [`ServerHandler::handle_caret`].
+    fn preedit_caret(
+        &mut self,
+        ic: &InputContext,
+        position: i32,
+        direction: CaretDirection,
+        style: CaretStyle,
+    ) -> Result<(), ServerError>;
     fn commit(&mut self, ic: &InputContext, s: &str) -> Result<(), ServerError>;
+    /// Draw `s` in the status of a client with `StatusCallbacks`, sending `StatusStart` first
+    /// if the status isn't started yet.
+    fn status_draw(&mut self, ic: &mut InputContext, s: &str) -> Result<(), ServerError>;
+    /// Send `StatusDone` if the status was started, this is done on `UnsetIcFocus`.
+    fn status_done(&mut self, ic: &mut InputContext) -> Result<(), ServerError>;
+    fn commit_keysym(&mut self, ic: &InputContext, keysym: u32) -> Result<(), ServerError>;
+    fn commit_both(&mut self, ic: &InputContext, keysym: u32, s: &str) -> Result<(), ServerError>;
+    /// Commit `s` then send the `SyncReply` for a synchronous `ForwardEvent`, in one batch so
+    /// the client sees them in this order.
+    ///
+    /// Called while handling that `ForwardEvent`, it is its only `SyncReply`.
+    fn commit_and_sync(&mut self, ic: &mut InputContext, s: &str) -> Result<(), ServerError>;
+    /// Send `Sync`, the client answers with `SyncReply` once it handled what was sent before.
+    fn sync(&mut self, ic: &mut InputContext) -> Result<(), ServerError>;
 
     fn set_event_mask(
         &mut self,
@@ -132,6 +627,21 @@ pub trait Server {
         forward_event_mask: u32,
         synchronous_event_mask: u32,
     ) -> Result<(), ServerError>;
+
+    /// Send an event the server made up to the client as if it came from the X server.
+    ///
+    /// The serial number of the `ForwardEvent` is the sequence number of `ev`.
+    fn forward_synthetic_event(
+        &mut self,
+        ic: &InputContext,
+        ev: &Self::XEvent,
+    ) -> Result<(), ServerError>;
+
+    /// Send several requests in order, all or nothing.
+    ///
+    /// Every request is validated before the first one is sent, then they are written with
+    /// [`ServerCore::send_reqs`].
+    fn send_batch(&mut self, client_win: u32, reqs: &[Request]) -> Result<(), ServerError>;
 }
 
 impl<S: ServerCore> Server for S {
@@ -145,86 +655,76 @@ impl<S: ServerCore> Server for S {
         input_method_id: Option<NonZeroU16>,
         user_ic_id: Option<NonZeroU16>,
     ) -> Result<(), ServerError> {
-        let mut flag = ErrorFlag::empty();
-
-        let input_method_id = if let Some(id) = input_method_id {
-            flag |= ErrorFlag::INPUT_METHOD_ID_VALID;
-            id.get()
-        } else {
-            0
-        };
-
-        let input_context_id = if let Some(id) = user_ic_id {
-            flag |= ErrorFlag::INPUT_CONTEXT_ID_VALID;
-            id.get()
-        } else {
-            0
-        };
-
         self.send_req(
             client_win,
-            Request::Error {
-                input_method_id,
-                input_context_id,
-                code,
-                detail,
-                flag,
-            },
+            error_request(code, detail, input_method_id, user_ic_id),
         )
     }
 
     fn preedit_draw(&mut self, ic: &mut InputContext, s: &str) -> Result<(), ServerError> {
+        if ic.preedit_frozen {
+            return Ok(());
+        }
+
         let preedit_length = s.chars().count();
 
         if preedit_length == 0 {
             if ic.preedit_started {
                 self.send_req(
                     ic.client_win(),
-                    Request::PreeditDraw {
-                        input_method_id: ic.input_method_id().get(),
-                        input_context_id: ic.input_context_id().get(),
-                        chg_first: 0,
-                        chg_length: ic.prev_preedit_length as _,
-                        caret: preedit_length as _,
-                        preedit_string: Vec::new(),
-                        feedbacks: Vec::new(),
-                        status: PreeditDrawStatus::NO_FEEDBACK | PreeditDrawStatus::NO_STRING,
-                    },
-                )?;
-                self.send_req(
-                    ic.client_win(),
-                    Request::PreeditDone {
-                        input_method_id: ic.input_method_id().get(),
-                        input_context_id: ic.input_context_id().get(),
-                    },
+                    ic_request!(
+                        ic,
+                        PreeditDraw {
+                            chg_first: 0,
+                            chg_length: ic.prev_preedit_length as _,
+                            caret: preedit_length as _,
+                            preedit_string: Vec::new(),
+                            feedbacks: Vec::new(),
+                            status: PreeditDrawStatus::NO_FEEDBACK | PreeditDrawStatus::NO_STRING,
+                        }
+                    ),
                 )?;
+                self.send_req(ic.client_win(), ic_request!(ic, PreeditDone {}))?;
                 ic.preedit_started = false;
                 ic.prev_preedit_length = 0;
+                ic.preedit_limit = None;
             }
         } else {
+            if ic.input_style().is_over_the_spot() && !ic.spot_received() {
+                return Err(ServerError::SpotNotReceived);
+            }
+
+            // keep to what the client said it renders in `PreeditStartReply`
+            let (s, preedit_length) = match ic.preedit_limit() {
+                Some(0) => return Ok(()),
+                Some(limit) if limit < preedit_length => {
+                    let end = s.char_indices().nth(limit).map_or(s.len(), |(i, _)| i);
+                    (&s[..end], limit)
+                }
+                _ => (s, preedit_length),
+            };
+
             if !ic.preedit_started {
-                self.send_req(
-                    ic.client_win(),
-                    Request::PreeditStart {
-                        input_method_id: ic.input_method_id().get(),
-                        input_context_id: ic.input_context_id().get(),
-                    },
-                )?;
+                self.send_req(ic.client_win(), ic_request!(ic, PreeditStart {}))?;
                 ic.preedit_started = true;
+                if let Some(deadline) = self.reply_deadline() {
+                    ic.pending.push((PendingOperation::PreeditStart, deadline));
+                }
             }
 
             self.send_req(
                 ic.client_win(),
-                Request::PreeditDraw {
-                    input_method_id: ic.input_method_id().get(),
-                    input_context_id: ic.input_context_id().get(),
-                    chg_first: 0,
-                    chg_length: ic.prev_preedit_length as _,
-                    caret: preedit_length as _,
-                    preedit_string: xim_ctext::utf8_to_compound_text(s),
-                    feedbacks: vec![Feedback::Underline; preedit_length],
-                    status: PreeditDrawStatus::empty(),
-                },
+                ic_request!(
+                    ic,
+                    PreeditDraw {
+                        chg_first: 0,
+                        chg_length: ic.prev_preedit_length as _,
+                        caret: preedit_length as _,
+                        preedit_string: ic.encoding().encode(s),
+                        feedbacks: vec![Feedback::UNDERLINE; preedit_length],
+                        status: PreeditDrawStatus::empty(),
+                    }
+                ),
             )?;
 
             ic.prev_preedit_length = preedit_length;
@@ -233,17 +733,140 @@ impl<S: ServerCore> Server for S {
         Ok(())
     }
 
+    fn preedit_caret(
+        &mut self,
+        ic: &InputContext,
+        position: i32,
+        direction: CaretDirection,
+        style: CaretStyle,
+    ) -> Result<(), ServerError> {
+        self.send_req(
+            ic.client_win(),
+            ic_request!(
+                ic,
+                PreeditCaret {
+                    position,
+                    direction,
+                    style,
+                }
+            ),
+        )
+    }
+
     fn commit(&mut self, ic: &InputContext, s: &str) -> Result<(), ServerError> {
+        check_negotiated_encoding(self, ic)?;
+
+        self.send_req(
+            ic.client_win(),
+            ic_request!(
+                ic,
+                Commit {
+                    data: CommitData::Chars {
+                        commited: ic.encoding().encode(s),
+                        syncronous: false,
+                    },
+                }
+            ),
+        )
+    }
+
+    fn status_draw(&mut self, ic: &mut InputContext, s: &str) -> Result<(), ServerError> {
+        if ic.status_state == StatusState::Idle {
+            self.send_req(ic.client_win(), ic_request!(ic, StatusStart {}))?;
+            ic.status_state = StatusState::Started;
+        }
+
+        let status = if s.is_empty() {
+            PreeditDrawStatus::NO_FEEDBACK | PreeditDrawStatus::NO_STRING
+        } else {
+            PreeditDrawStatus::NO_FEEDBACK
+        };
+
+        self.send_req(
+            ic.client_win(),
+            ic_request!(
+                ic,
+                StatusDraw {
+                    content: StatusContent::Text(StatusTextContent {
+                        status,
+                        status_string: s.into(),
+                        feedbacks: Vec::new(),
+                    }),
+                }
+            ),
+        )
+    }
+
+    fn status_done(&mut self, ic: &mut InputContext) -> Result<(), ServerError> {
+        if ic.status_state == StatusState::Started {
+            self.send_req(ic.client_win(), ic_request!(ic, StatusDone {}))?;
+            ic.status_state = StatusState::Idle;
+        }
+
+        Ok(())
+    }
+
+    fn commit_and_sync(&mut self, ic: &mut InputContext, s: &str) -> Result<(), ServerError> {
+        check_negotiated_encoding(self, ic)?;
+
+        self.send_batch(
+            ic.client_win(),
+            &[
+                ic_request!(
+                    ic,
+                    Commit {
+                        data: CommitData::Chars {
+                            commited: ic.encoding().encode(s),
+                            syncronous: false,
+                        },
+                    }
+                ),
+                ic_request!(ic, SyncReply {}),
+            ],
+        )?;
+        ic.unanswered_syncs = ic.unanswered_syncs.saturating_sub(1);
+
+        Ok(())
+    }
+
+    fn sync(&mut self, ic: &mut InputContext) -> Result<(), ServerError> {
+        self.send_req(ic.client_win(), ic_request!(ic, Sync {}))?;
+        if let Some(deadline) = self.reply_deadline() {
+            ic.pending.push((PendingOperation::Sync, deadline));
+        }
+        Ok(())
+    }
+
+    fn commit_keysym(&mut self, ic: &InputContext, keysym: u32) -> Result<(), ServerError> {
+        self.send_req(
+            ic.client_win(),
+            ic_request!(
+                ic,
+                Commit {
+                    data: CommitData::Keysym {
+                        keysym,
+                        syncronous: false,
+                    },
+                }
+            ),
+        )
+    }
+
+    fn commit_both(&mut self, ic: &InputContext, keysym: u32, s: &str) -> Result<(), ServerError> {
+        check_negotiated_encoding(self, ic)?;
+
         self.send_req(
             ic.client_win(),
-            Request::Commit {
-                input_method_id: ic.input_method_id().get(),
-                input_context_id: ic.input_context_id().get(),
-                data: CommitData::Chars {
-                    commited: xim_ctext::utf8_to_compound_text(s),
-                    syncronous: false,
-                },
-            },
+            ic_request!(
+                ic,
+                Commit {
+                    data: CommitData::Both {
+                        keysym,
+                        commited: ic.encoding().encode(s),
+                        syncronous: false,
+                    },
+                }
+            ),
         )
     }
 
@@ -255,19 +878,170 @@ impl<S: ServerCore> Server for S {
     ) -> Result<(), ServerError> {
         self.send_req(
             ic.client_win(),
-            Request::SetEventMask {
-                input_method_id: ic.input_method_id().get(),
-                input_context_id: ic.input_context_id().get(),
-                forward_event_mask,
-                synchronous_event_mask,
-            },
+            ic_request!(
+                ic,
+                SetEventMask {
+                    forward_event_mask,
+                    synchronous_event_mask,
+                }
+            ),
         )
     }
+
+    fn forward_synthetic_event(
+        &mut self,
+        ic: &InputContext,
+        ev: &Self::XEvent,
+    ) -> Result<(), ServerError> {
+        let xev = self.serialize_event(ev)?;
+
+        self.send_req(
+            ic.client_win(),
+            ic_request!(
+                ic,
+                ForwardEvent {
+                    flag: ForwardEventFlag::empty(),
+                    serial_number: xev.sequence,
+                    xev,
+                }
+            ),
+        )
+    }
+
+    fn send_batch(&mut self, client_win: u32, reqs: &[Request]) -> Result<(), ServerError> {
+        for req in reqs {
+            req.validate()?;
+        }
+
+        self.send_reqs(client_win, reqs)
+    }
+}
+
+/// Fail or warn, as the server requires, when `ic` commits before negotiating an encoding.
+fn check_negotiated_encoding<S: ServerCore>(
+    server: &S,
+    ic: &InputContext,
+) -> Result<(), ServerError> {
+    if ic.negotiated_encoding().is_some() {
+        Ok(())
+    } else if server.require_negotiated_encoding() {
+        Err(ServerError::EncodingNotNegotiated)
+    } else {
+        log::warn!(
+            "Committing before encoding negotiation, using {:?}",
+            ic.encoding()
+        );
+        Ok(())
+    }
+}
+
+/// Build an `Error` request, flagging exactly the ids that are given as valid.
+fn error_request(
+    code: ErrorCode,
+    detail: String,
+    input_method_id: Option<NonZeroU16>,
+    input_context_id: Option<NonZeroU16>,
+) -> Request {
+    let mut flag = ErrorFlag::empty();
+
+    if input_method_id.is_some() {
+        flag |= ErrorFlag::INPUT_METHOD_ID_VALID;
+    }
+
+    if input_context_id.is_some() {
+        flag |= ErrorFlag::INPUT_CONTEXT_ID_VALID;
+    }
+
+    Request::Error {
+        input_method_id: input_method_id.map_or(0, NonZeroU16::get),
+        input_context_id: input_context_id.map_or(0, NonZeroU16::get),
+        code,
+        detail,
+        flag,
+    }
 }
 
 pub trait ServerCore {
     type XEvent;
 
+    /// The protocol form of `ev`, needed to send events back to the client, with
+    /// [`EventResult::Replace`] and [`Server::forward_synthetic_event`].
+    ///
+    /// Defaults to failing with [`ServerError::EventNotSerializable`].
+    fn serialize_event(&self, _ev: &Self::XEvent) -> Result<xim_parser::XEvent, ServerError> {
+        Err(ServerError::EventNotSerializable)
+    }
     fn deserialize_event(&self, ev: &xim_parser::XEvent) -> Self::XEvent;
     fn send_req(&mut self, client_win: u32, req: Request) -> Result<(), ServerError>;
+
+    /// Parse the wire form of an event, as it is in a `ForwardEvent` of a client in `endian`.
+    fn event_from_bytes(
+        &self,
+        bytes: &XEventBytes,
+        endian: Endian,
+    ) -> Result<Self::XEvent, ServerError> {
+        Ok(self.deserialize_event(&bytes.read_event(endian)?))
+    }
+
+    /// The wire form of `ev` in `endian`.
+    fn event_to_bytes(
+        &self,
+        ev: &Self::XEvent,
+        endian: Endian,
+    ) -> Result<XEventBytes, ServerError> {
+        Ok(XEventBytes::from_event(&self.serialize_event(ev)?, endian))
+    }
+
+    /// Send several requests in order, transports can override this to write them at once.
+    fn send_reqs(&mut self, client_win: u32, reqs: &[Request]) -> Result<(), ServerError> {
+        for req in reqs {
+            self.send_req(client_win, req.clone())?;
+        }
+
+        Ok(())
+    }
+
+    /// Width and height of `win`, used to keep the preedit spot inside the window.
+    ///
+    /// Defaults to `None`, leaving the spot as the client sent it.
+    fn window_size(&mut self, _win: u32) -> Option<(u16, u16)> {
+        None
+    }
+
+    /// When a request sent now that waits for a reply is given up, on the clock passed to
+    /// [`XimConnections::expire_pending`].
+    ///
+    /// Defaults to `None`, replies are waited for as long as it takes.
+    fn reply_deadline(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Whether committing text before the input method negotiated an encoding fails with
+    /// [`ServerError::EncodingNotNegotiated`].
+    ///
+    /// Defaults to `false`, the text is committed in the default encoding with a warning.
+    fn require_negotiated_encoding(&self) -> bool {
+        false
+    }
+
+    /// The key of `ev`, `None` if it isn't a `KeyPress` or `KeyRelease`.
+    fn decode_key_event(&self, ev: &Self::XEvent) -> Option<KeyEvent> {
+        const KEY_PRESS: u8 = 2;
+        const KEY_RELEASE: u8 = 3;
+
+        let xev = self.serialize_event(ev).ok()?;
+        // the high bit is set on events sent with `SendEvent`
+        let kind = match xev.response_type & 0x7f {
+            KEY_PRESS => KeyEventKind::Press,
+            KEY_RELEASE => KeyEventKind::Release,
+            _ => return None,
+        };
+
+        Some(KeyEvent {
+            keycode: xev.detail,
+            state: xev.state,
+            time: xev.time,
+            kind,
+        })
+    }
 }
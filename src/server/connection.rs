@@ -4,14 +4,26 @@ use crate::AHashMap;
 use alloc::string::String;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::convert::TryFrom;
 use core::num::{NonZeroU16, NonZeroU32};
+use core::time::Duration;
 use xim_parser::{
-    attrs, Attribute, AttributeName, ErrorCode, ForwardEventFlag, InputStyle, InputStyleList,
-    Point, Request, XimWrite,
+    attrs, Attr, AttrType, Attribute, AttributeName, Endian, ErrorCode, Extension, ExtensionTable,
+    ForwardEventFlag, InputStyle, InputStyleList, Point, PreeditStateFlag, ReadError, Reader,
+    Request, TriggerNotifyFlag, XimRead,
 };
 
 use self::im_vec::ImVec;
-use crate::server::{Server, ServerCore, ServerError, ServerHandler};
+use crate::server::{
+    ClientRequest, ConnectInfo, DisconnectReason, DuplicateIcPolicy, Encoding, EventResult,
+    PendingOperation, Server, ServerCore, ServerError, ServerHandler, StatusState,
+    IC_LOCALE_EXTENSION, PREEDIT_STATE_EXTENSION,
+};
+
+#[cfg(feature = "tracing")]
+type DispatchSpan = tracing::span::EnteredSpan;
+#[cfg(not(feature = "tracing"))]
+type DispatchSpan = ();
 
 pub struct InputContext {
     client_win: u32,
@@ -21,8 +33,24 @@ pub struct InputContext {
     input_context_id: NonZeroU16,
     input_style: InputStyle,
     preedit_spot: Point,
+    spot_received: bool,
+    triggered: bool,
     pub(super) preedit_started: bool,
     pub(super) prev_preedit_length: usize,
+    pub(super) preedit_limit: Option<usize>,
+    pub(super) preedit_frozen: bool,
+    pub(super) status_state: StatusState,
+    applied_attributes: Vec<AttributeName>,
+    /// `(list, name, value)` of the attributes kept as the client sent them, `list` is the
+    /// nested list they came in.
+    raw_values: Vec<(Option<AttributeName>, AttributeName, Vec<u8>)>,
+    /// Synchronous `ForwardEvent`s still waiting for their `SyncReply`.
+    pub(super) unanswered_syncs: u32,
+    /// `(operation, deadline)` of requests sent to it waiting for a reply.
+    pub(super) pending: Vec<(PendingOperation, Duration)>,
+    last_event_time: Option<u32>,
+    encoding: Encoding,
+    encoding_is_default: bool,
     locale: String,
 }
 
@@ -33,18 +61,16 @@ impl InputContext {
         input_context_id: NonZeroU16,
         locale: String,
     ) -> Self {
-        Self {
-            client_win,
-            app_win: None,
-            app_focus_win: None,
-            input_method_id,
-            input_context_id,
-            input_style: InputStyle::empty(),
-            preedit_spot: Point { x: 0, y: 0 },
-            preedit_started: false,
-            prev_preedit_length: 0,
-            locale,
-        }
+        InputContextBuilder::new()
+            .client_win(client_win)
+            .ids(input_method_id, input_context_id)
+            .locale(locale)
+            .build()
+            .ic
+    }
+
+    pub fn builder() -> InputContextBuilder<()> {
+        InputContextBuilder::new()
     }
 
     pub fn client_win(&self) -> u32 {
@@ -63,6 +89,52 @@ impl InputContext {
         self.preedit_spot.clone()
     }
 
+    /// Whether input was turned on with a trigger key and not turned off since.
+    pub fn triggered(&self) -> bool {
+        self.triggered
+    }
+
+    /// Whether the client has sent a spot location for this input context yet.
+    pub fn spot_received(&self) -> bool {
+        self.spot_received
+    }
+
+    /// Most characters of preedit the client renders, from its `PreeditStartReply`.
+    ///
+    /// `None` is unlimited, `Some(0)` means the client doesn't render preedit at all.
+    pub fn preedit_limit(&self) -> Option<usize> {
+        self.preedit_limit
+    }
+
+    /// Whether the client froze the preedit with the
+    /// [`PREEDIT_STATE_EXTENSION`](crate::server::PREEDIT_STATE_EXTENSION).
+    pub fn preedit_frozen(&self) -> bool {
+        self.preedit_frozen
+    }
+
+    /// Whether a `StatusStart` was sent without its `StatusDone` yet.
+    pub fn status_state(&self) -> StatusState {
+        self.status_state
+    }
+
+    /// Attributes the last `CreateIc` or `SetIcValues` changed, the rest kept their values.
+    pub fn applied_attributes(&self) -> &[AttributeName] {
+        &self.applied_attributes
+    }
+
+    /// The value of an attribute the server keeps without interpreting it, like the font set or
+    /// the colors. `list` is the nested list it was set in, `None` for a top level attribute.
+    pub fn attribute_value(
+        &self,
+        list: Option<AttributeName>,
+        name: AttributeName,
+    ) -> Option<&[u8]> {
+        self.raw_values
+            .iter()
+            .find(|(l, n, _)| *l == list && *n == name)
+            .map(|(_, _, value)| &value[..])
+    }
+
     pub fn input_method_id(&self) -> NonZeroU16 {
         self.input_method_id
     }
@@ -71,6 +143,11 @@ impl InputContext {
         self.input_context_id
     }
 
+    /// The `(input_method_id, input_context_id)` pair addressing this input context.
+    pub fn address(&self) -> (NonZeroU16, NonZeroU16) {
+        (self.input_method_id, self.input_context_id)
+    }
+
     pub fn input_style(&self) -> InputStyle {
         self.input_style
     }
@@ -78,8 +155,136 @@ impl InputContext {
     pub fn locale(&self) -> &str {
         self.locale.as_str()
     }
+
+    /// Encoding of the text sent to the client, the one of its input method.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Whether [`encoding`](Self::encoding) is the default because the input method hasn't
+    /// negotiated one yet.
+    pub fn encoding_is_default(&self) -> bool {
+        self.encoding_is_default
+    }
+
+    /// The encoding its input method negotiated, `None` until `EncodingNegotiation`.
+    pub fn negotiated_encoding(&self) -> Option<Encoding> {
+        if self.encoding_is_default {
+            None
+        } else {
+            Some(self.encoding)
+        }
+    }
+}
+
+/// Builds a [`UserInputContext`] with named setters instead of positional arguments.
+///
+/// The ids default to 1, the style to none and the spot to the origin. A spot set here isn't
+/// one the client sent, [`InputContext::spot_received`] stays `false`.
+pub struct InputContextBuilder<T> {
+    client_win: u32,
+    input_method_id: NonZeroU16,
+    input_context_id: NonZeroU16,
+    locale: String,
+    input_style: InputStyle,
+    preedit_spot: Point,
+    user_data: T,
+}
+
+impl InputContextBuilder<()> {
+    pub fn new() -> Self {
+        Self {
+            client_win: 0,
+            input_method_id: NonZeroU16::new(1).unwrap(),
+            input_context_id: NonZeroU16::new(1).unwrap(),
+            locale: String::new(),
+            input_style: InputStyle::empty(),
+            preedit_spot: Point { x: 0, y: 0 },
+            user_data: (),
+        }
+    }
 }
 
+impl Default for InputContextBuilder<()> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> InputContextBuilder<T> {
+    pub fn client_win(mut self, client_win: u32) -> Self {
+        self.client_win = client_win;
+        self
+    }
+
+    /// Set the address of the input context, in the order of [`InputContext::address`].
+    pub fn ids(mut self, input_method_id: NonZeroU16, input_context_id: NonZeroU16) -> Self {
+        self.input_method_id = input_method_id;
+        self.input_context_id = input_context_id;
+        self
+    }
+
+    pub fn locale(mut self, locale: String) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    pub fn input_style(mut self, input_style: InputStyle) -> Self {
+        self.input_style = input_style;
+        self
+    }
+
+    pub fn preedit_spot(mut self, preedit_spot: Point) -> Self {
+        self.preedit_spot = preedit_spot;
+        self
+    }
+
+    pub fn user_data<U>(self, user_data: U) -> InputContextBuilder<U> {
+        InputContextBuilder {
+            client_win: self.client_win,
+            input_method_id: self.input_method_id,
+            input_context_id: self.input_context_id,
+            locale: self.locale,
+            input_style: self.input_style,
+            preedit_spot: self.preedit_spot,
+            user_data,
+        }
+    }
+
+    pub fn build(self) -> UserInputContext<T> {
+        let ic = InputContext {
+            client_win: self.client_win,
+            app_win: None,
+            app_focus_win: None,
+            input_method_id: self.input_method_id,
+            input_context_id: self.input_context_id,
+            input_style: self.input_style,
+            preedit_spot: self.preedit_spot,
+            spot_received: false,
+            triggered: false,
+            preedit_started: false,
+            prev_preedit_length: 0,
+            preedit_limit: None,
+            preedit_frozen: false,
+            status_state: StatusState::Idle,
+            applied_attributes: Vec::new(),
+            raw_values: Vec::new(),
+            unanswered_syncs: 0,
+            pending: Vec::new(),
+            last_event_time: None,
+            encoding: Encoding::default(),
+            encoding_is_default: true,
+            locale: self.locale,
+        };
+
+        UserInputContext::new(ic, self.user_data)
+    }
+}
+
+/// An input context with the data the handler keeps for it.
+///
+/// The data is moved when the input context is, state shared between input contexts like a
+/// dictionary belongs in the handler or behind an `Rc`/`Arc` in the data.
 pub struct UserInputContext<T> {
     pub ic: InputContext,
     pub user_data: T,
@@ -89,11 +294,98 @@ impl<T> UserInputContext<T> {
     pub fn new(ic: InputContext, user_data: T) -> Self {
         Self { ic, user_data }
     }
+
+    pub fn user_data(&self) -> &T {
+        &self.user_data
+    }
+
+    pub fn user_data_mut(&mut self) -> &mut T {
+        &mut self.user_data
+    }
+}
+
+/// Id 0 never names an input method or input context.
+fn require_nonzero(id: u16) -> Result<NonZeroU16, ServerError> {
+    NonZeroU16::new(id).ok_or(ServerError::ClientNotExists)
+}
+
+/// The input context attributes advertised in `OpenReply`, the only ones `set_ic_attrs` accepts
+/// with [`attrs::LOCALE`] of the [`IC_LOCALE_EXTENSION`].
+const IC_ATTR_TABLE: &[Attr] = &[
+    attrs::INPUT_STYLE,
+    attrs::CLIENTWIN,
+    attrs::FOCUSWIN,
+    attrs::FILTER_EVENTS,
+    attrs::PREEDIT_ATTRIBUTES,
+    attrs::STATUS_ATTRIBUTES,
+    attrs::FONT_SET,
+    attrs::AREA,
+    attrs::AREA_NEEDED,
+    attrs::COLOR_MAP,
+    attrs::STD_COLOR_MAP,
+    attrs::FOREGROUND,
+    attrs::BACKGROUND,
+    attrs::BACKGROUND_PIXMAP,
+    attrs::SPOT_LOCATION,
+    attrs::LINE_SPACE,
+    attrs::SEPARATOR_OF_NESTED_LIST,
+];
+
+/// The name of an input context attribute id from [`IC_ATTR_TABLE`] or [`attrs::LOCALE`], a
+/// client only knows the id of the latter when it was advertised.
+pub(super) fn ic_attr_name(id: u16) -> Option<AttributeName> {
+    IC_ATTR_TABLE
+        .iter()
+        .chain([&attrs::LOCALE])
+        .find(|attr| attr.id == id)
+        .map(|attr| attr.name)
+}
+
+/// Read a window of the application, `None` for 0 or the XIM communication window of the
+/// client, which would make the server send events meant for the application to itself.
+fn app_window(ic: &InputContext, name: AttributeName, value: &[u8]) -> Option<NonZeroU32> {
+    match xim_parser::read::<u32>(value) {
+        Ok(win) if win != 0 && win != ic.client_win => NonZeroU32::new(win),
+        Ok(win) => {
+            log::warn!(
+                "Reject {:?} {}, it isn't a window of the application",
+                name,
+                win
+            );
+            None
+        }
+        Err(_) => {
+            log::warn!("Ignore invalid {:?}: {:?}", name, value);
+            None
+        }
+    }
+}
+
+fn set_raw_value(
+    ic: &mut InputContext,
+    list: Option<AttributeName>,
+    name: AttributeName,
+    value: Vec<u8>,
+) {
+    match ic
+        .raw_values
+        .iter_mut()
+        .find(|(l, n, _)| *l == list && *n == name)
+    {
+        Some(entry) => entry.2 = value,
+        None => ic.raw_values.push((list, name, value)),
+    }
 }
 
-fn set_ic_attrs(ic: &mut InputContext, ic_attributes: Vec<Attribute>) {
+/// Apply the attributes to `ic`, returns the names of the ones applied.
+///
+/// The attributes of a nested list are reported after the name of the list. The ones the server
+/// doesn't interpret are kept for [`InputContext::attribute_value`].
+fn set_ic_attrs(ic: &mut InputContext, ic_attributes: Vec<Attribute>) -> Vec<AttributeName> {
+    let mut applied = Vec::with_capacity(ic_attributes.len());
+
     for attr in ic_attributes {
-        let name = if let Some(name) = attrs::get_name(attr.id) {
+        let name = if let Some(name) = ic_attr_name(attr.id) {
             name
         } else {
             log::warn!("Unknown attr id: {}", attr.id);
@@ -105,47 +397,116 @@ fn set_ic_attrs(ic: &mut InputContext, ic_attributes: Vec<Attribute>) {
                 if let Ok(style) = xim_parser::read(&attr.value) {
                     log::debug!("Style: {:?}", style);
                     ic.input_style = style;
+                    applied.push(name);
                 }
             }
             AttributeName::ClientWindow => {
-                ic.app_win = xim_parser::read(&attr.value).ok().and_then(NonZeroU32::new);
+                if let Some(win) = app_window(ic, name, &attr.value) {
+                    ic.app_win = Some(win);
+                    applied.push(name);
+                }
             }
             AttributeName::FocusWindow => {
-                ic.app_focus_win = xim_parser::read(&attr.value).ok().and_then(NonZeroU32::new);
-            }
-            AttributeName::PreeditAttributes => {
-                let mut b = &attr.value[..];
-                while !b.is_empty() {
-                    match xim_parser::read::<Attribute>(b) {
-                        Ok(attr) => {
-                            b = &b[attr.size()..];
-                            match attrs::get_name(attr.id) {
-                                Some(AttributeName::SpotLocation) => {
-                                    if let Ok(spot) = xim_parser::read(&attr.value) {
-                                        log::debug!("Spot: {:?}", spot);
-                                        ic.preedit_spot = spot;
-                                    }
-                                }
-                                name => {
-                                    log::warn!("Ignore unhandled preedit attr: {:?}", name);
+                if let Some(win) = app_window(ic, name, &attr.value) {
+                    ic.app_focus_win = Some(win);
+                    applied.push(name);
+                }
+            }
+            // the input method keeps the locale of `Open`
+            AttributeName::Locale => {
+                let value = attr.value.split(|&b| b == 0).next().unwrap_or_default();
+                match core::str::from_utf8(value) {
+                    Ok(locale) if !locale.is_empty() => {
+                        log::debug!("Locale: {}", locale);
+                        ic.locale = locale.into();
+                        applied.push(name);
+                    }
+                    _ => log::warn!("Ignore invalid locale: {:?}", attr.value),
+                }
+            }
+            // sent flat by clients that don't nest it
+            AttributeName::SpotLocation => {
+                if let Ok(spot) = xim_parser::read(&attr.value) {
+                    log::debug!("Spot: {:?}", spot);
+                    ic.preedit_spot = spot;
+                    ic.spot_received = true;
+                    applied.push(name);
+                }
+            }
+            AttributeName::PreeditAttributes | AttributeName::StatusAttributes => {
+                applied.push(name);
+                let mut reader = Reader::new(&attr.value);
+                while reader.cursor() > 0 {
+                    match Attribute::read(&mut reader) {
+                        Ok(nested) => match ic_attr_name(nested.id) {
+                            Some(AttributeName::SpotLocation)
+                                if name == AttributeName::PreeditAttributes =>
+                            {
+                                if let Ok(spot) = xim_parser::read(&nested.value) {
+                                    log::debug!("Spot: {:?}", spot);
+                                    ic.preedit_spot = spot;
+                                    ic.spot_received = true;
+                                    applied.push(AttributeName::SpotLocation);
                                 }
                             }
-                        }
+                            Some(AttributeName::SeparatorofNestedList) => break,
+                            Some(nested_name) => {
+                                set_raw_value(ic, Some(name), nested_name, nested.value);
+                                applied.push(nested_name);
+                            }
+                            None => {
+                                log::warn!("Unknown nested attr id: {}", nested.id);
+                            }
+                        },
                         Err(_) => {
                             break;
                         }
                     }
                 }
             }
+            AttributeName::SeparatorofNestedList => {
+                log::warn!("Ignore separator outside of a nested list");
+            }
             name => {
-                log::warn!("Ignore unhandled attr: {:?}", name);
+                set_raw_value(ic, None, name, attr.value);
+                applied.push(name);
             }
         }
     }
+
+    applied
+}
+
+/// Apply the attributes to `ic`, a new spot location is clamped to the focus window, or the
+/// client window without one, when the server knows its size.
+fn set_ic_attrs_clamped<S: ServerCore, H: ServerHandler<S>>(
+    server: &mut S,
+    handler: &H,
+    ic: &mut InputContext,
+    ic_attributes: Vec<Attribute>,
+) {
+    ic.applied_attributes = set_ic_attrs(ic, ic_attributes);
+    if !ic.applied_attributes.contains(&AttributeName::SpotLocation) {
+        return;
+    }
+
+    let size = ic
+        .app_focus_win
+        .or(ic.app_win)
+        .and_then(|win| server.window_size(win.get()));
+
+    if let Some(size) = size {
+        let spot = handler.clamp_preedit_spot(ic.preedit_spot(), size);
+        if spot != ic.preedit_spot {
+            log::debug!("Clamped spot {:?} to {:?}", ic.preedit_spot, spot);
+            ic.preedit_spot = spot;
+        }
+    }
 }
 
 pub struct InputMethod<T> {
     pub(crate) locale: String,
+    pub(crate) encoding: Option<Encoding>,
     pub(crate) input_contexts: ImVec<UserInputContext<T>>,
 }
 
@@ -153,15 +514,40 @@ impl<T> InputMethod<T> {
     pub fn new(locale: String) -> Self {
         Self {
             locale,
+            encoding: None,
             input_contexts: ImVec::new(),
         }
     }
 
+    /// Set the negotiated encoding, existing input contexts switch to it as well.
+    ///
+    /// A client negotiating again replaces the previous encoding.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        if let Some(prev) = self.encoding.replace(encoding) {
+            if prev != encoding {
+                log::debug!("Renegotiated encoding: {:?} -> {:?}", prev, encoding);
+            }
+        }
+
+        for (_id, user_ic) in self.input_contexts.iter_mut() {
+            user_ic.ic.encoding = encoding;
+            user_ic.ic.encoding_is_default = false;
+        }
+    }
+
+    /// The encoding the client negotiated, `None` until `EncodingNegotiation`.
+    pub fn negotiated_encoding(&self) -> Option<Encoding> {
+        self.encoding
+    }
+
     pub fn clone_locale(&self) -> String {
         self.locale.clone()
     }
 
-    pub fn new_ic(&mut self, ic: UserInputContext<T>) -> (NonZeroU16, &mut UserInputContext<T>) {
+    pub fn new_ic(
+        &mut self,
+        ic: UserInputContext<T>,
+    ) -> Result<(NonZeroU16, &mut UserInputContext<T>), ServerError> {
         self.input_contexts.new_item(ic)
     }
 
@@ -184,6 +570,21 @@ impl<T> InputMethod<T> {
 pub struct XimConnection<T> {
     pub(crate) client_win: u32,
     pub(crate) disconnected: bool,
+    pub(crate) endian: Endian,
+    batch_forward_events: bool,
+    reject_trailing_bytes: bool,
+    pending_forward_events: Vec<Request>,
+    extensions: ExtensionTable,
+    max_input_methods: usize,
+    max_input_contexts_per_im: usize,
+    flat_attributes: bool,
+    request_buf: Vec<u8>,
+    max_request_bytes: usize,
+    discarding_request: bool,
+    sequence: u64,
+    last_focused: Option<(u16, u16)>,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
     pub(crate) input_methods: ImVec<InputMethod<T>>,
 }
 
@@ -192,19 +593,342 @@ impl<T> XimConnection<T> {
         Self {
             client_win,
             disconnected: false,
+            endian: Endian::Native,
+            batch_forward_events: false,
+            reject_trailing_bytes: false,
+            pending_forward_events: Vec::new(),
+            extensions: ExtensionTable::new(),
+            max_input_methods: usize::MAX,
+            max_input_contexts_per_im: usize::MAX,
+            flat_attributes: false,
+            request_buf: Vec::new(),
+            max_request_bytes: usize::MAX,
+            discarding_request: false,
+            sequence: 0,
+            last_focused: None,
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!("xim_connection", client_win),
             input_methods: ImVec::new(),
         }
     }
 
+    /// Number of the last request handled, counting from 1.
+    ///
+    /// It isn't sent on the wire, it only tells apart requests in logs and in
+    /// [`ServerHandler::before_dispatch`].
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// `(input_method_id, input_context_id)` of the input context the client last focused
+    /// with `SetIcFocus`, `None` once it's unfocused or destroyed.
+    pub fn last_focused(&self) -> Option<(u16, u16)> {
+        self.last_focused
+    }
+
+    /// Wait for the reply to `operation` sent to an input context until `deadline`, after which
+    /// [`XimConnections::expire_pending`] gives it up.
+    ///
+    /// `deadline` is measured from any clock, as long as `expire_pending` gets the same one.
+    /// [`Server::preedit_draw`] and [`Server::sync`] add theirs with
+    /// [`ServerCore::reply_deadline`].
+    pub fn add_pending(
+        &mut self,
+        input_method_id: u16,
+        input_context_id: u16,
+        operation: PendingOperation,
+        deadline: Duration,
+    ) -> Result<(), ServerError> {
+        self.get_input_method(input_method_id)?
+            .get_input_context(input_context_id)?
+            .ic
+            .pending
+            .push((operation, deadline));
+        Ok(())
+    }
+
+    fn complete_pending(&mut self, address: (u16, u16), operation: PendingOperation) {
+        let user_ic = self
+            .get_input_method(address.0)
+            .and_then(|im| im.get_input_context(address.1));
+        if let Ok(user_ic) = user_ic {
+            let pending = &mut user_ic.ic.pending;
+            if let Some(pos) = pending.iter().position(|&(op, _)| op == operation) {
+                pending.remove(pos);
+            }
+        }
+    }
+
+    /// Queue events the handler didn't consume instead of sending each back on its own.
+    ///
+    /// Queued events are sent in one transport write before the next other request is handled,
+    /// before a `SyncReply`, or on [`flush_forward_events`](Self::flush_forward_events). Requests
+    /// the handler sends itself meanwhile, like a commit, can arrive at the client before the
+    /// queued events. Defaults to `false`.
+    pub fn set_batch_forward_events(&mut self, batch: bool) {
+        self.batch_forward_events = batch;
+    }
+
+    /// Reject a message with bytes left after the request it holds in
+    /// [`read_request`](Self::read_request).
+    ///
+    /// Defaults to `false`. A transport padding short requests, like the 20 bytes of a
+    /// `ClientMessage`, has to strip the padding before turning this on.
+    pub fn set_reject_trailing_bytes(&mut self, reject: bool) {
+        self.reject_trailing_bytes = reject;
+    }
+
+    /// Limit the input methods a client can open, `Open` beyond it is answered with `BadAlloc`.
+    ///
+    /// Defaults to no limit besides running out of ids.
+    pub fn set_max_input_methods(&mut self, max: usize) {
+        self.max_input_methods = max;
+    }
+
+    /// Limit the input contexts a client can create in each input method, `CreateIc` beyond it
+    /// is answered with `BadAlloc`.
+    ///
+    /// Defaults to no limit besides running out of ids.
+    pub fn set_max_input_contexts_per_im(&mut self, max: usize) {
+        self.max_input_contexts_per_im = max;
+    }
+
+    /// Advertise and reply input context attributes without nested lists, for clients that
+    /// can't parse them.
+    ///
+    /// `Connect` sets this from [`ServerHandler::flat_attributes`]. Defaults to `false`.
+    pub fn set_flat_attributes(&mut self, flat: bool) {
+        self.flat_attributes = flat;
+    }
+
+    /// Limit the bytes of a request read from a property or reassembled from `_XIM_MOREDATA`
+    /// messages, a longer request is answered with `BadAlloc` and dropped.
+    ///
+    /// Defaults to no limit besides the 16-bit length of the header.
+    pub fn set_max_request_bytes(&mut self, max: usize) {
+        self.max_request_bytes = max;
+    }
+
+    pub fn max_request_bytes(&self) -> usize {
+        self.max_request_bytes
+    }
+
+    /// Add a part of a request divided over several messages, `more` is set for every
+    /// `_XIM_MOREDATA` part before the last one.
+    ///
+    /// Returns the whole request with its last part. A request growing over
+    /// [`max_request_bytes`](Self::max_request_bytes) is rejected and its remaining parts are
+    /// dropped.
+    pub fn reassemble_request<S: ServerCore>(
+        &mut self,
+        server: &mut S,
+        data: &[u8],
+        more: bool,
+    ) -> Result<Option<Vec<u8>>, ServerError> {
+        if self.discarding_request {
+            self.discarding_request = more;
+            return Ok(None);
+        }
+
+        if self.request_buf.len() + data.len() > self.max_request_bytes {
+            self.reject_request(server, more)?;
+            return Ok(None);
+        }
+
+        self.request_buf.extend_from_slice(data);
+
+        if more {
+            Ok(None)
+        } else {
+            Ok(Some(core::mem::take(&mut self.request_buf)))
+        }
+    }
+
+    /// Answer a request over [`max_request_bytes`](Self::max_request_bytes) with `BadAlloc`,
+    /// dropping what was reassembled of it and its parts still to come if `more` is set.
+    pub(crate) fn reject_request<S: ServerCore>(
+        &mut self,
+        server: &mut S,
+        more: bool,
+    ) -> Result<(), ServerError> {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(parent: &self.span, max = self.max_request_bytes, "request too long");
+
+        // free the memory instead of keeping it for the next request
+        self.request_buf = Vec::new();
+        self.discarding_request = more;
+        server.error(
+            self.client_win,
+            ErrorCode::BadAlloc,
+            "Request too long".into(),
+            None,
+            None,
+        )
+    }
+
+    /// Send the events queued by [`set_batch_forward_events`](Self::set_batch_forward_events).
+    pub fn flush_forward_events<S: ServerCore>(
+        &mut self,
+        server: &mut S,
+    ) -> Result<(), ServerError> {
+        if self.pending_forward_events.is_empty() {
+            return Ok(());
+        }
+
+        let ret = server.send_reqs(self.client_win, &self.pending_forward_events);
+        self.pending_forward_events.clear();
+        ret
+    }
+
+    /// Byte order of the client, set by its `Connect` request.
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    /// Create a [`Reader`] over a message from the client in its byte order.
+    pub fn reader<'b>(&self, bytes: &'b [u8]) -> Reader<'b> {
+        Reader::with_endian(bytes, self.endian)
+    }
+
+    /// Read a request from the client and reject it if it isn't internally consistent, or
+    /// followed by other bytes with [`set_reject_trailing_bytes`](Self::set_reject_trailing_bytes).
+    pub fn read_request(&self, bytes: &[u8]) -> Result<Request, ReadError> {
+        let mut reader = self.reader(bytes);
+        let req = Request::read(&mut reader)?;
+        if self.reject_trailing_bytes {
+            reader.expect_consumed()?;
+        }
+        req.validate()?;
+        Ok(req)
+    }
+
+    /// Move an input context to another input method of this connection, keeping its state.
+    ///
+    /// Returns the new id of the input context under `to_im`. If either input method or the
+    /// input context doesn't exist, or `to_im` is at the
+    /// [`set_max_input_contexts_per_im`](Self::set_max_input_contexts_per_im) limit, nothing is
+    /// moved.
+    ///
+    /// The move is internal to the server, XIM has no request telling the client the new ids.
+    /// Requests of the client for the old ones fail like for a destroyed input context.
+    pub fn move_ic(&mut self, from_im: u16, ic_id: u16, to_im: u16) -> Result<u16, ServerError> {
+        let max_input_contexts = self.max_input_contexts_per_im;
+        let to = self.get_input_method(to_im)?;
+        if to.input_contexts.len() >= max_input_contexts {
+            return Err(ServerError::XimError(
+                ErrorCode::BadAlloc,
+                "Too many input contexts".into(),
+            ));
+        }
+        if to.input_contexts.is_full() {
+            return Err(ServerError::IdExhausted);
+        }
+
+        let mut ic = self
+            .get_input_method(from_im)?
+            .remove_input_context(ic_id)?;
+        let to = self.get_input_method(to_im)?;
+        ic.ic.input_method_id = require_nonzero(to_im)?;
+        let (new_id, ic) = to.new_ic(ic)?;
+        ic.ic.input_context_id = new_id;
+
+        if self.last_focused == Some((from_im, ic_id)) {
+            self.last_focused = Some((to_im, new_id.get()));
+        }
+
+        Ok(new_id.get())
+    }
+
+    /// Extensions negotiated with the client through `QueryExtension`.
+    pub fn extensions(&self) -> &ExtensionTable {
+        &self.extensions
+    }
+
+    /// Route a message at a negotiated extension opcode to [`ServerHandler::handle_extension`].
+    ///
+    /// Returns `false` without doing anything if `bytes` isn't an extension request, it should
+    /// be read with [`read_request`](Self::read_request) then.
+    pub fn handle_extension_request<S: ServerCore, H: ServerHandler<S, InputContextData = T>>(
+        &mut self,
+        server: &mut S,
+        bytes: &[u8],
+        handler: &mut H,
+    ) -> Result<bool, ServerError> {
+        let mut reader = self.reader(bytes);
+        let (major_opcode, minor_opcode) = (reader.u8()?, reader.u8()?);
+        let extension = match self.extensions.get(major_opcode, minor_opcode) {
+            Some(extension) => extension.clone(),
+            None => return Ok(false),
+        };
+        let length = reader.u16()? as usize * 4;
+        let body = reader.consume(length)?;
+
+        let req = ClientRequest::Extension {
+            extension: &extension,
+            body,
+        };
+        let (sequence, _span) = self.begin_dispatch(server, req, handler)?;
+        let ret = self.dispatch_extension(server, &extension, body, handler);
+        handler.after_dispatch(sequence, &extension.name, &ret);
+
+        ret.map(|()| true)
+    }
+
+    fn dispatch_extension<S: ServerCore, H: ServerHandler<S, InputContextData = T>>(
+        &mut self,
+        server: &mut S,
+        extension: &Extension,
+        body: &[u8],
+        handler: &mut H,
+    ) -> Result<(), ServerError> {
+        if extension.name == PREEDIT_STATE_EXTENSION {
+            let mut reader = self.reader(body);
+            let input_method_id = reader.u16()?;
+            let input_context_id = reader.u16()?;
+            let frozen = PreeditStateFlag::read(&mut reader)?.contains(PreeditStateFlag::DISABLE);
+
+            let user_ic = self
+                .get_input_method(input_method_id)?
+                .get_input_context(input_context_id)?;
+            user_ic.ic.preedit_frozen = frozen;
+            handler.handle_preedit_state(server, user_ic, frozen)
+        } else {
+            handler.handle_extension(server, extension, body)
+        }
+    }
+
+    /// Handle one whole message from the client, an extension request or a core request.
+    ///
+    /// A transport calls this with the bytes of each message, after
+    /// [`reassemble_request`](Self::reassemble_request) when they were divided.
+    pub fn handle_message<S: ServerCore, H: ServerHandler<S, InputContextData = T>>(
+        &mut self,
+        server: &mut S,
+        bytes: &[u8],
+        handler: &mut H,
+    ) -> Result<(), ServerError> {
+        if self.handle_extension_request(server, bytes, handler)? {
+            return Ok(());
+        }
+
+        let req = self.read_request(bytes)?;
+        self.handle_request(server, req, handler)
+    }
+
     pub fn disconnect<S: ServerCore + Server, H: ServerHandler<S, InputContextData = T>>(
         &mut self,
         server: &mut S,
         handler: &mut H,
+        reason: DisconnectReason,
     ) -> Result<(), ServerError> {
-        for (_id, im) in self.input_methods.drain() {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(parent: &self.span, ?reason, "disconnect");
+
+        for (id, im) in self.input_methods.drain() {
             for (_id, ic) in im.input_contexts {
-                handler.handle_destroy_ic(server, ic)?;
+                handler.handle_disconnect_ic(server, ic, reason)?;
             }
+            handler.handle_close(server, id.get())?;
         }
 
         self.disconnected = true;
@@ -230,12 +954,73 @@ impl<T> XimConnection<T> {
         req: Request,
         handler: &mut H,
     ) -> Result<(), ServerError> {
+        let (sequence, _span) =
+            self.begin_dispatch(server, ClientRequest::Request(&req), handler)?;
+        let name = req.name();
+        let ret = self.dispatch_request(server, req, handler);
+        handler.after_dispatch(sequence, name, &ret);
+
+        ret
+    }
+
+    /// Number and log a request, then pass it to [`ServerHandler::before_dispatch`]. The span
+    /// of the request lasts as long as the returned guard.
+    fn begin_dispatch<S: ServerCore, H: ServerHandler<S, InputContextData = T>>(
+        &mut self,
+        server: &mut S,
+        req: ClientRequest,
+        handler: &mut H,
+    ) -> Result<(u64, DispatchSpan), ServerError> {
+        self.sequence += 1;
+        let sequence = self.sequence;
+
         if log::log_enabled!(log::Level::Trace) {
-            log::trace!("<-: {:?}", req);
+            match req {
+                ClientRequest::Request(req) => log::trace!("<- #{}: {:?}", sequence, req),
+                ClientRequest::Extension { body, .. } => {
+                    log::trace!("<- #{}: {} {:?}", sequence, req.name(), body)
+                }
+            }
         } else {
-            log::debug!("<-: {}", req.name());
+            log::debug!("<- #{}: {}", sequence, req.name());
         }
 
+        #[cfg(feature = "tracing")]
+        let span = {
+            let (input_method_id, input_context_id) = match req {
+                ClientRequest::Request(req) => (req.input_method_id(), req.input_context_id()),
+                ClientRequest::Extension { .. } => (None, None),
+            };
+            let span = tracing::debug_span!(
+                parent: &self.span,
+                "xim_request",
+                request = req.name(),
+                sequence,
+                input_method_id,
+                input_context_id,
+            )
+            .entered();
+            tracing::debug!("dispatch");
+            span
+        };
+        #[cfg(not(feature = "tracing"))]
+        let span = ();
+
+        if !matches!(req, ClientRequest::Request(Request::ForwardEvent { .. })) {
+            self.flush_forward_events(server)?;
+        }
+
+        handler.before_dispatch(sequence, req);
+
+        Ok((sequence, span))
+    }
+
+    fn dispatch_request<S: ServerCore, H: ServerHandler<S, InputContextData = T>>(
+        &mut self,
+        server: &mut S,
+        req: Request,
+        handler: &mut H,
+    ) -> Result<(), ServerError> {
         match req {
             Request::Error {
                 code,
@@ -249,7 +1034,39 @@ impl<T> XimConnection<T> {
                 log::error!("XIM ERROR! code: {:?}, detail: {}", code, detail);
             }
 
-            Request::Connect { .. } => {
+            Request::Connect {
+                endian,
+                client_major_protocol_version,
+                client_minor_protocol_version,
+                client_auth_protocol_names,
+            } => {
+                self.endian = endian;
+
+                if !client_auth_protocol_names.is_empty() {
+                    match handler
+                        .negotiate_auth(&client_auth_protocol_names)
+                        .and_then(|pos| client_auth_protocol_names.get(pos))
+                    {
+                        Some(name) => log::debug!("Authentication protocol: {}", name),
+                        None => {
+                            return server.error(
+                                self.client_win,
+                                ErrorCode::BadProtocol,
+                                "Unsupported authentication protocol".into(),
+                                None,
+                                None,
+                            );
+                        }
+                    }
+                }
+
+                let info = ConnectInfo {
+                    endian,
+                    client_major_protocol_version,
+                    client_minor_protocol_version,
+                    client_auth_protocol_names,
+                };
+
                 server.send_req(
                     self.client_win,
                     Request::ConnectReply {
@@ -257,41 +1074,47 @@ impl<T> XimConnection<T> {
                         server_minor_protocol_version: 0,
                     },
                 )?;
-                handler.handle_connect(server)?;
+                self.flat_attributes = handler.flat_attributes(&info);
+                handler.handle_connect(server, &info)?;
             }
 
             Request::Disconnect {} => {
-                self.disconnect(server, handler)?;
+                self.disconnect(server, handler, DisconnectReason::Clean)?;
                 server.send_req(self.client_win, Request::DisconnectReply {})?;
             }
 
             Request::Open { locale } => {
-                let (input_method_id, _im) = self.input_methods.new_item(InputMethod::new(locale));
+                if self.input_methods.len() >= self.max_input_methods {
+                    return server.error(
+                        self.client_win,
+                        ErrorCode::BadAlloc,
+                        "Too many input methods".into(),
+                        None,
+                        None,
+                    );
+                }
+
+                let (input_method_id, _im) =
+                    self.input_methods.new_item(InputMethod::new(locale))?;
+
+                let mut ic_attrs = IC_ATTR_TABLE.to_vec();
+                let extensions = handler.extensions();
+                if extensions.iter().any(|ext| ext.name == IC_LOCALE_EXTENSION) {
+                    // before the separator closing the table
+                    ic_attrs.insert(ic_attrs.len() - 1, attrs::LOCALE);
+                }
+                if self.flat_attributes {
+                    ic_attrs.retain(|attr| {
+                        !matches!(attr.ty, AttrType::NestedList | AttrType::Separator)
+                    });
+                }
 
                 server.send_req(
                     self.client_win,
                     Request::OpenReply {
                         input_method_id: input_method_id.get(),
                         im_attrs: vec![attrs::QUERY_INPUT_STYLE],
-                        ic_attrs: vec![
-                            attrs::INPUT_STYLE,
-                            attrs::CLIENTWIN,
-                            attrs::FOCUSWIN,
-                            attrs::FILTER_EVENTS,
-                            attrs::PREEDIT_ATTRIBUTES,
-                            attrs::STATUS_ATTRIBUTES,
-                            attrs::FONT_SET,
-                            attrs::AREA,
-                            attrs::AREA_NEEDED,
-                            attrs::COLOR_MAP,
-                            attrs::STD_COLOR_MAP,
-                            attrs::FOREGROUND,
-                            attrs::BACKGROUND,
-                            attrs::BACKGROUND_PIXMAP,
-                            attrs::SPOT_LOCATION,
-                            attrs::LINE_SPACE,
-                            attrs::SEPARATOR_OF_NESTED_LIST,
-                        ],
+                        ic_attrs,
                     },
                 )?;
             }
@@ -301,26 +1124,83 @@ impl<T> XimConnection<T> {
                 ic_attributes,
             } => {
                 let client_win = self.client_win;
+                let max_input_contexts = self.max_input_contexts_per_im;
+                let im_id = match require_nonzero(input_method_id) {
+                    Ok(id) => id,
+                    Err(_) => {
+                        return server.error(
+                            client_win,
+                            ErrorCode::BadSomething,
+                            "Input method id is zero".into(),
+                            None,
+                            None,
+                        );
+                    }
+                };
                 let im = self.get_input_method(input_method_id)?;
                 let mut ic = InputContext::new(
                     client_win,
-                    NonZeroU16::new(input_method_id).unwrap(),
+                    im_id,
                     NonZeroU16::new(1).unwrap(),
                     im.clone_locale(),
                 );
-                set_ic_attrs(&mut ic, ic_attributes);
-                let input_style = ic.input_style;
-                let ic = UserInputContext::new(ic, handler.new_ic_data(server, input_style)?);
-                let (input_context_id, ic) = im.new_ic(ic);
+                let defaults = handler.ic_defaults();
+                ic.input_style = defaults.input_style;
+                ic.preedit_spot = defaults.preedit_spot;
+                // without negotiation yet the default is kept until `EncodingNegotiation`
+                if let Some(encoding) = im.encoding {
+                    ic.encoding = encoding;
+                    ic.encoding_is_default = false;
+                }
+                let policy = handler.duplicate_ic_policy();
+                let reused_attributes = match policy {
+                    DuplicateIcPolicy::Reuse => ic_attributes.clone(),
+                    _ => Vec::new(),
+                };
+                set_ic_attrs_clamped(server, handler, &mut ic, ic_attributes);
+
+                let duplicate = match (policy, ic.app_win) {
+                    (DuplicateIcPolicy::Allow, _) | (_, None) => None,
+                    (_, app_win) => im
+                        .input_contexts
+                        .iter_mut()
+                        .find(|(_, user_ic)| user_ic.ic.app_win == app_win)
+                        .map(|(_, user_ic)| user_ic),
+                };
+
+                if let Some(user_ic) = duplicate {
+                    if policy == DuplicateIcPolicy::Error {
+                        return server.error(
+                            client_win,
+                            ErrorCode::BadClientWindow,
+                            "Input context already exists for the window".into(),
+                            NonZeroU16::new(input_method_id),
+                            None,
+                        );
+                    }
+
+                    set_ic_attrs_clamped(server, handler, &mut user_ic.ic, reused_attributes);
+                    server.send_req(client_win, ic_request!(user_ic.ic, CreateIcReply {}))?;
+
+                    return handler.handle_set_ic_values(server, user_ic);
+                }
+
+                if im.input_contexts.len() >= max_input_contexts {
+                    return server.error(
+                        client_win,
+                        ErrorCode::BadAlloc,
+                        "Too many input contexts".into(),
+                        Some(im_id),
+                        None,
+                    );
+                }
+
+                let data = handler.new_ic_data_with_locale(server, &ic.locale, ic.input_style)?;
+                let ic = UserInputContext::new(ic, data);
+                let (input_context_id, ic) = im.new_ic(ic)?;
                 ic.ic.input_context_id = input_context_id;
 
-                server.send_req(
-                    ic.ic.client_win(),
-                    Request::CreateIcReply {
-                        input_method_id,
-                        input_context_id: input_context_id.get(),
-                    },
-                )?;
+                server.send_req(ic.ic.client_win(), ic_request!(ic.ic, CreateIcReply {}))?;
 
                 handler.handle_create_ic(server, ic)?;
             }
@@ -329,11 +1209,26 @@ impl<T> XimConnection<T> {
                 input_context_id,
                 input_method_id,
             } => {
-                handler.handle_destroy_ic(
-                    server,
-                    self.get_input_method(input_method_id)?
-                        .remove_input_context(input_context_id)?,
-                )?;
+                // a second DestroyIc for the same input context is only answered
+                let removed = self.get_input_method(input_method_id).ok().and_then(|im| {
+                    let ic = im.remove_input_context(input_context_id).ok()?;
+                    Some((ic, im.input_contexts.is_empty()))
+                });
+                if self.last_focused == Some((input_method_id, input_context_id)) {
+                    self.last_focused = None;
+                }
+
+                let im_empty = match removed {
+                    Some((ic, im_empty)) => {
+                        handler.handle_destroy_ic(server, ic)?;
+                        im_empty
+                    }
+                    None => {
+                        log::debug!("Input context {} is already destroyed", input_context_id);
+                        false
+                    }
+                };
+
                 server.send_req(
                     self.client_win,
                     Request::DestroyIcReply {
@@ -341,59 +1236,99 @@ impl<T> XimConnection<T> {
                         input_context_id,
                     },
                 )?;
+
+                if im_empty && handler.auto_close_empty_im() {
+                    log::debug!("Close empty input method {}", input_method_id);
+                    self.remove_input_method(input_method_id)?;
+                    handler.handle_close(server, input_method_id)?;
+                }
             }
 
             Request::Close { input_method_id } => {
+                if matches!(self.last_focused, Some((im, _)) if im == input_method_id) {
+                    self.last_focused = None;
+                }
+
                 for (_id, ic) in self.remove_input_method(input_method_id)?.input_contexts {
                     handler.handle_destroy_ic(server, ic)?;
                 }
 
                 server.send_req(self.client_win, Request::CloseReply { input_method_id })?;
+                handler.handle_close(server, input_method_id)?;
             }
 
             Request::QueryExtension {
-                input_method_id, ..
+                input_method_id,
+                extensions,
             } => {
-                // Extension not supported now
+                let supported = handler.extensions();
+
+                // an empty list asks for every extension the server supports
+                let extensions = if extensions.is_empty() {
+                    supported
+                } else {
+                    extensions
+                        .iter()
+                        .filter_map(|name| supported.iter().find(|ext| &ext.name == name))
+                        .cloned()
+                        .collect()
+                };
+                self.extensions.extend(extensions.iter().cloned());
+
                 server.send_req(
                     self.client_win,
                     Request::QueryExtensionReply {
                         input_method_id,
-                        extensions: Vec::new(),
+                        extensions,
                     },
                 )?;
             }
             Request::EncodingNegotiation {
                 input_method_id,
                 encodings,
-                ..
+                encoding_infos,
             } => {
-                log::debug!("Encodings: {:?}", encodings);
-
-                match encodings
-                    .iter()
-                    .position(|e| e.starts_with("COMPOUND_TEXT"))
-                {
-                    Some(pos) => {
-                        server.send_req(
-                            self.client_win,
-                            Request::EncodingNegotiationReply {
-                                input_method_id,
-                                category: 0,
-                                index: pos as i16,
-                            },
-                        )?;
-                    }
-                    None => {
-                        server.send_req(
-                            self.client_win,
-                            Request::EncodingNegotiationReply {
-                                input_method_id,
-                                category: 0,
-                                index: -1,
-                            },
-                        )?;
+                log::debug!("Encodings: {:?}, details: {:?}", encodings, encoding_infos);
+
+                let selected = handler.select_encoding(&encodings);
+                let named =
+                    selected.and_then(|pos| Some((pos, Encoding::from_name(encodings.get(pos)?)?)));
+
+                // category 0 picks from the names, category 1 from the detailed data
+                let (category, index, encoding) = match (selected, named) {
+                    (Some(0), _) if encodings.is_empty() => (0, 0, Some(Encoding::CompoundText)),
+                    (_, Some((pos, encoding))) => (0, pos as i16, Some(encoding)),
+                    (selected, None) => {
+                        // the reply must not name an encoding the commits aren't in
+                        if let Some(name) = selected.and_then(|pos| encodings.get(pos)) {
+                            log::warn!("Can't encode {}, trying the detailed data", name);
+                        }
+                        match encoding_infos.iter().position(|info| {
+                            info.split(|c: char| c.is_whitespace() || c == ';' || c == ',')
+                                .any(|e| Encoding::from_name(e) == Some(Encoding::CompoundText))
+                        }) {
+                            Some(pos) => (1, pos as i16, Some(Encoding::CompoundText)),
+                            None => (0, -1, None),
+                        }
                     }
+                };
+
+                if let Some(encoding) = encoding {
+                    self.get_input_method(input_method_id)?
+                        .set_encoding(encoding);
+                }
+
+                server.send_req(
+                    self.client_win,
+                    Request::EncodingNegotiationReply {
+                        input_method_id,
+                        category,
+                        index,
+                    },
+                )?;
+
+                if let Some(encoding) = encoding {
+                    handler.handle_encoding_negotiated(server, input_method_id, encoding)?;
                 }
             }
             Request::ResetIc {
@@ -406,11 +1341,12 @@ impl<T> XimConnection<T> {
                 let ret = handler.handle_reset_ic(server, ic)?;
                 server.send_req(
                     ic.ic.client_win(),
-                    Request::ResetIcReply {
-                        input_method_id,
-                        input_context_id,
-                        preedit_string: xim_ctext::utf8_to_compound_text(&ret),
-                    },
+                    ic_request!(
+                        ic.ic,
+                        ResetIcReply {
+                            preedit_string: xim_ctext::utf8_to_compound_text(&ret),
+                        }
+                    ),
                 )?;
             }
             Request::GetImValues {
@@ -422,12 +1358,12 @@ impl<T> XimConnection<T> {
                 for name in im_attributes.into_iter().filter_map(attrs::get_name) {
                     match name {
                         AttributeName::QueryInputStyle => {
-                            out.push(Attribute {
-                                id: attrs::get_id(name),
-                                value: xim_parser::write_to_vec(InputStyleList {
+                            out.push(Attribute::new(
+                                attrs::get_id(name),
+                                InputStyleList {
                                     styles: handler.input_styles().as_ref().to_vec(),
-                                }),
-                            });
+                                },
+                            ));
                         }
                         _ => {
                             return server.error(
@@ -460,36 +1396,54 @@ impl<T> XimConnection<T> {
                     .get_input_context(input_context_id)?
                     .ic;
                 let mut out = Vec::with_capacity(ic_attributes.len());
+                let mut names = ic_attributes.into_iter().filter_map(attrs::get_name);
 
-                for name in ic_attributes.into_iter().filter_map(attrs::get_name) {
+                while let Some(name) = names.next() {
                     match name {
-                        AttributeName::InputStyle => out.push(Attribute {
-                            id: attrs::get_id(name),
-                            value: xim_parser::write_to_vec(ic.input_style()),
-                        }),
-                        AttributeName::ClientWindow => out.push(Attribute {
-                            id: attrs::get_id(name),
-                            value: xim_parser::write_to_vec(
-                                ic.app_win().map_or(0, NonZeroU32::get),
-                            ),
-                        }),
-                        AttributeName::FocusWindow => out.push(Attribute {
-                            id: attrs::get_id(name),
-                            value: xim_parser::write_to_vec(
-                                ic.app_focus_win().map_or(0, NonZeroU32::get),
-                            ),
-                        }),
-                        AttributeName::FilterEvents => out.push(Attribute {
-                            id: attrs::get_id(name),
-                            value: xim_parser::write_to_vec(handler.filter_events()),
-                        }),
+                        AttributeName::InputStyle => {
+                            out.push(Attribute::new(attrs::get_id(name), ic.input_style()))
+                        }
+                        AttributeName::ClientWindow => out.push(Attribute::new(
+                            attrs::get_id(name),
+                            ic.app_win().map_or(0, NonZeroU32::get),
+                        )),
+                        AttributeName::FocusWindow => out.push(Attribute::new(
+                            attrs::get_id(name),
+                            ic.app_focus_win().map_or(0, NonZeroU32::get),
+                        )),
+                        AttributeName::FilterEvents => {
+                            out.push(Attribute::new(attrs::get_id(name), handler.filter_events()))
+                        }
+                        AttributeName::SpotLocation => {
+                            out.push(Attribute::new(attrs::get_id(name), ic.preedit_spot()))
+                        }
+                        AttributeName::PreeditAttributes => {
+                            // the nested names follow up to the separator, none asks for all
+                            let nested = names
+                                .by_ref()
+                                .take_while(|name| *name != AttributeName::SeparatorofNestedList)
+                                .collect::<Vec<_>>();
+                            let mut inner = Vec::new();
+
+                            if nested.is_empty() || nested.contains(&AttributeName::SpotLocation) {
+                                inner.push(Attribute::new(
+                                    attrs::SPOT_LOCATION.id,
+                                    ic.preedit_spot(),
+                                ));
+                            }
+
+                            out.push(Attribute {
+                                id: attrs::get_id(name),
+                                value: xim_parser::encode_nested_attributes(&inner),
+                            });
+                        }
                         AttributeName::QueryInputStyle => {
                             return server.error(
                                 self.client_win,
                                 ErrorCode::BadName,
                                 "Unknown ic attribute name".into(),
                                 NonZeroU16::new(input_method_id),
-                                None,
+                                NonZeroU16::new(input_context_id),
                             );
                         }
                         name => {
@@ -517,15 +1471,9 @@ impl<T> XimConnection<T> {
                     .get_input_method(input_method_id)?
                     .get_input_context(input_context_id)?;
 
-                set_ic_attrs(&mut ic.ic, ic_attributes);
+                set_ic_attrs_clamped(server, handler, &mut ic.ic, ic_attributes);
 
-                server.send_req(
-                    ic.ic.client_win(),
-                    Request::SetIcValuesReply {
-                        input_method_id,
-                        input_context_id,
-                    },
-                )?;
+                server.send_req(ic.ic.client_win(), ic_request!(ic.ic, SetIcValuesReply {}))?;
 
                 handler.handle_set_ic_values(server, ic)?;
             }
@@ -534,10 +1482,43 @@ impl<T> XimConnection<T> {
                 input_method_id,
                 input_context_id,
             } => {
+                let focused = Some((input_method_id, input_context_id));
+                // some clients send it again on every focus in of their window
+                let unchanged = self.last_focused == focused;
                 let ic = self
                     .get_input_method(input_method_id)?
                     .get_input_context(input_context_id)?;
-                handler.handle_set_focus(server, ic)?;
+
+                if unchanged {
+                    log::debug!("Input context {} is already focused", input_context_id);
+                } else {
+                    handler.handle_set_focus(server, ic)?;
+                    self.last_focused = focused;
+                }
+            }
+
+            Request::TriggerNotify {
+                input_method_id,
+                input_context_id,
+                flag,
+                index,
+                event_mask: _,
+            } => {
+                let ic = self
+                    .get_input_method(input_method_id)?
+                    .get_input_context(input_context_id)?;
+                ic.ic.triggered = flag == TriggerNotifyFlag::OnKeyList;
+                server.send_req(
+                    ic.ic.client_win(),
+                    ic_request!(ic.ic, TriggerNotifyReply {}),
+                )?;
+
+                match flag {
+                    TriggerNotifyFlag::OnKeyList => handler.handle_trigger_on(server, ic, index)?,
+                    TriggerNotifyFlag::OffKeyList => {
+                        handler.handle_trigger_off(server, ic, index)?
+                    }
+                }
             }
 
             Request::UnsetIcFocus {
@@ -548,10 +1529,39 @@ impl<T> XimConnection<T> {
                     .get_input_method(input_method_id)?
                     .get_input_context(input_context_id)?;
                 handler.handle_unset_focus(server, ic)?;
+                server.status_done(&mut ic.ic)?;
+
+                if self.last_focused == Some((input_method_id, input_context_id)) {
+                    self.last_focused = None;
+                }
             }
 
-            // Ignore start reply
-            Request::PreeditStartReply { .. } => {}
+            Request::PreeditStartReply {
+                input_method_id,
+                input_context_id,
+                return_value,
+            } => {
+                self.complete_pending(
+                    (input_method_id, input_context_id),
+                    PendingOperation::PreeditStart,
+                );
+                let ic = self
+                    .get_input_method(input_method_id)?
+                    .get_input_context(input_context_id)?;
+                // -1 is unlimited
+                ic.ic.preedit_limit = usize::try_from(return_value).ok();
+            }
+
+            Request::PreeditCaretReply {
+                input_method_id,
+                input_context_id,
+                position,
+            } => {
+                let ic = self
+                    .get_input_method(input_method_id)?
+                    .get_input_context(input_context_id)?;
+                handler.handle_caret(server, ic, position)?;
+            }
 
             Request::ForwardEvent {
                 input_method_id,
@@ -564,22 +1574,100 @@ impl<T> XimConnection<T> {
                 let input_context = self
                     .get_input_method(input_method_id)?
                     .get_input_context(input_context_id)?;
-                let consumed = handler.handle_forward_event(server, input_context, &ev)?;
+                let sync = flag.contains(ForwardEventFlag::SYNCHRONOUS);
+
+                if sync {
+                    // a `SyncReply` that couldn't be sent, the client has moved on since
+                    if input_context.ic.unanswered_syncs > 0 {
+                        log::warn!(
+                            "{} synchronous ForwardEvent were never answered",
+                            input_context.ic.unanswered_syncs
+                        );
+                    }
+                    input_context.ic.unanswered_syncs += 1;
+                }
+                let unanswered_syncs = input_context.ic.unanswered_syncs;
 
-                if !consumed {
-                    server.send_req(
-                        self.client_win,
-                        Request::ForwardEvent {
-                            input_method_id,
-                            input_context_id,
-                            serial_number: 0,
-                            flag: ForwardEventFlag::empty(),
-                            xev,
-                        },
-                    )?;
+                // REQUEST_FILTERING is the bit the server sets when it sends an event back, one
+                // carrying it was already seen by the handler and only goes back to the client
+                let filtered = flag.contains(ForwardEventFlag::REQUEST_FILTERING);
+                if flag.contains(ForwardEventFlag::REQUEST_LOOP_UPSTRING) {
+                    log::debug!("Ignore lookup string request");
                 }
 
-                if flag.contains(ForwardEventFlag::SYNCHRONOUS) {
+                // the timestamps of the events are the clock, they are in milliseconds
+                let over_budget = match handler.forward_event_budget() {
+                    Some(budget) => {
+                        let budget = u32::try_from(budget.as_millis()).unwrap_or(u32::MAX);
+                        let over = input_context
+                            .ic
+                            .last_event_time
+                            .map_or(false, |last| xev.time.wrapping_sub(last) < budget);
+                        if !filtered && !over {
+                            input_context.ic.last_event_time = Some(xev.time);
+                        }
+                        over
+                    }
+                    None => false,
+                };
+
+                let xev = if filtered {
+                    Ok(Some(xev))
+                } else if over_budget {
+                    // unprocessed like an event the handler doesn't consume
+                    Ok(handler.passthrough().then_some(xev))
+                } else {
+                    handler
+                        .handle_forward_event_result(server, input_context, &ev)
+                        .and_then(|result| match result {
+                            EventResult::Consumed => Ok(None),
+                            EventResult::Forward if handler.passthrough() => Ok(Some(xev)),
+                            EventResult::Forward => Ok(None),
+                            EventResult::Replace(ev) => Ok(Some(server.serialize_event(&ev)?)),
+                        })
+                };
+                // the handler may have answered already with `commit_and_sync`
+                let answer_sync = sync && input_context.ic.unanswered_syncs == unanswered_syncs;
+                if answer_sync {
+                    input_context.ic.unanswered_syncs -= 1;
+                }
+
+                // the client waits for the `SyncReply` even when the handler failed
+                let xev = match xev {
+                    Ok(xev) => xev,
+                    Err(e) => {
+                        if answer_sync {
+                            server.send_req(
+                                self.client_win,
+                                Request::SyncReply {
+                                    input_method_id,
+                                    input_context_id,
+                                },
+                            )?;
+                        }
+                        return Err(e);
+                    }
+                };
+
+                if let Some(xev) = xev {
+                    let req = Request::ForwardEvent {
+                        input_method_id,
+                        input_context_id,
+                        serial_number: 0,
+                        flag: ForwardEventFlag::empty(),
+                        xev,
+                    };
+
+                    if self.batch_forward_events {
+                        self.pending_forward_events.push(req);
+                    } else {
+                        server.send_req(self.client_win, req)?;
+                    }
+                }
+
+                if answer_sync {
+                    self.flush_forward_events(server)?;
+                    log::debug!("SyncReply for ForwardEvent #{}", self.sequence);
                     server.send_req(
                         self.client_win,
                         Request::SyncReply {
@@ -603,7 +1691,12 @@ impl<T> XimConnection<T> {
                 )?;
             }
 
-            Request::SyncReply { .. } => {}
+            Request::SyncReply {
+                input_method_id,
+                input_context_id,
+            } => {
+                self.complete_pending((input_method_id, input_context_id), PendingOperation::Sync);
+            }
 
             _ => {
                 log::warn!("Unknown request: {:?}", req);
@@ -643,4 +1736,109 @@ impl<T> XimConnections<T> {
     pub fn remove_connection(&mut self, com_win: u32) -> Option<XimConnection<T>> {
         self.connections.remove(&com_win)
     }
+
+    /// Every live input context as `(com_win, input_method_id, input_context_id, ic)`.
+    pub fn iter_input_contexts(
+        &self,
+    ) -> impl Iterator<Item = (u32, NonZeroU16, NonZeroU16, &UserInputContext<T>)> + '_ {
+        self.connections.iter().flat_map(|(com_win, connection)| {
+            connection
+                .input_methods
+                .iter()
+                .flat_map(move |(im_id, im)| {
+                    im.input_contexts
+                        .iter()
+                        .map(move |(ic_id, ic)| (*com_win, im_id, ic_id, ic))
+                })
+        })
+    }
+
+    /// Send the events queued by every connection, see
+    /// [`XimConnection::set_batch_forward_events`].
+    pub fn flush_forward_events<S: ServerCore>(
+        &mut self,
+        server: &mut S,
+    ) -> Result<(), ServerError> {
+        for connection in self.connections.values_mut() {
+            connection.flush_forward_events(server)?;
+        }
+
+        Ok(())
+    }
+
+    /// Give up the operations of every input context whose deadline is `now` or earlier, see
+    /// [`XimConnection::add_pending`].
+    ///
+    /// The handler is told with [`ServerHandler::handle_pending_timeout`]. Returns
+    /// `(com_win, input_method_id, input_context_id, operation)` of each. The operations of an
+    /// input context go away with it when it's destroyed.
+    ///
+    /// An operation the handler fails on stays pending, so the next call reports it again. The
+    /// other connections are still processed and the first error is returned.
+    pub fn expire_pending<S: ServerCore, H: ServerHandler<S, InputContextData = T>>(
+        &mut self,
+        server: &mut S,
+        handler: &mut H,
+        now: Duration,
+    ) -> Result<Vec<(u32, u16, u16, PendingOperation)>, ServerError> {
+        let mut expired = Vec::new();
+        let mut ret = Ok(());
+
+        for (com_win, connection) in self.connections.iter_mut() {
+            for (im_id, im) in connection.input_methods.iter_mut() {
+                for (ic_id, user_ic) in im.input_contexts.iter_mut() {
+                    let address = (im_id.get(), ic_id.get());
+                    let mut i = 0;
+                    while i < user_ic.ic.pending.len() {
+                        let (operation, deadline) = user_ic.ic.pending[i];
+                        if deadline > now {
+                            i += 1;
+                            continue;
+                        }
+
+                        log::warn!("Client didn't answer {:?} of {:?}", operation, address);
+                        user_ic.ic.pending.remove(i);
+                        if let Err(e) = handler.handle_pending_timeout(server, user_ic, operation) {
+                            log::error!("Failed to expire {:?} of {:?}: {}", operation, address, e);
+                            if ret.is_ok() {
+                                ret = Err(e);
+                            }
+                            // kept for the next call
+                            let pos = i.min(user_ic.ic.pending.len());
+                            user_ic.ic.pending.insert(pos, (operation, deadline));
+                            i = pos + 1;
+                            continue;
+                        }
+
+                        expired.push((*com_win, address.0, address.1, operation));
+                    }
+                }
+            }
+        }
+
+        ret.map(|()| expired)
+    }
+
+    /// Disconnect every connection, destroying all input contexts through the handler.
+    ///
+    /// XIM has no way for the server to close a client, so nothing is sent to the clients here.
+    /// All connections are removed even when the handler fails, the first error is returned.
+    pub fn shutdown<S: ServerCore + Server, H: ServerHandler<S, InputContextData = T>>(
+        &mut self,
+        server: &mut S,
+        handler: &mut H,
+    ) -> Result<(), ServerError> {
+        let mut ret = Ok(());
+
+        for (_com_win, mut connection) in self.connections.drain() {
+            if let Err(e) = connection.disconnect(server, handler, DisconnectReason::Clean) {
+                log::error!("Failed to disconnect {}: {}", connection.client_win, e);
+                if ret.is_ok() {
+                    ret = Err(e);
+                }
+            }
+        }
+
+        ret
+    }
 }
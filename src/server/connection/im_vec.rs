@@ -1,7 +1,12 @@
+use crate::server::ServerError;
 use crate::AHashMap;
 use core::num::NonZeroU16;
-use hashbrown::hash_map::Entry;
 
+/// Items addressed by a 16-bit id.
+///
+/// Ids are handed out in increasing order and wrap around to 1 after `u16::MAX`, skipping ids
+/// that are still in use. A freed id is therefore only reused once the counter comes back around
+/// to it, and a live id is never handed out twice.
 pub struct ImVec<T> {
     next: NonZeroU16,
     inner: AHashMap<NonZeroU16, T>,
@@ -15,24 +20,32 @@ impl<T> ImVec<T> {
         }
     }
 
-    fn next(&mut self) -> NonZeroU16 {
-        let ret = self.next;
-        self.next = NonZeroU16::new(self.next.get() + 1).unwrap();
-        ret
-    }
+    fn next(&mut self) -> Option<NonZeroU16> {
+        if self.is_full() {
+            return None;
+        }
 
-    pub fn new_item(&mut self, data: T) -> (NonZeroU16, &mut T) {
-        let idx = self.next();
+        loop {
+            let ret = self.next;
+            self.next = NonZeroU16::new(ret.get().wrapping_add(1))
+                .unwrap_or_else(|| NonZeroU16::new(1).unwrap());
 
-        let val = match self.inner.entry(idx) {
-            Entry::Occupied(mut o) => {
-                o.insert(data);
-                o.into_mut()
+            if !self.inner.contains_key(&ret) {
+                return Some(ret);
             }
-            Entry::Vacant(v) => v.insert(data),
-        };
+        }
+    }
 
-        (idx, val)
+    /// Whether every id is in use.
+    pub fn is_full(&self) -> bool {
+        self.inner.len() >= u16::MAX as usize
+    }
+
+    /// Insert `data` under a free id, fails with [`ServerError::IdExhausted`] if there is none.
+    pub fn new_item(&mut self, data: T) -> Result<(NonZeroU16, &mut T), ServerError> {
+        let idx = self.next().ok_or(ServerError::IdExhausted)?;
+
+        Ok((idx, self.inner.entry(idx).or_insert(data)))
     }
 
     #[allow(unused)]
@@ -44,6 +57,22 @@ impl<T> ImVec<T> {
         self.inner.get_mut(&NonZeroU16::new(idx)?)
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = (NonZeroU16, &T)> + '_ {
+        self.inner.iter().map(|(id, item)| (*id, item))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&NonZeroU16, &mut T)> + '_ {
+        self.inner.iter_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
     pub fn drain(&mut self) -> impl Iterator<Item = (NonZeroU16, T)> + '_ {
         self.inner.drain()
     }
@@ -0,0 +1,3569 @@
+use super::*;
+use alloc::string::ToString;
+use core::num::NonZeroU32;
+use xim_parser::{
+    attrs, AttrType, Attribute, AttributeName, CaretDirection, CaretStyle, Endian, ErrorCode,
+    ErrorFlag, Extension, ForwardEventFlag, InputStyleList, Point, PreeditStateFlag, ReadError,
+    TriggerNotifyFlag, XEvent, XimRead, XimWrite,
+};
+
+const CLIENT_WIN: u32 = 10;
+
+#[derive(Default)]
+struct MockServer {
+    sent: Vec<(u32, Request)>,
+    batches: Vec<usize>,
+    window_size: Option<(u16, u16)>,
+    require_negotiated_encoding: bool,
+    reply_deadline: Option<Duration>,
+}
+
+impl MockServer {
+    fn take(&mut self) -> Vec<Request> {
+        self.sent.drain(..).map(|(_, req)| req).collect()
+    }
+}
+
+impl ServerCore for MockServer {
+    type XEvent = XEvent;
+
+    fn serialize_event(&self, ev: &XEvent) -> Result<XEvent, ServerError> {
+        Ok(ev.clone())
+    }
+
+    fn deserialize_event(&self, ev: &XEvent) -> XEvent {
+        ev.clone()
+    }
+
+    fn send_req(&mut self, client_win: u32, req: Request) -> Result<(), ServerError> {
+        self.sent.push((client_win, req));
+        Ok(())
+    }
+
+    fn send_reqs(&mut self, client_win: u32, reqs: &[Request]) -> Result<(), ServerError> {
+        self.batches.push(reqs.len());
+        self.sent
+            .extend(reqs.iter().map(|req| (client_win, req.clone())));
+        Ok(())
+    }
+
+    fn window_size(&mut self, _win: u32) -> Option<(u16, u16)> {
+        self.window_size
+    }
+
+    fn require_negotiated_encoding(&self) -> bool {
+        self.require_negotiated_encoding
+    }
+
+    fn reply_deadline(&self) -> Option<Duration> {
+        self.reply_deadline
+    }
+}
+
+#[derive(Default)]
+struct MockHandler {
+    auto_close: bool,
+    connected: Option<ConnectInfo>,
+    auth_protocols: Vec<&'static str>,
+    extensions: Vec<Extension>,
+    extension_requests: Vec<(String, Vec<u8>)>,
+    carets: Vec<i32>,
+    replace_detail: Option<u8>,
+    fail_forward: bool,
+    fail_timeout: bool,
+    commit_and_sync: Option<&'static str>,
+    passive: bool,
+    duplicate_ic: DuplicateIcPolicy,
+    ic_defaults: IcDefaults,
+    triggers: Vec<(bool, u32)>,
+    only_encoding: Option<&'static str>,
+    input_style: Option<InputStyle>,
+    flat_attributes: bool,
+    event_budget: Option<Duration>,
+    processed_events: Vec<u32>,
+    negotiated: Vec<(u16, Encoding)>,
+    disconnect_reasons: Vec<DisconnectReason>,
+    before: Vec<String>,
+    after: Vec<(String, bool)>,
+    sequences: Vec<u64>,
+    focused: Vec<u16>,
+    timeouts: Vec<(u16, PendingOperation)>,
+    preedit_states: Vec<bool>,
+    destroyed: Vec<(u16, u16)>,
+    ic_data: Vec<(String, InputStyle)>,
+    created: Vec<InputStyle>,
+    closed: Vec<u16>,
+}
+
+impl ServerHandler<MockServer> for MockHandler {
+    type InputStyleArray = [InputStyle; 1];
+    type InputContextData = ();
+
+    fn new_ic_data(
+        &mut self,
+        _server: &mut MockServer,
+        _input_style: InputStyle,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    fn new_ic_data_with_locale(
+        &mut self,
+        _server: &mut MockServer,
+        locale: &str,
+        input_style: InputStyle,
+    ) -> Result<(), ServerError> {
+        self.ic_data.push((locale.into(), input_style));
+        Ok(())
+    }
+
+    fn input_styles(&self) -> Self::InputStyleArray {
+        [self
+            .input_style
+            .unwrap_or(InputStyle::PREEDIT_CALLBACKS | InputStyle::STATUS_NOTHING)]
+    }
+
+    fn filter_events(&self) -> u32 {
+        1
+    }
+
+    fn handle_connect(
+        &mut self,
+        _server: &mut MockServer,
+        info: &ConnectInfo,
+    ) -> Result<(), ServerError> {
+        self.connected = Some(info.clone());
+        Ok(())
+    }
+
+    fn extensions(&self) -> Vec<Extension> {
+        self.extensions.clone()
+    }
+
+    fn duplicate_ic_policy(&self) -> DuplicateIcPolicy {
+        self.duplicate_ic
+    }
+
+    fn ic_defaults(&self) -> IcDefaults {
+        self.ic_defaults.clone()
+    }
+
+    fn negotiate_auth(&self, names: &[String]) -> Option<usize> {
+        names
+            .iter()
+            .position(|name| self.auth_protocols.contains(&name.as_str()))
+    }
+
+    fn flat_attributes(&self, _info: &ConnectInfo) -> bool {
+        self.flat_attributes
+    }
+
+    fn forward_event_budget(&self) -> Option<Duration> {
+        self.event_budget
+    }
+
+    fn handle_encoding_negotiated(
+        &mut self,
+        _server: &mut MockServer,
+        input_method_id: u16,
+        encoding: Encoding,
+    ) -> Result<(), ServerError> {
+        self.negotiated.push((input_method_id, encoding));
+        Ok(())
+    }
+
+    fn select_encoding(&self, offered: &[String]) -> Option<usize> {
+        match self.only_encoding {
+            Some(name) => offered.iter().position(|e| e == name),
+            None if offered.is_empty() => Some(0),
+            None => offered
+                .iter()
+                .position(|e| e.starts_with("COMPOUND_TEXT"))
+                .or_else(|| offered.iter().position(|e| e == "UTF-8")),
+        }
+    }
+
+    fn passthrough(&self) -> bool {
+        !self.passive
+    }
+
+    fn handle_extension(
+        &mut self,
+        _server: &mut MockServer,
+        extension: &Extension,
+        body: &[u8],
+    ) -> Result<(), ServerError> {
+        self.extension_requests
+            .push((extension.name.clone(), body.to_vec()));
+        Ok(())
+    }
+
+    fn before_dispatch(&mut self, sequence: u64, req: ClientRequest) {
+        self.before.push(req.name().into());
+        self.sequences.push(sequence);
+    }
+
+    fn after_dispatch(&mut self, sequence: u64, name: &str, result: &Result<(), ServerError>) {
+        assert_eq!(self.sequences.last(), Some(&sequence));
+        self.after.push((name.into(), result.is_ok()));
+    }
+
+    fn handle_close(
+        &mut self,
+        _server: &mut MockServer,
+        input_method_id: u16,
+    ) -> Result<(), ServerError> {
+        self.closed.push(input_method_id);
+        Ok(())
+    }
+
+    fn auto_close_empty_im(&self) -> bool {
+        self.auto_close
+    }
+
+    fn handle_create_ic(
+        &mut self,
+        _server: &mut MockServer,
+        user_ic: &mut UserInputContext<()>,
+    ) -> Result<(), ServerError> {
+        self.created.push(user_ic.ic.input_style());
+        Ok(())
+    }
+
+    fn handle_disconnect_ic(
+        &mut self,
+        server: &mut MockServer,
+        user_ic: UserInputContext<()>,
+        reason: DisconnectReason,
+    ) -> Result<(), ServerError> {
+        self.disconnect_reasons.push(reason);
+        self.handle_destroy_ic(server, user_ic)
+    }
+
+    fn handle_destroy_ic(
+        &mut self,
+        _server: &mut MockServer,
+        user_ic: UserInputContext<()>,
+    ) -> Result<(), ServerError> {
+        self.destroyed.push((
+            user_ic.ic.input_method_id().get(),
+            user_ic.ic.input_context_id().get(),
+        ));
+        Ok(())
+    }
+
+    fn handle_reset_ic(
+        &mut self,
+        _server: &mut MockServer,
+        _user_ic: &mut UserInputContext<()>,
+    ) -> Result<String, ServerError> {
+        Ok(String::new())
+    }
+
+    fn handle_trigger_on(
+        &mut self,
+        _server: &mut MockServer,
+        _user_ic: &mut UserInputContext<()>,
+        index: u32,
+    ) -> Result<(), ServerError> {
+        self.triggers.push((true, index));
+        Ok(())
+    }
+
+    fn handle_trigger_off(
+        &mut self,
+        _server: &mut MockServer,
+        _user_ic: &mut UserInputContext<()>,
+        index: u32,
+    ) -> Result<(), ServerError> {
+        self.triggers.push((false, index));
+        Ok(())
+    }
+
+    fn handle_preedit_state(
+        &mut self,
+        _server: &mut MockServer,
+        _user_ic: &mut UserInputContext<()>,
+        frozen: bool,
+    ) -> Result<(), ServerError> {
+        self.preedit_states.push(frozen);
+        Ok(())
+    }
+
+    fn handle_pending_timeout(
+        &mut self,
+        _server: &mut MockServer,
+        user_ic: &mut UserInputContext<()>,
+        operation: PendingOperation,
+    ) -> Result<(), ServerError> {
+        if self.fail_timeout {
+            return Err(ServerError::Internal("timeout failed".into()));
+        }
+        self.timeouts
+            .push((user_ic.ic.input_context_id().get(), operation));
+        Ok(())
+    }
+
+    fn handle_set_focus(
+        &mut self,
+        _server: &mut MockServer,
+        user_ic: &mut UserInputContext<()>,
+    ) -> Result<(), ServerError> {
+        self.focused.push(user_ic.ic.input_context_id().get());
+        Ok(())
+    }
+
+    fn handle_unset_focus(
+        &mut self,
+        _server: &mut MockServer,
+        _user_ic: &mut UserInputContext<()>,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    fn handle_set_ic_values(
+        &mut self,
+        _server: &mut MockServer,
+        _user_ic: &mut UserInputContext<()>,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    fn handle_caret(
+        &mut self,
+        _server: &mut MockServer,
+        _user_ic: &mut UserInputContext<()>,
+        position: i32,
+    ) -> Result<(), ServerError> {
+        self.carets.push(position);
+        Ok(())
+    }
+
+    fn handle_forward_event(
+        &mut self,
+        _server: &mut MockServer,
+        _user_ic: &mut UserInputContext<()>,
+        _xev: &XEvent,
+    ) -> Result<bool, ServerError> {
+        Ok(false)
+    }
+
+    fn handle_forward_event_result(
+        &mut self,
+        server: &mut MockServer,
+        user_ic: &mut UserInputContext<()>,
+        xev: &XEvent,
+    ) -> Result<EventResult<XEvent>, ServerError> {
+        if self.fail_forward {
+            return Err(ServerError::Internal("forward failed".into()));
+        }
+        self.processed_events.push(xev.time);
+
+        if let Some(s) = self.commit_and_sync {
+            server.commit_and_sync(&mut user_ic.ic, s)?;
+            return Ok(EventResult::Consumed);
+        }
+
+        Ok(match self.replace_detail {
+            Some(detail) => EventResult::Replace(XEvent {
+                detail,
+                ..xev.clone()
+            }),
+            None => EventResult::Forward,
+        })
+    }
+}
+
+fn open_im(
+    conn: &mut XimConnection<()>,
+    server: &mut MockServer,
+    handler: &mut MockHandler,
+) -> u16 {
+    conn.handle_request(
+        server,
+        Request::Open {
+            locale: "en_US".to_string(),
+        },
+        handler,
+    )
+    .unwrap();
+
+    match server.take().pop() {
+        Some(Request::OpenReply {
+            input_method_id, ..
+        }) => input_method_id,
+        other => panic!("Unexpected reply: {:?}", other),
+    }
+}
+
+fn create_ic(
+    conn: &mut XimConnection<()>,
+    server: &mut MockServer,
+    handler: &mut MockHandler,
+    input_method_id: u16,
+    ic_attributes: Vec<Attribute>,
+) -> u16 {
+    conn.handle_request(
+        server,
+        Request::CreateIc {
+            input_method_id,
+            ic_attributes,
+        },
+        handler,
+    )
+    .unwrap();
+
+    match server.take().pop() {
+        Some(Request::CreateIcReply {
+            input_context_id, ..
+        }) => input_context_id,
+        other => panic!("Unexpected reply: {:?}", other),
+    }
+}
+
+fn style_attr(style: InputStyle) -> Attribute {
+    Attribute {
+        id: attrs::INPUT_STYLE.id,
+        value: xim_parser::write_to_vec(style),
+    }
+}
+
+fn spot_attr(x: i16, y: i16) -> Attribute {
+    let spot = Attribute {
+        id: attrs::SPOT_LOCATION.id,
+        value: xim_parser::write_to_vec(Point { x, y }),
+    };
+
+    Attribute {
+        id: attrs::PREEDIT_ATTRIBUTES.id,
+        value: xim_parser::encode_nested_attributes(&[spot]),
+    }
+}
+
+fn get_ic(
+    conn: &mut XimConnection<()>,
+    input_method_id: u16,
+    input_context_id: u16,
+) -> &mut InputContext {
+    &mut conn
+        .input_methods
+        .get_item(input_method_id)
+        .unwrap()
+        .get_input_context(input_context_id)
+        .unwrap()
+        .ic
+}
+
+fn destroy_ic(
+    conn: &mut XimConnection<()>,
+    server: &mut MockServer,
+    handler: &mut MockHandler,
+    input_method_id: u16,
+    input_context_id: u16,
+) -> Result<(), ServerError> {
+    conn.handle_request(
+        server,
+        Request::DestroyIc {
+            input_method_id,
+            input_context_id,
+        },
+        handler,
+    )
+}
+
+#[test]
+fn destroy_last_ic_keeps_im() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    destroy_ic(&mut conn, &mut server, &mut handler, im, ic).unwrap();
+
+    assert_eq!(handler.destroyed, [(im, ic)]);
+    assert!(handler.closed.is_empty());
+    // the input method lingers until an explicit close
+    create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+}
+
+#[test]
+fn destroy_last_ic_auto_close_im() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler {
+        auto_close: true,
+        ..Default::default()
+    };
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic1 = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    let ic2 = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+
+    destroy_ic(&mut conn, &mut server, &mut handler, im, ic1).unwrap();
+    assert!(handler.closed.is_empty());
+
+    destroy_ic(&mut conn, &mut server, &mut handler, im, ic2).unwrap();
+    assert_eq!(handler.closed, [im]);
+    assert_eq!(
+        server.take(),
+        [
+            Request::DestroyIcReply {
+                input_method_id: im,
+                input_context_id: ic1,
+            },
+            Request::DestroyIcReply {
+                input_method_id: im,
+                input_context_id: ic2,
+            },
+        ]
+    );
+    assert!(matches!(
+        conn.handle_request(
+            &mut server,
+            Request::CreateIc {
+                input_method_id: im,
+                ic_attributes: Vec::new(),
+            },
+            &mut handler,
+        ),
+        Err(ServerError::ClientNotExists)
+    ));
+}
+
+#[test]
+fn over_the_spot_draw_needs_spot() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let style = InputStyle::PREEDIT_POSITION | InputStyle::STATUS_NOTHING;
+    let ic = create_ic(
+        &mut conn,
+        &mut server,
+        &mut handler,
+        im,
+        vec![style_attr(style)],
+    );
+
+    assert!(!get_ic(&mut conn, im, ic).spot_received());
+    assert!(matches!(
+        server.preedit_draw(get_ic(&mut conn, im, ic), "a"),
+        Err(ServerError::SpotNotReceived)
+    ));
+    assert!(server.take().is_empty());
+
+    conn.handle_request(
+        &mut server,
+        Request::SetIcValues {
+            input_method_id: im,
+            input_context_id: ic,
+            ic_attributes: vec![spot_attr(10, 20)],
+        },
+        &mut handler,
+    )
+    .unwrap();
+    server.take();
+
+    let user_ic = get_ic(&mut conn, im, ic);
+    assert!(user_ic.spot_received());
+    assert_eq!(user_ic.preedit_spot(), Point { x: 10, y: 20 });
+    server.preedit_draw(user_ic, "a").unwrap();
+}
+
+#[test]
+fn on_the_spot_draw_without_spot() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let style = InputStyle::PREEDIT_CALLBACKS | InputStyle::STATUS_NOTHING;
+    let ic = create_ic(
+        &mut conn,
+        &mut server,
+        &mut handler,
+        im,
+        vec![style_attr(style)],
+    );
+
+    server.preedit_draw(get_ic(&mut conn, im, ic), "a").unwrap();
+    assert!(matches!(
+        server.take()[..],
+        [Request::PreeditStart { .. }, Request::PreeditDraw { .. }]
+    ));
+}
+
+#[test]
+fn preedit_limit_from_start_reply() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let style = InputStyle::PREEDIT_CALLBACKS | InputStyle::STATUS_NOTHING;
+    let ic = create_ic(
+        &mut conn,
+        &mut server,
+        &mut handler,
+        im,
+        vec![style_attr(style)],
+    );
+    server.preedit_draw(get_ic(&mut conn, im, ic), "a").unwrap();
+    server.take();
+
+    for (return_value, drawn) in [(2, Some("ab")), (0, None), (-1, Some("abcd"))] {
+        conn.handle_request(
+            &mut server,
+            Request::PreeditStartReply {
+                input_method_id: im,
+                input_context_id: ic,
+                return_value,
+            },
+            &mut handler,
+        )
+        .unwrap();
+
+        server
+            .preedit_draw(get_ic(&mut conn, im, ic), "abcd")
+            .unwrap();
+        let reqs = server.take();
+
+        match drawn {
+            Some(drawn) => assert!(matches!(
+                &reqs[..],
+                [Request::PreeditDraw {
+                    caret,
+                    preedit_string,
+                    feedbacks,
+                    ..
+                }] if *caret as usize == drawn.len()
+                    && *preedit_string == xim_ctext::utf8_to_compound_text(drawn)
+                    && feedbacks.len() == drawn.len()
+            )),
+            None => assert!(reqs.is_empty()),
+        }
+    }
+}
+
+#[test]
+fn forward_synthetic_event() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+
+    let ev = match key_event(im, ic, ForwardEventFlag::empty()) {
+        Request::ForwardEvent { xev, .. } => XEvent { sequence: 7, ..xev },
+        _ => unreachable!(),
+    };
+    server
+        .forward_synthetic_event(get_ic(&mut conn, im, ic), &ev)
+        .unwrap();
+
+    assert_eq!(
+        server.sent,
+        [(
+            CLIENT_WIN,
+            Request::ForwardEvent {
+                input_method_id: im,
+                input_context_id: ic,
+                flag: ForwardEventFlag::empty(),
+                serial_number: 7,
+                xev: ev,
+            }
+        )]
+    );
+}
+
+#[test]
+fn query_extension_supported_only() {
+    let move_ext = Extension {
+        major_opcode: 128,
+        minor_opcode: 0,
+        name: "XIM_EXT_MOVE".to_string(),
+    };
+
+    let mut server = MockServer::default();
+    let mut handler = MockHandler {
+        extensions: vec![move_ext.clone()],
+        ..Default::default()
+    };
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    conn.handle_request(
+        &mut server,
+        Request::QueryExtension {
+            input_method_id: im,
+            extensions: vec![
+                "XIM_EXT_SET_EVENT_MASK".to_string(),
+                "XIM_EXT_MOVE".to_string(),
+            ],
+        },
+        &mut handler,
+    )
+    .unwrap();
+
+    assert_eq!(
+        server.take(),
+        [Request::QueryExtensionReply {
+            input_method_id: im,
+            extensions: vec![move_ext],
+        }]
+    );
+}
+
+#[test]
+fn preedit_state_extension() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler {
+        extensions: vec![Extension {
+            major_opcode: 130,
+            minor_opcode: 0,
+            name: PREEDIT_STATE_EXTENSION.to_string(),
+        }],
+        ..Default::default()
+    };
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    conn.handle_request(
+        &mut server,
+        Request::QueryExtension {
+            input_method_id: im,
+            extensions: vec![PREEDIT_STATE_EXTENSION.to_string()],
+        },
+        &mut handler,
+    )
+    .unwrap();
+    server.take();
+
+    let preedit_state = |flag: PreeditStateFlag| {
+        let mut msg = vec![130, 0, 2, 0];
+        msg.extend(im.to_le_bytes());
+        msg.extend(ic.to_le_bytes());
+        msg.extend(flag.bits().to_le_bytes());
+        msg
+    };
+
+    let freeze = preedit_state(PreeditStateFlag::DISABLE);
+    assert!(conn
+        .handle_extension_request(&mut server, &freeze, &mut handler)
+        .unwrap());
+    assert!(get_ic(&mut conn, im, ic).preedit_frozen());
+    server
+        .preedit_draw(get_ic(&mut conn, im, ic), "가")
+        .unwrap();
+    assert_eq!(server.take(), []);
+
+    let thaw = preedit_state(PreeditStateFlag::ENABLE);
+    assert!(conn
+        .handle_extension_request(&mut server, &thaw, &mut handler)
+        .unwrap());
+    assert!(!get_ic(&mut conn, im, ic).preedit_frozen());
+    server
+        .preedit_draw(get_ic(&mut conn, im, ic), "가")
+        .unwrap();
+    assert_eq!(
+        server.take().iter().map(Request::name).collect::<Vec<_>>(),
+        ["PreeditStart", "PreeditDraw"]
+    );
+
+    assert_eq!(handler.preedit_states, [true, false]);
+}
+
+#[test]
+fn dispatch_negotiated_extension() {
+    let move_ext = Extension {
+        major_opcode: 128,
+        minor_opcode: 0,
+        name: "XIM_EXT_MOVE".to_string(),
+    };
+
+    let mut server = MockServer::default();
+    let mut handler = MockHandler {
+        extensions: vec![move_ext.clone()],
+        ..Default::default()
+    };
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ext_move = [128, 0, 1, 0, 1, 0, 2, 0];
+
+    // not negotiated yet
+    assert!(!conn
+        .handle_extension_request(&mut server, &ext_move, &mut handler)
+        .unwrap());
+
+    conn.handle_request(
+        &mut server,
+        Request::QueryExtension {
+            input_method_id: im,
+            extensions: Vec::new(),
+        },
+        &mut handler,
+    )
+    .unwrap();
+    assert_eq!(conn.extensions().get(128, 0), Some(&move_ext));
+
+    assert!(conn
+        .handle_extension_request(&mut server, &ext_move, &mut handler)
+        .unwrap());
+    assert_eq!(
+        handler.extension_requests,
+        [("XIM_EXT_MOVE".to_string(), vec![1, 0, 2, 0])]
+    );
+    // numbered and seen by the hooks like any other request
+    assert_eq!(conn.sequence(), 3);
+    assert_eq!(handler.before, ["Open", "QueryExtension", "XIM_EXT_MOVE"]);
+    assert_eq!(handler.after.last(), Some(&("XIM_EXT_MOVE".into(), true)));
+
+    // other requests are left to `read_request`
+    let close = [32, 0, 1, 0, 1, 0, 0, 0];
+    assert!(!conn
+        .handle_extension_request(&mut server, &close, &mut handler)
+        .unwrap());
+}
+
+#[test]
+fn destroy_ic_twice() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler {
+        auto_close: true,
+        ..Default::default()
+    };
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+
+    // the second one comes after the input method was closed with its last input context
+    for _ in 0..2 {
+        destroy_ic(&mut conn, &mut server, &mut handler, im, ic).unwrap();
+        assert_eq!(
+            server.take(),
+            [Request::DestroyIcReply {
+                input_method_id: im,
+                input_context_id: ic,
+            }]
+        );
+    }
+
+    assert_eq!(handler.destroyed, [(im, ic)]);
+    assert_eq!(handler.closed, [im]);
+    assert!(!conn.disconnected);
+}
+
+#[test]
+fn open_reply_attrs_applied() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler {
+        extensions: vec![ic_locale_extension()],
+        ..Default::default()
+    };
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    conn.handle_request(
+        &mut server,
+        Request::Open {
+            locale: "en_US".to_string(),
+        },
+        &mut handler,
+    )
+    .unwrap();
+    let (im, ic_attrs) = match server.take().pop() {
+        Some(Request::OpenReply {
+            input_method_id,
+            ic_attrs,
+            ..
+        }) => (input_method_id, ic_attrs),
+        other => panic!("Unexpected reply: {:?}", other),
+    };
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    assert!(!ic_attrs.is_empty());
+
+    let foreground = [Attribute {
+        id: attrs::FOREGROUND.id,
+        value: xim_parser::write_to_vec(7u32),
+    }];
+    for attr in ic_attrs {
+        assert_eq!(connection::ic_attr_name(attr.id), Some(attr.name));
+        let value = match attr.name {
+            // only ends a nested list
+            AttributeName::SeparatorofNestedList => continue,
+            AttributeName::InputStyle => xim_parser::write_to_vec(InputStyle::PREEDIT_POSITION),
+            AttributeName::ClientWindow | AttributeName::FocusWindow => {
+                xim_parser::write_to_vec(0x50u32)
+            }
+            AttributeName::Locale => b"C".to_vec(),
+            AttributeName::SpotLocation => xim_parser::write_to_vec(Point { x: 1, y: 2 }),
+            AttributeName::PreeditAttributes | AttributeName::StatusAttributes => {
+                xim_parser::encode_nested_attributes(&foreground)
+            }
+            _ => xim_parser::write_to_vec(7u32),
+        };
+
+        conn.handle_request(
+            &mut server,
+            Request::SetIcValues {
+                input_method_id: im,
+                input_context_id: ic,
+                ic_attributes: vec![Attribute { id: attr.id, value }],
+            },
+            &mut handler,
+        )
+        .unwrap();
+        let user_ic = get_ic(&mut conn, im, ic);
+        assert_eq!(user_ic.applied_attributes()[0], attr.name);
+    }
+
+    let user_ic = get_ic(&mut conn, im, ic);
+    let value = xim_parser::write_to_vec(7u32);
+    assert_eq!(
+        user_ic.attribute_value(None, AttributeName::LineSpace),
+        Some(&value[..])
+    );
+    assert_eq!(
+        user_ic.attribute_value(
+            Some(AttributeName::StatusAttributes),
+            AttributeName::Foreground
+        ),
+        Some(&value[..])
+    );
+    assert_eq!(
+        user_ic.attribute_value(None, AttributeName::FontSet),
+        Some(&value[..])
+    );
+}
+
+#[test]
+fn reject_trailing_bytes() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    server.take();
+
+    let mut sync = xim_parser::write_to_vec(Request::Sync {
+        input_method_id: im,
+        input_context_id: ic,
+    });
+    sync.extend_from_slice(&[0; 4]);
+
+    // ignored by default
+    conn.handle_message(&mut server, &sync, &mut handler)
+        .unwrap();
+    assert!(matches!(server.take()[..], [Request::SyncReply { .. }]));
+
+    conn.set_reject_trailing_bytes(true);
+    assert!(matches!(
+        conn.handle_message(&mut server, &sync, &mut handler),
+        Err(ServerError::ReadProtocol(ReadError::TrailingBytes {
+            remaining: 4
+        }))
+    ));
+    assert!(server.take().is_empty());
+
+    // the request alone still goes through
+    conn.handle_message(&mut server, &sync[..sync.len() - 4], &mut handler)
+        .unwrap();
+    assert!(matches!(server.take()[..], [Request::SyncReply { .. }]));
+}
+
+#[test]
+fn reassemble_request_limit() {
+    let mut server = MockServer::default();
+    let mut conn = XimConnection::<()>::new(CLIENT_WIN);
+    conn.set_max_request_bytes(40);
+
+    let sync = xim_parser::write_to_vec(Request::Sync {
+        input_method_id: 1,
+        input_context_id: 2,
+    });
+    let (first, last) = sync.split_at(4);
+    assert_eq!(
+        conn.reassemble_request(&mut server, first, true).unwrap(),
+        None
+    );
+    let data = conn
+        .reassemble_request(&mut server, last, false)
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        conn.read_request(&data).unwrap(),
+        Request::Sync {
+            input_method_id: 1,
+            input_context_id: 2,
+        }
+    );
+
+    // the third part goes over the limit, the rest of the request is dropped
+    for _ in 0..3 {
+        assert_eq!(
+            conn.reassemble_request(&mut server, &[0; 20], true)
+                .unwrap(),
+            None
+        );
+    }
+    assert!(matches!(
+        server.take()[..],
+        [Request::Error {
+            code: ErrorCode::BadAlloc,
+            input_method_id: 0,
+            input_context_id: 0,
+            ..
+        }]
+    ));
+    assert_eq!(
+        conn.reassemble_request(&mut server, &[0; 20], false)
+            .unwrap(),
+        None
+    );
+
+    // the next request starts from an empty buffer
+    assert_eq!(
+        conn.reassemble_request(&mut server, &sync, false).unwrap(),
+        Some(sync)
+    );
+    assert!(server.take().is_empty());
+}
+
+#[test]
+fn resource_limits() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+    conn.set_max_input_methods(1);
+    conn.set_max_input_contexts_per_im(2);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    conn.handle_request(
+        &mut server,
+        Request::Open {
+            locale: "en_US".to_string(),
+        },
+        &mut handler,
+    )
+    .unwrap();
+    assert!(matches!(
+        server.take()[..],
+        [Request::Error {
+            code: ErrorCode::BadAlloc,
+            input_method_id: 0,
+            ..
+        }]
+    ));
+
+    create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    conn.handle_request(
+        &mut server,
+        Request::CreateIc {
+            input_method_id: im,
+            ic_attributes: Vec::new(),
+        },
+        &mut handler,
+    )
+    .unwrap();
+    assert!(matches!(
+        server.take()[..],
+        [Request::Error {
+            code: ErrorCode::BadAlloc,
+            input_method_id,
+            input_context_id: 0,
+            ..
+        }] if input_method_id == im
+    ));
+
+    assert_eq!(handler.created.len(), 2);
+    assert_eq!(handler.ic_data.len(), 2);
+    assert_eq!(conn.input_methods.len(), 1);
+    assert_eq!(
+        conn.input_methods
+            .get_item(im)
+            .unwrap()
+            .input_contexts
+            .len(),
+        2
+    );
+}
+
+#[test]
+fn dispatch_hooks() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    destroy_ic(&mut conn, &mut server, &mut handler, im, ic).unwrap();
+    // already destroyed
+    assert!(conn
+        .handle_request(
+            &mut server,
+            Request::SetIcFocus {
+                input_method_id: im,
+                input_context_id: ic,
+            },
+            &mut handler,
+        )
+        .is_err());
+
+    assert_eq!(
+        handler.before,
+        ["Open", "CreateIc", "CreateIc", "DestroyIc", "SetIcFocus"]
+    );
+    let after = handler
+        .after
+        .iter()
+        .map(|(name, ok)| (name.as_str(), *ok))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        after,
+        [
+            ("Open", true),
+            ("CreateIc", true),
+            ("CreateIc", true),
+            ("DestroyIc", true),
+            ("SetIcFocus", false),
+        ]
+    );
+}
+
+#[test]
+fn request_sequence() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+    assert_eq!(conn.sequence(), 0);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    destroy_ic(&mut conn, &mut server, &mut handler, im, ic).unwrap();
+    // failed requests are numbered too
+    conn.handle_request(
+        &mut server,
+        Request::SetIcFocus {
+            input_method_id: im,
+            input_context_id: ic,
+        },
+        &mut handler,
+    )
+    .unwrap_err();
+
+    assert_eq!(handler.sequences, [1, 2, 3, 4]);
+    assert_eq!(conn.sequence(), 4);
+}
+
+#[test]
+fn redundant_set_ic_focus() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let first = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    let second = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+
+    let mut focus = |conn: &mut XimConnection<()>, ic, set| {
+        let req = if set {
+            Request::SetIcFocus {
+                input_method_id: im,
+                input_context_id: ic,
+            }
+        } else {
+            Request::UnsetIcFocus {
+                input_method_id: im,
+                input_context_id: ic,
+            }
+        };
+        conn.handle_request(&mut server, req, &mut handler).unwrap();
+    };
+
+    focus(&mut conn, first, true);
+    focus(&mut conn, first, true);
+    assert_eq!(conn.last_focused(), Some((im, first)));
+    focus(&mut conn, second, true);
+    focus(&mut conn, second, false);
+    assert_eq!(conn.last_focused(), None);
+    focus(&mut conn, second, true);
+
+    assert_eq!(handler.focused, [first, second, second]);
+}
+
+#[test]
+fn expire_pending_operations() {
+    const COM_WIN: u32 = 20;
+
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut connections = XimConnections::new();
+    connections.new_connection(COM_WIN, CLIENT_WIN);
+    let conn = connections.get_connection(COM_WIN).unwrap();
+
+    let im = open_im(conn, &mut server, &mut handler);
+    let ic = create_ic(conn, &mut server, &mut handler, im, Vec::new());
+    conn.add_pending(im, ic, PendingOperation::Sync, Duration::from_millis(100))
+        .unwrap();
+    conn.add_pending(
+        im,
+        ic,
+        PendingOperation::PreeditStart,
+        Duration::from_millis(100),
+    )
+    .unwrap();
+    // answered in time
+    conn.handle_request(
+        &mut server,
+        Request::PreeditStartReply {
+            input_method_id: im,
+            input_context_id: ic,
+            return_value: -1,
+        },
+        &mut handler,
+    )
+    .unwrap();
+
+    let mut expire = |now| {
+        connections
+            .expire_pending(&mut server, &mut handler, Duration::from_millis(now))
+            .unwrap()
+    };
+    assert_eq!(expire(50), []);
+    assert_eq!(expire(150), [(COM_WIN, im, ic, PendingOperation::Sync)]);
+    assert_eq!(expire(200), []);
+
+    assert_eq!(handler.timeouts, [(ic, PendingOperation::Sync)]);
+}
+
+#[test]
+fn register_pending_replies() {
+    const COM_WIN: u32 = 20;
+
+    let mut server = MockServer {
+        reply_deadline: Some(Duration::from_millis(100)),
+        ..Default::default()
+    };
+    let mut handler = MockHandler::default();
+    let mut connections = XimConnections::new();
+    connections.new_connection(COM_WIN, CLIENT_WIN);
+    let conn = connections.get_connection(COM_WIN).unwrap();
+
+    let im = open_im(conn, &mut server, &mut handler);
+    let ic = create_ic(conn, &mut server, &mut handler, im, Vec::new());
+    server.preedit_draw(get_ic(conn, im, ic), "a").unwrap();
+    // only the first draw starts the preedit
+    server.preedit_draw(get_ic(conn, im, ic), "ab").unwrap();
+    server.sync(get_ic(conn, im, ic)).unwrap();
+    assert_eq!(
+        get_ic(conn, im, ic).pending,
+        [
+            (PendingOperation::PreeditStart, Duration::from_millis(100)),
+            (PendingOperation::Sync, Duration::from_millis(100)),
+        ]
+    );
+
+    conn.handle_request(
+        &mut server,
+        Request::SyncReply {
+            input_method_id: im,
+            input_context_id: ic,
+        },
+        &mut handler,
+    )
+    .unwrap();
+    assert_eq!(
+        connections
+            .expire_pending(&mut server, &mut handler, Duration::from_millis(150))
+            .unwrap(),
+        [(COM_WIN, im, ic, PendingOperation::PreeditStart)]
+    );
+}
+
+#[test]
+fn expire_pending_handler_error() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut connections = XimConnections::new();
+    let mut ics = Vec::new();
+    for com_win in [20, 30] {
+        connections.new_connection(com_win, CLIENT_WIN);
+        let conn = connections.get_connection(com_win).unwrap();
+        let im = open_im(conn, &mut server, &mut handler);
+        let ic = create_ic(conn, &mut server, &mut handler, im, Vec::new());
+        conn.add_pending(im, ic, PendingOperation::Sync, Duration::from_millis(100))
+            .unwrap();
+        ics.push((com_win, im, ic, PendingOperation::Sync));
+    }
+
+    // every connection is still visited, nothing is given up
+    handler.fail_timeout = true;
+    assert!(connections
+        .expire_pending(&mut server, &mut handler, Duration::from_millis(150))
+        .is_err());
+
+    handler.fail_timeout = false;
+    let mut expired = connections
+        .expire_pending(&mut server, &mut handler, Duration::from_millis(150))
+        .unwrap();
+    expired.sort_unstable_by_key(|&(com_win, ..)| com_win);
+    assert_eq!(expired, ics);
+    assert_eq!(handler.timeouts.len(), 2);
+}
+
+#[test]
+fn legacy_flat_attributes() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler {
+        flat_attributes: true,
+        ..Default::default()
+    };
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    conn.handle_request(
+        &mut server,
+        Request::Connect {
+            endian: Endian::Native,
+            client_major_protocol_version: 1,
+            client_minor_protocol_version: 0,
+            client_auth_protocol_names: Vec::new(),
+        },
+        &mut handler,
+    )
+    .unwrap();
+    conn.handle_request(
+        &mut server,
+        Request::Open {
+            locale: "en_US".to_string(),
+        },
+        &mut handler,
+    )
+    .unwrap();
+
+    let (im, ic_attrs) = match server.take().pop() {
+        Some(Request::OpenReply {
+            input_method_id,
+            ic_attrs,
+            ..
+        }) => (input_method_id, ic_attrs),
+        other => panic!("Unexpected reply: {:?}", other),
+    };
+    assert!(ic_attrs
+        .iter()
+        .all(|attr| !matches!(attr.ty, AttrType::NestedList | AttrType::Separator)));
+    assert!(ic_attrs.contains(&attrs::SPOT_LOCATION));
+
+    let spot = Attribute {
+        id: attrs::SPOT_LOCATION.id,
+        value: xim_parser::write_to_vec(Point { x: 5, y: 6 }),
+    };
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, vec![spot.clone()]);
+    conn.handle_request(
+        &mut server,
+        Request::GetIcValues {
+            input_method_id: im,
+            input_context_id: ic,
+            ic_attributes: vec![attrs::SPOT_LOCATION.id],
+        },
+        &mut handler,
+    )
+    .unwrap();
+    assert!(matches!(
+        &server.take()[..],
+        [Request::GetIcValuesReply { ic_attributes, .. }] if *ic_attributes == [spot]
+    ));
+}
+
+#[test]
+fn connect_auth_protocols() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler {
+        auth_protocols: vec!["MIT-MAGIC-COOKIE-1"],
+        ..Default::default()
+    };
+
+    for (names, accepted) in [
+        (vec!["XC-QUERY-SECURITY-1"], false),
+        (vec!["XC-QUERY-SECURITY-1", "MIT-MAGIC-COOKIE-1"], true),
+    ] {
+        let mut conn = XimConnection::new(CLIENT_WIN);
+        handler.connected = None;
+        conn.handle_request(
+            &mut server,
+            Request::Connect {
+                endian: Endian::Native,
+                client_major_protocol_version: 1,
+                client_minor_protocol_version: 0,
+                client_auth_protocol_names: names.into_iter().map(String::from).collect(),
+            },
+            &mut handler,
+        )
+        .unwrap();
+
+        let replies = server.take();
+        if accepted {
+            assert!(matches!(replies[..], [Request::ConnectReply { .. }]));
+            assert!(handler.connected.is_some());
+        } else {
+            assert!(matches!(
+                replies[..],
+                [Request::Error {
+                    code: ErrorCode::BadProtocol,
+                    input_method_id: 0,
+                    input_context_id: 0,
+                    ..
+                }]
+            ));
+            assert!(handler.connected.is_none());
+        }
+    }
+}
+
+#[test]
+fn big_endian_connect() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let connect = Request::Connect {
+        endian: Endian::Big,
+        client_major_protocol_version: 1,
+        client_minor_protocol_version: 0,
+        client_auth_protocol_names: Vec::new(),
+    };
+    let mut bytes = vec![0; connect.size()];
+    xim_parser::write_with_endian(&connect, &mut bytes, Endian::Big);
+
+    let req = Request::read(&mut conn.reader(&bytes)).unwrap();
+    assert_eq!(req, connect);
+    conn.handle_request(&mut server, req, &mut handler).unwrap();
+
+    assert_eq!(conn.endian(), Endian::Big);
+    assert_eq!(conn.reader(&[]).endian(), Endian::Big);
+    assert_eq!(
+        handler.connected,
+        Some(ConnectInfo {
+            endian: Endian::Big,
+            client_major_protocol_version: 1,
+            client_minor_protocol_version: 0,
+            client_auth_protocol_names: Vec::new(),
+        })
+    );
+
+    // following requests are read in the byte order of the client
+    let close = [32, 0, 0, 1, 0, 3, 0, 0];
+    assert_eq!(
+        Request::read(&mut conn.reader(&close)).unwrap(),
+        Request::Close { input_method_id: 3 }
+    );
+}
+
+#[test]
+fn malformed_nested_attributes() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let mut nested = spot_attr(1, 2).value;
+
+    // truncated nested attribute with an oversized length word
+    let mut truncated = xim_parser::write_to_vec(Attribute {
+        id: attrs::SPOT_LOCATION.id,
+        value: vec![0; 4],
+    });
+    truncated[2] = 0xff;
+    truncated[3] = 0xff;
+    nested.truncate(6);
+
+    for value in [truncated, nested, vec![0xff; 3]] {
+        let ic = create_ic(
+            &mut conn,
+            &mut server,
+            &mut handler,
+            im,
+            vec![Attribute {
+                id: attrs::PREEDIT_ATTRIBUTES.id,
+                value,
+            }],
+        );
+        assert!(!get_ic(&mut conn, im, ic).spot_received());
+    }
+}
+
+#[test]
+fn read_request_validates() {
+    let conn = XimConnection::<()>::new(CLIENT_WIN);
+
+    let get_im_values = [44, 0, 2, 0, 1, 0, 4, 0, 5, 0, 6, 0];
+    assert_eq!(
+        conn.read_request(&get_im_values).unwrap(),
+        Request::GetImValues {
+            input_method_id: 1,
+            im_attributes: vec![5, 6],
+        }
+    );
+
+    // the attribute list claims more bytes than the request holds
+    let get_im_values = [44, 0, 2, 0, 1, 0, 12, 0, 5, 0, 6, 0];
+    assert!(matches!(
+        conn.read_request(&get_im_values),
+        Err(ReadError::EndOfStream)
+    ));
+}
+
+#[test]
+fn input_styles_not_cached() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    server.take();
+
+    let mut query_styles = |handler: &mut MockHandler| {
+        conn.handle_request(
+            &mut server,
+            Request::GetImValues {
+                input_method_id: im,
+                im_attributes: vec![attrs::get_id(AttributeName::QueryInputStyle)],
+            },
+            handler,
+        )
+        .unwrap();
+        let reply = server.take().pop().unwrap();
+        reply
+            .find_attribute::<InputStyleList>(attrs::get_id(AttributeName::QueryInputStyle))
+            .unwrap()
+            .unwrap()
+            .styles
+    };
+
+    assert_eq!(
+        query_styles(&mut handler),
+        [InputStyle::PREEDIT_CALLBACKS | InputStyle::STATUS_NOTHING]
+    );
+    handler.input_style = Some(InputStyle::PREEDIT_POSITION | InputStyle::STATUS_NOTHING);
+    assert_eq!(
+        query_styles(&mut handler),
+        [InputStyle::PREEDIT_POSITION | InputStyle::STATUS_NOTHING]
+    );
+}
+
+#[test]
+fn move_ic_between_input_methods() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let from = open_im(&mut conn, &mut server, &mut handler);
+    let to = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(
+        &mut conn,
+        &mut server,
+        &mut handler,
+        from,
+        vec![spot_attr(3, 4)],
+    );
+
+    // a missing target keeps the input context where it was
+    assert!(matches!(
+        conn.move_ic(from, ic, 100),
+        Err(ServerError::ClientNotExists)
+    ));
+    assert!(get_ic(&mut conn, from, ic).spot_received());
+
+    // the target is held to the same limit as `CreateIc`
+    create_ic(&mut conn, &mut server, &mut handler, to, Vec::new());
+    conn.set_max_input_contexts_per_im(1);
+    assert!(matches!(
+        conn.move_ic(from, ic, to),
+        Err(ServerError::XimError(ErrorCode::BadAlloc, _))
+    ));
+    assert!(get_ic(&mut conn, from, ic).spot_received());
+    conn.set_max_input_contexts_per_im(2);
+
+    conn.add_pending(from, ic, PendingOperation::Sync, Duration::from_millis(100))
+        .unwrap();
+    let new_ic = conn.move_ic(from, ic, to).unwrap();
+    assert_eq!(
+        get_ic(&mut conn, to, new_ic).pending,
+        [(PendingOperation::Sync, Duration::from_millis(100))]
+    );
+    let moved = get_ic(&mut conn, to, new_ic);
+    assert_eq!(
+        moved.address(),
+        (
+            NonZeroU16::new(to).unwrap(),
+            NonZeroU16::new(new_ic).unwrap()
+        )
+    );
+    assert!(moved.spot_received());
+    assert!(conn
+        .input_methods
+        .get_item(from)
+        .unwrap()
+        .get_input_context(ic)
+        .is_err());
+}
+
+#[test]
+fn exhausted_ids() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    for _ in 0..u16::MAX {
+        conn.input_methods
+            .new_item(InputMethod::new(String::new()))
+            .unwrap();
+    }
+
+    let open = Request::Open {
+        locale: "en_US".into(),
+    };
+    assert!(matches!(
+        conn.handle_request(&mut server, open.clone(), &mut handler),
+        Err(ServerError::IdExhausted)
+    ));
+
+    // a freed id is handed out again instead of overwriting a live one
+    conn.input_methods.remove_item(7).unwrap();
+    conn.handle_request(&mut server, open, &mut handler)
+        .unwrap();
+    assert!(matches!(
+        server.take()[..],
+        [Request::OpenReply {
+            input_method_id: 7,
+            ..
+        }]
+    ));
+}
+
+#[test]
+fn shutdown_destroys_all() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut connections = XimConnections::new();
+    let mut expected = Vec::new();
+
+    for com_win in [1, 2] {
+        connections.new_connection(com_win, CLIENT_WIN + com_win);
+        let conn = connections.get_connection(com_win).unwrap();
+        let im = open_im(conn, &mut server, &mut handler);
+        for _ in 0..2 {
+            let ic = create_ic(conn, &mut server, &mut handler, im, Vec::new());
+            expected.push((im, ic));
+        }
+    }
+
+    connections.shutdown(&mut server, &mut handler).unwrap();
+
+    handler.destroyed.sort_unstable();
+    expected.sort_unstable();
+    assert_eq!(handler.destroyed, expected);
+    assert_eq!(handler.disconnect_reasons, [DisconnectReason::Clean; 4]);
+    assert_eq!(handler.closed, [1, 1]);
+    assert!(server.take().is_empty());
+    assert!(connections.get_connection(1).is_none());
+    assert!(connections.get_connection(2).is_none());
+}
+
+#[test]
+fn transport_error_disconnect() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let first = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    let second = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    server.take();
+
+    conn.disconnect(&mut server, &mut handler, DisconnectReason::TransportError)
+        .unwrap();
+
+    handler.destroyed.sort_unstable();
+    assert_eq!(handler.destroyed, [(im, first), (im, second)]);
+    assert_eq!(
+        handler.disconnect_reasons,
+        [DisconnectReason::TransportError; 2]
+    );
+    assert_eq!(handler.closed, [im]);
+    assert!(server.take().is_empty());
+}
+
+#[test]
+fn iter_input_contexts_across_connections() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut connections = XimConnections::new();
+    let mut expected = Vec::new();
+
+    for com_win in [1, 2] {
+        connections.new_connection(com_win, CLIENT_WIN + com_win);
+        let conn = connections.get_connection(com_win).unwrap();
+        let im = open_im(conn, &mut server, &mut handler);
+        for _ in 0..2 {
+            let ic = create_ic(conn, &mut server, &mut handler, im, Vec::new());
+            expected.push((com_win, im, ic));
+        }
+    }
+
+    let mut found = Vec::new();
+    for (com_win, im, ic, user_ic) in connections.iter_input_contexts() {
+        assert_eq!(user_ic.ic.client_win(), CLIENT_WIN + com_win);
+        assert_eq!(user_ic.ic.input_method_id(), im);
+        assert_eq!(user_ic.ic.input_context_id(), ic);
+        found.push((com_win, im.get(), ic.get()));
+    }
+
+    found.sort_unstable();
+    expected.sort_unstable();
+    assert_eq!(found, expected);
+}
+
+fn commit_bytes(f: impl FnOnce(&mut MockServer, &InputContext)) -> Vec<u8> {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    f(&mut server, get_ic(&mut conn, im, ic));
+
+    let mut reqs = server.take();
+    assert_eq!(reqs.len(), 1);
+    let req = reqs.pop().unwrap();
+    let mut out = vec![0; req.size()];
+    xim_parser::write_with_endian(&req, &mut out, Endian::Little);
+    assert_eq!(
+        xim_parser::read_with_endian::<Request>(&out, Endian::Little).unwrap(),
+        req
+    );
+    out
+}
+
+#[test]
+fn commit_chars_layout() {
+    let text = xim_ctext::utf8_to_compound_text("ab");
+    assert_eq!(text.len(), 8);
+
+    let out = commit_bytes(|server, ic| server.commit(ic, "ab").unwrap());
+    assert_eq!(out[..12], [63, 0, 4, 0, 1, 0, 1, 0, 2, 0, 8, 0]);
+    assert_eq!(out[12..], text[..]);
+}
+
+#[test]
+fn commit_empty_layout() {
+    // a canceled composition commits nothing, still as XimLookupChars
+    let out = commit_bytes(|server, ic| server.commit(ic, "").unwrap());
+    assert_eq!(
+        out,
+        [
+            63, 0, 2, 0, // header
+            1, 0, 1, 0, // ids
+            2, 0, 0, 0, // XimLookupChars, empty string
+        ]
+    );
+}
+
+#[test]
+fn commit_keysym_layout() {
+    let out = commit_bytes(|server, ic| server.commit_keysym(ic, 0xfe51).unwrap());
+    assert_eq!(
+        out,
+        [
+            63, 0, 3, 0, // header
+            1, 0, 1, 0, // ids
+            4, 0, 0, 0, // XimLookupKeySym, unused
+            0x51, 0xfe, 0, 0, // keysym
+        ]
+    );
+}
+
+#[test]
+fn commit_both_layout() {
+    let text = xim_ctext::utf8_to_compound_text("a");
+    assert_eq!(text.len(), 7);
+
+    let out = commit_bytes(|server, ic| server.commit_both(ic, 0x61, "a").unwrap());
+    assert_eq!(
+        out[..18],
+        [
+            63, 0, 6, 0, // header
+            1, 0, 1, 0, // ids
+            6, 0, 0, 0, // XimLookupBoth, unused
+            0x61, 0, 0, 0, // keysym
+            7, 0, // length
+        ]
+    );
+    assert_eq!(out[18..25], text[..]);
+    // pad
+    assert_eq!(out[25..], [0; 3]);
+}
+
+#[test]
+fn error_flags() {
+    let mut server = MockServer::default();
+
+    server
+        .error(
+            CLIENT_WIN,
+            ErrorCode::BadName,
+            "both".into(),
+            NonZeroU16::new(1),
+            NonZeroU16::new(2),
+        )
+        .unwrap();
+    server
+        .error(
+            CLIENT_WIN,
+            ErrorCode::BadName,
+            "im".into(),
+            NonZeroU16::new(1),
+            None,
+        )
+        .unwrap();
+    server
+        .error(CLIENT_WIN, ErrorCode::BadName, "none".into(), None, None)
+        .unwrap();
+
+    let flags: Vec<_> = server
+        .take()
+        .into_iter()
+        .map(|req| match req {
+            Request::Error {
+                flag,
+                input_method_id,
+                input_context_id,
+                ..
+            } => (flag, input_method_id, input_context_id),
+            other => panic!("Unexpected request: {:?}", other),
+        })
+        .collect();
+
+    assert_eq!(
+        flags,
+        [
+            (
+                ErrorFlag::INPUT_METHOD_ID_VALID | ErrorFlag::INPUT_CONTEXT_ID_VALID,
+                1,
+                2
+            ),
+            (ErrorFlag::INPUT_METHOD_ID_VALID, 1, 0),
+            (ErrorFlag::empty(), 0, 0),
+        ]
+    );
+}
+
+#[test]
+fn get_ic_values_error_scoped_to_ic() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    conn.handle_request(
+        &mut server,
+        Request::GetIcValues {
+            input_method_id: im,
+            input_context_id: ic,
+            ic_attributes: vec![attrs::QUERY_INPUT_STYLE.id],
+        },
+        &mut handler,
+    )
+    .unwrap();
+
+    assert!(matches!(
+        server.take()[..],
+        [Request::Error {
+            flag,
+            input_method_id,
+            input_context_id,
+            code: ErrorCode::BadName,
+            ..
+        }] if flag == ErrorFlag::INPUT_METHOD_ID_VALID | ErrorFlag::INPUT_CONTEXT_ID_VALID
+            && input_method_id == im
+            && input_context_id == ic
+    ));
+}
+
+#[test]
+fn set_ic_values_partial() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(
+        &mut conn,
+        &mut server,
+        &mut handler,
+        im,
+        vec![spot_attr(3, 4)],
+    );
+    assert_eq!(
+        get_ic(&mut conn, im, ic).applied_attributes(),
+        [
+            AttributeName::PreeditAttributes,
+            AttributeName::SpotLocation
+        ]
+    );
+
+    conn.handle_request(
+        &mut server,
+        Request::SetIcValues {
+            input_method_id: im,
+            input_context_id: ic,
+            ic_attributes: vec![
+                Attribute {
+                    id: attrs::FOCUSWIN.id,
+                    value: xim_parser::write_to_vec(0x50u32),
+                },
+                // unknown ids are skipped
+                Attribute {
+                    id: 0x7fff,
+                    value: vec![0; 4],
+                },
+            ],
+        },
+        &mut handler,
+    )
+    .unwrap();
+
+    let user_ic = get_ic(&mut conn, im, ic);
+    assert_eq!(user_ic.applied_attributes(), [AttributeName::FocusWindow]);
+    assert_eq!(user_ic.app_focus_win(), NonZeroU32::new(0x50));
+    assert_eq!(user_ic.preedit_spot(), Point { x: 3, y: 4 });
+}
+
+#[test]
+fn reject_com_window_as_app_window() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(
+        &mut conn,
+        &mut server,
+        &mut handler,
+        im,
+        vec![
+            Attribute {
+                id: attrs::CLIENTWIN.id,
+                value: xim_parser::write_to_vec(CLIENT_WIN),
+            },
+            Attribute {
+                id: attrs::FOCUSWIN.id,
+                value: xim_parser::write_to_vec(0u32),
+            },
+        ],
+    );
+
+    let user_ic = get_ic(&mut conn, im, ic);
+    assert_eq!(user_ic.applied_attributes(), []);
+    assert_eq!(user_ic.app_win(), None);
+    assert_eq!(user_ic.app_focus_win(), None);
+
+    conn.handle_request(
+        &mut server,
+        Request::SetIcValues {
+            input_method_id: im,
+            input_context_id: ic,
+            ic_attributes: vec![Attribute {
+                id: attrs::CLIENTWIN.id,
+                value: xim_parser::write_to_vec(0x50u32),
+            }],
+        },
+        &mut handler,
+    )
+    .unwrap();
+    assert_eq!(get_ic(&mut conn, im, ic).app_win(), NonZeroU32::new(0x50));
+}
+
+#[test]
+fn get_ic_values_nested_preedit() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(
+        &mut conn,
+        &mut server,
+        &mut handler,
+        im,
+        vec![spot_attr(3, 4)],
+    );
+    conn.handle_request(
+        &mut server,
+        Request::GetIcValues {
+            input_method_id: im,
+            input_context_id: ic,
+            ic_attributes: vec![
+                attrs::PREEDIT_ATTRIBUTES.id,
+                attrs::SPOT_LOCATION.id,
+                attrs::SEPARATOR_OF_NESTED_LIST.id,
+                attrs::INPUT_STYLE.id,
+            ],
+        },
+        &mut handler,
+    )
+    .unwrap();
+
+    let ic_attributes = match server.take().pop() {
+        Some(Request::GetIcValuesReply { ic_attributes, .. }) => ic_attributes,
+        other => panic!("Unexpected reply: {:?}", other),
+    };
+    assert_eq!(ic_attributes.len(), 2);
+    assert_eq!(ic_attributes[0].id, attrs::PREEDIT_ATTRIBUTES.id);
+    assert_eq!(ic_attributes[1].id, attrs::INPUT_STYLE.id);
+
+    let nested = xim_parser::decode_nested_attributes(&ic_attributes[0].value).unwrap();
+    assert_eq!(nested.len(), 1);
+    assert_eq!(nested[0].id, attrs::SPOT_LOCATION.id);
+    assert_eq!(
+        xim_parser::read::<Point>(&nested[0].value).unwrap(),
+        get_ic(&mut conn, im, ic).preedit_spot()
+    );
+}
+
+#[test]
+fn get_ic_values_windows() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(
+        &mut conn,
+        &mut server,
+        &mut handler,
+        im,
+        vec![Attribute::new(attrs::CLIENTWIN.id, 0x50u32)],
+    );
+
+    let get_windows =
+        |conn: &mut XimConnection<()>, server: &mut MockServer, handler: &mut MockHandler| {
+            conn.handle_request(
+                server,
+                Request::GetIcValues {
+                    input_method_id: im,
+                    input_context_id: ic,
+                    ic_attributes: vec![attrs::CLIENTWIN.id, attrs::FOCUSWIN.id],
+                },
+                handler,
+            )
+            .unwrap();
+            match server.take().pop() {
+                Some(Request::GetIcValuesReply { ic_attributes, .. }) => ic_attributes
+                    .iter()
+                    .map(|attr| (attr.id, attr.decode::<u32>().unwrap()))
+                    .collect::<Vec<_>>(),
+                other => panic!("Unexpected reply: {:?}", other),
+            }
+        };
+
+    // a window never set is 0
+    assert_eq!(
+        get_windows(&mut conn, &mut server, &mut handler),
+        [(attrs::CLIENTWIN.id, 0x50), (attrs::FOCUSWIN.id, 0)]
+    );
+
+    conn.handle_request(
+        &mut server,
+        Request::SetIcValues {
+            input_method_id: im,
+            input_context_id: ic,
+            ic_attributes: vec![Attribute::new(attrs::FOCUSWIN.id, 0x60u32)],
+        },
+        &mut handler,
+    )
+    .unwrap();
+    assert_eq!(
+        get_windows(&mut conn, &mut server, &mut handler),
+        [(attrs::CLIENTWIN.id, 0x50), (attrs::FOCUSWIN.id, 0x60)]
+    );
+}
+
+#[test]
+fn ic_address() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    // the reply carries the address of the new input context
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+
+    let (im_id, ic_id) = get_ic(&mut conn, im, ic).address();
+    assert_eq!((im_id.get(), ic_id.get()), (im, ic));
+
+    conn.handle_request(
+        &mut server,
+        Request::ResetIc {
+            input_method_id: im,
+            input_context_id: ic,
+        },
+        &mut handler,
+    )
+    .unwrap();
+    assert_eq!(
+        server.take(),
+        [Request::ResetIcReply {
+            input_method_id: im,
+            input_context_id: ic,
+            preedit_string: xim_ctext::utf8_to_compound_text(""),
+        }]
+    );
+}
+
+#[test]
+fn server_atom_names() {
+    assert_eq!(server_atom_name("myime").unwrap(), "@server=myime");
+
+    for name in ["", "my=ime", "@im", "my ime"] {
+        assert!(matches!(
+            server_atom_name(name),
+            Err(ServerError::InvalidServerName(n)) if n == name
+        ));
+    }
+}
+
+fn key_event(input_method_id: u16, input_context_id: u16, flag: ForwardEventFlag) -> Request {
+    Request::ForwardEvent {
+        input_method_id,
+        input_context_id,
+        flag,
+        serial_number: 0,
+        xev: XEvent {
+            response_type: 2,
+            detail: 38,
+            sequence: 0,
+            time: 0,
+            root: 0,
+            event: 0,
+            child: 0,
+            root_x: 0,
+            root_y: 0,
+            event_x: 0,
+            event_y: 0,
+            state: 0,
+            same_screen: true,
+        },
+    }
+}
+
+#[test]
+fn decode_key_event() {
+    let server = MockServer::default();
+    let xev = match key_event(1, 1, ForwardEventFlag::empty()) {
+        Request::ForwardEvent { xev, .. } => xev,
+        _ => unreachable!(),
+    };
+    let press = XEvent {
+        state: 1,
+        time: 1234,
+        ..xev.clone()
+    };
+
+    assert_eq!(
+        server.decode_key_event(&press),
+        Some(KeyEvent {
+            keycode: 38,
+            state: 1,
+            time: 1234,
+            kind: KeyEventKind::Press,
+        })
+    );
+    // sent with `SendEvent`
+    assert_eq!(
+        server
+            .decode_key_event(&XEvent {
+                response_type: 0x83,
+                ..press.clone()
+            })
+            .map(|key| key.kind),
+        Some(KeyEventKind::Release)
+    );
+    // ButtonPress
+    assert_eq!(
+        server.decode_key_event(&XEvent {
+            response_type: 4,
+            ..press
+        }),
+        None
+    );
+}
+
+#[test]
+fn serialize_event_default() {
+    /// A server core implementing only the required methods.
+    struct MinimalServer;
+
+    impl ServerCore for MinimalServer {
+        type XEvent = XEvent;
+
+        fn deserialize_event(&self, ev: &XEvent) -> XEvent {
+            ev.clone()
+        }
+
+        fn send_req(&mut self, _client_win: u32, _req: Request) -> Result<(), ServerError> {
+            Ok(())
+        }
+    }
+
+    let xev = match key_event(1, 1, ForwardEventFlag::empty()) {
+        Request::ForwardEvent { xev, .. } => xev,
+        _ => unreachable!(),
+    };
+    let ic = InputContext::builder().client_win(CLIENT_WIN).build().ic;
+
+    assert!(matches!(
+        MinimalServer.forward_synthetic_event(&ic, &xev),
+        Err(ServerError::EventNotSerializable)
+    ));
+    assert_eq!(MinimalServer.decode_key_event(&xev), None);
+}
+
+#[test]
+fn batch_forward_events() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+    conn.set_batch_forward_events(true);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+
+    for _ in 0..3 {
+        conn.handle_request(
+            &mut server,
+            key_event(im, ic, ForwardEventFlag::empty()),
+            &mut handler,
+        )
+        .unwrap();
+    }
+    assert!(server.sent.is_empty());
+
+    conn.flush_forward_events(&mut server).unwrap();
+    assert_eq!(server.batches, [3]);
+    assert_eq!(server.take().len(), 3);
+
+    // queued events go out before the reply to the next request
+    conn.handle_request(
+        &mut server,
+        key_event(im, ic, ForwardEventFlag::empty()),
+        &mut handler,
+    )
+    .unwrap();
+    conn.handle_request(
+        &mut server,
+        Request::Sync {
+            input_method_id: im,
+            input_context_id: ic,
+        },
+        &mut handler,
+    )
+    .unwrap();
+    assert_eq!(server.batches, [3, 1]);
+    assert!(matches!(
+        server.take()[..],
+        [Request::ForwardEvent { .. }, Request::SyncReply { .. }]
+    ));
+}
+
+#[test]
+fn unbatched_forward_events() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+
+    for _ in 0..3 {
+        conn.handle_request(
+            &mut server,
+            key_event(im, ic, ForwardEventFlag::SYNCHRONOUS),
+            &mut handler,
+        )
+        .unwrap();
+    }
+
+    assert!(server.batches.is_empty());
+    assert_eq!(server.take().len(), 6);
+}
+
+#[test]
+fn forward_replaced_event() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    server.take();
+
+    handler.replace_detail = Some(40);
+    conn.handle_request(
+        &mut server,
+        key_event(im, ic, ForwardEventFlag::empty()),
+        &mut handler,
+    )
+    .unwrap();
+
+    match &server.take()[..] {
+        [Request::ForwardEvent { xev, .. }] => assert_eq!(xev.detail, 40),
+        reqs => panic!("unexpected requests: {:?}", reqs),
+    }
+}
+
+#[test]
+fn duplicate_create_ic() {
+    let client_win_attr = |win: u32| Attribute {
+        id: attrs::CLIENTWIN.id,
+        value: xim_parser::write_to_vec(win),
+    };
+
+    for policy in [
+        DuplicateIcPolicy::Allow,
+        DuplicateIcPolicy::Reuse,
+        DuplicateIcPolicy::Error,
+    ] {
+        let mut server = MockServer::default();
+        let mut handler = MockHandler {
+            duplicate_ic: policy,
+            ..Default::default()
+        };
+        let mut conn = XimConnection::new(CLIENT_WIN);
+
+        let im = open_im(&mut conn, &mut server, &mut handler);
+        let first = create_ic(
+            &mut conn,
+            &mut server,
+            &mut handler,
+            im,
+            vec![client_win_attr(0x40)],
+        );
+
+        conn.handle_request(
+            &mut server,
+            Request::CreateIc {
+                input_method_id: im,
+                ic_attributes: vec![client_win_attr(0x40), spot_attr(1, 2)],
+            },
+            &mut handler,
+        )
+        .unwrap();
+        let reply = server.take();
+
+        match policy {
+            DuplicateIcPolicy::Allow => {
+                assert!(matches!(
+                    reply[..],
+                    [Request::CreateIcReply { input_context_id, .. }] if input_context_id != first
+                ));
+                assert_eq!(handler.created.len(), 2);
+            }
+            DuplicateIcPolicy::Reuse => {
+                assert!(matches!(
+                    reply[..],
+                    [Request::CreateIcReply { input_context_id, .. }] if input_context_id == first
+                ));
+                assert_eq!(handler.created.len(), 1);
+                assert!(get_ic(&mut conn, im, first).spot_received());
+            }
+            DuplicateIcPolicy::Error => {
+                assert!(matches!(
+                    reply[..],
+                    [Request::Error {
+                        code: ErrorCode::BadClientWindow,
+                        ..
+                    }]
+                ));
+                assert_eq!(handler.created.len(), 1);
+            }
+        }
+
+        // another window always gets its own input context
+        let other = create_ic(
+            &mut conn,
+            &mut server,
+            &mut handler,
+            im,
+            vec![client_win_attr(0x41)],
+        );
+        assert_ne!(other, first);
+    }
+}
+
+#[test]
+fn encoding_negotiation() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+
+    for (encodings, encoding_infos, category, index) in [
+        (vec!["UTF-8", "COMPOUND_TEXT"], vec![], 0, 1),
+        (
+            vec!["ISO8859-1"],
+            vec!["ISO8859-1", "ISO8859-1; COMPOUND_TEXT"],
+            1,
+            1,
+        ),
+        (vec!["COMPOUND_TEXT"], vec!["COMPOUND_TEXT"], 0, 0),
+        (vec!["ISO8859-1"], vec!["ISO8859-1"], 0, -1),
+    ] {
+        conn.handle_request(
+            &mut server,
+            Request::EncodingNegotiation {
+                input_method_id: im,
+                encodings: encodings.into_iter().map(String::from).collect(),
+                encoding_infos: encoding_infos.into_iter().map(String::from).collect(),
+            },
+            &mut handler,
+        )
+        .unwrap();
+
+        assert_eq!(
+            server.take(),
+            [Request::EncodingNegotiationReply {
+                input_method_id: im,
+                category,
+                index,
+            }]
+        );
+    }
+}
+
+#[test]
+fn encoding_decode() {
+    assert_eq!(Encoding::Utf8.decode("한글".as_bytes()).unwrap(), "한글");
+    assert!(matches!(
+        Encoding::Utf8.decode(b"en_\xffUS"),
+        Err(xim_ctext::DecodeError::Utf8Error(_))
+    ));
+    assert_eq!(
+        Encoding::CompoundText
+            .decode(&Encoding::CompoundText.encode("한글"))
+            .unwrap(),
+        "한글"
+    );
+}
+
+#[test]
+fn negotiated_encoding_accessor() {
+    let mut server = MockServer {
+        require_negotiated_encoding: true,
+        ..Default::default()
+    };
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    server.take();
+
+    assert_eq!(
+        conn.input_methods
+            .get_item(im)
+            .unwrap()
+            .negotiated_encoding(),
+        None
+    );
+    assert_eq!(get_ic(&mut conn, im, ic).negotiated_encoding(), None);
+    assert!(matches!(
+        server.commit(get_ic(&mut conn, im, ic), "a"),
+        Err(ServerError::EncodingNotNegotiated)
+    ));
+    assert!(server.sent.is_empty());
+
+    conn.handle_request(
+        &mut server,
+        Request::EncodingNegotiation {
+            input_method_id: im,
+            encodings: vec!["UTF-8".to_string()],
+            encoding_infos: vec![],
+        },
+        &mut handler,
+    )
+    .unwrap();
+    server.take();
+
+    assert_eq!(
+        conn.input_methods
+            .get_item(im)
+            .unwrap()
+            .negotiated_encoding(),
+        Some(Encoding::Utf8)
+    );
+    assert_eq!(
+        get_ic(&mut conn, im, ic).negotiated_encoding(),
+        Some(Encoding::Utf8)
+    );
+    server.commit(get_ic(&mut conn, im, ic), "a").unwrap();
+    assert_eq!(server.take().len(), 1);
+}
+
+#[test]
+fn empty_encodings_default() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    server.take();
+
+    conn.handle_request(
+        &mut server,
+        Request::EncodingNegotiation {
+            input_method_id: im,
+            encodings: vec![],
+            encoding_infos: vec![],
+        },
+        &mut handler,
+    )
+    .unwrap();
+
+    assert_eq!(
+        server.take(),
+        [Request::EncodingNegotiationReply {
+            input_method_id: im,
+            category: 0,
+            index: 0,
+        }]
+    );
+    assert_eq!(handler.negotiated, [(im, Encoding::CompoundText)]);
+
+    // a handler can still reject it
+    handler.only_encoding = Some("UTF-8");
+    conn.handle_request(
+        &mut server,
+        Request::EncodingNegotiation {
+            input_method_id: im,
+            encodings: vec![],
+            encoding_infos: vec![],
+        },
+        &mut handler,
+    )
+    .unwrap();
+    assert_eq!(
+        server.take(),
+        [Request::EncodingNegotiationReply {
+            input_method_id: im,
+            category: 0,
+            index: -1,
+        }]
+    );
+}
+
+#[test]
+fn create_ic_before_negotiation() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, vec![]);
+
+    let early = get_ic(&mut conn, im, ic);
+    assert_eq!(early.encoding(), Encoding::CompoundText);
+    assert!(early.encoding_is_default());
+
+    conn.handle_request(
+        &mut server,
+        Request::EncodingNegotiation {
+            input_method_id: im,
+            encodings: vec!["UTF-8".to_string()],
+            encoding_infos: vec![],
+        },
+        &mut handler,
+    )
+    .unwrap();
+    assert_eq!(
+        server.take(),
+        [Request::EncodingNegotiationReply {
+            input_method_id: im,
+            category: 0,
+            index: 0,
+        }]
+    );
+
+    let negotiated = get_ic(&mut conn, im, ic);
+    assert_eq!(negotiated.encoding(), Encoding::Utf8);
+    assert!(!negotiated.encoding_is_default());
+
+    let late = create_ic(&mut conn, &mut server, &mut handler, im, vec![]);
+    let late = get_ic(&mut conn, im, late);
+    assert_eq!(late.encoding(), Encoding::Utf8);
+    assert!(!late.encoding_is_default());
+}
+
+#[test]
+fn clamp_spot_to_window() {
+    let client_win_attr = Attribute {
+        id: attrs::CLIENTWIN.id,
+        value: xim_parser::write_to_vec(0x40u32),
+    };
+
+    for (window_size, expected) in [
+        (Some((100, 50)), Point { x: 99, y: 0 }),
+        (None, Point { x: 300, y: -5 }),
+    ] {
+        let mut server = MockServer {
+            window_size,
+            ..Default::default()
+        };
+        let mut handler = MockHandler::default();
+        let mut conn = XimConnection::new(CLIENT_WIN);
+
+        let im = open_im(&mut conn, &mut server, &mut handler);
+        let ic = create_ic(
+            &mut conn,
+            &mut server,
+            &mut handler,
+            im,
+            vec![client_win_attr.clone(), spot_attr(300, -5)],
+        );
+
+        assert_eq!(get_ic(&mut conn, im, ic).preedit_spot(), expected);
+    }
+}
+
+#[test]
+fn renegotiate_encoding() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, vec![]);
+
+    for (name, encoding) in [
+        ("COMPOUND_TEXT", Encoding::CompoundText),
+        ("UTF-8", Encoding::Utf8),
+        ("UTF-8", Encoding::Utf8),
+    ] {
+        conn.handle_request(
+            &mut server,
+            Request::EncodingNegotiation {
+                input_method_id: im,
+                encodings: vec![name.to_string()],
+                encoding_infos: vec![],
+            },
+            &mut handler,
+        )
+        .unwrap();
+        assert_eq!(
+            server.take(),
+            [Request::EncodingNegotiationReply {
+                input_method_id: im,
+                category: 0,
+                index: 0,
+            }]
+        );
+
+        let user_ic = get_ic(&mut conn, im, ic);
+        assert_eq!(user_ic.encoding(), encoding);
+        assert!(!user_ic.encoding_is_default());
+    }
+
+    server.commit(get_ic(&mut conn, im, ic), "ab").unwrap();
+    assert!(matches!(
+        &server.take()[..],
+        [Request::Commit {
+            data: CommitData::Chars { commited, .. },
+            ..
+        }] if commited == b"ab"
+    ));
+}
+
+#[test]
+fn create_ic_with_zero_im_id() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    open_im(&mut conn, &mut server, &mut handler);
+    conn.handle_request(
+        &mut server,
+        Request::CreateIc {
+            input_method_id: 0,
+            ic_attributes: vec![],
+        },
+        &mut handler,
+    )
+    .unwrap();
+
+    assert!(matches!(
+        &server.take()[..],
+        [Request::Error {
+            code: ErrorCode::BadSomething,
+            input_method_id: 0,
+            input_context_id: 0,
+            ..
+        }]
+    ));
+    assert!(handler.created.is_empty());
+}
+
+#[test]
+fn custom_encoding_selector() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler {
+        only_encoding: Some("UTF-8"),
+        ..Default::default()
+    };
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, vec![]);
+
+    for (encodings, index) in [
+        (vec!["COMPOUND_TEXT"], -1),
+        (vec!["COMPOUND_TEXT", "UTF-8"], 1),
+    ] {
+        conn.handle_request(
+            &mut server,
+            Request::EncodingNegotiation {
+                input_method_id: im,
+                encodings: encodings.into_iter().map(String::from).collect(),
+                encoding_infos: vec![],
+            },
+            &mut handler,
+        )
+        .unwrap();
+
+        assert_eq!(
+            server.take(),
+            [Request::EncodingNegotiationReply {
+                input_method_id: im,
+                category: 0,
+                index,
+            }]
+        );
+    }
+
+    assert_eq!(get_ic(&mut conn, im, ic).encoding(), Encoding::Utf8);
+    // the rejected negotiation isn't reported
+    assert_eq!(handler.negotiated, [(im, Encoding::Utf8)]);
+}
+
+#[test]
+fn unknown_selected_encoding() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler {
+        only_encoding: Some("ISO8859-1"),
+        ..Default::default()
+    };
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+
+    // never reported as picked, the commits wouldn't be in it
+    for (encoding_infos, category, index) in
+        [(vec![], 0, -1), (vec!["ISO8859-1; COMPOUND_TEXT"], 1, 0)]
+    {
+        conn.handle_request(
+            &mut server,
+            Request::EncodingNegotiation {
+                input_method_id: im,
+                encodings: vec!["ISO8859-1".to_string()],
+                encoding_infos: encoding_infos.into_iter().map(String::from).collect(),
+            },
+            &mut handler,
+        )
+        .unwrap();
+
+        assert_eq!(
+            server.take(),
+            [Request::EncodingNegotiationReply {
+                input_method_id: im,
+                category,
+                index,
+            }]
+        );
+    }
+
+    assert_eq!(handler.negotiated, [(im, Encoding::CompoundText)]);
+}
+
+#[test]
+fn encoding_negotiated_callback() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    conn.handle_request(
+        &mut server,
+        Request::EncodingNegotiation {
+            input_method_id: im,
+            encodings: vec!["UTF-8".into()],
+            encoding_infos: vec![],
+        },
+        &mut handler,
+    )
+    .unwrap();
+
+    assert_eq!(
+        server.take(),
+        [Request::EncodingNegotiationReply {
+            input_method_id: im,
+            category: 0,
+            index: 0,
+        }]
+    );
+    assert_eq!(handler.negotiated, [(im, Encoding::Utf8)]);
+}
+
+#[test]
+fn ic_defaults_without_client_attrs() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler {
+        ic_defaults: IcDefaults {
+            input_style: InputStyle::PREEDIT_POSITION | InputStyle::STATUS_NOTHING,
+            preedit_spot: Point { x: 10, y: 20 },
+        },
+        ..Default::default()
+    };
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let defaulted = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    let sent = create_ic(
+        &mut conn,
+        &mut server,
+        &mut handler,
+        im,
+        vec![spot_attr(1, 2)],
+    );
+
+    let ic = get_ic(&mut conn, im, defaulted);
+    assert_eq!(ic.preedit_spot(), Point { x: 10, y: 20 });
+    assert_eq!(
+        ic.input_style(),
+        InputStyle::PREEDIT_POSITION | InputStyle::STATUS_NOTHING
+    );
+    assert!(!ic.spot_received());
+
+    assert_eq!(
+        get_ic(&mut conn, im, sent).preedit_spot(),
+        Point { x: 1, y: 2 }
+    );
+}
+
+#[test]
+fn trigger_on_then_off() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    server.take();
+    assert!(!get_ic(&mut conn, im, ic).triggered());
+
+    for (flag, index, triggered) in [
+        (TriggerNotifyFlag::OnKeyList, 1, true),
+        (TriggerNotifyFlag::OffKeyList, 0, false),
+    ] {
+        conn.handle_request(
+            &mut server,
+            Request::TriggerNotify {
+                input_method_id: im,
+                input_context_id: ic,
+                flag,
+                index,
+                event_mask: 1,
+            },
+            &mut handler,
+        )
+        .unwrap();
+
+        assert_eq!(
+            server.take(),
+            [Request::TriggerNotifyReply {
+                input_method_id: im,
+                input_context_id: ic,
+            }]
+        );
+        assert_eq!(get_ic(&mut conn, im, ic).triggered(), triggered);
+    }
+
+    assert_eq!(handler.triggers, [(true, 1), (false, 0)]);
+}
+
+#[test]
+fn status_start_draw_done() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    conn.handle_request(
+        &mut server,
+        Request::SetIcFocus {
+            input_method_id: im,
+            input_context_id: ic,
+        },
+        &mut handler,
+    )
+    .unwrap();
+    server.take();
+
+    server.status_draw(get_ic(&mut conn, im, ic), "한").unwrap();
+    server.status_draw(get_ic(&mut conn, im, ic), "A").unwrap();
+    assert_eq!(
+        get_ic(&mut conn, im, ic).status_state(),
+        StatusState::Started
+    );
+
+    conn.handle_request(
+        &mut server,
+        Request::UnsetIcFocus {
+            input_method_id: im,
+            input_context_id: ic,
+        },
+        &mut handler,
+    )
+    .unwrap();
+    assert_eq!(get_ic(&mut conn, im, ic).status_state(), StatusState::Idle);
+
+    let draw = |s: &str| Request::StatusDraw {
+        input_method_id: im,
+        input_context_id: ic,
+        content: StatusContent::Text(StatusTextContent {
+            status: PreeditDrawStatus::NO_FEEDBACK,
+            status_string: s.into(),
+            feedbacks: Vec::new(),
+        }),
+    };
+    assert_eq!(
+        server.take(),
+        [
+            Request::StatusStart {
+                input_method_id: im,
+                input_context_id: ic,
+            },
+            draw("한"),
+            draw("A"),
+            Request::StatusDone {
+                input_method_id: im,
+                input_context_id: ic,
+            },
+        ]
+    );
+
+    // nothing was started so there's nothing to finish
+    conn.handle_request(
+        &mut server,
+        Request::UnsetIcFocus {
+            input_method_id: im,
+            input_context_id: ic,
+        },
+        &mut handler,
+    )
+    .unwrap();
+    assert_eq!(server.take(), []);
+}
+
+#[test]
+fn input_context_builder() {
+    let style = InputStyle::PREEDIT_POSITION | InputStyle::STATUS_NOTHING;
+    let user_ic = InputContext::builder()
+        .client_win(CLIENT_WIN)
+        .ids(NonZeroU16::new(2).unwrap(), NonZeroU16::new(3).unwrap())
+        .locale("ko_KR".into())
+        .input_style(style)
+        .preedit_spot(Point { x: 10, y: 20 })
+        .user_data("한")
+        .build();
+
+    assert_eq!(user_ic.user_data(), &"한");
+    let ic = &user_ic.ic;
+    assert_eq!(ic.client_win(), CLIENT_WIN);
+    assert_eq!(ic.address().0.get(), 2);
+    assert_eq!(ic.address().1.get(), 3);
+    assert_eq!(ic.locale(), "ko_KR");
+    assert_eq!(ic.input_style(), style);
+    assert_eq!(ic.preedit_spot(), Point { x: 10, y: 20 });
+    assert!(!ic.spot_received());
+}
+
+#[test]
+fn user_data_mut() {
+    let mut im = InputMethod::new("en_US".into());
+    let (ic, _) = im
+        .new_ic(UserInputContext::new(
+            InputContext::new(
+                CLIENT_WIN,
+                NonZeroU16::new(1).unwrap(),
+                NonZeroU16::new(1).unwrap(),
+                "en_US".into(),
+            ),
+            Vec::new(),
+        ))
+        .unwrap();
+
+    im.get_input_context(ic.get())
+        .unwrap()
+        .user_data_mut()
+        .push("가");
+
+    assert_eq!(im.get_input_context(ic.get()).unwrap().user_data(), &["가"]);
+}
+
+#[test]
+fn commit_and_sync_order() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    server.take();
+    server.batches.clear();
+
+    server
+        .commit_and_sync(get_ic(&mut conn, im, ic), "가")
+        .unwrap();
+
+    assert_eq!(server.batches, [2]);
+    assert_eq!(
+        server.take(),
+        [
+            Request::Commit {
+                input_method_id: im,
+                input_context_id: ic,
+                data: CommitData::Chars {
+                    commited: xim_ctext::utf8_to_compound_text("가"),
+                    syncronous: false,
+                },
+            },
+            Request::SyncReply {
+                input_method_id: im,
+                input_context_id: ic,
+            },
+        ]
+    );
+}
+
+#[test]
+fn send_batch_all_or_nothing() {
+    let mut server = MockServer::default();
+
+    let close = Request::Close { input_method_id: 1 };
+    let bad = Request::Open {
+        locale: "a".repeat(256),
+    };
+    assert!(matches!(
+        server.send_batch(CLIENT_WIN, &[close.clone(), bad]),
+        Err(ServerError::ReadProtocol(_))
+    ));
+    assert!(server.sent.is_empty());
+    assert!(server.batches.is_empty());
+
+    let sync = Request::Sync {
+        input_method_id: 1,
+        input_context_id: 2,
+    };
+    server
+        .send_batch(CLIENT_WIN, &[close.clone(), sync.clone()])
+        .unwrap();
+    assert_eq!(server.batches, [2]);
+    assert_eq!(server.take(), [close, sync]);
+}
+
+#[test]
+fn passive_server_drops_events() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler {
+        passive: true,
+        ..Default::default()
+    };
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    server.take();
+
+    conn.handle_request(
+        &mut server,
+        key_event(im, ic, ForwardEventFlag::empty()),
+        &mut handler,
+    )
+    .unwrap();
+    assert!(server.take().is_empty());
+
+    conn.handle_request(
+        &mut server,
+        key_event(im, ic, ForwardEventFlag::SYNCHRONOUS),
+        &mut handler,
+    )
+    .unwrap();
+    assert!(matches!(server.take()[..], [Request::SyncReply { .. }]));
+
+    // a replacement is still sent
+    handler.replace_detail = Some(40);
+    conn.handle_request(
+        &mut server,
+        key_event(im, ic, ForwardEventFlag::empty()),
+        &mut handler,
+    )
+    .unwrap();
+    assert!(matches!(server.take()[..], [Request::ForwardEvent { .. }]));
+}
+
+#[test]
+fn filtered_event_passes_through() {
+    let mut server = MockServer::default();
+    // any call to the handler would fail the request
+    let mut handler = MockHandler {
+        fail_forward: true,
+        passive: true,
+        ..Default::default()
+    };
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    server.take();
+
+    conn.handle_request(
+        &mut server,
+        key_event(
+            im,
+            ic,
+            ForwardEventFlag::REQUEST_FILTERING | ForwardEventFlag::SYNCHRONOUS,
+        ),
+        &mut handler,
+    )
+    .unwrap();
+    assert!(matches!(
+        server.take()[..],
+        [
+            Request::ForwardEvent { flag, .. },
+            Request::SyncReply { .. }
+        ] if flag.is_empty()
+    ));
+}
+
+#[test]
+fn forward_event_budget() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler {
+        event_budget: Some(Duration::from_millis(100)),
+        replace_detail: Some(40),
+        ..Default::default()
+    };
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    server.take();
+
+    let mut details = Vec::new();
+    for time in [1000, 1050, 1100, 1150, 1250] {
+        let mut req = key_event(im, ic, ForwardEventFlag::empty());
+        if let Request::ForwardEvent { xev, .. } = &mut req {
+            xev.time = time;
+        }
+        conn.handle_request(&mut server, req, &mut handler).unwrap();
+
+        match &server.take()[..] {
+            [Request::ForwardEvent { xev, .. }] => details.push(xev.detail),
+            other => panic!("Unexpected requests: {:?}", other),
+        }
+    }
+
+    // events over the budget go back untouched
+    assert_eq!(handler.processed_events, [1000, 1100, 1250]);
+    assert_eq!(details, [40, 38, 40, 38, 40]);
+}
+
+#[test]
+fn passive_server_drops_events_over_budget() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler {
+        event_budget: Some(Duration::from_millis(100)),
+        passive: true,
+        ..Default::default()
+    };
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    server.take();
+
+    for time in [1000, 1050] {
+        let mut req = key_event(im, ic, ForwardEventFlag::SYNCHRONOUS);
+        if let Request::ForwardEvent { xev, .. } = &mut req {
+            xev.time = time;
+        }
+        conn.handle_request(&mut server, req, &mut handler).unwrap();
+        assert!(matches!(server.take()[..], [Request::SyncReply { .. }]));
+    }
+    assert_eq!(handler.processed_events, [1000]);
+}
+
+#[test]
+fn commit_and_sync_in_forward_event() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler {
+        commit_and_sync: Some("a"),
+        ..Default::default()
+    };
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    server.take();
+
+    conn.handle_request(
+        &mut server,
+        key_event(im, ic, ForwardEventFlag::SYNCHRONOUS),
+        &mut handler,
+    )
+    .unwrap();
+
+    // the helper answered the event, the dispatch doesn't answer it again
+    assert!(matches!(
+        server.take()[..],
+        [Request::Commit { .. }, Request::SyncReply { .. }]
+    ));
+    assert_eq!(get_ic(&mut conn, im, ic).unanswered_syncs, 0);
+}
+
+#[test]
+fn overlapping_sync_forwards() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let first = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    let second = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+    server.take();
+
+    for ic in [first, second, first] {
+        conn.handle_request(
+            &mut server,
+            key_event(im, ic, ForwardEventFlag::SYNCHRONOUS),
+            &mut handler,
+        )
+        .unwrap();
+    }
+
+    let replies = server
+        .take()
+        .into_iter()
+        .filter_map(|req| match req {
+            Request::SyncReply {
+                input_context_id, ..
+            } => Some(input_context_id),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(replies, [first, second, first]);
+
+    // a failed handler still lets the client go on
+    handler.fail_forward = true;
+    assert!(conn
+        .handle_request(
+            &mut server,
+            key_event(im, first, ForwardEventFlag::SYNCHRONOUS),
+            &mut handler,
+        )
+        .is_err());
+    assert_eq!(get_ic(&mut conn, im, first).unanswered_syncs, 0);
+    assert!(matches!(
+        server.take()[..],
+        [Request::SyncReply { input_context_id, .. }] if input_context_id == first
+    ));
+
+    // a reply that couldn't be sent is counted until it's answered
+    get_ic(&mut conn, im, first).unanswered_syncs = 1;
+
+    handler.fail_forward = false;
+    conn.handle_request(
+        &mut server,
+        key_event(im, first, ForwardEventFlag::SYNCHRONOUS),
+        &mut handler,
+    )
+    .unwrap();
+    assert!(matches!(
+        server.take()[..],
+        [Request::ForwardEvent { .. }, Request::SyncReply { .. }]
+    ));
+    assert_eq!(get_ic(&mut conn, im, first).unanswered_syncs, 1);
+}
+
+#[test]
+fn preedit_caret() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+
+    server
+        .preedit_caret(
+            get_ic(&mut conn, im, ic),
+            3,
+            CaretDirection::AbsolutePosition,
+            CaretStyle::Secondary,
+        )
+        .unwrap();
+
+    let req = server.take().pop().unwrap();
+    assert_eq!(
+        req,
+        Request::PreeditCaret {
+            input_method_id: im,
+            input_context_id: ic,
+            position: 3,
+            direction: CaretDirection::AbsolutePosition,
+            style: CaretStyle::Secondary,
+        }
+    );
+    let mut out = vec![0; req.size()];
+    xim_parser::write_with_endian(&req, &mut out, Endian::Little);
+    assert_eq!(
+        out,
+        [
+            76, 0, 4, 0, // header
+            1, 0, 1, 0, // ids
+            3, 0, 0, 0, // position
+            10, 0, 0, 0, // direction
+            2, 0, 0, 0, // style
+        ]
+    );
+
+    conn.handle_request(
+        &mut server,
+        Request::PreeditCaretReply {
+            input_method_id: im,
+            input_context_id: ic,
+            position: 3,
+        },
+        &mut handler,
+    )
+    .unwrap();
+    assert_eq!(handler.carets, [3]);
+}
+
+#[test]
+fn ic_data_sees_attributes() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let style = InputStyle::PREEDIT_CALLBACKS | InputStyle::STATUS_CALLBACKS;
+    create_ic(
+        &mut conn,
+        &mut server,
+        &mut handler,
+        im,
+        vec![style_attr(style)],
+    );
+
+    assert_eq!(handler.ic_data, [("en_US".to_string(), style)]);
+    assert_eq!(handler.created, [style]);
+}
+
+fn ic_locale_extension() -> Extension {
+    Extension {
+        major_opcode: 131,
+        minor_opcode: 0,
+        name: IC_LOCALE_EXTENSION.to_string(),
+    }
+}
+
+#[test]
+fn ic_locale_attr_opt_in() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler::default();
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let mut advertised = Vec::new();
+    for extensions in [Vec::new(), vec![ic_locale_extension()]] {
+        handler.extensions = extensions;
+        conn.handle_request(
+            &mut server,
+            Request::Open {
+                locale: "en_US".to_string(),
+            },
+            &mut handler,
+        )
+        .unwrap();
+        match server.take().pop() {
+            Some(Request::OpenReply { ic_attrs, .. }) => {
+                advertised.push(ic_attrs.contains(&attrs::LOCALE))
+            }
+            other => panic!("Unexpected reply: {:?}", other),
+        }
+    }
+
+    assert_eq!(advertised, [false, true]);
+}
+
+#[test]
+fn ic_locale_override() {
+    let mut server = MockServer::default();
+    let mut handler = MockHandler {
+        extensions: vec![ic_locale_extension()],
+        ..Default::default()
+    };
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    let im = open_im(&mut conn, &mut server, &mut handler);
+    let ic = create_ic(
+        &mut conn,
+        &mut server,
+        &mut handler,
+        im,
+        vec![Attribute {
+            id: attrs::LOCALE.id,
+            value: b"ko_KR\0".to_vec(),
+        }],
+    );
+
+    assert_eq!(get_ic(&mut conn, im, ic).locale(), "ko_KR");
+    assert_eq!(
+        conn.input_methods.get_item(im).unwrap().clone_locale(),
+        "en_US"
+    );
+}
+
+#[cfg(all(feature = "tracing", feature = "std"))]
+#[test]
+fn tracing_request_spans() {
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    #[derive(Default)]
+    struct Fields(Vec<(&'static str, String)>);
+
+    impl Visit for Fields {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.push((field.name(), value.to_string()));
+        }
+
+        fn record_u64(&mut self, field: &Field, value: u64) {
+            self.0.push((field.name(), value.to_string()));
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn core::fmt::Debug) {
+            self.0.push((field.name(), alloc::format!("{:?}", value)));
+        }
+    }
+
+    #[derive(Default)]
+    struct State {
+        spans: Vec<(&'static str, Fields)>,
+        stack: Vec<usize>,
+        // the innermost span each event was emitted in
+        events: Vec<Option<usize>>,
+    }
+
+    struct Recorder(Arc<Mutex<State>>);
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            let mut state = self.0.lock().unwrap();
+            state.spans.push((attrs.metadata().name(), fields));
+            Id::from_u64(state.spans.len() as u64)
+        }
+
+        fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, _event: &Event<'_>) {
+            let mut state = self.0.lock().unwrap();
+            let span = state.stack.last().copied();
+            state.events.push(span);
+        }
+
+        fn enter(&self, span: &Id) {
+            self.0
+                .lock()
+                .unwrap()
+                .stack
+                .push(span.into_u64() as usize - 1);
+        }
+
+        fn exit(&self, _span: &Id) {
+            self.0.lock().unwrap().stack.pop();
+        }
+    }
+
+    let state = Arc::new(Mutex::new(State::default()));
+
+    tracing::subscriber::with_default(Recorder(state.clone()), || {
+        let mut server = MockServer::default();
+        let mut handler = MockHandler::default();
+        let mut conn = XimConnection::new(CLIENT_WIN);
+
+        let im = open_im(&mut conn, &mut server, &mut handler);
+        let ic = create_ic(&mut conn, &mut server, &mut handler, im, Vec::new());
+        conn.handle_request(
+            &mut server,
+            key_event(im, ic, ForwardEventFlag::empty()),
+            &mut handler,
+        )
+        .unwrap();
+    });
+
+    let state = state.lock().unwrap();
+    assert_eq!(state.spans[0].0, "xim_connection");
+    assert_eq!(
+        state.spans[0].1 .0,
+        [("client_win", CLIENT_WIN.to_string())]
+    );
+
+    // every dispatch event is in the span of its request
+    let spans = state
+        .events
+        .iter()
+        .map(|span| &state.spans[span.unwrap()])
+        .collect::<Vec<_>>();
+    assert_eq!(spans.len(), 3);
+    assert!(spans.iter().all(|(name, _)| *name == "xim_request"));
+    assert_eq!(
+        spans[2].1 .0,
+        [
+            ("request", "ForwardEvent".to_string()),
+            ("sequence", "3".to_string()),
+            ("input_method_id", "1".to_string()),
+            ("input_context_id", "1".to_string()),
+        ]
+    );
+}
@@ -0,0 +1,171 @@
+use alloc::vec::Vec;
+
+/// The byte stream of XIM messages to and from the windows of the other side, under the
+/// requests carried on it.
+///
+/// A layer like logging or throttling is a transport wrapping another one and forwarding to
+/// it, so layers stack in the order they wrap each other. [`Inspect`] is such a layer for
+/// observing the bytes:
+///
+/// ```
+/// use xim::{Direction, Inspect, Transport};
+///
+/// fn logged<T: Transport>(inner: T) -> impl Transport<Error = T::Error> {
+///     Inspect::wrap(inner, |direction, win, bytes: &[u8]| {
+///         log::trace!("{:?} {} bytes with {}", direction, bytes.len(), win)
+///     })
+/// }
+/// ```
+///
+/// The x11rb server sends and receives through one, see `X11rbServer::map_transport`.
+pub trait Transport {
+    type Error;
+
+    /// Send `data`, one or more serialized requests, to `win`.
+    fn send(&mut self, win: u32, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Receive the next whole message from `win`, `None` when there is none waiting.
+    fn recv(&mut self, win: u32) -> Result<Option<Vec<u8>>, Self::Error>;
+}
+
+impl<T: Transport + ?Sized> Transport for &mut T {
+    type Error = T::Error;
+
+    fn send(&mut self, win: u32, data: &[u8]) -> Result<(), Self::Error> {
+        (**self).send(win, data)
+    }
+
+    fn recv(&mut self, win: u32) -> Result<Option<Vec<u8>>, Self::Error> {
+        (**self).recv(win)
+    }
+}
+
+/// Which way bytes seen by [`Inspect`] go.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    Send,
+    Recv,
+}
+
+/// A [`Transport`] passing the bytes sent and received through `inner` to a callback.
+///
+/// Bytes are seen before they are sent and after they are received, whether or not `inner`
+/// then fails to send them.
+#[derive(Clone, Debug)]
+pub struct Inspect<T, F> {
+    inner: T,
+    f: F,
+}
+
+impl<T, F> Inspect<T, F>
+where
+    T: Transport,
+    F: FnMut(Direction, u32, &[u8]),
+{
+    pub fn wrap(inner: T, f: F) -> Self {
+        Self { inner, f }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T, F> Transport for Inspect<T, F>
+where
+    T: Transport,
+    F: FnMut(Direction, u32, &[u8]),
+{
+    type Error = T::Error;
+
+    fn send(&mut self, win: u32, data: &[u8]) -> Result<(), Self::Error> {
+        (self.f)(Direction::Send, win, data);
+        self.inner.send(win, data)
+    }
+
+    fn recv(&mut self, win: u32) -> Result<Option<Vec<u8>>, Self::Error> {
+        let data = self.inner.recv(win)?;
+        if let Some(data) = &data {
+            (self.f)(Direction::Recv, win, data);
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Direction, Inspect, Transport};
+    use alloc::{collections::VecDeque, vec, vec::Vec};
+
+    /// Keeps what is sent, and hands out queued messages.
+    #[derive(Default)]
+    struct MockTransport {
+        sent: Vec<(u32, Vec<u8>)>,
+        incoming: VecDeque<Vec<u8>>,
+    }
+
+    impl Transport for MockTransport {
+        type Error = ();
+
+        fn send(&mut self, win: u32, data: &[u8]) -> Result<(), ()> {
+            self.sent.push((win, data.to_vec()));
+            Ok(())
+        }
+
+        fn recv(&mut self, _win: u32) -> Result<Option<Vec<u8>>, ()> {
+            Ok(self.incoming.pop_front())
+        }
+    }
+
+    #[test]
+    fn inspect_sees_all_bytes() {
+        let mut seen = Vec::new();
+        let mut inner = MockTransport::default();
+        inner.incoming.push_back(vec![5, 6, 7, 8]);
+
+        let mut transport = Inspect::wrap(&mut inner, |direction, win, bytes: &[u8]| {
+            seen.push((direction, win, bytes.to_vec()))
+        });
+        transport.send(10, &[1, 2, 3, 4]).unwrap();
+        transport.send(10, &[]).unwrap();
+        assert_eq!(transport.recv(10).unwrap(), Some(vec![5, 6, 7, 8]));
+        assert_eq!(transport.recv(10).unwrap(), None);
+
+        assert_eq!(
+            seen,
+            [
+                (Direction::Send, 10, vec![1, 2, 3, 4]),
+                (Direction::Send, 10, vec![]),
+                (Direction::Recv, 10, vec![5, 6, 7, 8]),
+            ]
+        );
+        assert_eq!(inner.sent, [(10, vec![1, 2, 3, 4]), (10, vec![])]);
+    }
+
+    #[test]
+    fn inspect_layers() {
+        let mut order = Vec::new();
+        let mut inner = MockTransport::default();
+
+        {
+            let log = core::cell::RefCell::new(&mut order);
+            let mut transport = Inspect::wrap(
+                Inspect::wrap(&mut inner, |_, _, _: &[u8]| log.borrow_mut().push("inner")),
+                |_, _, _: &[u8]| log.borrow_mut().push("outer"),
+            );
+            transport.send(10, &[1, 2, 3, 4]).unwrap();
+        }
+
+        // the outermost layer sees the bytes first
+        assert_eq!(order, ["outer", "inner"]);
+        assert_eq!(inner.sent.len(), 1);
+    }
+}
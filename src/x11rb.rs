@@ -5,10 +5,19 @@
 //!
 //! [`x11rb`]: https://crates.io/crates/x11rb
 
+#[cfg(feature = "x11rb-server")]
+use alloc::collections::VecDeque;
 use alloc::format;
+#[cfg(feature = "x11rb-client")]
 use alloc::string::String;
 use alloc::vec::Vec;
-use std::{convert::TryInto, rc::Rc, sync::Arc};
+#[cfg(feature = "x11rb-server")]
+use core::marker::PhantomData;
+#[cfg(feature = "x11rb-server")]
+use core::time::Duration;
+#[cfg(feature = "x11rb-server")]
+use std::time::Instant;
+use std::{rc::Rc, sync::Arc};
 use x11rb::protocol::xproto::EventMask;
 
 #[cfg(feature = "x11rb-client")]
@@ -16,10 +25,17 @@ use crate::client::{
     handle_request as client_handle_request, ClientCore, ClientError, ClientHandler,
 };
 #[cfg(feature = "x11rb-server")]
-use crate::server::{ServerCore, ServerError, ServerHandler, XimConnection, XimConnections};
-#[cfg(feature = "x11rb-client")]
+use crate::encode_supported_locales;
+#[cfg(feature = "x11rb-server")]
+use crate::server::{
+    DisconnectReason, ServerCore, ServerError, ServerHandler, XimConnection, XimConnections,
+};
 use crate::AHashMap;
 #[cfg(feature = "x11rb-client")]
+use crate::LocaleModifiers;
+#[cfg(feature = "x11rb-server")]
+use crate::{Direction, Inspect, Transport};
+#[cfg(feature = "x11rb-client")]
 use xim_parser::{Attr, AttributeName};
 
 use crate::Atoms;
@@ -33,9 +49,9 @@ use x11rb::{
     errors::{ConnectError, ConnectionError, ParseError, ReplyError, ReplyOrIdError},
     protocol::{
         xproto::{
-            Atom, AtomEnum, ClientMessageEvent, ConnectionExt, KeyPressEvent, PropMode, Screen,
-            SelectionNotifyEvent, SelectionRequestEvent, Window, WindowClass, CLIENT_MESSAGE_EVENT,
-            SELECTION_NOTIFY_EVENT,
+            Atom, AtomEnum, ChangeWindowAttributesAux, ClientMessageEvent, ConnectionExt,
+            KeyPressEvent, PropMode, Screen, SelectionNotifyEvent, SelectionRequestEvent, Window,
+            WindowClass, CLIENT_MESSAGE_EVENT, SELECTION_NOTIFY_EVENT,
         },
         Event,
     },
@@ -44,7 +60,10 @@ use x11rb::{
     COPY_DEPTH_FROM_PARENT, CURRENT_TIME,
 };
 
-use xim_parser::{Request, XimWrite};
+use xim_parser::{Endian, Request, XimWrite};
+
+#[cfg(all(test, feature = "x11rb-server"))]
+mod tests;
 
 macro_rules! convert_error {
     ($($ty:ty,)+) => {
@@ -110,7 +129,7 @@ impl<C: HasConnection> HasConnection for X11rbClient<C> {
 }
 
 #[cfg(feature = "x11rb-server")]
-impl<C: HasConnection> HasConnection for X11rbServer<C> {
+impl<C: HasConnection> HasConnection for X11rbTransport<C> {
     type Connection = C::Connection;
 
     #[inline(always)]
@@ -119,6 +138,16 @@ impl<C: HasConnection> HasConnection for X11rbServer<C> {
     }
 }
 
+#[cfg(feature = "x11rb-server")]
+impl<C: HasConnection, L: HasX11rbTransport<C>> HasConnection for X11rbServer<C, L> {
+    type Connection = C::Connection;
+
+    #[inline(always)]
+    fn conn(&self) -> &Self::Connection {
+        self.transport.base().conn()
+    }
+}
+
 impl<'x, C: HasConnection> HasConnection for &'x C {
     type Connection = C::Connection;
 
@@ -146,14 +175,121 @@ impl<C: HasConnection> HasConnection for Arc<C> {
     }
 }
 
+/// The XIM messages of an [`X11rbServer`] as `ClientMessage` events and window properties, the
+/// innermost of its [`Transport`] layers.
 #[cfg(feature = "x11rb-server")]
-pub struct X11rbServer<C: HasConnection> {
+pub struct X11rbTransport<C: HasConnection> {
     has_conn: C,
-    locale_data: String,
+    protocol_atom: Atom,
+    sequence: u16,
+    received: VecDeque<ClientMessageEvent>,
+}
+
+#[cfg(feature = "x11rb-server")]
+impl<C: HasConnection> X11rbTransport<C> {
+    fn new(has_conn: C, protocol_atom: Atom) -> Self {
+        Self {
+            has_conn,
+            protocol_atom,
+            sequence: 0,
+            received: VecDeque::new(),
+        }
+    }
+
+    /// Keep a message of the client until [`Transport::recv`] reads it.
+    fn receive(&mut self, msg: &ClientMessageEvent) {
+        self.received.push_back(*msg);
+    }
+}
+
+#[cfg(feature = "x11rb-server")]
+impl<C: HasConnection> Transport for X11rbTransport<C> {
+    type Error = ServerError;
+
+    fn send(&mut self, win: u32, data: &[u8]) -> Result<(), ServerError> {
+        send_bytes_impl(
+            &self.has_conn,
+            self.protocol_atom,
+            win,
+            data,
+            &mut self.sequence,
+            20,
+        )
+    }
+
+    fn recv(&mut self, win: u32) -> Result<Option<Vec<u8>>, ServerError> {
+        let msg = match self.received.iter().position(|msg| msg.window == win) {
+            Some(pos) => self.received.remove(pos).unwrap(),
+            None => return Ok(None),
+        };
+
+        let data = if msg.format == 32 {
+            let [length, atom, ..] = msg.data.as_data32();
+            self.has_conn
+                .conn()
+                .get_property(true, msg.window, atom, AtomEnum::ANY, 0, length)?
+                .reply()?
+                .value
+        } else {
+            msg.data.as_data8().to_vec()
+        };
+
+        Ok(Some(data))
+    }
+}
+
+/// A [`Transport`] with an [`X11rbTransport`] innermost, see [`X11rbServer::map_transport`].
+#[cfg(feature = "x11rb-server")]
+pub trait HasX11rbTransport<C: HasConnection>: Transport<Error = ServerError> {
+    fn base(&self) -> &X11rbTransport<C>;
+
+    fn base_mut(&mut self) -> &mut X11rbTransport<C>;
+}
+
+#[cfg(feature = "x11rb-server")]
+impl<C: HasConnection> HasX11rbTransport<C> for X11rbTransport<C> {
+    #[inline(always)]
+    fn base(&self) -> &X11rbTransport<C> {
+        self
+    }
+
+    #[inline(always)]
+    fn base_mut(&mut self) -> &mut X11rbTransport<C> {
+        self
+    }
+}
+
+#[cfg(feature = "x11rb-server")]
+impl<C, T, F> HasX11rbTransport<C> for Inspect<T, F>
+where
+    C: HasConnection,
+    T: HasX11rbTransport<C>,
+    F: FnMut(Direction, u32, &[u8]),
+{
+    #[inline(always)]
+    fn base(&self) -> &X11rbTransport<C> {
+        self.inner().base()
+    }
+
+    #[inline(always)]
+    fn base_mut(&mut self) -> &mut X11rbTransport<C> {
+        self.inner_mut().base_mut()
+    }
+}
+
+#[cfg(feature = "x11rb-server")]
+pub struct X11rbServer<C: HasConnection, L = X11rbTransport<C>> {
+    transport: L,
+    locale_data: Vec<u8>,
     im_win: Window,
     atoms: Atoms<Atom>,
     buf: Vec<u8>,
-    sequence: u16,
+    endians: AHashMap<u32, Endian>,
+    started: Instant,
+    reply_timeout: Option<Duration>,
+    /// Client windows a send failed for, disconnected at the end of `filter_event`.
+    failed: Vec<u32>,
+    has_conn: PhantomData<C>,
 }
 
 #[cfg(feature = "x11rb-server")]
@@ -164,7 +300,7 @@ impl<C: HasConnection> X11rbServer<C> {
         im_name: &str,
         locales: &str,
     ) -> Result<Self, ServerError> {
-        let im_name = format!("@server={}", im_name);
+        let im_name = crate::server::server_atom_name(im_name)?;
         let conn = has_conn.conn();
         let screen = &conn.setup().roots[screen_num];
         let im_win = conn.generate_id()?;
@@ -231,20 +367,137 @@ impl<C: HasConnection> X11rbServer<C> {
         log::info!("Start server win: {}", im_win);
 
         Ok(Self {
-            has_conn,
-            locale_data: format!("@locale={}", locales),
+            transport: X11rbTransport::new(has_conn, atoms.XIM_PROTOCOL),
+            locale_data: encode_supported_locales(&locales.split(',').collect::<Vec<_>>()),
             im_win,
             atoms,
             buf: Vec::with_capacity(1024),
-            sequence: 0,
+            endians: AHashMap::with_hasher(Default::default()),
+            started: Instant::now(),
+            reply_timeout: Some(Duration::from_secs(5)),
+            failed: Vec::new(),
+            has_conn: PhantomData,
         })
     }
+}
+
+#[cfg(feature = "x11rb-server")]
+impl<C: HasConnection, L: HasX11rbTransport<C>> X11rbServer<C, L> {
+    /// Put layers around the transport of the XIM messages, like [`Inspect`] to see the bytes
+    /// sent and received:
+    ///
+    /// ```no_run
+    /// use xim::{x11rb::X11rbServer, Direction, Inspect};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let (conn, screen_num) = x11rb::rust_connection::RustConnection::connect(None)?;
+    /// let server = X11rbServer::init(&conn, screen_num, "logged", xim::ALL_LOCALES)?
+    ///     .map_transport(|transport| {
+    ///         Inspect::wrap(transport, |direction, win, bytes: &[u8]| {
+    ///             log::trace!("{:?} {} bytes with {}", direction, bytes.len(), win)
+    ///         })
+    ///     });
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn map_transport<M: HasX11rbTransport<C>>(
+        self,
+        f: impl FnOnce(L) -> M,
+    ) -> X11rbServer<C, M> {
+        X11rbServer {
+            transport: f(self.transport),
+            locale_data: self.locale_data,
+            im_win: self.im_win,
+            atoms: self.atoms,
+            buf: self.buf,
+            endians: self.endians,
+            started: self.started,
+            reply_timeout: self.reply_timeout,
+            failed: self.failed,
+            has_conn: PhantomData,
+        }
+    }
+
+    /// How long the client has to answer `PreeditStart` and `Sync` before
+    /// [`ServerHandler::handle_pending_timeout`] is called, `None` to wait forever. Defaults to
+    /// 5 seconds.
+    pub fn set_reply_timeout(&mut self, timeout: Option<Duration>) {
+        self.reply_timeout = timeout;
+    }
 
+    /// Handle `e` if it's part of XIM, returns `false` if it isn't.
+    ///
+    /// The events a connection queues with [`XimConnection::set_batch_forward_events`] are
+    /// sent before it returns. Every call also gives up the replies that are overdue, see
+    /// [`set_reply_timeout`](Self::set_reply_timeout).
+    ///
+    /// A connection is disconnected with [`DisconnectReason::TransportError`] once a message
+    /// can't be sent to its client, and with [`DisconnectReason::WindowDestroyed`] when the
+    /// window of its client is destroyed.
     pub fn filter_event<T>(
         &mut self,
         e: &Event,
         connections: &mut XimConnections<T>,
         handler: &mut impl ServerHandler<Self, InputContextData = T>,
+    ) -> Result<bool, ServerError> {
+        let filtered = self.dispatch_event(e, connections, handler);
+        let expired = connections.expire_pending(self, handler, self.started.elapsed());
+        let disconnected = self.disconnect_failed(connections, handler);
+        let filtered = filtered?;
+        expired?;
+        disconnected?;
+        Ok(filtered)
+    }
+
+    fn disconnect_failed<T>(
+        &mut self,
+        connections: &mut XimConnections<T>,
+        handler: &mut impl ServerHandler<Self, InputContextData = T>,
+    ) -> Result<(), ServerError> {
+        let mut ret = Ok(());
+        for client_win in core::mem::take(&mut self.failed) {
+            let disconnected = self.disconnect_client(
+                client_win,
+                connections,
+                handler,
+                DisconnectReason::TransportError,
+            );
+            if let Err(e) = disconnected {
+                ret = ret.and(Err(e));
+            }
+        }
+        ret
+    }
+
+    /// Remove the connection of `client_win`, returns `false` if there isn't one.
+    fn disconnect_client<T>(
+        &mut self,
+        client_win: u32,
+        connections: &mut XimConnections<T>,
+        handler: &mut impl ServerHandler<Self, InputContextData = T>,
+        reason: DisconnectReason,
+    ) -> Result<bool, ServerError> {
+        let com_win = connections
+            .connections
+            .iter()
+            .find(|(_, connection)| connection.client_win == client_win)
+            .map(|(com_win, _)| *com_win);
+        let mut connection =
+            match com_win.and_then(|com_win| connections.remove_connection(com_win)) {
+                Some(connection) => connection,
+                None => return Ok(false),
+            };
+        log::info!("Disconnect {} ({:?})", client_win, reason);
+        self.endians.remove(&client_win);
+        connection.disconnect(self, handler, reason)?;
+        Ok(true)
+    }
+
+    fn dispatch_event<T>(
+        &mut self,
+        e: &Event,
+        connections: &mut XimConnections<T>,
+        handler: &mut impl ServerHandler<Self, InputContextData = T>,
     ) -> Result<bool, ServerError> {
         match e {
             Event::SelectionRequest(req) if req.owner == self.im_win => {
@@ -253,7 +506,7 @@ impl<C: HasConnection> X11rbServer<C> {
                     self.send_selection_notify(req, &self.locale_data)?;
                 } else if req.property == self.atoms.TRANSPORT {
                     log::trace!("Selection notify transport");
-                    self.send_selection_notify(req, "@transport=X/")?;
+                    self.send_selection_notify(req, b"@transport=X/")?;
                 }
                 Ok(true)
             }
@@ -275,6 +528,11 @@ impl<C: HasConnection> X11rbServer<C> {
                     )?;
                     let client_win = msg.data.as_data32()[0];
                     log::info!("XConnected with {}", client_win);
+                    // for the DestroyNotify of the client
+                    self.conn().change_window_attributes(
+                        client_win,
+                        &ChangeWindowAttributesAux::new().event_mask(EventMask::STRUCTURE_NOTIFY),
+                    )?;
                     self.conn().send_event(
                         false,
                         client_win,
@@ -290,10 +548,16 @@ impl<C: HasConnection> X11rbServer<C> {
                     )?;
                     self.conn().flush()?;
                     connections.new_connection(com_win, client_win);
-                } else if msg.type_ == self.atoms.XIM_PROTOCOL {
+                } else if msg.type_ == self.atoms.XIM_PROTOCOL
+                    || msg.type_ == self.atoms.XIM_MOREDATA
+                {
                     if let Some(connection) = connections.get_connection(msg.window) {
-                        self.handle_xim_protocol(msg, connection, handler)?;
+                        let ret = self.handle_xim_protocol(msg, connection, handler);
+                        // queued events don't wait for the next message of the client
+                        let flushed = connection.flush_forward_events(self);
+                        ret.and(flushed)?;
                         if connection.disconnected {
+                            self.endians.remove(&connection.client_win);
                             connections.remove_connection(msg.window);
                         }
                     } else {
@@ -303,6 +567,12 @@ impl<C: HasConnection> X11rbServer<C> {
 
                 Ok(true)
             }
+            Event::DestroyNotify(e) => self.disconnect_client(
+                e.window,
+                connections,
+                handler,
+                DisconnectReason::WindowDestroyed,
+            ),
             _ => Ok(false),
         }
     }
@@ -313,25 +583,51 @@ impl<C: HasConnection> X11rbServer<C> {
         connection: &mut XimConnection<T>,
         handler: &mut impl ServerHandler<Self, InputContextData = T>,
     ) -> Result<(), ServerError> {
+        let more = msg.type_ == self.atoms.XIM_MOREDATA;
         if msg.format == 32 {
             let [length, atom, ..] = msg.data.as_data32();
-            let data = self
-                .conn()
-                .get_property(true, msg.window, atom, AtomEnum::ANY, 0, length)?
-                .reply()?
-                .value;
-            let req = xim_parser::read(&data)?;
-            connection.handle_request(self, req, handler)
-        } else {
-            let req = xim_parser::read(&msg.data.as_data8())?;
-            connection.handle_request(self, req, handler)
+            // don't let the client make us read a property of any size
+            if length as usize > connection.max_request_bytes() {
+                self.conn().delete_property(msg.window, atom)?;
+                return connection.reject_request(self, more);
+            }
+        }
+
+        self.transport.base_mut().receive(msg);
+        let data = match self.transport.recv(msg.window)? {
+            Some(data) => data,
+            None => return Ok(()),
+        };
+
+        let data = match connection.reassemble_request(self, &data, more)? {
+            Some(data) => data,
+            None => return Ok(()),
+        };
+        if connection.handle_extension_request(self, &data, handler)? {
+            return Ok(());
+        }
+        let req = connection.read_request(&data)?;
+        self.handle_client_request(connection, req, handler)
+    }
+
+    fn handle_client_request<T>(
+        &mut self,
+        connection: &mut XimConnection<T>,
+        req: Request,
+        handler: &mut impl ServerHandler<Self, InputContextData = T>,
+    ) -> Result<(), ServerError> {
+        // replies must be written in the byte order of the client from `ConnectReply` on
+        if let Request::Connect { endian, .. } = req {
+            self.endians.insert(connection.client_win, endian);
         }
+
+        connection.handle_request(self, req, handler)
     }
 
     fn send_selection_notify(
         &self,
         req: &SelectionRequestEvent,
-        data: &str,
+        data: &[u8],
     ) -> Result<(), ServerError> {
         let e = SelectionNotifyEvent {
             response_type: SELECTION_NOTIFY_EVENT,
@@ -348,7 +644,7 @@ impl<C: HasConnection> X11rbServer<C> {
             req.requestor,
             req.property,
             req.target,
-            data.as_bytes(),
+            data,
         )?;
         self.conn()
             .send_event(false, req.requestor, EventMask::NO_EVENT, e)?;
@@ -359,25 +655,46 @@ impl<C: HasConnection> X11rbServer<C> {
 }
 
 #[cfg(feature = "x11rb-server")]
-impl<C: HasConnection> ServerCore for X11rbServer<C> {
+impl<C: HasConnection, L: HasX11rbTransport<C>> ServerCore for X11rbServer<C, L> {
     type XEvent = KeyPressEvent;
 
     fn send_req(&mut self, client_win: u32, req: Request) -> Result<(), ServerError> {
-        send_req_impl(
-            &self.has_conn,
-            &self.atoms,
-            client_win,
-            &mut self.buf,
-            &mut self.sequence,
-            20,
-            &req,
-        )
+        self.send_reqs(client_win, core::slice::from_ref(&req))
+    }
+
+    fn send_reqs(&mut self, client_win: u32, reqs: &[Request]) -> Result<(), ServerError> {
+        let endian = self
+            .endians
+            .get(&client_win)
+            .copied()
+            .unwrap_or(Endian::Native);
+        write_reqs_impl(&mut self.buf, endian, reqs)?;
+        let ret = self.transport.send(client_win, &self.buf);
+        self.buf.clear();
+        if ret.is_err() && !self.failed.contains(&client_win) {
+            self.failed.push(client_win);
+        }
+        ret
+    }
+
+    #[inline]
+    fn serialize_event(&self, ev: &Self::XEvent) -> Result<xim_parser::XEvent, ServerError> {
+        Ok(serialize_event_impl(ev))
     }
 
     #[inline]
     fn deserialize_event(&self, ev: &xim_parser::XEvent) -> Self::XEvent {
         deserialize_event_impl(ev)
     }
+
+    fn window_size(&mut self, win: u32) -> Option<(u16, u16)> {
+        let geometry = self.conn().get_geometry(win).ok()?.reply().ok()?;
+        Some((geometry.width, geometry.height))
+    }
+
+    fn reply_deadline(&self) -> Option<Duration> {
+        Some(self.started.elapsed() + self.reply_timeout?)
+    }
 }
 
 #[cfg(feature = "x11rb-client")]
@@ -421,7 +738,7 @@ impl<C: HasConnection> X11rbClient<C> {
         )?;
 
         let var = std::env::var("XMODIFIERS").ok();
-        let var = var.as_ref().and_then(|n| n.strip_prefix("@im="));
+        let var = var.as_deref().and_then(|n| LocaleModifiers::parse(n).im);
         let im_name = im_name.or(var).ok_or(ClientError::NoXimServer)?;
 
         log::info!("Try connect {}", im_name);
@@ -452,7 +769,7 @@ impl<C: HasConnection> X11rbClient<C> {
                     _ => continue,
                 };
 
-                if let Some(name) = name.strip_prefix("@server=") {
+                if let Some(name) = LocaleModifiers::parse(&name).server {
                     if name == im_name {
                         conn.convert_selection(
                             client_window,
@@ -640,21 +957,7 @@ impl<C: HasConnection> ClientCore for X11rbClient<C> {
 
     #[inline]
     fn serialize_event(&self, xev: &Self::XEvent) -> xim_parser::XEvent {
-        xim_parser::XEvent {
-            response_type: xev.response_type,
-            detail: xev.detail,
-            sequence: xev.sequence,
-            time: xev.time,
-            root: xev.root,
-            event: xev.event,
-            child: xev.child,
-            root_x: xev.root_x,
-            root_y: xev.root_y,
-            event_x: xev.event_x,
-            event_y: xev.event_y,
-            state: xev.state.into(),
-            same_screen: xev.same_screen,
-        }
+        serialize_event_impl(xev)
     }
 
     #[inline]
@@ -664,41 +967,57 @@ impl<C: HasConnection> ClientCore for X11rbClient<C> {
 
     #[inline]
     fn send_req(&mut self, req: Request) -> Result<(), ClientError> {
-        send_req_impl(
+        write_reqs_impl(&mut self.buf, Endian::Native, core::slice::from_ref(&req))?;
+        let ret = send_bytes_impl(
             &self.has_conn,
-            &self.atoms,
+            self.atoms.XIM_PROTOCOL,
             self.im_window,
-            &mut self.buf,
+            &self.buf,
             &mut self.sequence,
             self.transport_max,
-            &req,
-        )
+        );
+        self.buf.clear();
+        ret
     }
 }
 
-fn send_req_impl<C: HasConnection, E: From<ConnectionError> + From<ReplyError>>(
+fn write_reqs_impl(
+    buf: &mut Vec<u8>,
+    endian: Endian,
+    reqs: &[Request],
+) -> Result<(), xim_parser::ReadError> {
+    buf.clear();
+
+    for req in reqs {
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!("->: {:?}", req);
+        } else {
+            log::debug!("->: {}", req.name());
+        }
+        // a request too long for its length field isn't written at all
+        req.validate()?;
+        let from = buf.len();
+        buf.resize(from + req.size(), 0);
+        xim_parser::write_with_endian(req, &mut buf[from..], endian);
+    }
+
+    Ok(())
+}
+
+fn send_bytes_impl<C: HasConnection, E: From<ConnectionError> + From<ReplyError>>(
     c: &C,
-    atoms: &Atoms<Atom>,
+    protocol_atom: Atom,
     target: Window,
-    buf: &mut Vec<u8>,
+    data: &[u8],
     sequence: &mut u16,
     transport_max: usize,
-    req: &Request,
 ) -> Result<(), E> {
-    if log::log_enabled!(log::Level::Trace) {
-        log::trace!("->: {:?}", req);
-    } else {
-        log::debug!("->: {}", req.name());
-    }
-    buf.resize(req.size(), 0);
-    xim_parser::write(req, buf);
-
-    if buf.len() < transport_max {
-        if buf.len() > 20 {
+    if data.len() < transport_max {
+        if data.len() > 20 {
             todo!("multi-CM");
         }
-        buf.resize(20, 0);
-        let buf: [u8; 20] = buf.as_slice().try_into().unwrap();
+        let mut buf = [0; 20];
+        buf[..data.len()].copy_from_slice(data);
         c.conn().send_event(
             false,
             target,
@@ -708,7 +1027,7 @@ fn send_req_impl<C: HasConnection, E: From<ConnectionError> + From<ReplyError>>(
                 data: buf.into(),
                 format: 8,
                 sequence: 0,
-                type_: atoms.XIM_PROTOCOL,
+                type_: protocol_atom,
                 window: target,
             },
         )?;
@@ -725,28 +1044,46 @@ fn send_req_impl<C: HasConnection, E: From<ConnectionError> + From<ReplyError>>(
             prop,
             AtomEnum::STRING,
             8,
-            buf.len() as u32,
-            buf,
+            data.len() as u32,
+            data,
         )?;
         c.conn().send_event(
             false,
             target,
             EventMask::NO_EVENT,
             ClientMessageEvent {
-                data: [buf.len() as u32, prop, 0, 0, 0].into(),
+                data: [data.len() as u32, prop, 0, 0, 0].into(),
                 format: 32,
                 sequence: 0,
                 response_type: CLIENT_MESSAGE_EVENT,
-                type_: atoms.XIM_PROTOCOL,
+                type_: protocol_atom,
                 window: target,
             },
         )?;
     }
-    buf.clear();
     c.conn().flush()?;
     Ok(())
 }
 
+#[inline]
+fn serialize_event_impl(xev: &KeyPressEvent) -> xim_parser::XEvent {
+    xim_parser::XEvent {
+        response_type: xev.response_type,
+        detail: xev.detail,
+        sequence: xev.sequence,
+        time: xev.time,
+        root: xev.root,
+        event: xev.event,
+        child: xev.child,
+        root_x: xev.root_x,
+        root_y: xev.root_y,
+        event_x: xev.event_x,
+        event_y: xev.event_y,
+        state: xev.state.into(),
+        same_screen: xev.same_screen,
+    }
+}
+
 #[inline]
 fn deserialize_event_impl(xev: &xim_parser::XEvent) -> KeyPressEvent {
     KeyPressEvent {
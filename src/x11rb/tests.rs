@@ -0,0 +1,481 @@
+use super::*;
+use crate::server::{ConnectInfo, DisconnectReason, PendingOperation, Server, UserInputContext};
+use alloc::string::String;
+use core::cell::Cell;
+use x11rb::{
+    protocol::xproto::{DestroyNotifyEvent, Setup},
+    rust_connection::{PollMode, Stream},
+    utils::RawFdContainer,
+};
+use xim_parser::{ForwardEventFlag, InputStyle, XEvent};
+
+const COM_WIN: u32 = 20;
+const CLIENT_WIN: u32 = 10;
+
+/// An X connection nothing is read from, written bytes are dropped.
+struct FakeStream;
+
+impl Stream for FakeStream {
+    fn poll(&self, _mode: PollMode) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn read(&self, _buf: &mut [u8], _fds: &mut Vec<RawFdContainer>) -> std::io::Result<usize> {
+        Err(std::io::ErrorKind::WouldBlock.into())
+    }
+
+    fn write(&self, buf: &[u8], _fds: &mut Vec<RawFdContainer>) -> std::io::Result<usize> {
+        Ok(buf.len())
+    }
+}
+
+struct FakeConn(RustConnection<FakeStream>);
+
+impl HasConnection for FakeConn {
+    type Connection = RustConnection<FakeStream>;
+
+    fn conn(&self) -> &Self::Connection {
+        &self.0
+    }
+}
+
+/// Keeps what the server sends instead of writing it to the X connection.
+struct Capture<T> {
+    inner: T,
+    sent: Vec<(u32, Vec<u8>)>,
+    /// Fail every send as if the client was gone.
+    fail: bool,
+}
+
+impl<T: Transport<Error = ServerError>> Transport for Capture<T> {
+    type Error = ServerError;
+
+    fn send(&mut self, win: u32, data: &[u8]) -> Result<(), ServerError> {
+        if self.fail {
+            return Err(ServerError::Internal("send failed".into()));
+        }
+        self.sent.push((win, data.to_vec()));
+        Ok(())
+    }
+
+    fn recv(&mut self, win: u32) -> Result<Option<Vec<u8>>, ServerError> {
+        self.inner.recv(win)
+    }
+}
+
+impl<T: HasX11rbTransport<FakeConn>> HasX11rbTransport<FakeConn> for Capture<T> {
+    fn base(&self) -> &X11rbTransport<FakeConn> {
+        self.inner.base()
+    }
+
+    fn base_mut(&mut self) -> &mut X11rbTransport<FakeConn> {
+        self.inner.base_mut()
+    }
+}
+
+type TestServer = X11rbServer<FakeConn, Capture<X11rbTransport<FakeConn>>>;
+
+fn test_server() -> TestServer {
+    let setup = Setup {
+        resource_id_mask: 0xff,
+        ..Default::default()
+    };
+    let conn = RustConnection::for_connected_stream(FakeStream, setup).unwrap();
+    let next_atom = Cell::new(100);
+    let atoms = Atoms::new::<ServerError, _>(|_name| {
+        next_atom.set(next_atom.get() + 1);
+        Ok(next_atom.get())
+    })
+    .unwrap();
+
+    X11rbServer {
+        transport: Capture {
+            inner: X11rbTransport::new(FakeConn(conn), atoms.XIM_PROTOCOL),
+            sent: Vec::new(),
+            fail: false,
+        },
+        locale_data: Vec::new(),
+        im_win: 1,
+        atoms,
+        buf: Vec::new(),
+        endians: AHashMap::with_hasher(Default::default()),
+        started: Instant::now(),
+        reply_timeout: None,
+        failed: Vec::new(),
+        has_conn: PhantomData,
+    }
+}
+
+/// `req` as the client sends it, in `ClientMessage`s of 20 bytes.
+fn client_messages(server: &TestServer, req: Request) -> Vec<Event> {
+    let bytes = xim_parser::write_to_vec(req);
+    let chunks = bytes.chunks(20).count();
+    bytes
+        .chunks(20)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut data = [0; 20];
+            data[..chunk.len()].copy_from_slice(chunk);
+            Event::ClientMessage(ClientMessageEvent {
+                response_type: CLIENT_MESSAGE_EVENT,
+                format: 8,
+                sequence: 0,
+                window: COM_WIN,
+                type_: if i + 1 < chunks {
+                    server.atoms.XIM_MOREDATA
+                } else {
+                    server.atoms.XIM_PROTOCOL
+                },
+                data: data.into(),
+            })
+        })
+        .collect()
+}
+
+fn dispatch(
+    server: &mut TestServer,
+    connections: &mut XimConnections<()>,
+    handler: &mut Handler,
+    req: Request,
+) {
+    for e in client_messages(server, req) {
+        assert!(server.filter_event(&e, connections, handler).unwrap());
+    }
+}
+
+/// The requests sent to the client since the last call.
+fn take_sent(server: &mut TestServer) -> Vec<Request> {
+    let mut reqs = Vec::new();
+    for (win, bytes) in server.transport.sent.drain(..) {
+        assert_eq!(win, CLIENT_WIN);
+        let mut bytes = &bytes[..];
+        while !bytes.is_empty() {
+            let req: Request = xim_parser::read(bytes).unwrap();
+            bytes = &bytes[req.size()..];
+            reqs.push(req);
+        }
+    }
+    reqs
+}
+
+fn connect_request() -> Request {
+    Request::Connect {
+        endian: Endian::Native,
+        client_major_protocol_version: 1,
+        client_minor_protocol_version: 0,
+        client_auth_protocol_names: Vec::new(),
+    }
+}
+
+#[derive(Default)]
+struct Handler {
+    /// Drawn for every key instead of sending it back.
+    preedit: Option<&'static str>,
+    timeouts: Vec<PendingOperation>,
+    disconnect_reasons: Vec<DisconnectReason>,
+}
+
+impl<S: Server> ServerHandler<S> for Handler {
+    type InputStyleArray = [InputStyle; 1];
+    type InputContextData = ();
+
+    fn new_ic_data(&mut self, _server: &mut S, _style: InputStyle) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    fn input_styles(&self) -> Self::InputStyleArray {
+        [InputStyle::PREEDIT_NOTHING | InputStyle::STATUS_NOTHING]
+    }
+
+    fn filter_events(&self) -> u32 {
+        1
+    }
+
+    fn handle_connect(&mut self, _server: &mut S, _info: &ConnectInfo) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    fn handle_create_ic(
+        &mut self,
+        _server: &mut S,
+        _user_ic: &mut UserInputContext<()>,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    fn handle_destroy_ic(
+        &mut self,
+        _server: &mut S,
+        _user_ic: UserInputContext<()>,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    fn handle_reset_ic(
+        &mut self,
+        _server: &mut S,
+        _user_ic: &mut UserInputContext<()>,
+    ) -> Result<String, ServerError> {
+        Ok(String::new())
+    }
+
+    fn handle_set_focus(
+        &mut self,
+        _server: &mut S,
+        _user_ic: &mut UserInputContext<()>,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    fn handle_unset_focus(
+        &mut self,
+        _server: &mut S,
+        _user_ic: &mut UserInputContext<()>,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    fn handle_set_ic_values(
+        &mut self,
+        _server: &mut S,
+        _user_ic: &mut UserInputContext<()>,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    fn handle_forward_event(
+        &mut self,
+        server: &mut S,
+        user_ic: &mut UserInputContext<()>,
+        _xev: &S::XEvent,
+    ) -> Result<bool, ServerError> {
+        match self.preedit {
+            Some(preedit) => {
+                server.preedit_draw(&mut user_ic.ic, preedit)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn handle_pending_timeout(
+        &mut self,
+        _server: &mut S,
+        _user_ic: &mut UserInputContext<()>,
+        operation: PendingOperation,
+    ) -> Result<(), ServerError> {
+        self.timeouts.push(operation);
+        Ok(())
+    }
+
+    fn handle_disconnect_ic(
+        &mut self,
+        _server: &mut S,
+        _user_ic: UserInputContext<()>,
+        reason: DisconnectReason,
+    ) -> Result<(), ServerError> {
+        self.disconnect_reasons.push(reason);
+        Ok(())
+    }
+}
+
+/// Open an input method and create an input context in it, returns their ids.
+fn create_ic(
+    server: &mut TestServer,
+    connections: &mut XimConnections<()>,
+    handler: &mut Handler,
+) -> (u16, u16) {
+    dispatch(
+        server,
+        connections,
+        handler,
+        Request::Open {
+            locale: "en_US".into(),
+        },
+    );
+    let im = match take_sent(server).pop() {
+        Some(Request::OpenReply {
+            input_method_id, ..
+        }) => input_method_id,
+        other => panic!("Unexpected reply: {:?}", other),
+    };
+    dispatch(
+        server,
+        connections,
+        handler,
+        Request::CreateIc {
+            input_method_id: im,
+            ic_attributes: Vec::new(),
+        },
+    );
+    match take_sent(server).pop() {
+        Some(Request::CreateIcReply {
+            input_context_id, ..
+        }) => (im, input_context_id),
+        other => panic!("Unexpected reply: {:?}", other),
+    }
+}
+
+fn key_event(input_method_id: u16, input_context_id: u16) -> Request {
+    Request::ForwardEvent {
+        input_method_id,
+        input_context_id,
+        flag: ForwardEventFlag::empty(),
+        serial_number: 0,
+        xev: XEvent {
+            response_type: 2,
+            detail: 38,
+            sequence: 0,
+            time: 0,
+            root: 0,
+            event: 0,
+            child: 0,
+            root_x: 0,
+            root_y: 0,
+            event_x: 0,
+            event_y: 0,
+            state: 0,
+            same_screen: true,
+        },
+    }
+}
+
+#[test]
+fn inspect_x11rb_messages() {
+    let mut seen = Vec::new();
+    let mut connections = XimConnections::new();
+    connections.new_connection(COM_WIN, CLIENT_WIN);
+    let mut handler = Handler::default();
+
+    let server = test_server();
+    let connect = client_messages(&server, connect_request()).remove(0);
+    let mut server = server.map_transport(|transport| {
+        Inspect::wrap(transport, |direction, win, bytes: &[u8]| {
+            seen.push((direction, win, bytes.to_vec()))
+        })
+    });
+    assert!(server
+        .filter_event(&connect, &mut connections, &mut handler)
+        .unwrap());
+
+    let sent = server.transport.into_inner().sent;
+    let reply = xim_parser::write_to_vec(Request::ConnectReply {
+        server_major_protocol_version: 1,
+        server_minor_protocol_version: 0,
+    });
+    assert_eq!(sent, [(CLIENT_WIN, reply.clone())]);
+
+    let mut connect_bytes = xim_parser::write_to_vec(connect_request());
+    connect_bytes.resize(20, 0);
+    assert_eq!(
+        seen,
+        [
+            (Direction::Recv, COM_WIN, connect_bytes),
+            (Direction::Send, CLIENT_WIN, reply),
+        ]
+    );
+}
+
+#[test]
+fn flush_batched_forward_events() {
+    let mut server = test_server();
+    let mut connections = XimConnections::new();
+    connections.new_connection(COM_WIN, CLIENT_WIN);
+    let mut handler = Handler::default();
+
+    let (im, ic) = create_ic(&mut server, &mut connections, &mut handler);
+
+    connections
+        .get_connection(COM_WIN)
+        .unwrap()
+        .set_batch_forward_events(true);
+    let key = key_event(im, ic);
+    dispatch(&mut server, &mut connections, &mut handler, key.clone());
+
+    // the handler didn't consume it, it's back at the client without another request
+    assert_eq!(take_sent(&mut server), [key]);
+}
+
+#[test]
+fn expire_unanswered_preedit_start() {
+    let mut server = test_server();
+    let mut connections = XimConnections::new();
+    connections.new_connection(COM_WIN, CLIENT_WIN);
+    let mut handler = Handler {
+        preedit: Some("a"),
+        ..Default::default()
+    };
+    let (im, ic) = create_ic(&mut server, &mut connections, &mut handler);
+
+    server.set_reply_timeout(Some(Duration::from_millis(0)));
+    dispatch(
+        &mut server,
+        &mut connections,
+        &mut handler,
+        key_event(im, ic),
+    );
+    assert!(matches!(
+        take_sent(&mut server)[..],
+        [Request::PreeditStart { .. }, Request::PreeditDraw { .. }]
+    ));
+
+    // overdue by the end of the same call
+    assert_eq!(handler.timeouts, [PendingOperation::PreeditStart]);
+}
+
+#[test]
+fn disconnect_on_send_failure() {
+    let mut server = test_server();
+    let mut connections = XimConnections::new();
+    connections.new_connection(COM_WIN, CLIENT_WIN);
+    let mut handler = Handler::default();
+    let (im, ic) = create_ic(&mut server, &mut connections, &mut handler);
+
+    server.transport.fail = true;
+    let sync = Request::Sync {
+        input_method_id: im,
+        input_context_id: ic,
+    };
+    for e in client_messages(&server, sync) {
+        assert!(server
+            .filter_event(&e, &mut connections, &mut handler)
+            .is_err());
+    }
+
+    assert!(connections.get_connection(COM_WIN).is_none());
+    assert_eq!(
+        handler.disconnect_reasons,
+        [DisconnectReason::TransportError]
+    );
+}
+
+#[test]
+fn disconnect_on_client_window_destroyed() {
+    let mut server = test_server();
+    let mut connections = XimConnections::new();
+    connections.new_connection(COM_WIN, CLIENT_WIN);
+    let mut handler = Handler::default();
+    create_ic(&mut server, &mut connections, &mut handler);
+
+    let other = Event::DestroyNotify(DestroyNotifyEvent {
+        window: CLIENT_WIN + 1,
+        ..Default::default()
+    });
+    assert!(!server
+        .filter_event(&other, &mut connections, &mut handler)
+        .unwrap());
+    assert!(connections.get_connection(COM_WIN).is_some());
+
+    let destroyed = Event::DestroyNotify(DestroyNotifyEvent {
+        window: CLIENT_WIN,
+        ..Default::default()
+    });
+    assert!(server
+        .filter_event(&destroyed, &mut connections, &mut handler)
+        .unwrap());
+    assert!(connections.get_connection(COM_WIN).is_none());
+    assert_eq!(
+        handler.disconnect_reasons,
+        [DisconnectReason::WindowDestroyed]
+    );
+}
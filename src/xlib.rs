@@ -13,7 +13,7 @@ use std::{convert::TryInto, os::raw::c_long};
 
 use crate::{
     client::{handle_request, ClientCore, ClientError, ClientHandler},
-    Atoms,
+    Atoms, LocaleModifiers,
 };
 use x11_dl::xlib;
 use xim_parser::{AttributeName, Request, XimWrite};
@@ -153,7 +153,7 @@ impl<X: XlibRef> XlibClient<X> {
         let client_window = (xlib.XCreateSimpleWindow)(display, root, 0, 0, 1, 1, 0, 0, 0);
 
         let var = std::env::var("XMODIFIERS").ok();
-        let var = var.as_ref().and_then(|n| n.strip_prefix("@im="));
+        let var = var.as_deref().and_then(|n| LocaleModifiers::parse(n).im);
         let im_name = im_name.or(var).ok_or(ClientError::NoXimServer)?;
 
         let atoms = Atoms::new_null::<ClientError, _>(|name| {
@@ -209,7 +209,7 @@ impl<X: XlibRef> XlibClient<X> {
                     _ => continue,
                 };
 
-                if let Some(name) = name.strip_prefix("@server=") {
+                if let Some(name) = LocaleModifiers::parse(name).server {
                     if name == im_name {
                         (xlib.XConvertSelection)(
                             display,
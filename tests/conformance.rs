@@ -0,0 +1,184 @@
+//! Replay the client messages of the sessions in `tests/fixtures` through a connection and
+//! check the replies byte for byte.
+
+use xim::{
+    ConnectInfo, Endian, InputStyle, Request, Server, ServerCore, ServerError, ServerHandler,
+    UserInputContext, XEvent, XimConnection, XimWrite,
+};
+
+const CLIENT_WIN: u32 = 0x03a0_0007;
+
+/// Keeps the replies in the byte order of the fixtures.
+#[derive(Default)]
+struct RecordingServer {
+    replies: Vec<Vec<u8>>,
+}
+
+impl ServerCore for RecordingServer {
+    type XEvent = XEvent;
+
+    fn serialize_event(&self, ev: &XEvent) -> Result<XEvent, ServerError> {
+        Ok(ev.clone())
+    }
+
+    fn deserialize_event(&self, ev: &XEvent) -> XEvent {
+        ev.clone()
+    }
+
+    fn send_req(&mut self, _client_win: u32, req: Request) -> Result<(), ServerError> {
+        let mut buf = vec![0; req.size()];
+        xim::write_with_endian(&req, &mut buf, Endian::Little);
+        self.replies.push(buf);
+        Ok(())
+    }
+}
+
+/// Commits `a` for keycode 38 and gives every other key back.
+struct Handler;
+
+impl<S: Server<XEvent = XEvent>> ServerHandler<S> for Handler {
+    type InputContextData = ();
+    type InputStyleArray = [InputStyle; 2];
+
+    fn new_ic_data(
+        &mut self,
+        _server: &mut S,
+        _style: InputStyle,
+    ) -> Result<Self::InputContextData, ServerError> {
+        Ok(())
+    }
+
+    fn input_styles(&self) -> Self::InputStyleArray {
+        [
+            InputStyle::PREEDIT_NOTHING | InputStyle::STATUS_NOTHING,
+            InputStyle::PREEDIT_POSITION | InputStyle::STATUS_NOTHING,
+        ]
+    }
+
+    fn filter_events(&self) -> u32 {
+        1
+    }
+
+    fn handle_connect(&mut self, _server: &mut S, _info: &ConnectInfo) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    fn handle_create_ic(
+        &mut self,
+        server: &mut S,
+        user_ic: &mut UserInputContext<Self::InputContextData>,
+    ) -> Result<(), ServerError> {
+        server.set_event_mask(&user_ic.ic, 1, 0)
+    }
+
+    fn handle_forward_event(
+        &mut self,
+        server: &mut S,
+        user_ic: &mut UserInputContext<Self::InputContextData>,
+        xev: &XEvent,
+    ) -> Result<bool, ServerError> {
+        if xev.detail == 38 {
+            server.commit(&user_ic.ic, "a")?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn handle_destroy_ic(
+        &mut self,
+        _server: &mut S,
+        _user_ic: UserInputContext<Self::InputContextData>,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    fn handle_reset_ic(
+        &mut self,
+        _server: &mut S,
+        _user_ic: &mut UserInputContext<Self::InputContextData>,
+    ) -> Result<String, ServerError> {
+        Ok(String::new())
+    }
+
+    fn handle_set_ic_values(
+        &mut self,
+        _server: &mut S,
+        _user_ic: &mut UserInputContext<Self::InputContextData>,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    fn handle_set_focus(
+        &mut self,
+        _server: &mut S,
+        _user_ic: &mut UserInputContext<Self::InputContextData>,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    fn handle_unset_focus(
+        &mut self,
+        _server: &mut S,
+        _user_ic: &mut UserInputContext<Self::InputContextData>,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+}
+
+/// A client message with the line it is on and the replies expected to it.
+struct Step {
+    line: usize,
+    message: Vec<u8>,
+    replies: Vec<Vec<u8>>,
+}
+
+fn hex(s: &str) -> Vec<u8> {
+    s.split_whitespace()
+        .map(|b| u8::from_str_radix(b, 16).unwrap())
+        .collect()
+}
+
+fn parse_fixture(text: &str) -> Vec<Step> {
+    let mut steps: Vec<Step> = Vec::new();
+
+    for (line, text) in text.lines().enumerate() {
+        if let Some(message) = text.strip_prefix('>') {
+            steps.push(Step {
+                line: line + 1,
+                message: hex(message),
+                replies: Vec::new(),
+            });
+        } else if let Some(reply) = text.strip_prefix('<') {
+            steps
+                .last_mut()
+                .expect("reply before the first message")
+                .replies
+                .push(hex(reply));
+        }
+    }
+
+    steps
+}
+
+fn replay(fixture: &str) {
+    let mut server = RecordingServer::default();
+    let mut handler = Handler;
+    let mut conn = XimConnection::new(CLIENT_WIN);
+
+    for step in parse_fixture(fixture) {
+        conn.handle_message(&mut server, &step.message, &mut handler)
+            .unwrap_or_else(|e| panic!("line {}: {}", step.line, e));
+        assert_eq!(
+            server.replies.drain(..).collect::<Vec<_>>(),
+            step.replies,
+            "replies to line {}",
+            step.line
+        );
+    }
+}
+
+#[test]
+fn xlib_session() {
+    replay(include_str!("fixtures/xlib_session.txt"));
+}
@@ -161,6 +161,138 @@ pub fn compound_text_to_utf8(bytes: &[u8]) -> Result<String, DecodeError> {
     }
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Charset {
+    Latin1,
+    Utf8,
+    Jis,
+}
+
+/// Incremental COMPOUND_TEXT decoder for text that arrives split across several buffers.
+///
+/// An escape sequence or a multi-byte character cut off at the end of a buffer is kept until the
+/// rest of it is fed. Supports the same encodings as [`compound_text_to_utf8`] as well as the
+/// default Latin-1 text.
+#[derive(Clone, Debug)]
+pub struct CompoundTextDecoder {
+    charset: Charset,
+    pending: Vec<u8>,
+}
+
+impl Default for CompoundTextDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CompoundTextDecoder {
+    pub fn new() -> Self {
+        Self {
+            charset: Charset::Latin1,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Whether the end of the last fed buffer is still waiting for the rest of its sequence.
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Decode `bytes`, returning the text that is complete so far.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<String, DecodeError> {
+        let mut buf = core::mem::take(&mut self.pending);
+        buf.extend_from_slice(bytes);
+
+        let mut out = String::new();
+        let mut pos = 0;
+
+        while pos < buf.len() {
+            if buf[pos] == 0x1B {
+                match escape_sequence(&buf[pos..])? {
+                    Some((charset, len)) => {
+                        self.charset = charset;
+                        pos += len;
+                    }
+                    None => break,
+                }
+            } else {
+                let end = buf[pos..]
+                    .iter()
+                    .position(|&b| b == 0x1B)
+                    .map_or(buf.len(), |i| pos + i);
+                let consumed = decode_text(self.charset, &buf[pos..end], &mut out)?;
+                pos += consumed;
+
+                if pos < end {
+                    break;
+                }
+            }
+        }
+
+        buf.drain(..pos);
+        self.pending = buf;
+
+        Ok(out)
+    }
+}
+
+/// Parse the escape sequence at the start of `bytes`, `None` if it isn't complete yet.
+fn escape_sequence(bytes: &[u8]) -> Result<Option<(Charset, usize)>, DecodeError> {
+    // ESC, intermediate bytes, then a final byte
+    for (i, &b) in bytes.iter().enumerate().skip(1) {
+        match b {
+            0x20..=0x2F => continue,
+            0x30..=0x7E => {
+                let charset = match &bytes[1..=i] {
+                    [0x25, 0x47] => Charset::Utf8,
+                    [0x25, 0x40] | [0x28, 0x42] | [0x2D, 0x41] => Charset::Latin1,
+                    [0x24, 0x28, 0x42] => Charset::Jis,
+                    [0x24, 0x28, 0x41] | [0x24, 0x28, 0x43] => {
+                        return Err(DecodeError::UnsupportedEncoding)
+                    }
+                    [_, ..] if i > 1 => return Err(DecodeError::UnsupportedEncoding),
+                    _ => return Err(DecodeError::InvalidEncoding),
+                };
+
+                return Ok(Some((charset, i + 1)));
+            }
+            _ => return Err(DecodeError::InvalidEncoding),
+        }
+    }
+
+    Ok(None)
+}
+
+/// Decode text without escape sequences, returning how many bytes were complete.
+fn decode_text(charset: Charset, bytes: &[u8], out: &mut String) -> Result<usize, DecodeError> {
+    match charset {
+        Charset::Latin1 => {
+            out.extend(bytes.iter().map(|&b| char::from(b)));
+            Ok(bytes.len())
+        }
+        Charset::Utf8 => {
+            let len = match core::str::from_utf8(bytes) {
+                Ok(_) => bytes.len(),
+                // a character cut off at the end
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                Err(_) => return Err(String::from_utf8(bytes.to_vec()).unwrap_err().into()),
+            };
+            out.push_str(core::str::from_utf8(&bytes[..len]).unwrap());
+            Ok(len)
+        }
+        Charset::Jis => {
+            // two bytes per character
+            let len = bytes.len() & !1;
+            let mut decoder = encoding_rs::ISO_2022_JP.new_decoder_without_bom_handling();
+
+            decode!(decoder, out, &[0x1B, 0x24, 0x42], false);
+            decode!(decoder, out, &bytes[..len], true);
+
+            Ok(len)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -179,4 +311,45 @@ mod tests {
         const COMP: &[u8] = &[27, 36, 40, 66, 69, 108, 53, 126];
         assert_eq!(crate::compound_text_to_utf8(COMP).unwrap(), UTF8);
     }
+
+    #[test]
+    fn stream_latin1() {
+        const COMP: &[u8] = &[b'a', 0x1B, 0x2D, 0x41, 0xE9];
+        let mut decoder = crate::CompoundTextDecoder::new();
+
+        assert_eq!(decoder.feed(&COMP[..1]).unwrap(), "a");
+        for &b in &COMP[1..3] {
+            assert_eq!(decoder.feed(&[b]).unwrap(), "");
+            assert!(decoder.has_pending());
+        }
+        assert_eq!(decoder.feed(&COMP[3..4]).unwrap(), "");
+        assert!(!decoder.has_pending());
+        assert_eq!(decoder.feed(&COMP[4..]).unwrap(), "é");
+        assert!(!decoder.has_pending());
+    }
+
+    #[test]
+    fn stream_korean() {
+        const COMP: &[u8] = &[
+            27, 37, 71, 234, 176, 128, 235, 130, 152, 235, 139, 164, 27, 37, 64,
+        ];
+        let mut decoder = crate::CompoundTextDecoder::new();
+        let mut out = alloc::string::String::new();
+
+        for &b in COMP {
+            out.push_str(&decoder.feed(&[b]).unwrap());
+        }
+        assert_eq!(out, "가나다");
+        assert!(!decoder.has_pending());
+    }
+
+    #[test]
+    fn stream_iso_2011_jp() {
+        const COMP: &[u8] = &[27, 36, 40, 66, 69, 108, 53, 126];
+        let mut decoder = crate::CompoundTextDecoder::new();
+
+        assert_eq!(decoder.feed(&COMP[..5]).unwrap(), "");
+        assert_eq!(decoder.feed(&COMP[5..7]).unwrap(), "東");
+        assert_eq!(decoder.feed(&COMP[7..]).unwrap(), "京");
+    }
 }
@@ -2,11 +2,13 @@
 // This source is auto generated by xim-gen
 
 #![allow(clippy::identity_op)]
+// not every consumer uses every generated item
+#![allow(dead_code, unused_imports)]
 
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
-use core::convert::TryInto;
+use core::convert::{TryFrom, TryInto};
 use core::fmt;
 
 pub fn read<T>(b: &[u8]) -> Result<T, ReadError>
@@ -23,24 +25,56 @@ where
     val.write(&mut Writer::new(out));
 }
 
+/// Write `val` as slices, see [`VectoredWriter`].
+pub fn write_vectored<T>(val: &T, endian: Endian) -> VectoredWriter<'_>
+where
+    T: XimWrite,
+{
+    let mut writer = VectoredWriter::with_endian(endian);
+    val.write_vectored(&mut writer);
+    writer
+}
+
+pub fn read_with_endian<T>(b: &[u8], endian: Endian) -> Result<T, ReadError>
+where
+    T: XimRead,
+{
+    T::read(&mut Reader::with_endian(b, endian))
+}
+
+pub fn write_with_endian<T>(val: T, out: &mut [u8], endian: Endian)
+where
+    T: XimWrite,
+{
+    val.write(&mut Writer::with_endian(out, endian));
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[repr(u8)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Endian {
+    Big = 0x42,
+    Little = 0x6c,
+}
+
+impl Endian {
+    #[allow(non_upper_case_globals)]
     #[cfg(target_endian = "little")]
-    Native = 0x6c,
+    pub const Native: Self = Self::Little;
+    #[allow(non_upper_case_globals)]
     #[cfg(target_endian = "big")]
-    Native = 0x42,
-    // Big = 0x42,
-    // Little = 0x6c,
+    pub const Native: Self = Self::Big;
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum StatusContent {
     Text(StatusTextContent),
     Pixmap(u32),
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CommitData {
     Keysym {
         keysym: u32,
@@ -57,6 +91,7 @@ pub enum CommitData {
     },
 }
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InputStyleList {
     pub styles: Vec<InputStyle>,
 }
@@ -88,7 +123,63 @@ impl XimWrite for InputStyleList {
     }
 }
 
+/// A `STR`: a one byte length then the bytes, without padding.
+///
+/// Usable as a field type in `xim-format.yaml` where the string isn't framed by the field.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Str8(pub String);
+
+impl XimRead for Str8 {
+    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
+        let len = reader.u8()?;
+        Ok(Self(String::from_utf8(
+            reader.consume(len as usize)?.to_vec(),
+        )?))
+    }
+}
+
+impl XimWrite for Str8 {
+    fn write(&self, writer: &mut Writer) {
+        (self.0.len() as u8).write(writer);
+        writer.write(self.0.as_bytes());
+    }
+
+    fn size(&self) -> usize {
+        self.0.len() + 1
+    }
+}
+
+/// A `STRING`: a two byte length, the bytes, then padding of the whole to four bytes.
+///
+/// Usable as a field type in `xim-format.yaml` where the string isn't framed by the field.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Str16(pub String);
+
+impl XimRead for Str16 {
+    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
+        let len = reader.u16()? as usize;
+        let s = String::from_utf8(reader.consume(len)?.to_vec())?;
+        reader.consume(pad4(2 + len))?;
+        Ok(Self(s))
+    }
+}
+
+impl XimWrite for Str16 {
+    fn write(&self, writer: &mut Writer) {
+        (self.0.len() as u16).write(writer);
+        writer.write(self.0.as_bytes());
+        writer.write(&[0u8; 4][..pad4(2 + self.0.len())]);
+    }
+
+    fn size(&self) -> usize {
+        with_pad4(self.0.len() + 2)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct HotKeyTriggers {
     pub triggers: Vec<(TriggerKey, HotKeyState)>,
 }
@@ -99,6 +190,7 @@ pub enum ReadError {
     InvalidData(&'static str, String),
     Utf8Error(alloc::string::FromUtf8Error),
     NotNativeEndian,
+    TrailingBytes { remaining: usize },
 }
 
 impl From<alloc::string::FromUtf8Error> for ReadError {
@@ -114,6 +206,9 @@ impl fmt::Display for ReadError {
             Self::InvalidData(name, reason) => write!(f, "Invalid Data {}: {}", name, reason),
             Self::Utf8Error(e) => write!(f, "Not a Utf8 text {}", e),
             Self::NotNativeEndian => write!(f, "Not a native endian"),
+            Self::TrailingBytes { remaining } => {
+                write!(f, "{} trailing bytes after the end of data", remaining)
+            }
         }
     }
 }
@@ -129,68 +224,81 @@ fn pad4(len: usize) -> usize {
 }
 
 fn with_pad4(len: usize) -> usize {
-    len + pad4(len)
+    len.saturating_add(pad4(len))
 }
 
 pub struct Reader<'b> {
     bytes: &'b [u8],
     start: usize,
+    endian: Endian,
 }
 
 impl<'b> Reader<'b> {
     pub fn new(bytes: &'b [u8]) -> Self {
+        Self::with_endian(bytes, Endian::Native)
+    }
+
+    pub fn with_endian(bytes: &'b [u8], endian: Endian) -> Self {
         Self {
             bytes,
             start: bytes.as_ptr() as usize,
+            endian,
         }
     }
 
-    fn ptr_offset(&self) -> usize {
-        self.bytes.as_ptr() as usize - self.start
+    pub fn endian(&self) -> Endian {
+        self.endian
     }
 
-    pub fn cursor(&self) -> usize {
-        self.bytes.len()
+    /// Change the byte order of the following reads, reading an [`Endian`] does this too.
+    pub fn set_endian(&mut self, endian: Endian) {
+        self.endian = endian;
     }
 
-    pub fn pad4(&mut self) -> Result<(), ReadError> {
-        self.consume(pad4(self.ptr_offset()))?;
-        Ok(())
+    /// Number of bytes read so far.
+    pub fn position(&self) -> usize {
+        self.bytes.as_ptr() as usize - self.start
     }
 
-    #[inline(always)]
-    pub fn eos(&self) -> ReadError {
-        ReadError::EndOfStream
+    /// Number of bytes left to read.
+    pub fn remaining(&self) -> usize {
+        self.bytes.len()
     }
 
-    pub fn invalid_data(&self, ty: &'static str, item: impl ToString) -> ReadError {
-        ReadError::InvalidData(ty, item.to_string())
+    pub fn cursor(&self) -> usize {
+        self.bytes.len()
     }
 
-    pub fn u8(&mut self) -> Result<u8, ReadError> {
-        let (b, new) = self.bytes.split_first().ok_or(ReadError::EndOfStream)?;
-        self.bytes = new;
-        Ok(*b)
+    pub fn pad4(&mut self) -> Result<(), ReadError> {
+        self.consume(pad4(self.position()))?;
+        Ok(())
     }
 
-    pub fn i16(&mut self) -> Result<i16, ReadError> {
-        let bytes = self.consume(2)?.try_into().unwrap();
-        Ok(i16::from_ne_bytes(bytes))
+    /// Check that every byte of the input has been consumed.
+    ///
+    /// Reading never fails on leftover bytes by itself, call this after reading when the input
+    /// is expected to hold exactly one value.
+    pub fn expect_consumed(&self) -> Result<(), ReadError> {
+        match self.remaining() {
+            0 => Ok(()),
+            remaining => Err(ReadError::TrailingBytes { remaining }),
+        }
     }
 
-    pub fn u16(&mut self) -> Result<u16, ReadError> {
-        let bytes = self.consume(2)?.try_into().unwrap();
-        Ok(u16::from_ne_bytes(bytes))
+    /// Switch to the byte order stored `offset` bytes ahead without consuming anything.
+    fn peek_endian(&mut self, offset: usize) -> Result<(), ReadError> {
+        let n = *self.bytes.get(offset).ok_or(ReadError::EndOfStream)?;
+        self.endian = Endian::from_byte(self, n)?;
+        Ok(())
     }
 
-    pub fn u32(&mut self) -> Result<u32, ReadError> {
-        let bytes = self.consume(4)?.try_into().unwrap();
-        Ok(u32::from_ne_bytes(bytes))
+    #[inline(always)]
+    pub fn eos(&self) -> ReadError {
+        ReadError::EndOfStream
     }
 
-    pub fn i32(&mut self) -> Result<i32, ReadError> {
-        let bytes = self.consume(4)?.try_into().unwrap();
-        Ok(i32::from_ne_bytes(bytes))
+    pub fn invalid_data(&self, ty: &'static str, item: impl ToString) -> ReadError {
+        ReadError::InvalidData(ty, item.to_string())
     }
 
     pub fn consume(&mut self, len: usize) -> Result<&'b [u8], ReadError> {
@@ -207,11 +315,33 @@ impl<'b> Reader<'b> {
 pub struct Writer<'b> {
     out: &'b mut [u8],
     idx: usize,
+    endian: Endian,
 }
 
 impl<'b> Writer<'b> {
     pub fn new(out: &'b mut [u8]) -> Self {
-        Self { out, idx: 0 }
+        Self::with_endian(out, Endian::Native)
+    }
+
+    pub fn with_endian(out: &'b mut [u8], endian: Endian) -> Self {
+        Self {
+            out,
+            idx: 0,
+            endian,
+        }
+    }
+
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    /// Number of bytes written so far.
+    pub fn len(&self) -> usize {
+        self.idx
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.idx == 0
     }
 
     pub fn write_u8(&mut self, b: u8) {
@@ -231,6 +361,111 @@ impl<'b> Writer<'b> {
     }
 }
 
+enum Segment<'a> {
+    Scratch(usize, usize),
+    Borrowed(&'a [u8]),
+}
+
+/// Collects the bytes of an item as a list of slices, borrowing strings and other byte payloads
+/// of the item instead of copying them.
+pub struct VectoredWriter<'a> {
+    scratch: Vec<u8>,
+    segments: Vec<Segment<'a>>,
+    len: usize,
+    endian: Endian,
+}
+
+impl<'a> Default for VectoredWriter<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> VectoredWriter<'a> {
+    pub fn new() -> Self {
+        Self::with_endian(Endian::Native)
+    }
+
+    pub fn with_endian(endian: Endian) -> Self {
+        Self {
+            scratch: Vec::new(),
+            segments: Vec::new(),
+            len: 0,
+            endian,
+        }
+    }
+
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    /// Total length of the written bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copy `item` into the internal buffer.
+    pub fn copy(&mut self, item: &(impl XimWrite + ?Sized)) {
+        let size = item.size();
+        if size == 0 {
+            return;
+        }
+
+        // keep the padding of `item` aligned to the whole output
+        let offset = self.len % 4;
+        let start = self.scratch.len();
+        self.scratch.resize(start + offset + size, 0);
+        item.write(&mut Writer {
+            out: &mut self.scratch[start..],
+            idx: offset,
+            endian: self.endian,
+        });
+
+        let start = start + offset;
+        match self.segments.last_mut() {
+            Some(Segment::Scratch(prev, len)) if *prev + *len == start => *len += size,
+            _ => self.segments.push(Segment::Scratch(start, size)),
+        }
+        self.len += size;
+    }
+
+    /// Append `bytes` without copying them.
+    pub fn borrow(&mut self, bytes: &'a [u8]) {
+        if !bytes.is_empty() {
+            self.segments.push(Segment::Borrowed(bytes));
+            self.len += bytes.len();
+        }
+    }
+
+    pub fn write_zeros(&mut self, len: usize) {
+        for _ in 0..len {
+            self.copy(&0u8);
+        }
+    }
+
+    pub fn write_pad4(&mut self) {
+        self.write_zeros(pad4(self.len));
+    }
+
+    /// The written bytes in order.
+    pub fn segments(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.segments.iter().map(move |segment| match *segment {
+            Segment::Scratch(start, len) => &self.scratch[start..start + len],
+            Segment::Borrowed(bytes) => bytes,
+        })
+    }
+
+    /// The written bytes as slices for [`std::io::Write::write_vectored`].
+    #[cfg(feature = "std")]
+    pub fn io_slices(&self) -> Vec<std::io::IoSlice<'_>> {
+        self.segments().map(std::io::IoSlice::new).collect()
+    }
+}
+
 pub trait XimRead: Sized {
     fn read(reader: &mut Reader) -> Result<Self, ReadError>;
 }
@@ -239,6 +474,10 @@ pub trait XimWrite {
     fn write(&self, writer: &mut Writer);
     /// byte size of format
     fn size(&self) -> usize;
+    /// Write into a [`VectoredWriter`], defaults to copying the whole item.
+    fn write_vectored<'a>(&'a self, writer: &mut VectoredWriter<'a>) {
+        writer.copy(self);
+    }
 }
 
 impl<'a, T> XimWrite for &'a T
@@ -253,17 +492,31 @@ where
     fn size(&self) -> usize {
         (**self).size()
     }
+    #[inline(always)]
+    fn write_vectored<'w>(&'w self, writer: &mut VectoredWriter<'w>) {
+        (**self).write_vectored(writer);
+    }
+}
+
+impl Endian {
+    fn from_byte(reader: &Reader, n: u8) -> Result<Self, ReadError> {
+        match n {
+            0x42 => Ok(Self::Big),
+            0x6c => Ok(Self::Little),
+            _ => Err(reader.invalid_data("Endian", n)),
+        }
+    }
 }
 
 impl XimRead for Endian {
     fn read(reader: &mut Reader) -> Result<Self, ReadError> {
         let n = u8::read(reader)?;
+        let endian = Self::from_byte(reader, n)?;
 
-        if n == Endian::Native as u8 {
-            Ok(Self::Native)
-        } else {
-            Err(ReadError::NotNativeEndian)
-        }
+        // the rest of the message is in this byte order
+        reader.set_endian(endian);
+
+        Ok(endian)
     }
 }
 
@@ -324,7 +577,7 @@ impl XimRead for CommitData {
                 reader.pad4()?;
                 Ok(Self::Chars {
                     commited: bytes.to_vec(),
-                    syncronous: ty == 5,
+                    syncronous: ty == 3,
                 })
             }
             4 | 5 => {
@@ -332,7 +585,7 @@ impl XimRead for CommitData {
                 let keysym = reader.u32()?;
                 Ok(Self::Keysym {
                     keysym,
-                    syncronous: ty == 3,
+                    syncronous: ty == 5,
                 })
             }
             6 | 7 => {
@@ -399,14 +652,20 @@ impl XimWrite for CommitData {
 impl XimRead for HotKeyTriggers {
     fn read(reader: &mut Reader) -> Result<Self, ReadError> {
         let n = reader.u32()? as usize;
+
+        // each trigger takes 16 bytes, don't trust the count for allocation
+        if n > reader.cursor() / 16 {
+            return Err(reader.eos());
+        }
+
         let mut out = Vec::with_capacity(n);
 
         for _ in 0..n {
             out.push((TriggerKey::read(reader)?, HotKeyState::Off));
         }
 
-        for _ in 0..n {
-            out[n].1 = HotKeyState::read(reader)?;
+        for (_, state) in out.iter_mut() {
+            *state = HotKeyState::read(reader)?;
         }
 
         Ok(Self { triggers: out })
@@ -427,23 +686,7 @@ impl XimWrite for HotKeyTriggers {
     }
 
     fn size(&self) -> usize {
-        self.triggers.len() * 8 + 4
-    }
-}
-
-impl XimRead for u8 {
-    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
-        reader.u8()
-    }
-}
-
-impl XimWrite for u8 {
-    fn write(&self, writer: &mut Writer) {
-        writer.write_u8(*self)
-    }
-
-    fn size(&self) -> usize {
-        1
+        self.triggers.len() * 16 + 4
     }
 }
 
@@ -462,28 +705,3 @@ impl XimWrite for bool {
         1
     }
 }
-
-macro_rules! impl_int {
-    ($ty:ident) => {
-        impl XimRead for $ty {
-            fn read(reader: &mut Reader) -> Result<Self, ReadError> {
-                reader.$ty()
-            }
-        }
-
-        impl XimWrite for $ty {
-            fn write(&self, writer: &mut Writer) {
-                writer.write(&self.to_ne_bytes())
-            }
-
-            fn size(&self) -> usize {
-                core::mem::size_of::<$ty>()
-            }
-        }
-    };
-}
-
-impl_int!(u16);
-impl_int!(i16);
-impl_int!(u32);
-impl_int!(i32);
@@ -33,7 +33,7 @@ impl FormatType {
                 write!(out, "; reader.pad4()?; inner }}")?;
             }
             FormatType::List(inner, prefix, len) => {
-                writeln!(out, "{{ let mut out = Vec::new(); let len = u{}::read(reader)? as usize; let end = reader.cursor() - len;", len * 8)?;
+                writeln!(out, "{{ let mut out = Vec::new(); let len = u{}::read(reader)? as usize; let end = reader.cursor().checked_sub(len).ok_or_else(|| reader.eos())?;", len * 8)?;
                 if *prefix > 0 {
                     writeln!(out, "u{}::read(reader)?;", prefix * 8)?;
                 }
@@ -119,11 +119,130 @@ impl FormatType {
         Ok(())
     }
 
+    pub fn write_vectored(&self, this: &str, out: &mut impl Write) -> io::Result<()> {
+        match self {
+            FormatType::Append(inner, size) => {
+                inner.write_vectored(this, out)?;
+                writeln!(out, "writer.write_zeros({});", size)?;
+            }
+            FormatType::List(inner, prefix, len) => {
+                write!(out, "writer.copy(&((")?;
+                self.size(this, out)?;
+                writeln!(out, " - {} - {}) as u{}));", len, prefix, len * 8)?;
+
+                if *prefix > 0 {
+                    writeln!(out, "writer.write_zeros({});", prefix)?;
+                }
+
+                writeln!(out, "for elem in {}.iter() {{", this)?;
+                inner.write_vectored("elem", out)?;
+                writeln!(out, "}}")?;
+            }
+            FormatType::Pad(inner, _size_add) => {
+                inner.write_vectored(this, out)?;
+                writeln!(out, "writer.write_pad4();")?;
+            }
+            FormatType::XString => {
+                writeln!(out, "writer.copy(&({}.len() as u16));", this)?;
+                writeln!(out, "writer.borrow(&{}[..]);", this)?
+            }
+            FormatType::String {
+                len,
+                between_unused,
+            } => {
+                writeln!(out, "writer.copy(&({}.len() as u{}));", this, len * 8)?;
+                if *between_unused > 0 {
+                    writeln!(out, "writer.write_zeros({});", between_unused)?;
+                }
+                writeln!(out, "writer.borrow({}.as_bytes());", this)?;
+            }
+            FormatType::Normal(_name) => writeln!(out, "{}.write_vectored(writer);", this)?,
+        }
+
+        Ok(())
+    }
+
+    /// Check that every length prefix of the field fits its width.
+    pub fn validate(&self, this: &str, field: &str, out: &mut impl Write) -> io::Result<()> {
+        let mut check = |len: &str, width: usize| {
+            writeln!(
+                out,
+                "{{ let len = {}; if len > u{}::MAX as usize {{ return Err(ReadError::InvalidData(\"Length\", alloc::format!(\"{}: {{}} bytes don't fit u{}\", len))); }} }}",
+                len,
+                width * 8,
+                field,
+                width * 8,
+            )
+        };
+
+        match self {
+            FormatType::Append(inner, _) | FormatType::Pad(inner, _) => {
+                inner.validate(this, field, out)
+            }
+            FormatType::XString => check(&format!("{}.len()", this), 2),
+            FormatType::String { len, .. } => check(&format!("{}.len()", this), *len),
+            FormatType::List(inner, prefix, len) => {
+                let mut size = Vec::new();
+                self.size(this, &mut size)?;
+                let size = String::from_utf8(size).unwrap();
+                check(&format!("{} - {} - {}", size, len, prefix), *len)?;
+
+                let mut elem = Vec::new();
+                inner.validate("e", field, &mut elem)?;
+                if !elem.is_empty() {
+                    writeln!(out, "for e in {}.iter() {{", this)?;
+                    out.write_all(&elem)?;
+                    writeln!(out, "}}")?;
+                }
+
+                Ok(())
+            }
+            FormatType::Normal(_name) => Ok(()),
+        }
+    }
+
+    /// Name of the type under `@append` and `@pad`, if it isn't a list or string.
+    pub fn inner_name(&self) -> Option<&str> {
+        match self {
+            FormatType::Append(inner, _) | FormatType::Pad(inner, _) => inner.inner_name(),
+            FormatType::Normal(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// The type of the field that has no `Default`, `None` if the field has one.
+    ///
+    /// Lists and strings always have one, `is_default` tells for the other types.
+    pub fn non_default(&self, is_default: impl Fn(&str) -> bool) -> Option<&str> {
+        match self {
+            FormatType::Append(inner, _) | FormatType::Pad(inner, _) => {
+                inner.non_default(is_default)
+            }
+            FormatType::List(..) | FormatType::String { .. } | FormatType::XString => None,
+            FormatType::Normal(name) if is_default(name) => None,
+            FormatType::Normal(name) => Some(name),
+        }
+    }
+
+    /// Collect every type name the field refers to, including the ones inside lists.
+    pub fn normal_names<'a>(&'a self, out: &mut Vec<&'a str>) {
+        match self {
+            FormatType::Append(inner, _)
+            | FormatType::Pad(inner, _)
+            | FormatType::List(inner, _, _) => inner.normal_names(out),
+            FormatType::Normal(name) => out.push(name),
+            FormatType::String { .. } | FormatType::XString => {}
+        }
+    }
+
+    /// Byte size of the field, saturating at `usize::MAX` so a huge list can't wrap around to
+    /// a small size.
     pub fn size(&self, this: &str, out: &mut impl Write) -> io::Result<()> {
         match self {
             FormatType::Append(inner, size) => {
+                write!(out, "usize::saturating_add(")?;
                 inner.size(this, out)?;
-                write!(out, "+ {}", size)
+                write!(out, ", {})", size)
             }
             FormatType::XString => write!(out, "{}.len() + 2", this),
             FormatType::String {
@@ -133,16 +252,23 @@ impl FormatType {
                 write!(out, "{}.len() + {} + {}", this, len, between_unused)
             }
             FormatType::List(inner, prefix, len) => {
-                write!(out, "{}.iter().map(|e| ", this)?;
+                write!(
+                    out,
+                    "usize::saturating_add({}.iter().fold(0usize, |acc, e| usize::saturating_add(acc, ",
+                    this
+                )?;
                 inner.size("e", out)?;
-                write!(out, ").sum::<usize>() + {} + {}", prefix, len)
+                write!(out, ")), {} + {})", prefix, len)
             }
             FormatType::Pad(inner, size_add) => {
+                if *size_add > 0 {
+                    write!(out, "usize::saturating_add(")?;
+                }
                 write!(out, "with_pad4(")?;
                 inner.size(this, out)?;
-                write!(out, "- {})", size_add)?;
+                write!(out, " - {})", size_add)?;
                 if *size_add > 0 {
-                    write!(out, " + {}", size_add)
+                    write!(out, ", {})", size_add)
                 } else {
                     Ok(())
                 }
@@ -11,6 +11,9 @@ use std::path::Path;
 
 mod format_type;
 
+const SERDE_DERIVE: &str =
+    "#[cfg_attr(feature = \"serde\", derive(serde::Serialize, serde::Deserialize))]";
+
 #[derive(Deserialize)]
 #[cfg_attr(debug_assertions, derive(Debug, Eq, PartialEq))]
 struct EnumFormat {
@@ -29,6 +32,7 @@ impl EnumFormat {
         if self.bitflag {
             writeln!(out, "bitflags::bitflags! {{")?;
             writeln!(out, "#[derive(Clone, Copy, Debug, Eq, PartialEq)]")?;
+            writeln!(out, "{}", SERDE_DERIVE)?;
             writeln!(out, "pub struct {}: {} {{", name, self.repr)?;
             for (name, variant) in variants.iter() {
                 writeln!(
@@ -43,6 +47,7 @@ impl EnumFormat {
             writeln!(out, "}}")?;
         } else {
             writeln!(out, "#[derive(Clone, Copy, Debug, Eq, PartialEq)]")?;
+            writeln!(out, "{}", SERDE_DERIVE)?;
             writeln!(out, "#[repr({})]", self.repr)?;
             writeln!(out, "pub enum {} {{", name)?;
 
@@ -52,19 +57,14 @@ impl EnumFormat {
             writeln!(out, "}}")?;
         }
 
-        writeln!(out, "impl XimRead for {} {{", name)?;
-
-        writeln!(
-            out,
-            "fn read(reader: &mut Reader) -> Result<Self, ReadError> {{ let repr = {}::read(reader)?;", self.repr)?;
-
-        if self.bitflag {
+        if !self.bitflag {
+            writeln!(out, "impl TryFrom<{}> for {} {{", self.repr, name)?;
+            writeln!(out, "type Error = ReadError;")?;
             writeln!(
                 out,
-                "Self::from_bits(repr).ok_or_else(|| reader.invalid_data(\"{}\", repr))",
-                name
+                "fn try_from(repr: {}) -> Result<Self, ReadError> {{",
+                self.repr
             )?;
-        } else {
             writeln!(out, "match repr {{")?;
             for (name, variants) in variants.iter() {
                 writeln!(out, "{v} => Ok(Self::{n}),", v = variants, n = name)?;
@@ -72,13 +72,42 @@ impl EnumFormat {
 
             writeln!(
                 out,
-                "_ => Err(reader.invalid_data(\"{n}\", repr)),",
+                "_ => Err(ReadError::InvalidData(\"{n}\", repr.to_string())),",
                 n = name
             )?;
 
+            // match
+            writeln!(out, "}}")?;
+            // fn try_from
+            writeln!(out, "}}")?;
+            // impl TryFrom
+            writeln!(out, "}}")?;
+
+            writeln!(out, "impl From<{}> for {} {{", name, self.repr)?;
+            writeln!(
+                out,
+                "fn from(val: {}) -> Self {{ val as {} }}",
+                name, self.repr
+            )?;
             writeln!(out, "}}")?;
         }
 
+        writeln!(out, "impl XimRead for {} {{", name)?;
+
+        writeln!(
+            out,
+            "fn read(reader: &mut Reader) -> Result<Self, ReadError> {{ let repr = {}::read(reader)?;", self.repr)?;
+
+        if self.bitflag {
+            writeln!(
+                out,
+                "Self::from_bits(repr).ok_or_else(|| reader.invalid_data(\"{}\", repr))",
+                name
+            )?;
+        } else {
+            writeln!(out, "Self::try_from(repr)")?;
+        }
+
         writeln!(out, "}}")?;
 
         // impl XimRead
@@ -109,6 +138,67 @@ impl EnumFormat {
     }
 }
 
+/// An integer read and written in the byte order of the connection.
+#[derive(Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug, Eq, PartialEq))]
+struct PrimitiveFormat {
+    /// Size in bytes.
+    width: usize,
+    #[serde(default)]
+    signed: bool,
+}
+
+impl PrimitiveFormat {
+    fn name(&self) -> String {
+        format!("{}{}", if self.signed { 'i' } else { 'u' }, self.width * 8)
+    }
+
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let name = self.name();
+
+        writeln!(out, "impl<'b> Reader<'b> {{")?;
+        writeln!(
+            out,
+            "pub fn {}(&mut self) -> Result<{}, ReadError> {{",
+            name, name
+        )?;
+        writeln!(
+            out,
+            "let bytes = self.consume({})?.try_into().unwrap();",
+            self.width
+        )?;
+        writeln!(out, "Ok(match self.endian {{")?;
+        writeln!(out, "Endian::Big => {}::from_be_bytes(bytes),", name)?;
+        writeln!(out, "Endian::Little => {}::from_le_bytes(bytes),", name)?;
+        writeln!(out, "}})")?;
+        writeln!(out, "}}")?;
+        writeln!(out, "}}")?;
+
+        writeln!(out, "impl XimRead for {} {{", name)?;
+        writeln!(
+            out,
+            "fn read(reader: &mut Reader) -> Result<Self, ReadError> {{"
+        )?;
+        writeln!(out, "reader.{}()", name)?;
+        writeln!(out, "}}")?;
+        writeln!(out, "}}")?;
+
+        writeln!(out, "impl XimWrite for {} {{", name)?;
+        writeln!(out, "fn write(&self, writer: &mut Writer) {{")?;
+        writeln!(out, "match writer.endian() {{")?;
+        writeln!(out, "Endian::Big => writer.write(&self.to_be_bytes()),")?;
+        writeln!(out, "Endian::Little => writer.write(&self.to_le_bytes()),")?;
+        writeln!(out, "}}")?;
+        writeln!(out, "}}")?;
+        writeln!(out, "fn size(&self) -> usize {{")?;
+        writeln!(out, "{}", self.width)?;
+        writeln!(out, "}}")?;
+        writeln!(out, "}}")?;
+
+        Ok(())
+    }
+}
+
 #[derive(Deserialize)]
 #[cfg_attr(debug_assertions, derive(Debug, Eq, PartialEq))]
 struct RequestFormat {
@@ -117,6 +207,12 @@ struct RequestFormat {
     body: Vec<Field>,
 }
 
+impl RequestFormat {
+    fn starts_with_endian(&self) -> bool {
+        matches!(self.body.first(), Some(field) if field.ty.inner_name() == Some("Endian"))
+    }
+}
+
 #[derive(Deserialize)]
 #[cfg_attr(debug_assertions, derive(Debug, Eq, PartialEq))]
 #[serde(transparent)]
@@ -127,6 +223,7 @@ struct StructFormat {
 impl StructFormat {
     pub fn write(&self, name: &str, out: &mut impl Write) -> io::Result<()> {
         writeln!(out, "#[derive(Clone, Debug, Eq, PartialEq)]")?;
+        writeln!(out, "{}", SERDE_DERIVE)?;
         write!(out, "pub struct {}", name)?;
         writeln!(out, "{{")?;
 
@@ -165,12 +262,12 @@ impl StructFormat {
         writeln!(out, "}}")?;
 
         writeln!(out, "fn size(&self) -> usize {{")?;
-        writeln!(out, "let mut content_size = 0;")?;
+        writeln!(out, "let mut content_size = 0usize;")?;
 
         for field in self.body.iter() {
-            write!(out, "content_size += ")?;
+            write!(out, "content_size = content_size.saturating_add(")?;
             field.ty.size(&format!("self.{}", field.name), out)?;
-            writeln!(out, ";")?;
+            writeln!(out, ");")?;
         }
 
         writeln!(out, "content_size")?;
@@ -178,6 +275,18 @@ impl StructFormat {
         // fn size
         writeln!(out, "}}")?;
 
+        writeln!(
+            out,
+            "fn write_vectored<'a>(&'a self, writer: &mut VectoredWriter<'a>) {{"
+        )?;
+        for field in self.body.iter() {
+            field
+                .ty
+                .write_vectored(&format!("self.{}", field.name), out)?;
+        }
+        // fn write_vectored
+        writeln!(out, "}}")?;
+
         // end impl
         writeln!(out, "}}")?;
 
@@ -188,6 +297,8 @@ impl StructFormat {
 #[derive(Deserialize)]
 #[cfg_attr(debug_assertions, derive(Debug, Eq, PartialEq))]
 struct XimFormat {
+    #[serde(rename = "Primitives", default)]
+    primitives: Vec<PrimitiveFormat>,
     #[serde(rename = "Enums")]
     enums: BTreeMap<String, EnumFormat>,
     #[serde(rename = "AttributeNames")]
@@ -200,6 +311,10 @@ struct XimFormat {
 
 impl XimFormat {
     pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        for primitive in self.primitives.iter() {
+            primitive.write(out)?;
+        }
+
         for (name, em) in self.enums.iter() {
             em.write(name, out)?;
         }
@@ -212,6 +327,7 @@ impl XimFormat {
             out,
             "#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]"
         )?;
+        writeln!(out, "{}", SERDE_DERIVE)?;
         writeln!(out, "pub enum AttributeName {{")?;
         for (key, _value) in self.attribute_names.iter() {
             writeln!(out, "{},", key)?;
@@ -267,6 +383,9 @@ impl XimFormat {
         writeln!(out, "}}")?;
 
         writeln!(out, "#[derive(Debug, Clone, Eq, PartialEq)]")?;
+        writeln!(out, "{}", SERDE_DERIVE)?;
+        // new requests shouldn't break downstream matches that already have a wildcard arm
+        writeln!(out, "#[non_exhaustive]")?;
         writeln!(out, "pub enum Request {{")?;
 
         for (name, req) in self.requests.iter() {
@@ -289,6 +408,72 @@ impl XimFormat {
         writeln!(out, "}}")?;
         // fn name
         writeln!(out, "}}")?;
+
+        for id in ["input_method_id", "input_context_id"] {
+            writeln!(out, "/// The `{}` of the request, if it has one.", id)?;
+            writeln!(out, "pub fn {}(&self) -> Option<u16> {{", id)?;
+            writeln!(out, "match self {{")?;
+            for (name, req) in self.requests.iter() {
+                if req.body.iter().any(|field| field.name == id) {
+                    writeln!(out, "Request::{} {{ {}, .. }} => Some(*{}),", name, id, id)?;
+                }
+            }
+            writeln!(out, "_ => None,")?;
+            // match
+            writeln!(out, "}}")?;
+            // fn
+            writeln!(out, "}}")?;
+        }
+
+        writeln!(
+            out,
+            "/// The major opcode and, if the request has one, the minor opcode."
+        )?;
+        writeln!(out, "pub fn opcode(&self) -> (u8, Option<u8>) {{")?;
+        writeln!(out, "match self {{")?;
+        for (name, req) in self.requests.iter() {
+            write!(out, "Request::{} {{ .. }} => ({}, ", name, req.major_opcode)?;
+            match req.minor_opcode {
+                Some(minor) => writeln!(out, "Some({})),", minor)?,
+                None => writeln!(out, "None),")?,
+            }
+        }
+        // match
+        writeln!(out, "}}")?;
+        // fn opcode
+        writeln!(out, "}}")?;
+
+        writeln!(
+            out,
+            "/// Check the internal consistency of the request: every string and list must fit its"
+        )?;
+        writeln!(
+            out,
+            "/// length prefix and the whole request must fit the 16-bit length of the header."
+        )?;
+        writeln!(out, "pub fn validate(&self) -> Result<(), ReadError> {{")?;
+        writeln!(out, "match self {{")?;
+        for (name, req) in self.requests.iter() {
+            let mut checks = Vec::new();
+            write!(out, "Request::{} {{", name)?;
+            for field in req.body.iter() {
+                let start = checks.len();
+                field.ty.validate(&field.name, &field.name, &mut checks)?;
+                if checks.len() > start {
+                    write!(out, "{}, ", field.name)?;
+                }
+            }
+            writeln!(out, ".. }} => {{")?;
+            out.write_all(&checks)?;
+            writeln!(out, "}}")?;
+        }
+        // match
+        writeln!(out, "}}")?;
+        writeln!(out, "let length = (self.size() - 4) / 4; if length > u16::MAX as usize {{ return Err(ReadError::InvalidData(\"Length\", alloc::format!(\"{{}} words don't fit u16\", length))); }}")?;
+        writeln!(out, "Ok(())")?;
+        // fn validate
+        writeln!(out, "}}")?;
+
         // impl Request
         writeln!(out, "}}")?;
 
@@ -301,12 +486,35 @@ impl XimFormat {
 
         writeln!(
             out,
-            "let major_opcode = reader.u8()?; let minor_opcode = reader.u8()?; let _length = reader.u16()?;"
+            "let major_opcode = reader.u8()?; let minor_opcode = reader.u8()?;"
+        )?;
+
+        // the length of a request starting with the byte order is already in that byte order
+        for req in self
+            .requests
+            .values()
+            .filter(|req| req.starts_with_endian())
+        {
+            writeln!(
+                out,
+                "if major_opcode == {} {{ reader.peek_endian(2)?; }}",
+                req.major_opcode
+            )?;
+        }
+
+        // bound the body by the declared length so a bad field can't read into the next request
+        writeln!(
+            out,
+            "let length = reader.u16()? as usize * 4; let endian = reader.endian(); let reader = &mut Reader::with_endian(reader.consume(length)?, endian);"
         )?;
 
         writeln!(out, "match (major_opcode, minor_opcode) {{")?;
 
-        for (name, req) in self.requests.iter() {
+        // ascending opcodes let the compiler turn the match into a jump table
+        let mut requests = self.requests.iter().collect::<Vec<_>>();
+        requests.sort_by_key(|(_, req)| (req.major_opcode, req.minor_opcode.unwrap_or(0)));
+
+        for (name, req) in requests {
             write!(out, "({}, ", req.major_opcode)?;
 
             if let Some(minor) = req.minor_opcode {
@@ -366,7 +574,7 @@ impl XimFormat {
         writeln!(out, "}}")?;
 
         writeln!(out, "fn size(&self) -> usize {{")?;
-        writeln!(out, "let mut content_size = 0;")?;
+        writeln!(out, "let mut content_size = 0usize;")?;
 
         writeln!(out, "match self {{")?;
 
@@ -378,9 +586,9 @@ impl XimFormat {
             writeln!(out, "}} => {{")?;
 
             for field in req.body.iter() {
-                write!(out, "content_size += ")?;
+                write!(out, "content_size = content_size.saturating_add(")?;
                 field.ty.size(&field.name, out)?;
-                writeln!(out, ";")?;
+                writeln!(out, ");")?;
             }
 
             writeln!(out, "}}")?;
@@ -388,15 +596,251 @@ impl XimFormat {
 
         // match
         writeln!(out, "}}")?;
-        writeln!(out, "content_size + 4")?;
+        writeln!(out, "content_size.saturating_add(4)")?;
 
         // fn size
         writeln!(out, "}}")?;
 
+        writeln!(
+            out,
+            "fn write_vectored<'a>(&'a self, writer: &mut VectoredWriter<'a>) {{"
+        )?;
+        writeln!(out, "match self {{")?;
+
+        for (name, req) in self.requests.iter() {
+            writeln!(out, "Request::{} {{", name)?;
+            for field in req.body.iter() {
+                write!(out, "{}, ", field.name)?;
+            }
+            writeln!(out, "}} => {{")?;
+
+            writeln!(out, "writer.copy(&{}u8);", req.major_opcode)?;
+            writeln!(out, "writer.copy(&{}u8);", req.minor_opcode.unwrap_or(0))?;
+            writeln!(out, "writer.copy(&(((self.size() - 4) / 4) as u16));")?;
+
+            for field in req.body.iter() {
+                field.ty.write_vectored(&field.name, out)?;
+            }
+
+            writeln!(out, "}}")?;
+        }
+
+        // match
+        writeln!(out, "}}")?;
+        // fn write_vectored
+        writeln!(out, "}}")?;
+
         // impl XimWrite
         writeln!(out, "}}")?;
 
+        self.write_default_requests(out)
+    }
+
+    /// `Request::default_for` and a test round-tripping every request it builds.
+    fn write_default_requests(&self, out: &mut impl Write) -> io::Result<()> {
+        let primitives = self
+            .primitives
+            .iter()
+            .map(PrimitiveFormat::name)
+            .collect::<Vec<_>>();
+        let is_default = |name: &str| {
+            matches!(name, "bool" | "Str8" | "Str16") || primitives.iter().any(|p| p == name)
+        };
+
+        let mut defaults = Vec::new();
+
+        writeln!(out, "impl Request {{")?;
+        writeln!(
+            out,
+            "/// The request `name` with every field at its default, `None` for the requests with a field without one."
+        )?;
+        writeln!(out, "pub fn default_for(name: &str) -> Option<Request> {{")?;
+        writeln!(out, "match name {{")?;
+        for (name, req) in self.requests.iter() {
+            let non_default = req
+                .body
+                .iter()
+                .find_map(|field| field.ty.non_default(is_default));
+
+            match non_default {
+                Some(ty) => writeln!(out, "// {} is skipped, {} has no default", name, ty)?,
+                None => {
+                    write!(out, "\"{}\" => Some(Request::{} {{", name, name)?;
+                    for field in req.body.iter() {
+                        write!(out, "{}: Default::default(),", field.name)?;
+                    }
+                    writeln!(out, "}}),")?;
+                    defaults.push(name);
+                }
+            }
+        }
+        writeln!(out, "_ => None,")?;
+        // match
+        writeln!(out, "}}")?;
+        // fn default_for
+        writeln!(out, "}}")?;
+        // impl Request
+        writeln!(out, "}}")?;
+
+        writeln!(out, "#[cfg(test)]")?;
+        writeln!(out, "#[test]")?;
+        writeln!(out, "fn default_requests_round_trip() {{")?;
+        write!(out, "for name in [")?;
+        for name in defaults {
+            write!(out, "\"{}\",", name)?;
+        }
+        writeln!(out, "] {{")?;
+        writeln!(out, "let req = Request::default_for(name).unwrap();")?;
+        writeln!(out, "let mut out = alloc::vec![0; req.size()];")?;
+        writeln!(out, "req.write(&mut Writer::new(&mut out));")?;
+        writeln!(
+            out,
+            "assert_eq!(Request::read(&mut Reader::new(&out)).unwrap(), req, \"{{}}\", name);"
+        )?;
+        writeln!(out, "}}")?;
+        writeln!(out, "}}")?;
+
+        Ok(())
+    }
+}
+
+/// Types written by hand in `res/snippet.rs` that fields may use besides the ones in the format.
+const SNIPPET_TYPES: &[&str] = &[
+    "bool",
+    "Endian",
+    "CommitData",
+    "StatusContent",
+    "InputStyleList",
+    "HotKeyTriggers",
+    "Str8",
+    "Str16",
+];
+
+/// A problem found by [`validate_format`].
+#[derive(Debug, Eq, PartialEq)]
+pub enum FormatError {
+    /// The YAML itself couldn't be parsed.
+    Parse(String),
+    /// Two requests use the same opcodes.
+    DuplicateOpcode {
+        first: String,
+        second: String,
+        major_opcode: u8,
+        minor_opcode: Option<u8>,
+    },
+    /// An enum has a `repr` that isn't one of the primitives.
+    InvalidRepr { name: String, repr: String },
+    /// A field refers to a type that is neither in the format nor in the snippet.
+    UnknownType {
+        owner: String,
+        field: String,
+        ty: String,
+    },
+}
+
+impl std::fmt::Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatError::Parse(e) => write!(f, "invalid format: {}", e),
+            FormatError::DuplicateOpcode {
+                first,
+                second,
+                major_opcode,
+                minor_opcode,
+            } => write!(
+                f,
+                "{} and {} both use opcode ({}, {:?})",
+                first, second, major_opcode, minor_opcode
+            ),
+            FormatError::InvalidRepr { name, repr } => {
+                write!(f, "enum {} has invalid repr {}", name, repr)
+            }
+            FormatError::UnknownType { owner, field, ty } => {
+                write!(f, "{}.{} has unknown type {}", owner, field, ty)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+impl XimFormat {
+    fn check(&self) -> Vec<FormatError> {
+        let mut errors = Vec::new();
+
+        let mut opcodes = BTreeMap::new();
+        for (name, req) in self.requests.iter() {
+            let opcode = (req.major_opcode, req.minor_opcode);
+            if let Some(first) = opcodes.insert(opcode, name) {
+                errors.push(FormatError::DuplicateOpcode {
+                    first: first.clone(),
+                    second: name.clone(),
+                    major_opcode: opcode.0,
+                    minor_opcode: opcode.1,
+                });
+            }
+        }
+
+        let primitives = self
+            .primitives
+            .iter()
+            .map(PrimitiveFormat::name)
+            .collect::<Vec<_>>();
+
+        for (name, e) in self.enums.iter() {
+            if !primitives.contains(&e.repr) {
+                errors.push(FormatError::InvalidRepr {
+                    name: name.clone(),
+                    repr: e.repr.clone(),
+                });
+            }
+        }
+
+        let bodies = self
+            .structs
+            .iter()
+            .map(|(name, s)| (name, &s.body))
+            .chain(self.requests.iter().map(|(name, r)| (name, &r.body)));
+
+        for (owner, body) in bodies {
+            for field in body {
+                let mut names = Vec::new();
+                field.ty.normal_names(&mut names);
+
+                for ty in names {
+                    // `AttributeName` is generated from `AttributeNames`
+                    if !SNIPPET_TYPES.contains(&ty)
+                        && !primitives.iter().any(|p| p == ty)
+                        && ty != "AttributeName"
+                        && !self.enums.contains_key(ty)
+                        && !self.structs.contains_key(ty)
+                    {
+                        errors.push(FormatError::UnknownType {
+                            owner: owner.clone(),
+                            field: field.name.clone(),
+                            ty: ty.into(),
+                        });
+                    }
+                }
+            }
+        }
+
+        errors
+    }
+}
+
+/// Parse a protocol YAML and run every consistency check on it without generating anything,
+/// returning all the problems found.
+pub fn validate_format(format_str: &str) -> Result<(), Vec<FormatError>> {
+    let format: XimFormat =
+        serde_yaml::from_str(format_str).map_err(|e| vec![FormatError::Parse(e.to_string())])?;
+
+    let errors = format.check();
+
+    if errors.is_empty() {
         Ok(())
+    } else {
+        Err(errors)
     }
 }
 
@@ -406,6 +850,10 @@ pub fn write_format(
 ) -> Result<(), Box<dyn std::error::Error>> {
     let format: XimFormat = serde_yaml::from_str(format_str)?;
 
+    if let Some(e) = format.check().into_iter().next() {
+        return Err(e.into());
+    }
+
     let mut file = std::io::BufWriter::new(std::fs::File::create(out_path.as_ref())?);
 
     file.write_all(include_bytes!("../res/snippet.rs"))?;
@@ -423,3 +871,153 @@ pub fn write_format(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{FormatError, XimFormat};
+
+    #[test]
+    fn request_is_non_exhaustive() {
+        let format: XimFormat =
+            serde_yaml::from_str(include_str!("../../xim-parser/xim-format.yaml")).unwrap();
+        let mut out = Vec::new();
+        format.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains("#[non_exhaustive]\npub enum Request {"));
+    }
+
+    #[test]
+    fn list_size_saturates() {
+        let ty: crate::format_type::FormatType = "@padadd2 @list Attribute".parse().unwrap();
+        let mut out = Vec::new();
+        ty.size("attrs", &mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "usize::saturating_add(with_pad4(usize::saturating_add(attrs.iter().fold(0usize, |acc, e| usize::saturating_add(acc, e.size())), 0 + 2) - 2), 2)"
+        );
+    }
+
+    #[test]
+    fn read_arms_sorted_by_opcode() {
+        let format: XimFormat =
+            serde_yaml::from_str(include_str!("../../xim-parser/xim-format.yaml")).unwrap();
+        let mut out = Vec::new();
+        format.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let read = &out[out.find("match (major_opcode, minor_opcode) {").unwrap()..];
+        let opcodes = read
+            .lines()
+            .skip(1)
+            .take_while(|line| !line.starts_with("_ =>"))
+            .filter_map(|line| line.strip_prefix('('))
+            .map(|line| {
+                let (major, rest) = line.split_once(", ").unwrap();
+                let minor = rest.split(')').next().unwrap();
+                (
+                    major.parse::<u8>().unwrap(),
+                    minor.parse::<u8>().unwrap_or(0),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(opcodes.len(), format.requests.len());
+        assert!(opcodes.windows(2).all(|w| w[0] < w[1]), "{:?}", opcodes);
+    }
+
+    #[test]
+    fn primitives_generated_from_table() {
+        let format: XimFormat = serde_yaml::from_str(
+            r#"
+Primitives:
+  - width: 1
+  - width: 2
+  - width: 4
+    signed: true
+Enums: {}
+AttributeNames: {}
+Structs: {}
+Requests: {}
+"#,
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        format.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        for (name, width) in [("u8", 1), ("u16", 2), ("i32", 4)] {
+            assert!(out.contains(&format!(
+                "pub fn {}(&mut self) -> Result<{}, ReadError> {{\nlet bytes = self.consume({})?",
+                name, name, width
+            )));
+            assert!(out.contains(&format!("impl XimRead for {} {{", name)));
+            assert!(out.contains(&format!("impl XimWrite for {} {{", name)));
+        }
+        assert!(!out.contains("impl XimRead for u32 {"));
+    }
+
+    #[test]
+    fn validate_reports_every_error() {
+        assert_eq!(
+            super::validate_format(include_str!("../../xim-parser/xim-format.yaml")),
+            Ok(())
+        );
+
+        let format = r#"
+Enums:
+  Flag:
+    repr: f32
+    variants:
+      A: 1
+AttributeNames: {}
+Structs: {}
+Requests:
+  Foo:
+    major_opcode: 1
+    body: []
+  Bar:
+    major_opcode: 1
+    body: []
+"#;
+
+        assert_eq!(
+            super::validate_format(format),
+            Err(vec![
+                FormatError::DuplicateOpcode {
+                    first: "Bar".into(),
+                    second: "Foo".into(),
+                    major_opcode: 1,
+                    minor_opcode: None,
+                },
+                FormatError::InvalidRepr {
+                    name: "Flag".into(),
+                    repr: "f32".into(),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn generated_file_allows_unused() {
+        let path = std::env::temp_dir().join(format!("xim-gen-{}.rs", std::process::id()));
+        super::write_format(include_str!("../../xim-parser/xim-format.yaml"), &path).unwrap();
+        let out = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let attrs = out
+            .lines()
+            .filter(|line| !line.starts_with("//") && !line.is_empty())
+            .take_while(|line| line.starts_with("#!["))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            attrs,
+            [
+                "#![allow(clippy::identity_op)]",
+                "#![allow(dead_code, unused_imports)]"
+            ]
+        );
+    }
+}
@@ -1,10 +1,57 @@
 use crate::{Attr, AttrType, AttributeName};
 
+impl Attr {
+    /// Declare a `CARD32` attribute.
+    pub const fn long(id: u16, name: AttributeName) -> Self {
+        Self {
+            id,
+            ty: AttrType::Long,
+            name,
+        }
+    }
+
+    /// Declare a `Window` attribute.
+    pub const fn window(id: u16, name: AttributeName) -> Self {
+        Self {
+            id,
+            ty: AttrType::Window,
+            name,
+        }
+    }
+
+    /// Declare an `XIMStyles` attribute.
+    pub const fn style(id: u16, name: AttributeName) -> Self {
+        Self {
+            id,
+            ty: AttrType::Style,
+            name,
+        }
+    }
+
+    /// Declare an `XPoint` attribute.
+    pub const fn point(id: u16, name: AttributeName) -> Self {
+        Self {
+            id,
+            ty: AttrType::XPoint,
+            name,
+        }
+    }
+
+    /// Declare a nested list attribute, its values end with the separator attribute.
+    pub const fn nested_list(id: u16, name: AttributeName) -> Self {
+        Self {
+            id,
+            ty: AttrType::NestedList,
+            name,
+        }
+    }
+}
+
 macro_rules! define_attrs {
     ($(($name:ident, $attr_name:expr, $ty:expr),)+) => {
         pub const fn get_name(id: u16) -> Option<AttributeName> {
             $(
-                if id == $attr_name as _ {
+                if id == $attr_name as u16 {
                     return Some($attr_name);
                 }
             )+
@@ -44,5 +91,6 @@ define_attrs! {
     (BACKGROUND_PIXMAP, AttributeName::BackgroundPixmap, AttrType::Long),
     (SPOT_LOCATION, AttributeName::SpotLocation, AttrType::XPoint),
     (LINE_SPACE, AttributeName::LineSpace, AttrType::Long),
+    (LOCALE, AttributeName::Locale, AttrType::Char),
     (SEPARATOR_OF_NESTED_LIST, AttributeName::SeparatorofNestedList, AttrType::Separator),
 }
@@ -15,6 +15,7 @@ extern crate alloc;
 extern crate std;
 
 use alloc::vec::Vec;
+use core::convert::TryFrom;
 
 pub mod attrs;
 mod parser;
@@ -33,9 +34,354 @@ pub fn write_to_vec(f: impl XimWrite) -> Vec<u8> {
     out
 }
 
+/// Like [`write_to_vec`] with zeros after the item up to a multiple of 4 bytes, so items can be
+/// concatenated the way the transport packs requests.
+pub fn write_to_vec_aligned(f: impl XimWrite) -> Vec<u8> {
+    let mut out = write_to_vec(f);
+    out.resize((out.len() + 3) & !3, 0);
+    out
+}
+
+/// The buffer passed to [`write_to_slice`] can't hold the item.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BufferTooSmall {
+    pub needed: usize,
+    pub available: usize,
+}
+
+impl core::fmt::Display for BufferTooSmall {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Buffer too small: {} bytes needed, {} available",
+            self.needed, self.available
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BufferTooSmall {}
+
+/// Write `f` at the start of `out` without allocating, returns the number of bytes written.
+pub fn write_to_slice(
+    f: impl XimWrite,
+    out: &mut [u8],
+    endian: Endian,
+) -> Result<usize, BufferTooSmall> {
+    let needed = f.size();
+    let available = out.len();
+    let out = out
+        .get_mut(..needed)
+        .ok_or(BufferTooSmall { needed, available })?;
+    f.write(&mut Writer::with_endian(out, endian));
+    Ok(needed)
+}
+
+/// Like [`write_to_slice`] into a zeroed `[u8; N]` on the stack.
+pub fn write_to_array<const N: usize>(
+    f: impl XimWrite,
+    endian: Endian,
+) -> Result<([u8; N], usize), BufferTooSmall> {
+    let mut out = [0; N];
+    let len = write_to_slice(f, &mut out, endian)?;
+    Ok((out, len))
+}
+
+/// The 4 byte header every request starts with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RequestHeader {
+    pub major_opcode: u8,
+    pub minor_opcode: u8,
+    /// Length of the body after the header in bytes.
+    pub length: usize,
+}
+
+/// Read the header of the request at the start of `buf` without reading its body, which may
+/// not be there yet.
+///
+/// The length is in `endian`, except for `Connect` which gives its own byte order. Returns
+/// `None` when `buf` is too short to hold the header.
+pub fn peek_header(buf: &[u8], endian: Endian) -> Option<RequestHeader> {
+    let (&major_opcode, &minor_opcode) = (buf.first()?, buf.get(1)?);
+    let endian = if major_opcode == 1 {
+        read_with_endian(buf.get(4..5)?, endian).ok()?
+    } else {
+        endian
+    };
+    let length = read_with_endian::<u16>(buf.get(2..4)?, endian).ok()? as usize * 4;
+
+    Some(RequestHeader {
+        major_opcode,
+        minor_opcode,
+        length,
+    })
+}
+
+/// Extensions negotiated with `QueryExtension`, looked up by their assigned opcodes.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ExtensionTable {
+    extensions: Vec<Extension>,
+}
+
+impl ExtensionTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a negotiated extension, replacing an earlier one with the same name.
+    pub fn insert(&mut self, extension: Extension) {
+        match self
+            .extensions
+            .iter_mut()
+            .find(|e| e.name == extension.name)
+        {
+            Some(e) => *e = extension,
+            None => self.extensions.push(extension),
+        }
+    }
+
+    /// The extension assigned to a request's opcodes.
+    pub fn get(&self, major_opcode: u8, minor_opcode: u8) -> Option<&Extension> {
+        self.extensions
+            .iter()
+            .find(|e| e.major_opcode == major_opcode && e.minor_opcode == minor_opcode)
+    }
+
+    pub fn get_by_name(&self, name: &str) -> Option<&Extension> {
+        self.extensions.iter().find(|e| e.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Extension> + '_ {
+        self.extensions.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.extensions.is_empty()
+    }
+}
+
+impl Extend<Extension> for ExtensionTable {
+    fn extend<I: IntoIterator<Item = Extension>>(&mut self, iter: I) {
+        for extension in iter {
+            self.insert(extension);
+        }
+    }
+}
+
+impl Attribute {
+    /// An attribute with `value` serialized.
+    pub fn new(id: u16, value: impl XimWrite) -> Self {
+        Self {
+            id,
+            value: write_to_vec(value),
+        }
+    }
+
+    /// Read the value back as `T`.
+    pub fn decode<T: XimRead>(&self) -> Result<T, ReadError> {
+        read(&self.value)
+    }
+}
+
+impl Request {
+    /// The attributes of a `GetImValuesReply` or `GetIcValuesReply` as `(id, value)`, nothing
+    /// for other requests.
+    pub fn attributes(&self) -> impl Iterator<Item = (u16, &[u8])> {
+        let attrs: &[Attribute] = match self {
+            Request::GetImValuesReply { im_attributes, .. } => im_attributes,
+            Request::GetIcValuesReply { ic_attributes, .. } => ic_attributes,
+            _ => &[],
+        };
+
+        attrs.iter().map(|attr| (attr.id, attr.value.as_slice()))
+    }
+
+    /// Read the value of the first attribute with `id` from [`attributes`](Self::attributes).
+    pub fn find_attribute<T: XimRead>(&self, id: u16) -> Option<Result<T, ReadError>> {
+        self.attributes()
+            .find(|&(attr_id, _)| attr_id == id)
+            .map(|(_, value)| read(value))
+    }
+
+    /// The preedit string of a `PreeditDraw` in the encoding the input method negotiated,
+    /// `None` for other requests.
+    pub fn preedit_text(&self) -> Option<&[u8]> {
+        match self {
+            Request::PreeditDraw { preedit_string, .. } => Some(preedit_string),
+            _ => None,
+        }
+    }
+
+    /// The feedback array of a `PreeditDraw` as it was sent, each entry as `(position, feedback)`
+    /// with positions in the whole preedit starting at `chg_first`.
+    ///
+    /// Nothing is expanded or checked against `preedit_text`: a server sending fewer entries than
+    /// characters leaves the rest without one. Nothing for other requests, or when the status has
+    /// `NO_FEEDBACK`.
+    pub fn raw_preedit_feedbacks(&self) -> impl Iterator<Item = (usize, Feedback)> + '_ {
+        let (first, feedbacks): (usize, &[Feedback]) = match self {
+            Request::PreeditDraw {
+                chg_first,
+                feedbacks,
+                ..
+            } => (usize::try_from(*chg_first).unwrap_or(0), feedbacks),
+            _ => (0, &[]),
+        };
+
+        feedbacks
+            .iter()
+            .enumerate()
+            .map(move |(i, feedback)| (first + i, *feedback))
+    }
+
+    /// Compare like `==` but take any serial and flag of a `ForwardEvent` as equal.
+    ///
+    /// The serial of a `ForwardEvent` is split in its `serial_number`, the high 16 bits, and
+    /// the `sequence` of the event, the low 16 bits, both are ignored.
+    pub fn eq_ignoring_serial(&self, other: &Request) -> bool {
+        match (self, other) {
+            (
+                Request::ForwardEvent {
+                    input_method_id,
+                    input_context_id,
+                    xev,
+                    ..
+                },
+                Request::ForwardEvent {
+                    input_method_id: other_input_method_id,
+                    input_context_id: other_input_context_id,
+                    xev: other_xev,
+                    ..
+                },
+            ) => {
+                input_method_id == other_input_method_id
+                    && input_context_id == other_input_context_id
+                    && XEvent {
+                        sequence: other_xev.sequence,
+                        ..xev.clone()
+                    } == *other_xev
+            }
+            _ => self == other,
+        }
+    }
+}
+
+impl InputStyle {
+    /// OverTheSpot: the client draws the preedit at the spot location.
+    pub fn is_over_the_spot(self) -> bool {
+        self.contains(Self::PREEDIT_POSITION)
+    }
+
+    /// OnTheSpot: the client draws the preedit through the preedit callbacks.
+    pub fn is_on_the_spot(self) -> bool {
+        self.contains(Self::PREEDIT_CALLBACKS)
+    }
+
+    /// Root window: the server draws the preedit in its own window.
+    pub fn is_root_window(self) -> bool {
+        self.contains(Self::PREEDIT_NOTHING)
+    }
+
+    /// OffTheSpot: the preedit is drawn in an area of the client window.
+    pub fn has_preedit_area(self) -> bool {
+        self.contains(Self::PREEDIT_AREA)
+    }
+
+    /// The status is drawn in an area of the client window.
+    pub fn has_status_area(self) -> bool {
+        self.contains(Self::STATUS_AREA)
+    }
+}
+
+/// The wire form of an [`XEvent`], always [`XEventBytes::LEN`] bytes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct XEventBytes([u8; XEventBytes::LEN]);
+
+impl XEventBytes {
+    /// Size of an X event on the wire.
+    pub const LEN: usize = 32;
+
+    /// Serialize `ev` in `endian`.
+    pub fn from_event(ev: &XEvent, endian: Endian) -> Self {
+        let mut out = [0; Self::LEN];
+        write_with_endian(ev, &mut out, endian);
+        Self(out)
+    }
+
+    /// Parse the event in `endian`.
+    pub fn read_event(&self, endian: Endian) -> Result<XEvent, ReadError> {
+        read_with_endian(&self.0, endian)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; Self::LEN] {
+        &self.0
+    }
+}
+
+impl core::convert::TryFrom<&[u8]> for XEventBytes {
+    type Error = ReadError;
+
+    fn try_from(b: &[u8]) -> Result<Self, ReadError> {
+        <[u8; Self::LEN]>::try_from(b)
+            .map(Self)
+            .map_err(|_| ReadError::InvalidData("XEventBytes", alloc::format!("{} bytes", b.len())))
+    }
+}
+
+/// Encode a nested attribute list like `preeditAttributes`, terminated by an empty
+/// [`attrs::SEPARATOR_OF_NESTED_LIST`].
+pub fn encode_nested_attributes(attrs: &[Attribute]) -> Vec<u8> {
+    encode_nested_groups(&[attrs])
+}
+
+/// Encode several nested attribute lists one after another, each terminated by an empty
+/// [`attrs::SEPARATOR_OF_NESTED_LIST`] so exactly one separator sits between two groups.
+pub fn encode_nested_groups(groups: &[&[Attribute]]) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    for group in groups {
+        for attr in group.iter() {
+            write_extend_vec(attr, &mut out);
+        }
+
+        write_extend_vec(
+            Attribute {
+                id: attrs::SEPARATOR_OF_NESTED_LIST.id,
+                value: Vec::new(),
+            },
+            &mut out,
+        );
+    }
+
+    out
+}
+
+/// Decode a nested attribute list, stopping at [`attrs::SEPARATOR_OF_NESTED_LIST`] or at the end
+/// of `b`.
+pub fn decode_nested_attributes(b: &[u8]) -> Result<Vec<Attribute>, ReadError> {
+    let mut reader = Reader::new(b);
+    let mut out = Vec::new();
+
+    while reader.cursor() > 0 {
+        let attr = Attribute::read(&mut reader)?;
+
+        if attr.id == attrs::SEPARATOR_OF_NESTED_LIST.id {
+            break;
+        }
+
+        out.push(attr);
+    }
+
+    Ok(out)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{parser::*, write_to_vec};
+    use crate::{
+        attrs, decode_nested_attributes, encode_nested_attributes, encode_nested_groups, parser::*,
+        peek_header, write_to_array, write_to_slice, write_to_vec, write_to_vec_aligned,
+        BufferTooSmall, XEventBytes,
+    };
     use alloc::vec;
     use alloc::vec::Vec;
     use pretty_assertions::assert_eq;
@@ -43,7 +389,7 @@ mod tests {
     #[cfg(target_endian = "little")]
     #[test]
     fn read_connect_req() {
-        let req: Request = read(b"\x01\x00\x00\x00\x6c\x00\x00\x00\x00\x00\x00\x00").unwrap();
+        let req: Request = read(b"\x01\x00\x02\x00\x6c\x00\x00\x00\x00\x00\x00\x00").unwrap();
 
         assert_eq!(
             req,
@@ -56,6 +402,169 @@ mod tests {
         );
     }
 
+    #[test]
+    fn primitives_round_trip() {
+        for endian in [Endian::Big, Endian::Little] {
+            let mut out = [0u8; 7];
+            let mut writer = Writer::with_endian(&mut out, endian);
+            0xabu8.write(&mut writer);
+            0x1234u16.write(&mut writer);
+            0xdead_beefu32.write(&mut writer);
+            assert_eq!(writer.len(), 7);
+
+            match endian {
+                Endian::Big => assert_eq!(out, [0xab, 0x12, 0x34, 0xde, 0xad, 0xbe, 0xef]),
+                _ => assert_eq!(out, [0xab, 0x34, 0x12, 0xef, 0xbe, 0xad, 0xde]),
+            }
+
+            let mut reader = Reader::with_endian(&out, endian);
+            assert_eq!(u8::read(&mut reader).unwrap(), 0xab);
+            assert_eq!(u16::read(&mut reader).unwrap(), 0x1234);
+            assert_eq!(u32::read(&mut reader).unwrap(), 0xdead_beef);
+            assert!(matches!(u8::read(&mut reader), Err(ReadError::EndOfStream)));
+        }
+    }
+
+    #[test]
+    fn aligned_vec() {
+        let s = Str8("hello".into());
+        assert_eq!(s.size(), 6);
+        assert_eq!(write_to_vec_aligned(&s), b"\x05hello\0\0");
+
+        let req = Request::Disconnect {};
+        assert_eq!(write_to_vec_aligned(&req), write_to_vec(&req));
+    }
+
+    #[test]
+    fn preedit_draw_feedbacks() {
+        let req = Request::PreeditDraw {
+            input_method_id: 1,
+            input_context_id: 1,
+            caret: 5,
+            chg_first: 2,
+            chg_length: 0,
+            status: PreeditDrawStatus::empty(),
+            preedit_string: b"abc".to_vec(),
+            feedbacks: vec![Feedback::REVERSE, Feedback::UNDERLINE, Feedback::UNDERLINE],
+        };
+
+        assert_eq!(req.preedit_text(), Some(&b"abc"[..]));
+        assert_eq!(
+            req.raw_preedit_feedbacks().collect::<Vec<_>>(),
+            [
+                (2, Feedback::REVERSE),
+                (3, Feedback::UNDERLINE),
+                (4, Feedback::UNDERLINE)
+            ]
+        );
+        assert_eq!(Request::Disconnect {}.preedit_text(), None);
+        assert_eq!(Request::Disconnect {}.raw_preedit_feedbacks().count(), 0);
+
+        // the array is returned as sent, even shorter than the text
+        let mut short = req;
+        if let Request::PreeditDraw { feedbacks, .. } = &mut short {
+            feedbacks.truncate(1);
+        }
+        assert_eq!(
+            short.raw_preedit_feedbacks().collect::<Vec<_>>(),
+            [(2, Feedback::REVERSE)]
+        );
+    }
+
+    #[test]
+    fn forward_event_eq_ignoring_serial() {
+        let xev = XEvent {
+            response_type: 2,
+            detail: 38,
+            sequence: 10,
+            time: 1234,
+            root: 0,
+            event: 0,
+            child: 0,
+            root_x: 0,
+            root_y: 0,
+            event_x: 0,
+            event_y: 0,
+            state: 0,
+            same_screen: true,
+        };
+        let original = Request::ForwardEvent {
+            input_method_id: 1,
+            input_context_id: 1,
+            flag: ForwardEventFlag::SYNCHRONOUS,
+            serial_number: 3,
+            xev: xev.clone(),
+        };
+        let forwarded = Request::ForwardEvent {
+            input_method_id: 1,
+            input_context_id: 1,
+            flag: ForwardEventFlag::empty(),
+            serial_number: 0,
+            xev: XEvent {
+                sequence: 0,
+                ..xev.clone()
+            },
+        };
+        let other_key = Request::ForwardEvent {
+            input_method_id: 1,
+            input_context_id: 1,
+            flag: ForwardEventFlag::empty(),
+            serial_number: 0,
+            xev: XEvent { detail: 39, ..xev },
+        };
+
+        assert_ne!(original, forwarded);
+        assert!(original.eq_ignoring_serial(&forwarded));
+        assert!(forwarded.eq_ignoring_serial(&original));
+        assert!(!original.eq_ignoring_serial(&other_key));
+        assert!(!original.eq_ignoring_serial(&Request::Disconnect {}));
+    }
+
+    #[test]
+    fn read_big_endian_connect_req() {
+        let mut reader = Reader::new(b"\x01\x00\x00\x02\x42\x00\x00\x01\x00\x02\x00\x00");
+        let req = Request::read(&mut reader).unwrap();
+
+        assert_eq!(
+            req,
+            Request::Connect {
+                endian: Endian::Big,
+                client_auth_protocol_names: vec![],
+                client_minor_protocol_version: 2,
+                client_major_protocol_version: 1,
+            }
+        );
+        assert_eq!(reader.endian(), Endian::Big);
+    }
+
+    #[test]
+    fn read_bounded_by_length() {
+        let mut bytes = vec![
+            // Open declaring a 4 byte body while its locale claims 5 bytes
+            30, 0, 1, 0, 5, b'e', b'n', b'_',
+        ];
+        bytes.extend_from_slice(&write_to_vec(Request::Close { input_method_id: 3 }));
+
+        let mut reader = Reader::new(&bytes);
+        assert!(matches!(
+            Request::read(&mut reader),
+            Err(ReadError::EndOfStream)
+        ));
+        assert_eq!(
+            Request::read(&mut reader).unwrap(),
+            Request::Close { input_method_id: 3 }
+        );
+        reader.expect_consumed().unwrap();
+    }
+
+    #[test]
+    fn write_big_endian() {
+        let req = Request::Close { input_method_id: 3 };
+        let mut out = vec![0; req.size()];
+        write_with_endian(&req, &mut out, Endian::Big);
+        assert_eq!(out, [32, 0, 0, 1, 0, 3, 0, 0]);
+    }
+
     #[test]
     fn read_open() {
         let req = read::<Request>(&[
@@ -70,6 +579,43 @@ mod tests {
         );
     }
 
+    #[test]
+    fn str8_round_trip() {
+        for (s, bytes) in [("", &b"\0"[..]), ("en_US", b"\x05en_US")] {
+            let s = Str8(s.into());
+            assert_eq!(s.size(), bytes.len());
+            assert_eq!(write_to_vec(&s), bytes);
+            assert_eq!(read::<Str8>(bytes).unwrap(), s);
+        }
+    }
+
+    #[test]
+    fn str16_round_trip() {
+        for (s, pad) in [("", 2), ("ab", 0), ("abc", 3), ("COMPOUND_TEXT", 1)] {
+            let mut bytes = (s.len() as u16).to_ne_bytes().to_vec();
+            bytes.extend_from_slice(s.as_bytes());
+            bytes.extend_from_slice(&[0; 4][..pad]);
+
+            let s = Str16(s.into());
+            assert_eq!(s.size(), bytes.len());
+            assert_eq!(s.size() % 4, 0);
+            assert_eq!(write_to_vec(&s), bytes);
+            assert_eq!(read::<Str16>(&bytes).unwrap(), s);
+        }
+
+        // the padding must be there
+        assert!(matches!(
+            read::<Str16>(&[3, 0, b'a', b'b', b'c'][..]),
+            Err(ReadError::EndOfStream)
+        ));
+    }
+
+    #[test]
+    fn read_open_invalid_locale() {
+        let req = read::<Request>(&[30, 0, 2, 0, 5, 101, 110, 0xff, 85, 83, 0, 0]);
+        assert!(matches!(req, Err(ReadError::Utf8Error(_))));
+    }
+
     #[test]
     fn read_query() {
         let req = read::<Request>(&[
@@ -111,6 +657,69 @@ mod tests {
         write_to_vec(req);
     }
 
+    #[test]
+    fn commit_synchronous_roundtrip() {
+        for syncronous in [false, true] {
+            for data in [
+                CommitData::Chars {
+                    commited: b"a".to_vec(),
+                    syncronous,
+                },
+                CommitData::Keysym {
+                    keysym: 0x61,
+                    syncronous,
+                },
+                CommitData::Both {
+                    keysym: 0x61,
+                    commited: b"a".to_vec(),
+                    syncronous,
+                },
+            ] {
+                let out = write_to_vec(&data);
+                assert_eq!(out[0] & 1 == 1, syncronous);
+                assert_eq!(read::<CommitData>(&out).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn commit_synchronous_read() {
+        // XimLookupChars | XimSYNCHRONUS, one byte, pad
+        assert_eq!(
+            read::<CommitData>(&[3, 0, 1, 0, b'a', 0, 0, 0]).unwrap(),
+            CommitData::Chars {
+                commited: b"a".to_vec(),
+                syncronous: true,
+            }
+        );
+        // XimLookupKeySym | XimSYNCHRONUS, unused, keysym
+        assert_eq!(
+            read::<CommitData>(&[5, 0, 0, 0, 0x61, 0, 0, 0]).unwrap(),
+            CommitData::Keysym {
+                keysym: 0x61,
+                syncronous: true,
+            }
+        );
+    }
+
+    #[test]
+    fn enum_try_from() {
+        use core::convert::TryFrom;
+
+        assert_eq!(ErrorCode::try_from(2).unwrap(), ErrorCode::BadStyle);
+        assert_eq!(u16::from(ErrorCode::BadStyle), 2);
+        assert_eq!(u32::from(HotKeyState::Off), 2);
+
+        match ErrorCode::try_from(1000) {
+            Err(ReadError::InvalidData(name, value)) => {
+                assert_eq!(name, "ErrorCode");
+                assert_eq!(value, "1000");
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert!(HotKeyState::try_from(0).is_err());
+    }
+
     #[test]
     fn set_event_mask() {
         let req = Request::SetEventMask {
@@ -163,6 +772,62 @@ mod tests {
         assert_eq!(req, new_req);
     }
 
+    #[test]
+    fn attribute_round_trip() {
+        let styles = InputStyleList {
+            styles: vec![
+                InputStyle::PREEDIT_NOTHING | InputStyle::STATUS_NOTHING,
+                InputStyle::PREEDIT_POSITION | InputStyle::STATUS_NOTHING,
+            ],
+        };
+        let attr = Attribute::new(attrs::QUERY_INPUT_STYLE.id, &styles);
+
+        assert_eq!(attr.id, attrs::QUERY_INPUT_STYLE.id);
+        assert_eq!(attr.value, write_to_vec(&styles));
+        assert_eq!(attr.decode::<InputStyleList>().unwrap(), styles);
+        assert!(matches!(
+            Attribute::new(attrs::INPUT_STYLE.id, 1u16).decode::<u32>(),
+            Err(ReadError::EndOfStream)
+        ));
+    }
+
+    #[test]
+    fn find_im_reply_attribute() {
+        let styles = InputStyleList {
+            styles: vec![InputStyle::PREEDIT_POSITION | InputStyle::STATUS_AREA],
+        };
+        let req = Request::GetImValuesReply {
+            input_method_id: 3,
+            im_attributes: vec![
+                Attribute {
+                    id: attrs::QUERY_INPUT_STYLE.id,
+                    value: write_to_vec(&styles),
+                },
+                Attribute {
+                    id: attrs::FILTER_EVENTS.id,
+                    value: Vec::new(),
+                },
+            ],
+        };
+
+        assert_eq!(
+            req.attributes().map(|(id, _)| id).collect::<Vec<_>>(),
+            [attrs::QUERY_INPUT_STYLE.id, attrs::FILTER_EVENTS.id]
+        );
+        assert_eq!(
+            req.find_attribute::<InputStyleList>(attrs::QUERY_INPUT_STYLE.id)
+                .unwrap()
+                .unwrap(),
+            styles
+        );
+        assert!(req.find_attribute::<u32>(attrs::INPUT_STYLE.id).is_none());
+        assert!(matches!(
+            req.find_attribute::<u32>(attrs::FILTER_EVENTS.id),
+            Some(Err(ReadError::EndOfStream))
+        ));
+        assert_eq!(Request::Disconnect {}.attributes().count(), 0);
+    }
+
     #[test]
     fn spot_attr() {
         let value = [4, 0, 4, 0, 0, 0, 0, 0];
@@ -173,6 +838,289 @@ mod tests {
         assert_eq!(read::<Point>(&attr.value).unwrap(), Point { x: 0, y: 0 });
     }
 
+    #[test]
+    fn nested_attributes_roundtrip() {
+        let spot = Attribute {
+            id: crate::attrs::SPOT_LOCATION.id,
+            value: write_to_vec(Point { x: 12, y: -3 }),
+        };
+
+        let bytes = encode_nested_attributes(core::slice::from_ref(&spot));
+        assert_eq!(bytes.len(), 8 + 4);
+
+        let attrs = decode_nested_attributes(&bytes).unwrap();
+        assert_eq!(attrs, [spot]);
+        assert_eq!(
+            read::<Point>(&attrs[0].value).unwrap(),
+            Point { x: 12, y: -3 }
+        );
+    }
+
+    #[test]
+    fn nested_groups_separator() {
+        let spot = Attribute {
+            id: crate::attrs::SPOT_LOCATION.id,
+            value: write_to_vec(Point { x: 1, y: 2 }),
+        };
+        let line_space = Attribute {
+            id: crate::attrs::LINE_SPACE.id,
+            value: write_to_vec(16u32),
+        };
+        let separator = crate::attrs::SEPARATOR_OF_NESTED_LIST.id;
+
+        let bytes = encode_nested_groups(&[
+            core::slice::from_ref(&spot),
+            core::slice::from_ref(&line_space),
+        ]);
+
+        let mut reader = Reader::new(&bytes);
+        let mut ids = Vec::new();
+        while reader.remaining() > 0 {
+            ids.push(Attribute::read(&mut reader).unwrap().id);
+        }
+        assert_eq!(ids, [spot.id, separator, line_space.id, separator]);
+
+        assert_eq!(decode_nested_attributes(&bytes).unwrap(), [spot]);
+    }
+
+    #[test]
+    fn xevent_bytes() {
+        use core::convert::TryFrom;
+
+        assert!(XEventBytes::try_from(&[0u8; 31][..]).is_err());
+        assert!(XEventBytes::try_from(&[0u8; 33][..]).is_err());
+
+        let ev = XEvent {
+            response_type: 2,
+            detail: 38,
+            sequence: 1,
+            time: 2,
+            root: 3,
+            event: 4,
+            child: 5,
+            root_x: 6,
+            root_y: 7,
+            event_x: 8,
+            event_y: 9,
+            state: 1,
+            same_screen: true,
+        };
+        let bytes = XEventBytes::from_event(&ev, Endian::Big);
+        let parsed = XEventBytes::try_from(&bytes.as_bytes()[..]).unwrap();
+        assert_eq!(parsed, bytes);
+        assert_eq!(parsed.read_event(Endian::Big).unwrap(), ev);
+    }
+
+    #[test]
+    fn reader_writer_position() {
+        let bytes = [1, 0, 2, 0, 0, 0, 3, 0];
+        let mut reader = Reader::with_endian(&bytes, Endian::Little);
+        assert_eq!((reader.position(), reader.remaining()), (0, 8));
+
+        assert_eq!(u16::read(&mut reader).unwrap(), 1);
+        assert_eq!((reader.position(), reader.remaining()), (2, 6));
+        assert_eq!(u32::read(&mut reader).unwrap(), 2);
+        assert_eq!((reader.position(), reader.remaining()), (6, 2));
+
+        let mut out = [0; 8];
+        let mut writer = Writer::new(&mut out);
+        assert!(writer.is_empty());
+        1u16.write(&mut writer);
+        2u32.write(&mut writer);
+        assert_eq!(writer.len(), 6);
+    }
+
+    #[test]
+    fn validate_lengths() {
+        let req = Request::GetImValues {
+            input_method_id: 1,
+            im_attributes: vec![0; 4],
+        };
+        assert!(req.validate().is_ok());
+
+        // the list length doesn't fit its u16 prefix
+        let req = Request::GetImValues {
+            input_method_id: 1,
+            im_attributes: vec![0; 0x8000],
+        };
+        assert!(matches!(
+            req.validate(),
+            Err(ReadError::InvalidData("Length", _))
+        ));
+
+        let req = Request::Open {
+            locale: "a".repeat(256),
+        };
+        assert!(matches!(
+            req.validate(),
+            Err(ReadError::InvalidData("Length", _))
+        ));
+    }
+
+    #[test]
+    fn write_fixed_buffer() {
+        let req = Request::SyncReply {
+            input_method_id: 1,
+            input_context_id: 2,
+        };
+
+        let (out, len) = write_to_array::<32>(&req, Endian::Little).unwrap();
+        assert_eq!(len, req.size());
+        assert_eq!(out[..len], write_to_vec(&req)[..]);
+        assert!(out[len..].iter().all(|&b| b == 0));
+
+        let mut small = [0; 4];
+        assert_eq!(
+            write_to_slice(&req, &mut small, Endian::Little),
+            Err(BufferTooSmall {
+                needed: 8,
+                available: 4,
+            })
+        );
+        assert_eq!(small, [0; 4]);
+    }
+
+    #[test]
+    fn request_opcode() {
+        let req = Request::Connect {
+            endian: Endian::Native,
+            client_major_protocol_version: 1,
+            client_minor_protocol_version: 0,
+            client_auth_protocol_names: Vec::new(),
+        };
+        assert_eq!(req.opcode(), (1, None));
+        assert_eq!(write_to_vec(&req)[..2], [1, 0]);
+
+        let req = Request::SetEventMask {
+            input_method_id: 1,
+            input_context_id: 1,
+            forward_event_mask: 0,
+            synchronous_event_mask: 0,
+        };
+        assert_eq!(req.opcode(), (37, None));
+    }
+
+    #[test]
+    fn combined_feedback() {
+        let feedback = Feedback::UNDERLINE | Feedback::REVERSE;
+        let out = write_to_vec(feedback);
+        assert_eq!(out, 3u32.to_ne_bytes());
+        assert_eq!(read::<Feedback>(&out).unwrap(), feedback);
+
+        assert_eq!(
+            read::<Feedback>(&0u32.to_ne_bytes()).unwrap(),
+            Feedback::empty()
+        );
+        assert!(read::<Feedback>(&0x200u32.to_ne_bytes()).is_err());
+    }
+
+    #[test]
+    fn attr_declarations() {
+        let cases = [
+            (
+                Attr::long(1, AttributeName::InputStyle),
+                AttrType::Long,
+                AttributeName::InputStyle,
+            ),
+            (
+                Attr::window(2, AttributeName::ClientWindow),
+                AttrType::Window,
+                AttributeName::ClientWindow,
+            ),
+            (
+                Attr::style(3, AttributeName::QueryInputStyle),
+                AttrType::Style,
+                AttributeName::QueryInputStyle,
+            ),
+            (
+                Attr::point(4, AttributeName::SpotLocation),
+                AttrType::XPoint,
+                AttributeName::SpotLocation,
+            ),
+            (
+                Attr::nested_list(5, AttributeName::PreeditAttributes),
+                AttrType::NestedList,
+                AttributeName::PreeditAttributes,
+            ),
+        ];
+
+        for (attr, ty, name) in cases {
+            assert_eq!(attr.ty, ty);
+            let by_hand = Attr {
+                id: attr.id,
+                ty,
+                name,
+            };
+            assert_eq!(write_to_vec(&attr), write_to_vec(&by_hand));
+            assert_eq!(attr, by_hand);
+        }
+
+        assert_eq!(
+            Attr::window(crate::attrs::CLIENTWIN.id, AttributeName::ClientWindow),
+            crate::attrs::CLIENTWIN
+        );
+    }
+
+    #[test]
+    fn input_style_predicates() {
+        let over = InputStyle::PREEDIT_POSITION | InputStyle::STATUS_AREA;
+        assert!(over.is_over_the_spot());
+        assert!(over.has_status_area());
+        assert!(!over.is_on_the_spot());
+        assert!(!over.is_root_window());
+        assert!(!over.has_preedit_area());
+
+        let on = InputStyle::PREEDIT_CALLBACKS | InputStyle::STATUS_CALLBACKS;
+        assert!(on.is_on_the_spot());
+        assert!(!on.is_over_the_spot());
+        assert!(!on.has_status_area());
+
+        let root = InputStyle::PREEDIT_NOTHING | InputStyle::STATUS_NOTHING;
+        assert!(root.is_root_window());
+        assert!(!root.is_over_the_spot());
+        assert!(!root.has_status_area());
+
+        let off = InputStyle::PREEDIT_AREA | InputStyle::STATUS_AREA;
+        assert!(off.has_preedit_area());
+        assert!(off.has_status_area());
+        assert!(!off.is_root_window());
+
+        assert!(!InputStyle::PREEDIT_NONE.is_root_window());
+    }
+
+    #[test]
+    fn extension_table() {
+        let mut table = crate::ExtensionTable::new();
+        table.extend([
+            Extension {
+                major_opcode: 128,
+                minor_opcode: 0,
+                name: "XIM_EXT_MOVE".into(),
+            },
+            Extension {
+                major_opcode: 129,
+                minor_opcode: 0,
+                name: "XIM_EXT_SET_EVENT_MASK".into(),
+            },
+        ]);
+        table.insert(Extension {
+            major_opcode: 130,
+            minor_opcode: 1,
+            name: "XIM_EXT_MOVE".into(),
+        });
+
+        assert_eq!(table.iter().count(), 2);
+        assert!(table.get(128, 0).is_none());
+        assert_eq!(table.get(130, 1).unwrap().name, "XIM_EXT_MOVE");
+        assert_eq!(
+            table
+                .get_by_name("XIM_EXT_SET_EVENT_MASK")
+                .unwrap()
+                .major_opcode,
+            129
+        );
+    }
+
     #[test]
     fn read_error() {
         let req: Request = read(&[
@@ -193,6 +1141,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn read_trailing_bytes() {
+        let bytes = [32, 0, 1, 0, 3, 0, 0, 0, 0xff, 0xff];
+
+        // lenient by default
+        let mut reader = Reader::new(&bytes);
+        let req = Request::read(&mut reader).unwrap();
+        assert_eq!(req, Request::Close { input_method_id: 3 });
+
+        match reader.expect_consumed() {
+            Err(ReadError::TrailingBytes { remaining }) => assert_eq!(remaining, 2),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let mut reader = Reader::new(&bytes[..8]);
+        Request::read(&mut reader).unwrap();
+        reader.expect_consumed().unwrap();
+    }
+
     #[test]
     fn write_get_im_values() {
         let req = Request::GetImValues {
@@ -234,6 +1201,36 @@ mod tests {
         assert!(out.starts_with(b"\x3c\x00\x0a\x00"));
     }
 
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_forward_event() {
+        let req = Request::ForwardEvent {
+            input_method_id: 1,
+            input_context_id: 2,
+            flag: ForwardEventFlag::SYNCHRONOUS | ForwardEventFlag::REQUEST_FILTERING,
+            serial_number: 3,
+            xev: XEvent {
+                response_type: 2,
+                detail: 38,
+                sequence: 4,
+                time: 5,
+                root: 6,
+                event: 7,
+                child: 0,
+                root_x: -1,
+                root_y: 10,
+                event_x: 11,
+                event_y: -12,
+                state: 1,
+                same_screen: true,
+            },
+        };
+
+        let json = serde_json::to_string(&req).unwrap();
+        let back: Request = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, req);
+    }
+
     #[test]
     fn write_create_ic() {
         let req = Request::CreateIc {
@@ -373,4 +1370,288 @@ mod tests {
         let new: Request = read(&out).unwrap();
         assert_eq!(value, new);
     }
+
+    /// Small LCG so the fuzz tests are deterministic without extra dependencies.
+    struct Lcg(u32);
+
+    impl Lcg {
+        fn next(&mut self) -> u32 {
+            self.0 = self.0.wrapping_mul(1103515245).wrapping_add(12345);
+            self.0 >> 16
+        }
+    }
+
+    fn fuzz_samples() -> Vec<Vec<u8>> {
+        vec![
+            OPEN_REPLY.to_vec(),
+            write_to_vec(Request::QueryExtension {
+                input_method_id: 1,
+                extensions: vec!["XIM_EXT_MOVE".into()],
+            }),
+            write_to_vec(Request::CreateIc {
+                input_method_id: 1,
+                ic_attributes: vec![Attribute {
+                    id: 5,
+                    value: vec![0xf, 0, 4, 0, 1, 0, 2, 0],
+                }],
+            }),
+            write_to_vec(Request::PreeditDraw {
+                input_method_id: 1,
+                input_context_id: 1,
+                caret: 1,
+                chg_first: 0,
+                chg_length: 0,
+                status: PreeditDrawStatus::empty(),
+                preedit_string: b"abc".to_vec(),
+                feedbacks: vec![Feedback::UNDERLINE; 3],
+            }),
+            write_to_vec(Request::Commit {
+                input_method_id: 1,
+                input_context_id: 1,
+                data: CommitData::Both {
+                    keysym: 0x61,
+                    commited: b"a".to_vec(),
+                    syncronous: true,
+                },
+            }),
+            write_to_vec(Request::RegisterTriggerKeys {
+                input_method_id: 1,
+                on_keys: vec![TriggerKey {
+                    keysym: 0x20,
+                    modifier: 1,
+                    modifier_mask: 1,
+                }],
+                off_keys: Vec::new(),
+            }),
+        ]
+    }
+
+    #[test]
+    fn vectored_matches_write() {
+        let mut reqs = fuzz_samples()
+            .iter()
+            .map(|sample| read::<Request>(sample).unwrap())
+            .collect::<Vec<_>>();
+        reqs.push(Request::GetImValuesReply {
+            input_method_id: 1,
+            im_attributes: vec![
+                Attribute {
+                    id: 1,
+                    value: vec![1, 2, 3],
+                },
+                Attribute {
+                    id: 2,
+                    value: vec![4],
+                },
+            ],
+        });
+
+        for req in reqs.iter() {
+            for endian in [Endian::Little, Endian::Big] {
+                let mut out = vec![0; req.size()];
+                write_with_endian(req, &mut out, endian);
+
+                let writer = write_vectored(req, endian);
+                assert_eq!(writer.len(), out.len());
+                assert_eq!(writer.segments().collect::<Vec<_>>().concat(), out);
+            }
+        }
+
+        // the preedit string is borrowed, not copied
+        let req = &reqs[3];
+        let preedit = match req {
+            Request::PreeditDraw { preedit_string, .. } => preedit_string.as_ptr(),
+            _ => unreachable!(),
+        };
+        assert!(write_vectored(req, Endian::Native)
+            .segments()
+            .any(|segment| segment.as_ptr() == preedit));
+    }
+
+    #[test]
+    fn fuzz_truncated() {
+        for sample in fuzz_samples() {
+            for len in 0..sample.len() {
+                let _ = read::<Request>(&sample[..len]);
+            }
+        }
+    }
+
+    #[test]
+    fn fuzz_oversized_lengths() {
+        let mut rng = Lcg(0x1234);
+
+        for sample in fuzz_samples() {
+            // overwrite every 16bit word with a huge length
+            for idx in 0..sample.len() - 1 {
+                let mut bytes = sample.clone();
+                bytes[idx] = 0xff;
+                bytes[idx + 1] = 0xff;
+                let _ = read::<Request>(&bytes);
+            }
+
+            for _ in 0..256 {
+                let mut bytes = sample.clone();
+                let idx = rng.next() as usize % bytes.len();
+                bytes[idx] = rng.next() as u8;
+                let len = rng.next() as usize % (bytes.len() + 1);
+                let _ = read::<Request>(&bytes[..len]);
+            }
+        }
+    }
+
+    #[test]
+    fn fuzz_random() {
+        let mut rng = Lcg(42);
+        let mut bytes = Vec::new();
+
+        for _ in 0..4096 {
+            bytes.clear();
+            let len = rng.next() as usize % 64;
+            bytes.extend((0..len).map(|_| rng.next() as u8));
+            let _ = read::<Request>(&bytes);
+            let _ = read::<HotKeyTriggers>(&bytes);
+        }
+    }
+
+    #[test]
+    fn query_extension_layout() {
+        let req = Request::QueryExtension {
+            input_method_id: 1,
+            extensions: vec!["XIM_EXT_MOVE".into()],
+        };
+        let out = write_to_vec(&req);
+        assert_eq!(
+            out,
+            [
+                40, 0, 5, 0, // header
+                1, 0, // input method id
+                13, 0, // byte length of the names
+                12, b'X', b'I', b'M', b'_', b'E', b'X', b'T', b'_', b'M', b'O', b'V', b'E', 0, 0,
+                0, // pad to 4 counting the 4 bytes before the names
+            ]
+        );
+        assert_eq!(read::<Request>(&out).unwrap(), req);
+    }
+
+    #[test]
+    fn oversized_list_length() {
+        // QueryExtension claiming 0xffff bytes of extension names
+        let bytes = [40, 0, 1, 0, 1, 0, 0xff, 0xff];
+        assert!(matches!(
+            read::<Request>(&bytes),
+            Err(ReadError::EndOfStream)
+        ));
+
+        // trigger count far beyond the data
+        let bytes = [0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0];
+        assert!(matches!(
+            read::<HotKeyTriggers>(&bytes),
+            Err(ReadError::EndOfStream)
+        ));
+    }
+
+    #[test]
+    fn hotkey_triggers_layout() {
+        let triggers = HotKeyTriggers {
+            triggers: vec![(
+                TriggerKey {
+                    keysym: 0x20,
+                    modifier: 1,
+                    modifier_mask: 1,
+                },
+                HotKeyState::On,
+            )],
+        };
+        let out = write_to_vec(&triggers);
+        assert_eq!(
+            out,
+            [
+                1, 0, 0, 0, // count
+                0x20, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, // keysym, modifier, mask
+                1, 0, 0, 0, // on
+            ]
+        );
+    }
+
+    #[test]
+    fn hotkey_triggers_roundtrip() {
+        let triggers = HotKeyTriggers {
+            triggers: vec![
+                (
+                    TriggerKey {
+                        keysym: 0x20,
+                        modifier: 1,
+                        modifier_mask: 1,
+                    },
+                    HotKeyState::On,
+                ),
+                (
+                    TriggerKey {
+                        keysym: 0x21,
+                        modifier: 0,
+                        modifier_mask: 0,
+                    },
+                    HotKeyState::Off,
+                ),
+            ],
+        };
+
+        let out = write_to_vec(&triggers);
+        assert_eq!(out.len(), triggers.size());
+        assert_eq!(read::<HotKeyTriggers>(&out).unwrap(), triggers);
+    }
+
+    #[test]
+    fn peek_forward_event_header() {
+        let req = Request::ForwardEvent {
+            input_method_id: 1,
+            input_context_id: 2,
+            flag: ForwardEventFlag::SYNCHRONOUS,
+            serial_number: 3,
+            xev: XEvent {
+                response_type: 2,
+                detail: 38,
+                sequence: 3,
+                time: 1234,
+                root: 0,
+                event: 0,
+                child: 0,
+                root_x: 0,
+                root_y: 0,
+                event_x: 0,
+                event_y: 0,
+                state: 0,
+                same_screen: true,
+            },
+        };
+
+        for endian in [Endian::Little, Endian::Big] {
+            let mut out = vec![0; req.size()];
+            write_with_endian(&req, &mut out, endian);
+
+            // only the header is needed
+            let header = peek_header(&out[..4], endian).unwrap();
+            assert_eq!(header.major_opcode, 60);
+            assert_eq!(header.minor_opcode, 0);
+            assert_eq!(header.length, out.len() - 4);
+        }
+        assert_eq!(peek_header(&[60, 0, 0], Endian::Little), None);
+    }
+
+    #[test]
+    fn peek_connect_header() {
+        let req = Request::Connect {
+            endian: Endian::Big,
+            client_major_protocol_version: 1,
+            client_minor_protocol_version: 0,
+            client_auth_protocol_names: Vec::new(),
+        };
+        let mut out = vec![0; req.size()];
+        write_with_endian(&req, &mut out, Endian::Big);
+
+        // the byte order of the connection isn't known yet
+        let header = peek_header(&out, Endian::Little).unwrap();
+        assert_eq!(header.length, out.len() - 4);
+    }
 }
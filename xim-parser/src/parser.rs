@@ -2,11 +2,13 @@
 // This source is auto generated by xim-gen
 
 #![allow(clippy::identity_op)]
+// not every consumer uses every generated item
+#![allow(dead_code, unused_imports)]
 
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
-use core::convert::TryInto;
+use core::convert::{TryFrom, TryInto};
 use core::fmt;
 
 pub fn read<T>(b: &[u8]) -> Result<T, ReadError>
@@ -23,24 +25,56 @@ where
     val.write(&mut Writer::new(out));
 }
 
+/// Write `val` as slices, see [`VectoredWriter`].
+pub fn write_vectored<T>(val: &T, endian: Endian) -> VectoredWriter<'_>
+where
+    T: XimWrite,
+{
+    let mut writer = VectoredWriter::with_endian(endian);
+    val.write_vectored(&mut writer);
+    writer
+}
+
+pub fn read_with_endian<T>(b: &[u8], endian: Endian) -> Result<T, ReadError>
+where
+    T: XimRead,
+{
+    T::read(&mut Reader::with_endian(b, endian))
+}
+
+pub fn write_with_endian<T>(val: T, out: &mut [u8], endian: Endian)
+where
+    T: XimWrite,
+{
+    val.write(&mut Writer::with_endian(out, endian));
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[repr(u8)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Endian {
+    Big = 0x42,
+    Little = 0x6c,
+}
+
+impl Endian {
+    #[allow(non_upper_case_globals)]
     #[cfg(target_endian = "little")]
-    Native = 0x6c,
+    pub const Native: Self = Self::Little;
+    #[allow(non_upper_case_globals)]
     #[cfg(target_endian = "big")]
-    Native = 0x42,
-    // Big = 0x42,
-    // Little = 0x6c,
+    pub const Native: Self = Self::Big;
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum StatusContent {
     Text(StatusTextContent),
     Pixmap(u32),
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CommitData {
     Keysym {
         keysym: u32,
@@ -57,6 +91,7 @@ pub enum CommitData {
     },
 }
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InputStyleList {
     pub styles: Vec<InputStyle>,
 }
@@ -88,7 +123,63 @@ impl XimWrite for InputStyleList {
     }
 }
 
+/// A `STR`: a one byte length then the bytes, without padding.
+///
+/// Usable as a field type in `xim-format.yaml` where the string isn't framed by the field.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Str8(pub String);
+
+impl XimRead for Str8 {
+    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
+        let len = reader.u8()?;
+        Ok(Self(String::from_utf8(
+            reader.consume(len as usize)?.to_vec(),
+        )?))
+    }
+}
+
+impl XimWrite for Str8 {
+    fn write(&self, writer: &mut Writer) {
+        (self.0.len() as u8).write(writer);
+        writer.write(self.0.as_bytes());
+    }
+
+    fn size(&self) -> usize {
+        self.0.len() + 1
+    }
+}
+
+/// A `STRING`: a two byte length, the bytes, then padding of the whole to four bytes.
+///
+/// Usable as a field type in `xim-format.yaml` where the string isn't framed by the field.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Str16(pub String);
+
+impl XimRead for Str16 {
+    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
+        let len = reader.u16()? as usize;
+        let s = String::from_utf8(reader.consume(len)?.to_vec())?;
+        reader.consume(pad4(2 + len))?;
+        Ok(Self(s))
+    }
+}
+
+impl XimWrite for Str16 {
+    fn write(&self, writer: &mut Writer) {
+        (self.0.len() as u16).write(writer);
+        writer.write(self.0.as_bytes());
+        writer.write(&[0u8; 4][..pad4(2 + self.0.len())]);
+    }
+
+    fn size(&self) -> usize {
+        with_pad4(self.0.len() + 2)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct HotKeyTriggers {
     pub triggers: Vec<(TriggerKey, HotKeyState)>,
 }
@@ -99,6 +190,7 @@ pub enum ReadError {
     InvalidData(&'static str, String),
     Utf8Error(alloc::string::FromUtf8Error),
     NotNativeEndian,
+    TrailingBytes { remaining: usize },
 }
 
 impl From<alloc::string::FromUtf8Error> for ReadError {
@@ -114,6 +206,9 @@ impl fmt::Display for ReadError {
             Self::InvalidData(name, reason) => write!(f, "Invalid Data {}: {}", name, reason),
             Self::Utf8Error(e) => write!(f, "Not a Utf8 text {}", e),
             Self::NotNativeEndian => write!(f, "Not a native endian"),
+            Self::TrailingBytes { remaining } => {
+                write!(f, "{} trailing bytes after the end of data", remaining)
+            }
         }
     }
 }
@@ -129,68 +224,81 @@ fn pad4(len: usize) -> usize {
 }
 
 fn with_pad4(len: usize) -> usize {
-    len + pad4(len)
+    len.saturating_add(pad4(len))
 }
 
 pub struct Reader<'b> {
     bytes: &'b [u8],
     start: usize,
+    endian: Endian,
 }
 
 impl<'b> Reader<'b> {
     pub fn new(bytes: &'b [u8]) -> Self {
+        Self::with_endian(bytes, Endian::Native)
+    }
+
+    pub fn with_endian(bytes: &'b [u8], endian: Endian) -> Self {
         Self {
             bytes,
             start: bytes.as_ptr() as usize,
+            endian,
         }
     }
 
-    fn ptr_offset(&self) -> usize {
-        self.bytes.as_ptr() as usize - self.start
+    pub fn endian(&self) -> Endian {
+        self.endian
     }
 
-    pub fn cursor(&self) -> usize {
-        self.bytes.len()
+    /// Change the byte order of the following reads, reading an [`Endian`] does this too.
+    pub fn set_endian(&mut self, endian: Endian) {
+        self.endian = endian;
     }
 
-    pub fn pad4(&mut self) -> Result<(), ReadError> {
-        self.consume(pad4(self.ptr_offset()))?;
-        Ok(())
+    /// Number of bytes read so far.
+    pub fn position(&self) -> usize {
+        self.bytes.as_ptr() as usize - self.start
     }
 
-    #[inline(always)]
-    pub fn eos(&self) -> ReadError {
-        ReadError::EndOfStream
+    /// Number of bytes left to read.
+    pub fn remaining(&self) -> usize {
+        self.bytes.len()
     }
 
-    pub fn invalid_data(&self, ty: &'static str, item: impl ToString) -> ReadError {
-        ReadError::InvalidData(ty, item.to_string())
+    pub fn cursor(&self) -> usize {
+        self.bytes.len()
     }
 
-    pub fn u8(&mut self) -> Result<u8, ReadError> {
-        let (b, new) = self.bytes.split_first().ok_or(ReadError::EndOfStream)?;
-        self.bytes = new;
-        Ok(*b)
+    pub fn pad4(&mut self) -> Result<(), ReadError> {
+        self.consume(pad4(self.position()))?;
+        Ok(())
     }
 
-    pub fn i16(&mut self) -> Result<i16, ReadError> {
-        let bytes = self.consume(2)?.try_into().unwrap();
-        Ok(i16::from_ne_bytes(bytes))
+    /// Check that every byte of the input has been consumed.
+    ///
+    /// Reading never fails on leftover bytes by itself, call this after reading when the input
+    /// is expected to hold exactly one value.
+    pub fn expect_consumed(&self) -> Result<(), ReadError> {
+        match self.remaining() {
+            0 => Ok(()),
+            remaining => Err(ReadError::TrailingBytes { remaining }),
+        }
     }
 
-    pub fn u16(&mut self) -> Result<u16, ReadError> {
-        let bytes = self.consume(2)?.try_into().unwrap();
-        Ok(u16::from_ne_bytes(bytes))
+    /// Switch to the byte order stored `offset` bytes ahead without consuming anything.
+    fn peek_endian(&mut self, offset: usize) -> Result<(), ReadError> {
+        let n = *self.bytes.get(offset).ok_or(ReadError::EndOfStream)?;
+        self.endian = Endian::from_byte(self, n)?;
+        Ok(())
     }
 
-    pub fn u32(&mut self) -> Result<u32, ReadError> {
-        let bytes = self.consume(4)?.try_into().unwrap();
-        Ok(u32::from_ne_bytes(bytes))
+    #[inline(always)]
+    pub fn eos(&self) -> ReadError {
+        ReadError::EndOfStream
     }
 
-    pub fn i32(&mut self) -> Result<i32, ReadError> {
-        let bytes = self.consume(4)?.try_into().unwrap();
-        Ok(i32::from_ne_bytes(bytes))
+    pub fn invalid_data(&self, ty: &'static str, item: impl ToString) -> ReadError {
+        ReadError::InvalidData(ty, item.to_string())
     }
 
     pub fn consume(&mut self, len: usize) -> Result<&'b [u8], ReadError> {
@@ -207,11 +315,33 @@ impl<'b> Reader<'b> {
 pub struct Writer<'b> {
     out: &'b mut [u8],
     idx: usize,
+    endian: Endian,
 }
 
 impl<'b> Writer<'b> {
     pub fn new(out: &'b mut [u8]) -> Self {
-        Self { out, idx: 0 }
+        Self::with_endian(out, Endian::Native)
+    }
+
+    pub fn with_endian(out: &'b mut [u8], endian: Endian) -> Self {
+        Self {
+            out,
+            idx: 0,
+            endian,
+        }
+    }
+
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    /// Number of bytes written so far.
+    pub fn len(&self) -> usize {
+        self.idx
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.idx == 0
     }
 
     pub fn write_u8(&mut self, b: u8) {
@@ -231,6 +361,111 @@ impl<'b> Writer<'b> {
     }
 }
 
+enum Segment<'a> {
+    Scratch(usize, usize),
+    Borrowed(&'a [u8]),
+}
+
+/// Collects the bytes of an item as a list of slices, borrowing strings and other byte payloads
+/// of the item instead of copying them.
+pub struct VectoredWriter<'a> {
+    scratch: Vec<u8>,
+    segments: Vec<Segment<'a>>,
+    len: usize,
+    endian: Endian,
+}
+
+impl<'a> Default for VectoredWriter<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> VectoredWriter<'a> {
+    pub fn new() -> Self {
+        Self::with_endian(Endian::Native)
+    }
+
+    pub fn with_endian(endian: Endian) -> Self {
+        Self {
+            scratch: Vec::new(),
+            segments: Vec::new(),
+            len: 0,
+            endian,
+        }
+    }
+
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    /// Total length of the written bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copy `item` into the internal buffer.
+    pub fn copy(&mut self, item: &(impl XimWrite + ?Sized)) {
+        let size = item.size();
+        if size == 0 {
+            return;
+        }
+
+        // keep the padding of `item` aligned to the whole output
+        let offset = self.len % 4;
+        let start = self.scratch.len();
+        self.scratch.resize(start + offset + size, 0);
+        item.write(&mut Writer {
+            out: &mut self.scratch[start..],
+            idx: offset,
+            endian: self.endian,
+        });
+
+        let start = start + offset;
+        match self.segments.last_mut() {
+            Some(Segment::Scratch(prev, len)) if *prev + *len == start => *len += size,
+            _ => self.segments.push(Segment::Scratch(start, size)),
+        }
+        self.len += size;
+    }
+
+    /// Append `bytes` without copying them.
+    pub fn borrow(&mut self, bytes: &'a [u8]) {
+        if !bytes.is_empty() {
+            self.segments.push(Segment::Borrowed(bytes));
+            self.len += bytes.len();
+        }
+    }
+
+    pub fn write_zeros(&mut self, len: usize) {
+        for _ in 0..len {
+            self.copy(&0u8);
+        }
+    }
+
+    pub fn write_pad4(&mut self) {
+        self.write_zeros(pad4(self.len));
+    }
+
+    /// The written bytes in order.
+    pub fn segments(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.segments.iter().map(move |segment| match *segment {
+            Segment::Scratch(start, len) => &self.scratch[start..start + len],
+            Segment::Borrowed(bytes) => bytes,
+        })
+    }
+
+    /// The written bytes as slices for [`std::io::Write::write_vectored`].
+    #[cfg(feature = "std")]
+    pub fn io_slices(&self) -> Vec<std::io::IoSlice<'_>> {
+        self.segments().map(std::io::IoSlice::new).collect()
+    }
+}
+
 pub trait XimRead: Sized {
     fn read(reader: &mut Reader) -> Result<Self, ReadError>;
 }
@@ -239,6 +474,10 @@ pub trait XimWrite {
     fn write(&self, writer: &mut Writer);
     /// byte size of format
     fn size(&self) -> usize;
+    /// Write into a [`VectoredWriter`], defaults to copying the whole item.
+    fn write_vectored<'a>(&'a self, writer: &mut VectoredWriter<'a>) {
+        writer.copy(self);
+    }
 }
 
 impl<'a, T> XimWrite for &'a T
@@ -253,17 +492,31 @@ where
     fn size(&self) -> usize {
         (**self).size()
     }
+    #[inline(always)]
+    fn write_vectored<'w>(&'w self, writer: &mut VectoredWriter<'w>) {
+        (**self).write_vectored(writer);
+    }
+}
+
+impl Endian {
+    fn from_byte(reader: &Reader, n: u8) -> Result<Self, ReadError> {
+        match n {
+            0x42 => Ok(Self::Big),
+            0x6c => Ok(Self::Little),
+            _ => Err(reader.invalid_data("Endian", n)),
+        }
+    }
 }
 
 impl XimRead for Endian {
     fn read(reader: &mut Reader) -> Result<Self, ReadError> {
         let n = u8::read(reader)?;
+        let endian = Self::from_byte(reader, n)?;
 
-        if n == Endian::Native as u8 {
-            Ok(Self::Native)
-        } else {
-            Err(ReadError::NotNativeEndian)
-        }
+        // the rest of the message is in this byte order
+        reader.set_endian(endian);
+
+        Ok(endian)
     }
 }
 
@@ -324,7 +577,7 @@ impl XimRead for CommitData {
                 reader.pad4()?;
                 Ok(Self::Chars {
                     commited: bytes.to_vec(),
-                    syncronous: ty == 5,
+                    syncronous: ty == 3,
                 })
             }
             4 | 5 => {
@@ -332,7 +585,7 @@ impl XimRead for CommitData {
                 let keysym = reader.u32()?;
                 Ok(Self::Keysym {
                     keysym,
-                    syncronous: ty == 3,
+                    syncronous: ty == 5,
                 })
             }
             6 | 7 => {
@@ -399,14 +652,20 @@ impl XimWrite for CommitData {
 impl XimRead for HotKeyTriggers {
     fn read(reader: &mut Reader) -> Result<Self, ReadError> {
         let n = reader.u32()? as usize;
+
+        // each trigger takes 16 bytes, don't trust the count for allocation
+        if n > reader.cursor() / 16 {
+            return Err(reader.eos());
+        }
+
         let mut out = Vec::with_capacity(n);
 
         for _ in 0..n {
             out.push((TriggerKey::read(reader)?, HotKeyState::Off));
         }
 
-        for _ in 0..n {
-            out[n].1 = HotKeyState::read(reader)?;
+        for (_, state) in out.iter_mut() {
+            *state = HotKeyState::read(reader)?;
         }
 
         Ok(Self { triggers: out })
@@ -427,67 +686,152 @@ impl XimWrite for HotKeyTriggers {
     }
 
     fn size(&self) -> usize {
-        self.triggers.len() * 8 + 4
+        self.triggers.len() * 16 + 4
     }
 }
 
-impl XimRead for u8 {
+impl XimRead for bool {
     fn read(reader: &mut Reader) -> Result<Self, ReadError> {
-        reader.u8()
+        Ok(reader.u8()? != 0)
     }
 }
 
-impl XimWrite for u8 {
+impl XimWrite for bool {
     fn write(&self, writer: &mut Writer) {
-        writer.write_u8(*self)
+        writer.write_u8(*self as u8)
     }
 
     fn size(&self) -> usize {
         1
     }
 }
-
-impl XimRead for bool {
+impl<'b> Reader<'b> {
+    pub fn u8(&mut self) -> Result<u8, ReadError> {
+        let bytes = self.consume(1)?.try_into().unwrap();
+        Ok(match self.endian {
+            Endian::Big => u8::from_be_bytes(bytes),
+            Endian::Little => u8::from_le_bytes(bytes),
+        })
+    }
+}
+impl XimRead for u8 {
     fn read(reader: &mut Reader) -> Result<Self, ReadError> {
-        Ok(reader.u8()? != 0)
+        reader.u8()
     }
 }
-
-impl XimWrite for bool {
+impl XimWrite for u8 {
     fn write(&self, writer: &mut Writer) {
-        writer.write_u8(*self as u8)
+        match writer.endian() {
+            Endian::Big => writer.write(&self.to_be_bytes()),
+            Endian::Little => writer.write(&self.to_le_bytes()),
+        }
     }
-
     fn size(&self) -> usize {
         1
     }
 }
-
-macro_rules! impl_int {
-    ($ty:ident) => {
-        impl XimRead for $ty {
-            fn read(reader: &mut Reader) -> Result<Self, ReadError> {
-                reader.$ty()
-            }
+impl<'b> Reader<'b> {
+    pub fn u16(&mut self) -> Result<u16, ReadError> {
+        let bytes = self.consume(2)?.try_into().unwrap();
+        Ok(match self.endian {
+            Endian::Big => u16::from_be_bytes(bytes),
+            Endian::Little => u16::from_le_bytes(bytes),
+        })
+    }
+}
+impl XimRead for u16 {
+    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
+        reader.u16()
+    }
+}
+impl XimWrite for u16 {
+    fn write(&self, writer: &mut Writer) {
+        match writer.endian() {
+            Endian::Big => writer.write(&self.to_be_bytes()),
+            Endian::Little => writer.write(&self.to_le_bytes()),
         }
-
-        impl XimWrite for $ty {
-            fn write(&self, writer: &mut Writer) {
-                writer.write(&self.to_ne_bytes())
-            }
-
-            fn size(&self) -> usize {
-                core::mem::size_of::<$ty>()
-            }
+    }
+    fn size(&self) -> usize {
+        2
+    }
+}
+impl<'b> Reader<'b> {
+    pub fn i16(&mut self) -> Result<i16, ReadError> {
+        let bytes = self.consume(2)?.try_into().unwrap();
+        Ok(match self.endian {
+            Endian::Big => i16::from_be_bytes(bytes),
+            Endian::Little => i16::from_le_bytes(bytes),
+        })
+    }
+}
+impl XimRead for i16 {
+    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
+        reader.i16()
+    }
+}
+impl XimWrite for i16 {
+    fn write(&self, writer: &mut Writer) {
+        match writer.endian() {
+            Endian::Big => writer.write(&self.to_be_bytes()),
+            Endian::Little => writer.write(&self.to_le_bytes()),
         }
-    };
+    }
+    fn size(&self) -> usize {
+        2
+    }
+}
+impl<'b> Reader<'b> {
+    pub fn u32(&mut self) -> Result<u32, ReadError> {
+        let bytes = self.consume(4)?.try_into().unwrap();
+        Ok(match self.endian {
+            Endian::Big => u32::from_be_bytes(bytes),
+            Endian::Little => u32::from_le_bytes(bytes),
+        })
+    }
+}
+impl XimRead for u32 {
+    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
+        reader.u32()
+    }
+}
+impl XimWrite for u32 {
+    fn write(&self, writer: &mut Writer) {
+        match writer.endian() {
+            Endian::Big => writer.write(&self.to_be_bytes()),
+            Endian::Little => writer.write(&self.to_le_bytes()),
+        }
+    }
+    fn size(&self) -> usize {
+        4
+    }
+}
+impl<'b> Reader<'b> {
+    pub fn i32(&mut self) -> Result<i32, ReadError> {
+        let bytes = self.consume(4)?.try_into().unwrap();
+        Ok(match self.endian {
+            Endian::Big => i32::from_be_bytes(bytes),
+            Endian::Little => i32::from_le_bytes(bytes),
+        })
+    }
+}
+impl XimRead for i32 {
+    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
+        reader.i32()
+    }
+}
+impl XimWrite for i32 {
+    fn write(&self, writer: &mut Writer) {
+        match writer.endian() {
+            Endian::Big => writer.write(&self.to_be_bytes()),
+            Endian::Little => writer.write(&self.to_le_bytes()),
+        }
+    }
+    fn size(&self) -> usize {
+        4
+    }
 }
-
-impl_int!(u16);
-impl_int!(i16);
-impl_int!(u32);
-impl_int!(i32);
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u16)]
 pub enum AttrType {
     Separator = 0,
@@ -506,9 +850,9 @@ pub enum AttrType {
     ResetState = 19,
     NestedList = 32767,
 }
-impl XimRead for AttrType {
-    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
-        let repr = u16::read(reader)?;
+impl TryFrom<u16> for AttrType {
+    type Error = ReadError;
+    fn try_from(repr: u16) -> Result<Self, ReadError> {
         match repr {
             0 => Ok(Self::Separator),
             1 => Ok(Self::Byte),
@@ -525,10 +869,21 @@ impl XimRead for AttrType {
             18 => Ok(Self::PreeditState),
             19 => Ok(Self::ResetState),
             32767 => Ok(Self::NestedList),
-            _ => Err(reader.invalid_data("AttrType", repr)),
+            _ => Err(ReadError::InvalidData("AttrType", repr.to_string())),
         }
     }
 }
+impl From<AttrType> for u16 {
+    fn from(val: AttrType) -> Self {
+        val as u16
+    }
+}
+impl XimRead for AttrType {
+    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
+        let repr = u16::read(reader)?;
+        Self::try_from(repr)
+    }
+}
 impl XimWrite for AttrType {
     fn write(&self, writer: &mut Writer) {
         (*self as u16).write(writer);
@@ -538,6 +893,7 @@ impl XimWrite for AttrType {
     }
 }
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u32)]
 pub enum CaretDirection {
     ForwardChar = 0,
@@ -553,9 +909,9 @@ pub enum CaretDirection {
     AbsolutePosition = 10,
     DontChange = 11,
 }
-impl XimRead for CaretDirection {
-    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
-        let repr = u32::read(reader)?;
+impl TryFrom<u32> for CaretDirection {
+    type Error = ReadError;
+    fn try_from(repr: u32) -> Result<Self, ReadError> {
         match repr {
             0 => Ok(Self::ForwardChar),
             1 => Ok(Self::BackwardChar),
@@ -569,10 +925,21 @@ impl XimRead for CaretDirection {
             9 => Ok(Self::LineEnd),
             10 => Ok(Self::AbsolutePosition),
             11 => Ok(Self::DontChange),
-            _ => Err(reader.invalid_data("CaretDirection", repr)),
+            _ => Err(ReadError::InvalidData("CaretDirection", repr.to_string())),
         }
     }
 }
+impl From<CaretDirection> for u32 {
+    fn from(val: CaretDirection) -> Self {
+        val as u32
+    }
+}
+impl XimRead for CaretDirection {
+    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
+        let repr = u32::read(reader)?;
+        Self::try_from(repr)
+    }
+}
 impl XimWrite for CaretDirection {
     fn write(&self, writer: &mut Writer) {
         (*self as u32).write(writer);
@@ -582,25 +949,37 @@ impl XimWrite for CaretDirection {
     }
 }
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u32)]
 pub enum CaretStyle {
     Invisible = 0,
     Primary = 1,
     Secondary = 2,
 }
-impl XimRead for CaretStyle {
-    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
-        let repr = u32::read(reader)?;
+impl TryFrom<u32> for CaretStyle {
+    type Error = ReadError;
+    fn try_from(repr: u32) -> Result<Self, ReadError> {
         match repr {
             0 => Ok(Self::Invisible),
             1 => Ok(Self::Primary),
             2 => Ok(Self::Secondary),
-            _ => Err(reader.invalid_data("CaretStyle", repr)),
+            _ => Err(ReadError::InvalidData("CaretStyle", repr.to_string())),
         }
     }
 }
-impl XimWrite for CaretStyle {
-    fn write(&self, writer: &mut Writer) {
+impl From<CaretStyle> for u32 {
+    fn from(val: CaretStyle) -> Self {
+        val as u32
+    }
+}
+impl XimRead for CaretStyle {
+    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
+        let repr = u32::read(reader)?;
+        Self::try_from(repr)
+    }
+}
+impl XimWrite for CaretStyle {
+    fn write(&self, writer: &mut Writer) {
         (*self as u32).write(writer);
     }
     fn size(&self) -> usize {
@@ -608,6 +987,7 @@ impl XimWrite for CaretStyle {
     }
 }
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u16)]
 pub enum ErrorCode {
     BadAlloc = 1,
@@ -628,9 +1008,9 @@ pub enum ErrorCode {
     LocaleNotSupported = 16,
     BadSomething = 999,
 }
-impl XimRead for ErrorCode {
-    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
-        let repr = u16::read(reader)?;
+impl TryFrom<u16> for ErrorCode {
+    type Error = ReadError;
+    fn try_from(repr: u16) -> Result<Self, ReadError> {
         match repr {
             1 => Ok(Self::BadAlloc),
             2 => Ok(Self::BadStyle),
@@ -649,10 +1029,21 @@ impl XimRead for ErrorCode {
             15 => Ok(Self::BadBackground),
             16 => Ok(Self::LocaleNotSupported),
             999 => Ok(Self::BadSomething),
-            _ => Err(reader.invalid_data("ErrorCode", repr)),
+            _ => Err(ReadError::InvalidData("ErrorCode", repr.to_string())),
         }
     }
 }
+impl From<ErrorCode> for u16 {
+    fn from(val: ErrorCode) -> Self {
+        val as u16
+    }
+}
+impl XimRead for ErrorCode {
+    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
+        let repr = u16::read(reader)?;
+        Self::try_from(repr)
+    }
+}
 impl XimWrite for ErrorCode {
     fn write(&self, writer: &mut Writer) {
         (*self as u16).write(writer);
@@ -663,6 +1054,7 @@ impl XimWrite for ErrorCode {
 }
 bitflags::bitflags! {
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ErrorFlag: u16 {
 const INPUT_METHOD_ID_VALID = 1;
 const INPUT_CONTEXT_ID_VALID = 2;
@@ -682,39 +1074,30 @@ impl XimWrite for ErrorFlag {
         core::mem::size_of::<u16>()
     }
 }
+bitflags::bitflags! {
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-#[repr(u32)]
-pub enum Feedback {
-    Reverse = 1,
-    Underline = 2,
-    Highlight = 4,
-    Primary = 8,
-    Secondary = 16,
-    Tertiary = 32,
-    VisibleToForward = 64,
-    VisibleToBackward = 128,
-    VisibleCenter = 256,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Feedback: u32 {
+const REVERSE = 1;
+const UNDERLINE = 2;
+const HIGHLIGHT = 4;
+const PRIMARY = 8;
+const SECONDARY = 16;
+const TERTIARY = 32;
+const VISIBLE_TO_FORWARD = 64;
+const VISIBLE_TO_BACKWARD = 128;
+const VISIBLE_CENTER = 256;
+}
 }
 impl XimRead for Feedback {
     fn read(reader: &mut Reader) -> Result<Self, ReadError> {
         let repr = u32::read(reader)?;
-        match repr {
-            1 => Ok(Self::Reverse),
-            2 => Ok(Self::Underline),
-            4 => Ok(Self::Highlight),
-            8 => Ok(Self::Primary),
-            16 => Ok(Self::Secondary),
-            32 => Ok(Self::Tertiary),
-            64 => Ok(Self::VisibleToForward),
-            128 => Ok(Self::VisibleToBackward),
-            256 => Ok(Self::VisibleCenter),
-            _ => Err(reader.invalid_data("Feedback", repr)),
-        }
+        Self::from_bits(repr).ok_or_else(|| reader.invalid_data("Feedback", repr))
     }
 }
 impl XimWrite for Feedback {
     fn write(&self, writer: &mut Writer) {
-        (*self as u32).write(writer);
+        self.bits().write(writer);
     }
     fn size(&self) -> usize {
         core::mem::size_of::<u32>()
@@ -722,6 +1105,7 @@ impl XimWrite for Feedback {
 }
 bitflags::bitflags! {
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ForwardEventFlag: u16 {
 const SYNCHRONOUS = 1;
 const REQUEST_FILTERING = 2;
@@ -743,21 +1127,33 @@ impl XimWrite for ForwardEventFlag {
     }
 }
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u32)]
 pub enum HotKeyState {
     On = 1,
     Off = 2,
 }
-impl XimRead for HotKeyState {
-    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
-        let repr = u32::read(reader)?;
+impl TryFrom<u32> for HotKeyState {
+    type Error = ReadError;
+    fn try_from(repr: u32) -> Result<Self, ReadError> {
         match repr {
             1 => Ok(Self::On),
             2 => Ok(Self::Off),
-            _ => Err(reader.invalid_data("HotKeyState", repr)),
+            _ => Err(ReadError::InvalidData("HotKeyState", repr.to_string())),
         }
     }
 }
+impl From<HotKeyState> for u32 {
+    fn from(val: HotKeyState) -> Self {
+        val as u32
+    }
+}
+impl XimRead for HotKeyState {
+    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
+        let repr = u32::read(reader)?;
+        Self::try_from(repr)
+    }
+}
 impl XimWrite for HotKeyState {
     fn write(&self, writer: &mut Writer) {
         (*self as u32).write(writer);
@@ -768,6 +1164,7 @@ impl XimWrite for HotKeyState {
 }
 bitflags::bitflags! {
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InputStyle: u32 {
 const PREEDIT_AREA = 1;
 const PREEDIT_CALLBACKS = 2;
@@ -796,6 +1193,7 @@ impl XimWrite for InputStyle {
 }
 bitflags::bitflags! {
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PreeditDrawStatus: u32 {
 const NO_STRING = 1;
 const NO_FEEDBACK = 2;
@@ -817,6 +1215,7 @@ impl XimWrite for PreeditDrawStatus {
 }
 bitflags::bitflags! {
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PreeditStateFlag: u32 {
 const UNKNOWN = 0;
 const ENABLE = 1;
@@ -838,21 +1237,36 @@ impl XimWrite for PreeditStateFlag {
     }
 }
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u32)]
 pub enum TriggerNotifyFlag {
     OnKeyList = 0,
     OffKeyList = 1,
 }
-impl XimRead for TriggerNotifyFlag {
-    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
-        let repr = u32::read(reader)?;
+impl TryFrom<u32> for TriggerNotifyFlag {
+    type Error = ReadError;
+    fn try_from(repr: u32) -> Result<Self, ReadError> {
         match repr {
             0 => Ok(Self::OnKeyList),
             1 => Ok(Self::OffKeyList),
-            _ => Err(reader.invalid_data("TriggerNotifyFlag", repr)),
+            _ => Err(ReadError::InvalidData(
+                "TriggerNotifyFlag",
+                repr.to_string(),
+            )),
         }
     }
 }
+impl From<TriggerNotifyFlag> for u32 {
+    fn from(val: TriggerNotifyFlag) -> Self {
+        val as u32
+    }
+}
+impl XimRead for TriggerNotifyFlag {
+    fn read(reader: &mut Reader) -> Result<Self, ReadError> {
+        let repr = u32::read(reader)?;
+        Self::try_from(repr)
+    }
+}
 impl XimWrite for TriggerNotifyFlag {
     fn write(&self, writer: &mut Writer) {
         (*self as u32).write(writer);
@@ -862,6 +1276,7 @@ impl XimWrite for TriggerNotifyFlag {
     }
 }
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Attr {
     pub id: u16,
     pub ty: AttrType,
@@ -888,14 +1303,21 @@ impl XimWrite for Attr {
         writer.write_pad4();
     }
     fn size(&self) -> usize {
-        let mut content_size = 0;
-        content_size += self.id.size();
-        content_size += self.ty.size();
-        content_size += with_pad4(self.name.size() - 0);
+        let mut content_size = 0usize;
+        content_size = content_size.saturating_add(self.id.size());
+        content_size = content_size.saturating_add(self.ty.size());
+        content_size = content_size.saturating_add(with_pad4(self.name.size() - 0));
         content_size
     }
+    fn write_vectored<'a>(&'a self, writer: &mut VectoredWriter<'a>) {
+        self.id.write_vectored(writer);
+        self.ty.write_vectored(writer);
+        self.name.write_vectored(writer);
+        writer.write_pad4();
+    }
 }
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Attribute {
     pub id: u16,
     pub value: Vec<u8>,
@@ -923,13 +1345,23 @@ impl XimWrite for Attribute {
         writer.write_pad4();
     }
     fn size(&self) -> usize {
-        let mut content_size = 0;
-        content_size += self.id.size();
-        content_size += with_pad4(self.value.len() + 2 - 2) + 2;
+        let mut content_size = 0usize;
+        content_size = content_size.saturating_add(self.id.size());
+        content_size = content_size.saturating_add(usize::saturating_add(
+            with_pad4(self.value.len() + 2 - 2),
+            2,
+        ));
         content_size
     }
+    fn write_vectored<'a>(&'a self, writer: &mut VectoredWriter<'a>) {
+        self.id.write_vectored(writer);
+        writer.copy(&(self.value.len() as u16));
+        writer.borrow(&self.value[..]);
+        writer.write_pad4();
+    }
 }
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Extension {
     pub major_opcode: u8,
     pub minor_opcode: u8,
@@ -960,14 +1392,22 @@ impl XimWrite for Extension {
         writer.write_pad4();
     }
     fn size(&self) -> usize {
-        let mut content_size = 0;
-        content_size += self.major_opcode.size();
-        content_size += self.minor_opcode.size();
-        content_size += with_pad4(self.name.len() + 2 + 0 - 0);
+        let mut content_size = 0usize;
+        content_size = content_size.saturating_add(self.major_opcode.size());
+        content_size = content_size.saturating_add(self.minor_opcode.size());
+        content_size = content_size.saturating_add(with_pad4(self.name.len() + 2 + 0 - 0));
         content_size
     }
+    fn write_vectored<'a>(&'a self, writer: &mut VectoredWriter<'a>) {
+        self.major_opcode.write_vectored(writer);
+        self.minor_opcode.write_vectored(writer);
+        writer.copy(&(self.name.len() as u16));
+        writer.borrow(self.name.as_bytes());
+        writer.write_pad4();
+    }
 }
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FontSet {
     pub name: String,
 }
@@ -992,12 +1432,18 @@ impl XimWrite for FontSet {
         writer.write_pad4();
     }
     fn size(&self) -> usize {
-        let mut content_size = 0;
-        content_size += with_pad4(self.name.len() + 2 + 0 - 0);
+        let mut content_size = 0usize;
+        content_size = content_size.saturating_add(with_pad4(self.name.len() + 2 + 0 - 0));
         content_size
     }
+    fn write_vectored<'a>(&'a self, writer: &mut VectoredWriter<'a>) {
+        writer.copy(&(self.name.len() as u16));
+        writer.borrow(self.name.as_bytes());
+        writer.write_pad4();
+    }
 }
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Point {
     pub x: i16,
     pub y: i16,
@@ -1016,13 +1462,18 @@ impl XimWrite for Point {
         self.y.write(writer);
     }
     fn size(&self) -> usize {
-        let mut content_size = 0;
-        content_size += self.x.size();
-        content_size += self.y.size();
+        let mut content_size = 0usize;
+        content_size = content_size.saturating_add(self.x.size());
+        content_size = content_size.saturating_add(self.y.size());
         content_size
     }
+    fn write_vectored<'a>(&'a self, writer: &mut VectoredWriter<'a>) {
+        self.x.write_vectored(writer);
+        self.y.write_vectored(writer);
+    }
 }
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Rectangle {
     pub x: i16,
     pub y: i16,
@@ -1047,15 +1498,22 @@ impl XimWrite for Rectangle {
         self.height.write(writer);
     }
     fn size(&self) -> usize {
-        let mut content_size = 0;
-        content_size += self.x.size();
-        content_size += self.y.size();
-        content_size += self.width.size();
-        content_size += self.height.size();
+        let mut content_size = 0usize;
+        content_size = content_size.saturating_add(self.x.size());
+        content_size = content_size.saturating_add(self.y.size());
+        content_size = content_size.saturating_add(self.width.size());
+        content_size = content_size.saturating_add(self.height.size());
         content_size
     }
+    fn write_vectored<'a>(&'a self, writer: &mut VectoredWriter<'a>) {
+        self.x.write_vectored(writer);
+        self.y.write_vectored(writer);
+        self.width.write_vectored(writer);
+        self.height.write_vectored(writer);
+    }
 }
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StatusTextContent {
     pub status: PreeditDrawStatus,
     pub status_string: String,
@@ -1076,7 +1534,10 @@ impl XimRead for StatusTextContent {
             feedbacks: {
                 let mut out = Vec::new();
                 let len = u16::read(reader)? as usize;
-                let end = reader.cursor() - len;
+                let end = reader
+                    .cursor()
+                    .checked_sub(len)
+                    .ok_or_else(|| reader.eos())?;
                 u16::read(reader)?;
                 while reader.cursor() > end {
                     out.push(Feedback::read(reader)?);
@@ -1092,7 +1553,13 @@ impl XimWrite for StatusTextContent {
         (self.status_string.len() as u16).write(writer);
         writer.write(self.status_string.as_bytes());
         writer.write_pad4();
-        ((self.feedbacks.iter().map(|e| e.size()).sum::<usize>() + 2 + 2 - 2 - 2) as u16)
+        ((usize::saturating_add(
+            self.feedbacks
+                .iter()
+                .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+            2 + 2,
+        ) - 2
+            - 2) as u16)
             .write(writer);
         0u16.write(writer);
         for elem in self.feedbacks.iter() {
@@ -1100,14 +1567,39 @@ impl XimWrite for StatusTextContent {
         }
     }
     fn size(&self) -> usize {
-        let mut content_size = 0;
-        content_size += self.status.size();
-        content_size += with_pad4(self.status_string.len() + 2 + 0 - 0);
-        content_size += self.feedbacks.iter().map(|e| e.size()).sum::<usize>() + 2 + 2;
+        let mut content_size = 0usize;
+        content_size = content_size.saturating_add(self.status.size());
+        content_size = content_size.saturating_add(with_pad4(self.status_string.len() + 2 + 0 - 0));
+        content_size = content_size.saturating_add(usize::saturating_add(
+            self.feedbacks
+                .iter()
+                .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+            2 + 2,
+        ));
         content_size
     }
+    fn write_vectored<'a>(&'a self, writer: &mut VectoredWriter<'a>) {
+        self.status.write_vectored(writer);
+        writer.copy(&(self.status_string.len() as u16));
+        writer.borrow(self.status_string.as_bytes());
+        writer.write_pad4();
+        writer.copy(
+            &((usize::saturating_add(
+                self.feedbacks
+                    .iter()
+                    .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                2 + 2,
+            ) - 2
+                - 2) as u16),
+        );
+        writer.write_zeros(2);
+        for elem in self.feedbacks.iter() {
+            elem.write_vectored(writer);
+        }
+    }
 }
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TriggerKey {
     pub keysym: u32,
     pub modifier: u32,
@@ -1129,14 +1621,20 @@ impl XimWrite for TriggerKey {
         self.modifier_mask.write(writer);
     }
     fn size(&self) -> usize {
-        let mut content_size = 0;
-        content_size += self.keysym.size();
-        content_size += self.modifier.size();
-        content_size += self.modifier_mask.size();
+        let mut content_size = 0usize;
+        content_size = content_size.saturating_add(self.keysym.size());
+        content_size = content_size.saturating_add(self.modifier.size());
+        content_size = content_size.saturating_add(self.modifier_mask.size());
         content_size
     }
+    fn write_vectored<'a>(&'a self, writer: &mut VectoredWriter<'a>) {
+        self.keysym.write_vectored(writer);
+        self.modifier.write_vectored(writer);
+        self.modifier_mask.write_vectored(writer);
+    }
 }
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct XEvent {
     pub response_type: u8,
     pub detail: u8,
@@ -1193,24 +1691,42 @@ impl XimWrite for XEvent {
         writer.write(&[0u8; 1]);
     }
     fn size(&self) -> usize {
-        let mut content_size = 0;
-        content_size += self.response_type.size();
-        content_size += self.detail.size();
-        content_size += self.sequence.size();
-        content_size += self.time.size();
-        content_size += self.root.size();
-        content_size += self.event.size();
-        content_size += self.child.size();
-        content_size += self.root_x.size();
-        content_size += self.root_y.size();
-        content_size += self.event_x.size();
-        content_size += self.event_y.size();
-        content_size += self.state.size();
-        content_size += self.same_screen.size() + 1;
+        let mut content_size = 0usize;
+        content_size = content_size.saturating_add(self.response_type.size());
+        content_size = content_size.saturating_add(self.detail.size());
+        content_size = content_size.saturating_add(self.sequence.size());
+        content_size = content_size.saturating_add(self.time.size());
+        content_size = content_size.saturating_add(self.root.size());
+        content_size = content_size.saturating_add(self.event.size());
+        content_size = content_size.saturating_add(self.child.size());
+        content_size = content_size.saturating_add(self.root_x.size());
+        content_size = content_size.saturating_add(self.root_y.size());
+        content_size = content_size.saturating_add(self.event_x.size());
+        content_size = content_size.saturating_add(self.event_y.size());
+        content_size = content_size.saturating_add(self.state.size());
+        content_size =
+            content_size.saturating_add(usize::saturating_add(self.same_screen.size(), 1));
         content_size
     }
+    fn write_vectored<'a>(&'a self, writer: &mut VectoredWriter<'a>) {
+        self.response_type.write_vectored(writer);
+        self.detail.write_vectored(writer);
+        self.sequence.write_vectored(writer);
+        self.time.write_vectored(writer);
+        self.root.write_vectored(writer);
+        self.event.write_vectored(writer);
+        self.child.write_vectored(writer);
+        self.root_x.write_vectored(writer);
+        self.root_y.write_vectored(writer);
+        self.event_x.write_vectored(writer);
+        self.event_y.write_vectored(writer);
+        self.state.write_vectored(writer);
+        self.same_screen.write_vectored(writer);
+        writer.write_zeros(1);
+    }
 }
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AttributeName {
     Area,
     AreaNeeded,
@@ -1229,6 +1745,7 @@ pub enum AttributeName {
     HotKeyState,
     InputStyle,
     LineSpace,
+    Locale,
     NestedList,
     PreeditAttributes,
     PreeditCaretCallback,
@@ -1275,6 +1792,7 @@ impl AttributeName {
             Self::HotKeyState => "hotKeyState",
             Self::InputStyle => "inputStyle",
             Self::LineSpace => "lineSpace",
+            Self::Locale => "locale",
             Self::NestedList => "XNVaNestedList",
             Self::PreeditAttributes => "preeditAttributes",
             Self::PreeditCaretCallback => "preeditCaretCallback",
@@ -1324,6 +1842,7 @@ impl XimRead for AttributeName {
             b"hotKeyState" => Ok(Self::HotKeyState),
             b"inputStyle" => Ok(Self::InputStyle),
             b"lineSpace" => Ok(Self::LineSpace),
+            b"locale" => Ok(Self::Locale),
             b"XNVaNestedList" => Ok(Self::NestedList),
             b"preeditAttributes" => Ok(Self::PreeditAttributes),
             b"preeditCaretCallback" => Ok(Self::PreeditCaretCallback),
@@ -1367,6 +1886,8 @@ impl XimWrite for AttributeName {
     }
 }
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Request {
     AuthNext {},
     AuthNg {},
@@ -1653,37 +2174,729 @@ impl Request {
             Request::UnsetIcFocus { .. } => "UnsetIcFocus",
         }
     }
+    /// The `input_method_id` of the request, if it has one.
+    pub fn input_method_id(&self) -> Option<u16> {
+        match self {
+            Request::Close {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::CloseReply {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::Commit {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::CreateIc {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::CreateIcReply {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::DestroyIc {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::DestroyIcReply {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::EncodingNegotiation {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::EncodingNegotiationReply {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::Error {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::ForwardEvent {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::Geometry {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::GetIcValues {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::GetIcValuesReply {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::GetImValues {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::GetImValuesReply {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::OpenReply {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::PreeditCaret {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::PreeditCaretReply {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::PreeditDone {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::PreeditDraw {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::PreeditStart {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::PreeditStartReply {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::PreeditState {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::QueryExtension {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::QueryExtensionReply {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::RegisterTriggerKeys {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::ResetIc {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::ResetIcReply {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::SetEventMask {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::SetIcFocus {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::SetIcValues {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::SetIcValuesReply {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::SetImValues {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::SetImValuesReply {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::StatusDone {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::StatusDraw {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::StatusStart {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::Sync {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::SyncReply {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::TriggerNotify {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::TriggerNotifyReply {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            Request::UnsetIcFocus {
+                input_method_id, ..
+            } => Some(*input_method_id),
+            _ => None,
+        }
+    }
+    /// The `input_context_id` of the request, if it has one.
+    pub fn input_context_id(&self) -> Option<u16> {
+        match self {
+            Request::Commit {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::CreateIcReply {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::DestroyIc {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::DestroyIcReply {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::Error {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::ForwardEvent {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::Geometry {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::GetIcValues {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::GetIcValuesReply {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::PreeditCaret {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::PreeditCaretReply {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::PreeditDone {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::PreeditDraw {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::PreeditStart {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::PreeditStartReply {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::PreeditState {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::ResetIc {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::ResetIcReply {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::SetEventMask {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::SetIcFocus {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::SetIcValues {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::SetIcValuesReply {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::StatusDone {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::StatusDraw {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::StatusStart {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::Sync {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::SyncReply {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::TriggerNotify {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::TriggerNotifyReply {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            Request::UnsetIcFocus {
+                input_context_id, ..
+            } => Some(*input_context_id),
+            _ => None,
+        }
+    }
+    /// The major opcode and, if the request has one, the minor opcode.
+    pub fn opcode(&self) -> (u8, Option<u8>) {
+        match self {
+            Request::AuthNext { .. } => (12, None),
+            Request::AuthNg { .. } => (14, None),
+            Request::AuthReply { .. } => (11, None),
+            Request::AuthRequired { .. } => (10, None),
+            Request::AuthSetup { .. } => (13, None),
+            Request::Close { .. } => (32, None),
+            Request::CloseReply { .. } => (33, None),
+            Request::Commit { .. } => (63, None),
+            Request::Connect { .. } => (1, None),
+            Request::ConnectReply { .. } => (2, None),
+            Request::CreateIc { .. } => (50, None),
+            Request::CreateIcReply { .. } => (51, None),
+            Request::DestroyIc { .. } => (52, None),
+            Request::DestroyIcReply { .. } => (53, None),
+            Request::Disconnect { .. } => (3, None),
+            Request::DisconnectReply { .. } => (4, None),
+            Request::EncodingNegotiation { .. } => (38, None),
+            Request::EncodingNegotiationReply { .. } => (39, None),
+            Request::Error { .. } => (20, None),
+            Request::ForwardEvent { .. } => (60, None),
+            Request::Geometry { .. } => (70, None),
+            Request::GetIcValues { .. } => (56, None),
+            Request::GetIcValuesReply { .. } => (57, None),
+            Request::GetImValues { .. } => (44, None),
+            Request::GetImValuesReply { .. } => (45, None),
+            Request::Open { .. } => (30, None),
+            Request::OpenReply { .. } => (31, None),
+            Request::PreeditCaret { .. } => (76, None),
+            Request::PreeditCaretReply { .. } => (77, None),
+            Request::PreeditDone { .. } => (78, None),
+            Request::PreeditDraw { .. } => (75, None),
+            Request::PreeditStart { .. } => (73, None),
+            Request::PreeditStartReply { .. } => (74, None),
+            Request::PreeditState { .. } => (82, None),
+            Request::QueryExtension { .. } => (40, None),
+            Request::QueryExtensionReply { .. } => (41, None),
+            Request::RegisterTriggerKeys { .. } => (34, None),
+            Request::ResetIc { .. } => (64, None),
+            Request::ResetIcReply { .. } => (65, None),
+            Request::SetEventMask { .. } => (37, None),
+            Request::SetIcFocus { .. } => (58, None),
+            Request::SetIcValues { .. } => (54, None),
+            Request::SetIcValuesReply { .. } => (55, None),
+            Request::SetImValues { .. } => (42, None),
+            Request::SetImValuesReply { .. } => (43, None),
+            Request::StatusDone { .. } => (81, None),
+            Request::StatusDraw { .. } => (80, None),
+            Request::StatusStart { .. } => (79, None),
+            Request::StrConversion { .. } => (71, None),
+            Request::StrConversionReply { .. } => (72, None),
+            Request::Sync { .. } => (61, None),
+            Request::SyncReply { .. } => (62, None),
+            Request::TriggerNotify { .. } => (35, None),
+            Request::TriggerNotifyReply { .. } => (36, None),
+            Request::UnsetIcFocus { .. } => (59, None),
+        }
+    }
+    /// Check the internal consistency of the request: every string and list must fit its
+    /// length prefix and the whole request must fit the 16-bit length of the header.
+    pub fn validate(&self) -> Result<(), ReadError> {
+        match self {
+            Request::AuthNext { .. } => {}
+            Request::AuthNg { .. } => {}
+            Request::AuthReply { .. } => {}
+            Request::AuthRequired { .. } => {}
+            Request::AuthSetup { .. } => {}
+            Request::Close { .. } => {}
+            Request::CloseReply { .. } => {}
+            Request::Commit { .. } => {}
+            Request::Connect {
+                client_auth_protocol_names,
+                ..
+            } => {
+                {
+                    let len = usize::saturating_add(
+                        client_auth_protocol_names.iter().fold(0usize, |acc, e| {
+                            usize::saturating_add(acc, with_pad4(e.len() + 2 + 0 - 0))
+                        }),
+                        0 + 2,
+                    ) - 2
+                        - 0;
+                    if len > u16::MAX as usize {
+                        return Err(ReadError::InvalidData(
+                            "Length",
+                            alloc::format!(
+                                "client_auth_protocol_names: {} bytes don't fit u16",
+                                len
+                            ),
+                        ));
+                    }
+                }
+                for e in client_auth_protocol_names.iter() {
+                    {
+                        let len = e.len();
+                        if len > u16::MAX as usize {
+                            return Err(ReadError::InvalidData(
+                                "Length",
+                                alloc::format!(
+                                    "client_auth_protocol_names: {} bytes don't fit u16",
+                                    len
+                                ),
+                            ));
+                        }
+                    }
+                }
+            }
+            Request::ConnectReply { .. } => {}
+            Request::CreateIc { ic_attributes, .. } => {
+                let len = usize::saturating_add(
+                    ic_attributes
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 2,
+                ) - 2
+                    - 0;
+                if len > u16::MAX as usize {
+                    return Err(ReadError::InvalidData(
+                        "Length",
+                        alloc::format!("ic_attributes: {} bytes don't fit u16", len),
+                    ));
+                }
+            }
+            Request::CreateIcReply { .. } => {}
+            Request::DestroyIc { .. } => {}
+            Request::DestroyIcReply { .. } => {}
+            Request::Disconnect { .. } => {}
+            Request::DisconnectReply { .. } => {}
+            Request::EncodingNegotiation {
+                encodings,
+                encoding_infos,
+                ..
+            } => {
+                {
+                    let len = usize::saturating_add(
+                        encodings
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.len() + 1 + 0)),
+                        0 + 2,
+                    ) - 2
+                        - 0;
+                    if len > u16::MAX as usize {
+                        return Err(ReadError::InvalidData(
+                            "Length",
+                            alloc::format!("encodings: {} bytes don't fit u16", len),
+                        ));
+                    }
+                }
+                for e in encodings.iter() {
+                    {
+                        let len = e.len();
+                        if len > u8::MAX as usize {
+                            return Err(ReadError::InvalidData(
+                                "Length",
+                                alloc::format!("encodings: {} bytes don't fit u8", len),
+                            ));
+                        }
+                    }
+                }
+                {
+                    let len = usize::saturating_add(
+                        encoding_infos.iter().fold(0usize, |acc, e| {
+                            usize::saturating_add(acc, with_pad4(e.len() + 2 + 0 - 0))
+                        }),
+                        2 + 2,
+                    ) - 2
+                        - 2;
+                    if len > u16::MAX as usize {
+                        return Err(ReadError::InvalidData(
+                            "Length",
+                            alloc::format!("encoding_infos: {} bytes don't fit u16", len),
+                        ));
+                    }
+                }
+                for e in encoding_infos.iter() {
+                    {
+                        let len = e.len();
+                        if len > u16::MAX as usize {
+                            return Err(ReadError::InvalidData(
+                                "Length",
+                                alloc::format!("encoding_infos: {} bytes don't fit u16", len),
+                            ));
+                        }
+                    }
+                }
+            }
+            Request::EncodingNegotiationReply { .. } => {}
+            Request::Error { detail, .. } => {
+                let len = detail.len();
+                if len > u16::MAX as usize {
+                    return Err(ReadError::InvalidData(
+                        "Length",
+                        alloc::format!("detail: {} bytes don't fit u16", len),
+                    ));
+                }
+            }
+            Request::ForwardEvent { .. } => {}
+            Request::Geometry { .. } => {}
+            Request::GetIcValues { ic_attributes, .. } => {
+                let len = usize::saturating_add(
+                    ic_attributes
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 2,
+                ) - 2
+                    - 0;
+                if len > u16::MAX as usize {
+                    return Err(ReadError::InvalidData(
+                        "Length",
+                        alloc::format!("ic_attributes: {} bytes don't fit u16", len),
+                    ));
+                }
+            }
+            Request::GetIcValuesReply { ic_attributes, .. } => {
+                let len = usize::saturating_add(
+                    ic_attributes
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    2 + 2,
+                ) - 2
+                    - 2;
+                if len > u16::MAX as usize {
+                    return Err(ReadError::InvalidData(
+                        "Length",
+                        alloc::format!("ic_attributes: {} bytes don't fit u16", len),
+                    ));
+                }
+            }
+            Request::GetImValues { im_attributes, .. } => {
+                let len = usize::saturating_add(
+                    im_attributes
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 2,
+                ) - 2
+                    - 0;
+                if len > u16::MAX as usize {
+                    return Err(ReadError::InvalidData(
+                        "Length",
+                        alloc::format!("im_attributes: {} bytes don't fit u16", len),
+                    ));
+                }
+            }
+            Request::GetImValuesReply { im_attributes, .. } => {
+                let len = usize::saturating_add(
+                    im_attributes
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 2,
+                ) - 2
+                    - 0;
+                if len > u16::MAX as usize {
+                    return Err(ReadError::InvalidData(
+                        "Length",
+                        alloc::format!("im_attributes: {} bytes don't fit u16", len),
+                    ));
+                }
+            }
+            Request::Open { locale, .. } => {
+                let len = locale.len();
+                if len > u8::MAX as usize {
+                    return Err(ReadError::InvalidData(
+                        "Length",
+                        alloc::format!("locale: {} bytes don't fit u8", len),
+                    ));
+                }
+            }
+            Request::OpenReply {
+                im_attrs, ic_attrs, ..
+            } => {
+                {
+                    let len = usize::saturating_add(
+                        im_attrs
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                        0 + 2,
+                    ) - 2
+                        - 0;
+                    if len > u16::MAX as usize {
+                        return Err(ReadError::InvalidData(
+                            "Length",
+                            alloc::format!("im_attrs: {} bytes don't fit u16", len),
+                        ));
+                    }
+                }
+                {
+                    let len = usize::saturating_add(
+                        ic_attrs
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                        2 + 2,
+                    ) - 2
+                        - 2;
+                    if len > u16::MAX as usize {
+                        return Err(ReadError::InvalidData(
+                            "Length",
+                            alloc::format!("ic_attrs: {} bytes don't fit u16", len),
+                        ));
+                    }
+                }
+            }
+            Request::PreeditCaret { .. } => {}
+            Request::PreeditCaretReply { .. } => {}
+            Request::PreeditDone { .. } => {}
+            Request::PreeditDraw {
+                preedit_string,
+                feedbacks,
+                ..
+            } => {
+                {
+                    let len = preedit_string.len();
+                    if len > u16::MAX as usize {
+                        return Err(ReadError::InvalidData(
+                            "Length",
+                            alloc::format!("preedit_string: {} bytes don't fit u16", len),
+                        ));
+                    }
+                }
+                {
+                    let len = usize::saturating_add(
+                        feedbacks
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                        2 + 2,
+                    ) - 2
+                        - 2;
+                    if len > u16::MAX as usize {
+                        return Err(ReadError::InvalidData(
+                            "Length",
+                            alloc::format!("feedbacks: {} bytes don't fit u16", len),
+                        ));
+                    }
+                }
+            }
+            Request::PreeditStart { .. } => {}
+            Request::PreeditStartReply { .. } => {}
+            Request::PreeditState { .. } => {}
+            Request::QueryExtension { extensions, .. } => {
+                {
+                    let len = usize::saturating_add(
+                        extensions
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.len() + 1 + 0)),
+                        0 + 2,
+                    ) - 2
+                        - 0;
+                    if len > u16::MAX as usize {
+                        return Err(ReadError::InvalidData(
+                            "Length",
+                            alloc::format!("extensions: {} bytes don't fit u16", len),
+                        ));
+                    }
+                }
+                for e in extensions.iter() {
+                    {
+                        let len = e.len();
+                        if len > u8::MAX as usize {
+                            return Err(ReadError::InvalidData(
+                                "Length",
+                                alloc::format!("extensions: {} bytes don't fit u8", len),
+                            ));
+                        }
+                    }
+                }
+            }
+            Request::QueryExtensionReply { extensions, .. } => {
+                let len = usize::saturating_add(
+                    extensions
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 2,
+                ) - 2
+                    - 0;
+                if len > u16::MAX as usize {
+                    return Err(ReadError::InvalidData(
+                        "Length",
+                        alloc::format!("extensions: {} bytes don't fit u16", len),
+                    ));
+                }
+            }
+            Request::RegisterTriggerKeys {
+                on_keys, off_keys, ..
+            } => {
+                {
+                    let len = usize::saturating_add(
+                        on_keys
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                        0 + 4,
+                    ) - 4
+                        - 0;
+                    if len > u32::MAX as usize {
+                        return Err(ReadError::InvalidData(
+                            "Length",
+                            alloc::format!("on_keys: {} bytes don't fit u32", len),
+                        ));
+                    }
+                }
+                {
+                    let len = usize::saturating_add(
+                        off_keys
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                        0 + 4,
+                    ) - 4
+                        - 0;
+                    if len > u32::MAX as usize {
+                        return Err(ReadError::InvalidData(
+                            "Length",
+                            alloc::format!("off_keys: {} bytes don't fit u32", len),
+                        ));
+                    }
+                }
+            }
+            Request::ResetIc { .. } => {}
+            Request::ResetIcReply { preedit_string, .. } => {
+                let len = preedit_string.len();
+                if len > u16::MAX as usize {
+                    return Err(ReadError::InvalidData(
+                        "Length",
+                        alloc::format!("preedit_string: {} bytes don't fit u16", len),
+                    ));
+                }
+            }
+            Request::SetEventMask { .. } => {}
+            Request::SetIcFocus { .. } => {}
+            Request::SetIcValues { ic_attributes, .. } => {
+                let len = usize::saturating_add(
+                    ic_attributes
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    2 + 2,
+                ) - 2
+                    - 2;
+                if len > u16::MAX as usize {
+                    return Err(ReadError::InvalidData(
+                        "Length",
+                        alloc::format!("ic_attributes: {} bytes don't fit u16", len),
+                    ));
+                }
+            }
+            Request::SetIcValuesReply { .. } => {}
+            Request::SetImValues { attributes, .. } => {
+                let len = usize::saturating_add(
+                    attributes
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 2,
+                ) - 2
+                    - 0;
+                if len > u16::MAX as usize {
+                    return Err(ReadError::InvalidData(
+                        "Length",
+                        alloc::format!("attributes: {} bytes don't fit u16", len),
+                    ));
+                }
+            }
+            Request::SetImValuesReply { .. } => {}
+            Request::StatusDone { .. } => {}
+            Request::StatusDraw { .. } => {}
+            Request::StatusStart { .. } => {}
+            Request::StrConversion { .. } => {}
+            Request::StrConversionReply { .. } => {}
+            Request::Sync { .. } => {}
+            Request::SyncReply { .. } => {}
+            Request::TriggerNotify { .. } => {}
+            Request::TriggerNotifyReply { .. } => {}
+            Request::UnsetIcFocus { .. } => {}
+        }
+        let length = (self.size() - 4) / 4;
+        if length > u16::MAX as usize {
+            return Err(ReadError::InvalidData(
+                "Length",
+                alloc::format!("{} words don't fit u16", length),
+            ));
+        }
+        Ok(())
+    }
 }
 impl XimRead for Request {
     fn read(reader: &mut Reader) -> Result<Self, ReadError> {
         let major_opcode = reader.u8()?;
         let minor_opcode = reader.u8()?;
-        let _length = reader.u16()?;
+        if major_opcode == 1 {
+            reader.peek_endian(2)?;
+        }
+        let length = reader.u16()? as usize * 4;
+        let endian = reader.endian();
+        let reader = &mut Reader::with_endian(reader.consume(length)?, endian);
         match (major_opcode, minor_opcode) {
-            (12, _) => Ok(Request::AuthNext {}),
-            (14, _) => Ok(Request::AuthNg {}),
-            (11, _) => Ok(Request::AuthReply {}),
-            (10, _) => Ok(Request::AuthRequired {}),
-            (13, _) => Ok(Request::AuthSetup {}),
-            (32, _) => Ok(Request::Close {
-                input_method_id: {
-                    let inner = u16::read(reader)?;
-                    reader.consume(2)?;
-                    inner
-                },
-            }),
-            (33, _) => Ok(Request::CloseReply {
-                input_method_id: {
-                    let inner = u16::read(reader)?;
-                    reader.consume(2)?;
-                    inner
-                },
-            }),
-            (63, _) => Ok(Request::Commit {
-                input_method_id: u16::read(reader)?,
-                input_context_id: u16::read(reader)?,
-                data: CommitData::read(reader)?,
-            }),
             (1, _) => Ok(Request::Connect {
                 endian: {
                     let inner = Endian::read(reader)?;
@@ -1695,7 +2908,10 @@ impl XimRead for Request {
                 client_auth_protocol_names: {
                     let mut out = Vec::new();
                     let len = u16::read(reader)? as usize;
-                    let end = reader.cursor() - len;
+                    let end = reader
+                        .cursor()
+                        .checked_sub(len)
+                        .ok_or_else(|| reader.eos())?;
                     while reader.cursor() > end {
                         out.push({
                             let inner = {
@@ -1713,39 +2929,138 @@ impl XimRead for Request {
                 server_major_protocol_version: u16::read(reader)?,
                 server_minor_protocol_version: u16::read(reader)?,
             }),
-            (50, _) => Ok(Request::CreateIc {
-                input_method_id: u16::read(reader)?,
-                ic_attributes: {
-                    let mut out = Vec::new();
-                    let len = u16::read(reader)? as usize;
-                    let end = reader.cursor() - len;
-                    while reader.cursor() > end {
-                        out.push(Attribute::read(reader)?);
-                    }
+            (3, _) => Ok(Request::Disconnect {}),
+            (4, _) => Ok(Request::DisconnectReply {}),
+            (10, _) => Ok(Request::AuthRequired {}),
+            (11, _) => Ok(Request::AuthReply {}),
+            (12, _) => Ok(Request::AuthNext {}),
+            (13, _) => Ok(Request::AuthSetup {}),
+            (14, _) => Ok(Request::AuthNg {}),
+            (20, _) => Ok(Request::Error {
+                input_method_id: u16::read(reader)?,
+                input_context_id: u16::read(reader)?,
+                flag: ErrorFlag::read(reader)?,
+                code: ErrorCode::read(reader)?,
+                detail: {
+                    let inner = {
+                        let len = u16::read(reader)?;
+                        reader.consume(2)?;
+                        String::from_utf8(reader.consume(len as usize)?.to_vec())?
+                    };
+                    reader.pad4()?;
+                    inner
+                },
+            }),
+            (30, _) => Ok(Request::Open {
+                locale: {
+                    let inner = {
+                        let len = u8::read(reader)?;
+                        String::from_utf8(reader.consume(len as usize)?.to_vec())?
+                    };
+                    reader.pad4()?;
+                    inner
+                },
+            }),
+            (31, _) => Ok(Request::OpenReply {
+                input_method_id: u16::read(reader)?,
+                im_attrs: {
+                    let mut out = Vec::new();
+                    let len = u16::read(reader)? as usize;
+                    let end = reader
+                        .cursor()
+                        .checked_sub(len)
+                        .ok_or_else(|| reader.eos())?;
+                    while reader.cursor() > end {
+                        out.push(Attr::read(reader)?);
+                    }
+                    out
+                },
+                ic_attrs: {
+                    let mut out = Vec::new();
+                    let len = u16::read(reader)? as usize;
+                    let end = reader
+                        .cursor()
+                        .checked_sub(len)
+                        .ok_or_else(|| reader.eos())?;
+                    u16::read(reader)?;
+                    while reader.cursor() > end {
+                        out.push(Attr::read(reader)?);
+                    }
                     out
                 },
             }),
-            (51, _) => Ok(Request::CreateIcReply {
+            (32, _) => Ok(Request::Close {
+                input_method_id: {
+                    let inner = u16::read(reader)?;
+                    reader.consume(2)?;
+                    inner
+                },
+            }),
+            (33, _) => Ok(Request::CloseReply {
+                input_method_id: {
+                    let inner = u16::read(reader)?;
+                    reader.consume(2)?;
+                    inner
+                },
+            }),
+            (34, _) => Ok(Request::RegisterTriggerKeys {
+                input_method_id: {
+                    let inner = u16::read(reader)?;
+                    reader.consume(2)?;
+                    inner
+                },
+                on_keys: {
+                    let mut out = Vec::new();
+                    let len = u32::read(reader)? as usize;
+                    let end = reader
+                        .cursor()
+                        .checked_sub(len)
+                        .ok_or_else(|| reader.eos())?;
+                    while reader.cursor() > end {
+                        out.push(TriggerKey::read(reader)?);
+                    }
+                    out
+                },
+                off_keys: {
+                    let mut out = Vec::new();
+                    let len = u32::read(reader)? as usize;
+                    let end = reader
+                        .cursor()
+                        .checked_sub(len)
+                        .ok_or_else(|| reader.eos())?;
+                    while reader.cursor() > end {
+                        out.push(TriggerKey::read(reader)?);
+                    }
+                    out
+                },
+            }),
+            (35, _) => Ok(Request::TriggerNotify {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
+                flag: TriggerNotifyFlag::read(reader)?,
+                index: u32::read(reader)?,
+                event_mask: u32::read(reader)?,
             }),
-            (52, _) => Ok(Request::DestroyIc {
+            (36, _) => Ok(Request::TriggerNotifyReply {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
             }),
-            (53, _) => Ok(Request::DestroyIcReply {
+            (37, _) => Ok(Request::SetEventMask {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
+                forward_event_mask: u32::read(reader)?,
+                synchronous_event_mask: u32::read(reader)?,
             }),
-            (3, _) => Ok(Request::Disconnect {}),
-            (4, _) => Ok(Request::DisconnectReply {}),
             (38, _) => Ok(Request::EncodingNegotiation {
                 input_method_id: u16::read(reader)?,
                 encodings: {
                     let inner = {
                         let mut out = Vec::new();
                         let len = u16::read(reader)? as usize;
-                        let end = reader.cursor() - len;
+                        let end = reader
+                            .cursor()
+                            .checked_sub(len)
+                            .ok_or_else(|| reader.eos())?;
                         while reader.cursor() > end {
                             out.push({
                                 let len = u8::read(reader)?;
@@ -1760,7 +3075,10 @@ impl XimRead for Request {
                 encoding_infos: {
                     let mut out = Vec::new();
                     let len = u16::read(reader)? as usize;
-                    let end = reader.cursor() - len;
+                    let end = reader
+                        .cursor()
+                        .checked_sub(len)
+                        .ok_or_else(|| reader.eos())?;
                     u16::read(reader)?;
                     while reader.cursor() > end {
                         out.push({
@@ -1784,42 +3102,21 @@ impl XimRead for Request {
                     inner
                 },
             }),
-            (20, _) => Ok(Request::Error {
-                input_method_id: u16::read(reader)?,
-                input_context_id: u16::read(reader)?,
-                flag: ErrorFlag::read(reader)?,
-                code: ErrorCode::read(reader)?,
-                detail: {
-                    let inner = {
-                        let len = u16::read(reader)?;
-                        reader.consume(2)?;
-                        String::from_utf8(reader.consume(len as usize)?.to_vec())?
-                    };
-                    reader.pad4()?;
-                    inner
-                },
-            }),
-            (60, _) => Ok(Request::ForwardEvent {
-                input_method_id: u16::read(reader)?,
-                input_context_id: u16::read(reader)?,
-                flag: ForwardEventFlag::read(reader)?,
-                serial_number: u16::read(reader)?,
-                xev: XEvent::read(reader)?,
-            }),
-            (70, _) => Ok(Request::Geometry {
-                input_method_id: u16::read(reader)?,
-                input_context_id: u16::read(reader)?,
-            }),
-            (56, _) => Ok(Request::GetIcValues {
+            (40, _) => Ok(Request::QueryExtension {
                 input_method_id: u16::read(reader)?,
-                input_context_id: u16::read(reader)?,
-                ic_attributes: {
+                extensions: {
                     let inner = {
                         let mut out = Vec::new();
                         let len = u16::read(reader)? as usize;
-                        let end = reader.cursor() - len;
+                        let end = reader
+                            .cursor()
+                            .checked_sub(len)
+                            .ok_or_else(|| reader.eos())?;
                         while reader.cursor() > end {
-                            out.push(u16::read(reader)?);
+                            out.push({
+                                let len = u8::read(reader)?;
+                                String::from_utf8(reader.consume(len as usize)?.to_vec())?
+                            });
                         }
                         out
                     };
@@ -1827,27 +3124,53 @@ impl XimRead for Request {
                     inner
                 },
             }),
-            (57, _) => Ok(Request::GetIcValuesReply {
+            (41, _) => Ok(Request::QueryExtensionReply {
                 input_method_id: u16::read(reader)?,
-                input_context_id: u16::read(reader)?,
-                ic_attributes: {
+                extensions: {
                     let mut out = Vec::new();
                     let len = u16::read(reader)? as usize;
-                    let end = reader.cursor() - len;
-                    u16::read(reader)?;
+                    let end = reader
+                        .cursor()
+                        .checked_sub(len)
+                        .ok_or_else(|| reader.eos())?;
+                    while reader.cursor() > end {
+                        out.push(Extension::read(reader)?);
+                    }
+                    out
+                },
+            }),
+            (42, _) => Ok(Request::SetImValues {
+                input_method_id: u16::read(reader)?,
+                attributes: {
+                    let mut out = Vec::new();
+                    let len = u16::read(reader)? as usize;
+                    let end = reader
+                        .cursor()
+                        .checked_sub(len)
+                        .ok_or_else(|| reader.eos())?;
                     while reader.cursor() > end {
                         out.push(Attribute::read(reader)?);
                     }
                     out
                 },
             }),
+            (43, _) => Ok(Request::SetImValuesReply {
+                input_method_id: {
+                    let inner = u16::read(reader)?;
+                    reader.consume(2)?;
+                    inner
+                },
+            }),
             (44, _) => Ok(Request::GetImValues {
                 input_method_id: u16::read(reader)?,
                 im_attributes: {
                     let inner = {
                         let mut out = Vec::new();
                         let len = u16::read(reader)? as usize;
-                        let end = reader.cursor() - len;
+                        let end = reader
+                            .cursor()
+                            .checked_sub(len)
+                            .ok_or_else(|| reader.eos())?;
                         while reader.cursor() > end {
                             out.push(u16::read(reader)?);
                         }
@@ -1862,113 +3185,77 @@ impl XimRead for Request {
                 im_attributes: {
                     let mut out = Vec::new();
                     let len = u16::read(reader)? as usize;
-                    let end = reader.cursor() - len;
+                    let end = reader
+                        .cursor()
+                        .checked_sub(len)
+                        .ok_or_else(|| reader.eos())?;
                     while reader.cursor() > end {
                         out.push(Attribute::read(reader)?);
                     }
                     out
                 },
             }),
-            (30, _) => Ok(Request::Open {
-                locale: {
-                    let inner = {
-                        let len = u8::read(reader)?;
-                        String::from_utf8(reader.consume(len as usize)?.to_vec())?
-                    };
-                    reader.pad4()?;
-                    inner
-                },
-            }),
-            (31, _) => Ok(Request::OpenReply {
+            (50, _) => Ok(Request::CreateIc {
                 input_method_id: u16::read(reader)?,
-                im_attrs: {
-                    let mut out = Vec::new();
-                    let len = u16::read(reader)? as usize;
-                    let end = reader.cursor() - len;
-                    while reader.cursor() > end {
-                        out.push(Attr::read(reader)?);
-                    }
-                    out
-                },
-                ic_attrs: {
+                ic_attributes: {
                     let mut out = Vec::new();
                     let len = u16::read(reader)? as usize;
-                    let end = reader.cursor() - len;
-                    u16::read(reader)?;
+                    let end = reader
+                        .cursor()
+                        .checked_sub(len)
+                        .ok_or_else(|| reader.eos())?;
                     while reader.cursor() > end {
-                        out.push(Attr::read(reader)?);
+                        out.push(Attribute::read(reader)?);
                     }
                     out
                 },
             }),
-            (76, _) => Ok(Request::PreeditCaret {
+            (51, _) => Ok(Request::CreateIcReply {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
-                position: i32::read(reader)?,
-                direction: CaretDirection::read(reader)?,
-                style: CaretStyle::read(reader)?,
             }),
-            (77, _) => Ok(Request::PreeditCaretReply {
+            (52, _) => Ok(Request::DestroyIc {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
-                position: i32::read(reader)?,
             }),
-            (78, _) => Ok(Request::PreeditDone {
+            (53, _) => Ok(Request::DestroyIcReply {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
             }),
-            (75, _) => Ok(Request::PreeditDraw {
+            (54, _) => Ok(Request::SetIcValues {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
-                caret: i32::read(reader)?,
-                chg_first: i32::read(reader)?,
-                chg_length: i32::read(reader)?,
-                status: PreeditDrawStatus::read(reader)?,
-                preedit_string: {
-                    let inner = {
-                        let len = u16::read(reader)?;
-                        reader.consume(len as usize)?.to_vec()
-                    };
-                    reader.pad4()?;
-                    inner
-                },
-                feedbacks: {
+                ic_attributes: {
                     let mut out = Vec::new();
                     let len = u16::read(reader)? as usize;
-                    let end = reader.cursor() - len;
+                    let end = reader
+                        .cursor()
+                        .checked_sub(len)
+                        .ok_or_else(|| reader.eos())?;
                     u16::read(reader)?;
                     while reader.cursor() > end {
-                        out.push(Feedback::read(reader)?);
+                        out.push(Attribute::read(reader)?);
                     }
                     out
                 },
             }),
-            (73, _) => Ok(Request::PreeditStart {
-                input_method_id: u16::read(reader)?,
-                input_context_id: u16::read(reader)?,
-            }),
-            (74, _) => Ok(Request::PreeditStartReply {
+            (55, _) => Ok(Request::SetIcValuesReply {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
-                return_value: i32::read(reader)?,
             }),
-            (82, _) => Ok(Request::PreeditState {
+            (56, _) => Ok(Request::GetIcValues {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
-                state: PreeditStateFlag::read(reader)?,
-            }),
-            (40, _) => Ok(Request::QueryExtension {
-                input_method_id: u16::read(reader)?,
-                extensions: {
+                ic_attributes: {
                     let inner = {
                         let mut out = Vec::new();
                         let len = u16::read(reader)? as usize;
-                        let end = reader.cursor() - len;
+                        let end = reader
+                            .cursor()
+                            .checked_sub(len)
+                            .ok_or_else(|| reader.eos())?;
                         while reader.cursor() > end {
-                            out.push({
-                                let len = u8::read(reader)?;
-                                String::from_utf8(reader.consume(len as usize)?.to_vec())?
-                            });
+                            out.push(u16::read(reader)?);
                         }
                         out
                     };
@@ -1976,42 +3263,50 @@ impl XimRead for Request {
                     inner
                 },
             }),
-            (41, _) => Ok(Request::QueryExtensionReply {
+            (57, _) => Ok(Request::GetIcValuesReply {
                 input_method_id: u16::read(reader)?,
-                extensions: {
+                input_context_id: u16::read(reader)?,
+                ic_attributes: {
                     let mut out = Vec::new();
                     let len = u16::read(reader)? as usize;
-                    let end = reader.cursor() - len;
+                    let end = reader
+                        .cursor()
+                        .checked_sub(len)
+                        .ok_or_else(|| reader.eos())?;
+                    u16::read(reader)?;
                     while reader.cursor() > end {
-                        out.push(Extension::read(reader)?);
+                        out.push(Attribute::read(reader)?);
                     }
                     out
                 },
             }),
-            (34, _) => Ok(Request::RegisterTriggerKeys {
-                input_method_id: {
-                    let inner = u16::read(reader)?;
-                    reader.consume(2)?;
-                    inner
-                },
-                on_keys: {
-                    let mut out = Vec::new();
-                    let len = u32::read(reader)? as usize;
-                    let end = reader.cursor() - len;
-                    while reader.cursor() > end {
-                        out.push(TriggerKey::read(reader)?);
-                    }
-                    out
-                },
-                off_keys: {
-                    let mut out = Vec::new();
-                    let len = u32::read(reader)? as usize;
-                    let end = reader.cursor() - len;
-                    while reader.cursor() > end {
-                        out.push(TriggerKey::read(reader)?);
-                    }
-                    out
-                },
+            (58, _) => Ok(Request::SetIcFocus {
+                input_method_id: u16::read(reader)?,
+                input_context_id: u16::read(reader)?,
+            }),
+            (59, _) => Ok(Request::UnsetIcFocus {
+                input_method_id: u16::read(reader)?,
+                input_context_id: u16::read(reader)?,
+            }),
+            (60, _) => Ok(Request::ForwardEvent {
+                input_method_id: u16::read(reader)?,
+                input_context_id: u16::read(reader)?,
+                flag: ForwardEventFlag::read(reader)?,
+                serial_number: u16::read(reader)?,
+                xev: XEvent::read(reader)?,
+            }),
+            (61, _) => Ok(Request::Sync {
+                input_method_id: u16::read(reader)?,
+                input_context_id: u16::read(reader)?,
+            }),
+            (62, _) => Ok(Request::SyncReply {
+                input_method_id: u16::read(reader)?,
+                input_context_id: u16::read(reader)?,
+            }),
+            (63, _) => Ok(Request::Commit {
+                input_method_id: u16::read(reader)?,
+                input_context_id: u16::read(reader)?,
+                data: CommitData::read(reader)?,
             }),
             (64, _) => Ok(Request::ResetIc {
                 input_method_id: u16::read(reader)?,
@@ -2029,90 +3324,83 @@ impl XimRead for Request {
                     inner
                 },
             }),
-            (37, _) => Ok(Request::SetEventMask {
+            (70, _) => Ok(Request::Geometry {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
-                forward_event_mask: u32::read(reader)?,
-                synchronous_event_mask: u32::read(reader)?,
             }),
-            (58, _) => Ok(Request::SetIcFocus {
+            (71, _) => Ok(Request::StrConversion {}),
+            (72, _) => Ok(Request::StrConversionReply {}),
+            (73, _) => Ok(Request::PreeditStart {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
             }),
-            (54, _) => Ok(Request::SetIcValues {
+            (74, _) => Ok(Request::PreeditStartReply {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
-                ic_attributes: {
-                    let mut out = Vec::new();
-                    let len = u16::read(reader)? as usize;
-                    let end = reader.cursor() - len;
-                    u16::read(reader)?;
-                    while reader.cursor() > end {
-                        out.push(Attribute::read(reader)?);
-                    }
-                    out
-                },
+                return_value: i32::read(reader)?,
             }),
-            (55, _) => Ok(Request::SetIcValuesReply {
+            (75, _) => Ok(Request::PreeditDraw {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
-            }),
-            (42, _) => Ok(Request::SetImValues {
-                input_method_id: u16::read(reader)?,
-                attributes: {
+                caret: i32::read(reader)?,
+                chg_first: i32::read(reader)?,
+                chg_length: i32::read(reader)?,
+                status: PreeditDrawStatus::read(reader)?,
+                preedit_string: {
+                    let inner = {
+                        let len = u16::read(reader)?;
+                        reader.consume(len as usize)?.to_vec()
+                    };
+                    reader.pad4()?;
+                    inner
+                },
+                feedbacks: {
                     let mut out = Vec::new();
                     let len = u16::read(reader)? as usize;
-                    let end = reader.cursor() - len;
+                    let end = reader
+                        .cursor()
+                        .checked_sub(len)
+                        .ok_or_else(|| reader.eos())?;
+                    u16::read(reader)?;
                     while reader.cursor() > end {
-                        out.push(Attribute::read(reader)?);
+                        out.push(Feedback::read(reader)?);
                     }
                     out
                 },
             }),
-            (43, _) => Ok(Request::SetImValuesReply {
-                input_method_id: {
-                    let inner = u16::read(reader)?;
-                    reader.consume(2)?;
-                    inner
-                },
-            }),
-            (81, _) => Ok(Request::StatusDone {
-                input_method_id: u16::read(reader)?,
-                input_context_id: u16::read(reader)?,
-            }),
-            (80, _) => Ok(Request::StatusDraw {
+            (76, _) => Ok(Request::PreeditCaret {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
-                content: StatusContent::read(reader)?,
+                position: i32::read(reader)?,
+                direction: CaretDirection::read(reader)?,
+                style: CaretStyle::read(reader)?,
             }),
-            (79, _) => Ok(Request::StatusStart {
+            (77, _) => Ok(Request::PreeditCaretReply {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
+                position: i32::read(reader)?,
             }),
-            (71, _) => Ok(Request::StrConversion {}),
-            (72, _) => Ok(Request::StrConversionReply {}),
-            (61, _) => Ok(Request::Sync {
+            (78, _) => Ok(Request::PreeditDone {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
             }),
-            (62, _) => Ok(Request::SyncReply {
+            (79, _) => Ok(Request::StatusStart {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
             }),
-            (35, _) => Ok(Request::TriggerNotify {
+            (80, _) => Ok(Request::StatusDraw {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
-                flag: TriggerNotifyFlag::read(reader)?,
-                index: u32::read(reader)?,
-                event_mask: u32::read(reader)?,
+                content: StatusContent::read(reader)?,
             }),
-            (36, _) => Ok(Request::TriggerNotifyReply {
+            (81, _) => Ok(Request::StatusDone {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
             }),
-            (59, _) => Ok(Request::UnsetIcFocus {
+            (82, _) => Ok(Request::PreeditState {
                 input_method_id: u16::read(reader)?,
                 input_context_id: u16::read(reader)?,
+                state: PreeditStateFlag::read(reader)?,
             }),
             _ => Err(reader.invalid_data(
                 "Opcode",
@@ -2161,19 +3449,754 @@ impl XimWrite for Request {
                 0u8.write(writer);
                 (((self.size() - 4) / 4) as u16).write(writer);
                 input_method_id.write(writer);
-                writer.write(&[0u8; 2]);
+                writer.write(&[0u8; 2]);
+            }
+            Request::Commit {
+                input_method_id,
+                input_context_id,
+                data,
+            } => {
+                63u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+                data.write(writer);
+            }
+            Request::Connect {
+                endian,
+                client_major_protocol_version,
+                client_minor_protocol_version,
+                client_auth_protocol_names,
+            } => {
+                1u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                endian.write(writer);
+                writer.write(&[0u8; 1]);
+                client_major_protocol_version.write(writer);
+                client_minor_protocol_version.write(writer);
+                ((usize::saturating_add(
+                    client_auth_protocol_names.iter().fold(0usize, |acc, e| {
+                        usize::saturating_add(acc, with_pad4(e.len() + 2 + 0 - 0))
+                    }),
+                    0 + 2,
+                ) - 2
+                    - 0) as u16)
+                    .write(writer);
+                for elem in client_auth_protocol_names.iter() {
+                    (elem.len() as u16).write(writer);
+                    writer.write(elem.as_bytes());
+                    writer.write_pad4();
+                }
+            }
+            Request::ConnectReply {
+                server_major_protocol_version,
+                server_minor_protocol_version,
+            } => {
+                2u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                server_major_protocol_version.write(writer);
+                server_minor_protocol_version.write(writer);
+            }
+            Request::CreateIc {
+                input_method_id,
+                ic_attributes,
+            } => {
+                50u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                ((usize::saturating_add(
+                    ic_attributes
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 2,
+                ) - 2
+                    - 0) as u16)
+                    .write(writer);
+                for elem in ic_attributes.iter() {
+                    elem.write(writer);
+                }
+            }
+            Request::CreateIcReply {
+                input_method_id,
+                input_context_id,
+            } => {
+                51u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+            }
+            Request::DestroyIc {
+                input_method_id,
+                input_context_id,
+            } => {
+                52u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+            }
+            Request::DestroyIcReply {
+                input_method_id,
+                input_context_id,
+            } => {
+                53u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+            }
+            Request::Disconnect {} => {
+                3u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+            }
+            Request::DisconnectReply {} => {
+                4u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+            }
+            Request::EncodingNegotiation {
+                input_method_id,
+                encodings,
+                encoding_infos,
+            } => {
+                38u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                ((usize::saturating_add(
+                    encodings
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.len() + 1 + 0)),
+                    0 + 2,
+                ) - 2
+                    - 0) as u16)
+                    .write(writer);
+                for elem in encodings.iter() {
+                    (elem.len() as u8).write(writer);
+                    writer.write(elem.as_bytes());
+                }
+                writer.write_pad4();
+                ((usize::saturating_add(
+                    encoding_infos.iter().fold(0usize, |acc, e| {
+                        usize::saturating_add(acc, with_pad4(e.len() + 2 + 0 - 0))
+                    }),
+                    2 + 2,
+                ) - 2
+                    - 2) as u16)
+                    .write(writer);
+                0u16.write(writer);
+                for elem in encoding_infos.iter() {
+                    (elem.len() as u16).write(writer);
+                    writer.write(elem.as_bytes());
+                    writer.write_pad4();
+                }
+            }
+            Request::EncodingNegotiationReply {
+                input_method_id,
+                category,
+                index,
+            } => {
+                39u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                category.write(writer);
+                index.write(writer);
+                writer.write(&[0u8; 2]);
+            }
+            Request::Error {
+                input_method_id,
+                input_context_id,
+                flag,
+                code,
+                detail,
+            } => {
+                20u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+                flag.write(writer);
+                code.write(writer);
+                (detail.len() as u16).write(writer);
+                writer.write(&[0u8; 2]);
+                writer.write(detail.as_bytes());
+                writer.write_pad4();
+            }
+            Request::ForwardEvent {
+                input_method_id,
+                input_context_id,
+                flag,
+                serial_number,
+                xev,
+            } => {
+                60u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+                flag.write(writer);
+                serial_number.write(writer);
+                xev.write(writer);
+            }
+            Request::Geometry {
+                input_method_id,
+                input_context_id,
+            } => {
+                70u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+            }
+            Request::GetIcValues {
+                input_method_id,
+                input_context_id,
+                ic_attributes,
+            } => {
+                56u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+                ((usize::saturating_add(
+                    ic_attributes
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 2,
+                ) - 2
+                    - 0) as u16)
+                    .write(writer);
+                for elem in ic_attributes.iter() {
+                    elem.write(writer);
+                }
+                writer.write_pad4();
+            }
+            Request::GetIcValuesReply {
+                input_method_id,
+                input_context_id,
+                ic_attributes,
+            } => {
+                57u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+                ((usize::saturating_add(
+                    ic_attributes
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    2 + 2,
+                ) - 2
+                    - 2) as u16)
+                    .write(writer);
+                0u16.write(writer);
+                for elem in ic_attributes.iter() {
+                    elem.write(writer);
+                }
+            }
+            Request::GetImValues {
+                input_method_id,
+                im_attributes,
+            } => {
+                44u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                ((usize::saturating_add(
+                    im_attributes
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 2,
+                ) - 2
+                    - 0) as u16)
+                    .write(writer);
+                for elem in im_attributes.iter() {
+                    elem.write(writer);
+                }
+                writer.write_pad4();
+            }
+            Request::GetImValuesReply {
+                input_method_id,
+                im_attributes,
+            } => {
+                45u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                ((usize::saturating_add(
+                    im_attributes
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 2,
+                ) - 2
+                    - 0) as u16)
+                    .write(writer);
+                for elem in im_attributes.iter() {
+                    elem.write(writer);
+                }
+            }
+            Request::Open { locale } => {
+                30u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                (locale.len() as u8).write(writer);
+                writer.write(locale.as_bytes());
+                writer.write_pad4();
+            }
+            Request::OpenReply {
+                input_method_id,
+                im_attrs,
+                ic_attrs,
+            } => {
+                31u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                ((usize::saturating_add(
+                    im_attrs
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 2,
+                ) - 2
+                    - 0) as u16)
+                    .write(writer);
+                for elem in im_attrs.iter() {
+                    elem.write(writer);
+                }
+                ((usize::saturating_add(
+                    ic_attrs
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    2 + 2,
+                ) - 2
+                    - 2) as u16)
+                    .write(writer);
+                0u16.write(writer);
+                for elem in ic_attrs.iter() {
+                    elem.write(writer);
+                }
+            }
+            Request::PreeditCaret {
+                input_method_id,
+                input_context_id,
+                position,
+                direction,
+                style,
+            } => {
+                76u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+                position.write(writer);
+                direction.write(writer);
+                style.write(writer);
+            }
+            Request::PreeditCaretReply {
+                input_method_id,
+                input_context_id,
+                position,
+            } => {
+                77u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+                position.write(writer);
+            }
+            Request::PreeditDone {
+                input_method_id,
+                input_context_id,
+            } => {
+                78u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+            }
+            Request::PreeditDraw {
+                input_method_id,
+                input_context_id,
+                caret,
+                chg_first,
+                chg_length,
+                status,
+                preedit_string,
+                feedbacks,
+            } => {
+                75u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+                caret.write(writer);
+                chg_first.write(writer);
+                chg_length.write(writer);
+                status.write(writer);
+                (preedit_string.len() as u16).write(writer);
+                writer.write(&preedit_string);
+                writer.write_pad4();
+                ((usize::saturating_add(
+                    feedbacks
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    2 + 2,
+                ) - 2
+                    - 2) as u16)
+                    .write(writer);
+                0u16.write(writer);
+                for elem in feedbacks.iter() {
+                    elem.write(writer);
+                }
+            }
+            Request::PreeditStart {
+                input_method_id,
+                input_context_id,
+            } => {
+                73u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+            }
+            Request::PreeditStartReply {
+                input_method_id,
+                input_context_id,
+                return_value,
+            } => {
+                74u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+                return_value.write(writer);
+            }
+            Request::PreeditState {
+                input_method_id,
+                input_context_id,
+                state,
+            } => {
+                82u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+                state.write(writer);
+            }
+            Request::QueryExtension {
+                input_method_id,
+                extensions,
+            } => {
+                40u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                ((usize::saturating_add(
+                    extensions
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.len() + 1 + 0)),
+                    0 + 2,
+                ) - 2
+                    - 0) as u16)
+                    .write(writer);
+                for elem in extensions.iter() {
+                    (elem.len() as u8).write(writer);
+                    writer.write(elem.as_bytes());
+                }
+                writer.write_pad4();
+            }
+            Request::QueryExtensionReply {
+                input_method_id,
+                extensions,
+            } => {
+                41u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                ((usize::saturating_add(
+                    extensions
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 2,
+                ) - 2
+                    - 0) as u16)
+                    .write(writer);
+                for elem in extensions.iter() {
+                    elem.write(writer);
+                }
+            }
+            Request::RegisterTriggerKeys {
+                input_method_id,
+                on_keys,
+                off_keys,
+            } => {
+                34u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                writer.write(&[0u8; 2]);
+                ((usize::saturating_add(
+                    on_keys
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 4,
+                ) - 4
+                    - 0) as u32)
+                    .write(writer);
+                for elem in on_keys.iter() {
+                    elem.write(writer);
+                }
+                ((usize::saturating_add(
+                    off_keys
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 4,
+                ) - 4
+                    - 0) as u32)
+                    .write(writer);
+                for elem in off_keys.iter() {
+                    elem.write(writer);
+                }
+            }
+            Request::ResetIc {
+                input_method_id,
+                input_context_id,
+            } => {
+                64u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+            }
+            Request::ResetIcReply {
+                input_method_id,
+                input_context_id,
+                preedit_string,
+            } => {
+                65u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+                (preedit_string.len() as u16).write(writer);
+                writer.write(&preedit_string);
+                writer.write_pad4();
+            }
+            Request::SetEventMask {
+                input_method_id,
+                input_context_id,
+                forward_event_mask,
+                synchronous_event_mask,
+            } => {
+                37u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+                forward_event_mask.write(writer);
+                synchronous_event_mask.write(writer);
+            }
+            Request::SetIcFocus {
+                input_method_id,
+                input_context_id,
+            } => {
+                58u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+            }
+            Request::SetIcValues {
+                input_method_id,
+                input_context_id,
+                ic_attributes,
+            } => {
+                54u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+                ((usize::saturating_add(
+                    ic_attributes
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    2 + 2,
+                ) - 2
+                    - 2) as u16)
+                    .write(writer);
+                0u16.write(writer);
+                for elem in ic_attributes.iter() {
+                    elem.write(writer);
+                }
+            }
+            Request::SetIcValuesReply {
+                input_method_id,
+                input_context_id,
+            } => {
+                55u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+            }
+            Request::SetImValues {
+                input_method_id,
+                attributes,
+            } => {
+                42u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                ((usize::saturating_add(
+                    attributes
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 2,
+                ) - 2
+                    - 0) as u16)
+                    .write(writer);
+                for elem in attributes.iter() {
+                    elem.write(writer);
+                }
+            }
+            Request::SetImValuesReply { input_method_id } => {
+                43u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                writer.write(&[0u8; 2]);
+            }
+            Request::StatusDone {
+                input_method_id,
+                input_context_id,
+            } => {
+                81u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+            }
+            Request::StatusDraw {
+                input_method_id,
+                input_context_id,
+                content,
+            } => {
+                80u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+                content.write(writer);
+            }
+            Request::StatusStart {
+                input_method_id,
+                input_context_id,
+            } => {
+                79u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+            }
+            Request::StrConversion {} => {
+                71u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+            }
+            Request::StrConversionReply {} => {
+                72u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+            }
+            Request::Sync {
+                input_method_id,
+                input_context_id,
+            } => {
+                61u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+            }
+            Request::SyncReply {
+                input_method_id,
+                input_context_id,
+            } => {
+                62u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+            }
+            Request::TriggerNotify {
+                input_method_id,
+                input_context_id,
+                flag,
+                index,
+                event_mask,
+            } => {
+                35u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+                flag.write(writer);
+                index.write(writer);
+                event_mask.write(writer);
+            }
+            Request::TriggerNotifyReply {
+                input_method_id,
+                input_context_id,
+            } => {
+                36u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+            }
+            Request::UnsetIcFocus {
+                input_method_id,
+                input_context_id,
+            } => {
+                59u8.write(writer);
+                0u8.write(writer);
+                (((self.size() - 4) / 4) as u16).write(writer);
+                input_method_id.write(writer);
+                input_context_id.write(writer);
+            }
+        }
+    }
+    fn size(&self) -> usize {
+        let mut content_size = 0usize;
+        match self {
+            Request::AuthNext {} => {}
+            Request::AuthNg {} => {}
+            Request::AuthReply {} => {}
+            Request::AuthRequired {} => {}
+            Request::AuthSetup {} => {}
+            Request::Close { input_method_id } => {
+                content_size =
+                    content_size.saturating_add(usize::saturating_add(input_method_id.size(), 2));
+            }
+            Request::CloseReply { input_method_id } => {
+                content_size =
+                    content_size.saturating_add(usize::saturating_add(input_method_id.size(), 2));
             }
             Request::Commit {
                 input_method_id,
                 input_context_id,
                 data,
             } => {
-                63u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
-                data.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
+                content_size = content_size.saturating_add(data.size());
             }
             Request::Connect {
                 endian,
@@ -2181,136 +4204,90 @@ impl XimWrite for Request {
                 client_minor_protocol_version,
                 client_auth_protocol_names,
             } => {
-                1u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                endian.write(writer);
-                writer.write(&[0u8; 1]);
-                client_major_protocol_version.write(writer);
-                client_minor_protocol_version.write(writer);
-                ((client_auth_protocol_names
-                    .iter()
-                    .map(|e| with_pad4(e.len() + 2 + 0 - 0))
-                    .sum::<usize>()
-                    + 0
-                    + 2
-                    - 2
-                    - 0) as u16)
-                    .write(writer);
-                for elem in client_auth_protocol_names.iter() {
-                    (elem.len() as u16).write(writer);
-                    writer.write(elem.as_bytes());
-                    writer.write_pad4();
-                }
+                content_size = content_size.saturating_add(usize::saturating_add(endian.size(), 1));
+                content_size = content_size.saturating_add(client_major_protocol_version.size());
+                content_size = content_size.saturating_add(client_minor_protocol_version.size());
+                content_size = content_size.saturating_add(usize::saturating_add(
+                    client_auth_protocol_names.iter().fold(0usize, |acc, e| {
+                        usize::saturating_add(acc, with_pad4(e.len() + 2 + 0 - 0))
+                    }),
+                    0 + 2,
+                ));
             }
             Request::ConnectReply {
                 server_major_protocol_version,
                 server_minor_protocol_version,
             } => {
-                2u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                server_major_protocol_version.write(writer);
-                server_minor_protocol_version.write(writer);
+                content_size = content_size.saturating_add(server_major_protocol_version.size());
+                content_size = content_size.saturating_add(server_minor_protocol_version.size());
             }
             Request::CreateIc {
                 input_method_id,
                 ic_attributes,
             } => {
-                50u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                ((ic_attributes.iter().map(|e| e.size()).sum::<usize>() + 0 + 2 - 2 - 0) as u16)
-                    .write(writer);
-                for elem in ic_attributes.iter() {
-                    elem.write(writer);
-                }
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(usize::saturating_add(
+                    ic_attributes
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 2,
+                ));
             }
             Request::CreateIcReply {
                 input_method_id,
                 input_context_id,
             } => {
-                51u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
             }
             Request::DestroyIc {
                 input_method_id,
                 input_context_id,
             } => {
-                52u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
             }
             Request::DestroyIcReply {
                 input_method_id,
                 input_context_id,
             } => {
-                53u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
-            }
-            Request::Disconnect {} => {
-                3u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-            }
-            Request::DisconnectReply {} => {
-                4u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
             }
+            Request::Disconnect {} => {}
+            Request::DisconnectReply {} => {}
             Request::EncodingNegotiation {
                 input_method_id,
                 encodings,
                 encoding_infos,
             } => {
-                38u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                ((encodings.iter().map(|e| e.len() + 1 + 0).sum::<usize>() + 0 + 2 - 2 - 0) as u16)
-                    .write(writer);
-                for elem in encodings.iter() {
-                    (elem.len() as u8).write(writer);
-                    writer.write(elem.as_bytes());
-                }
-                writer.write_pad4();
-                ((encoding_infos
-                    .iter()
-                    .map(|e| with_pad4(e.len() + 2 + 0 - 0))
-                    .sum::<usize>()
-                    + 2
-                    + 2
-                    - 2
-                    - 2) as u16)
-                    .write(writer);
-                0u16.write(writer);
-                for elem in encoding_infos.iter() {
-                    (elem.len() as u16).write(writer);
-                    writer.write(elem.as_bytes());
-                    writer.write_pad4();
-                }
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(usize::saturating_add(
+                    with_pad4(
+                        usize::saturating_add(
+                            encodings
+                                .iter()
+                                .fold(0usize, |acc, e| usize::saturating_add(acc, e.len() + 1 + 0)),
+                            0 + 2,
+                        ) - 2,
+                    ),
+                    2,
+                ));
+                content_size = content_size.saturating_add(usize::saturating_add(
+                    encoding_infos.iter().fold(0usize, |acc, e| {
+                        usize::saturating_add(acc, with_pad4(e.len() + 2 + 0 - 0))
+                    }),
+                    2 + 2,
+                ));
             }
             Request::EncodingNegotiationReply {
                 input_method_id,
                 category,
                 index,
             } => {
-                39u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                category.write(writer);
-                index.write(writer);
-                writer.write(&[0u8; 2]);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(category.size());
+                content_size = content_size.saturating_add(usize::saturating_add(index.size(), 2));
             }
             Request::Error {
                 input_method_id,
@@ -2319,17 +4296,11 @@ impl XimWrite for Request {
                 code,
                 detail,
             } => {
-                20u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
-                flag.write(writer);
-                code.write(writer);
-                (detail.len() as u16).write(writer);
-                writer.write(&[0u8; 2]);
-                writer.write(detail.as_bytes());
-                writer.write_pad4();
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
+                content_size = content_size.saturating_add(flag.size());
+                content_size = content_size.saturating_add(code.size());
+                content_size = content_size.saturating_add(with_pad4(detail.len() + 2 + 2 - 0));
             }
             Request::ForwardEvent {
                 input_method_id,
@@ -2338,116 +4309,99 @@ impl XimWrite for Request {
                 serial_number,
                 xev,
             } => {
-                60u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
-                flag.write(writer);
-                serial_number.write(writer);
-                xev.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
+                content_size = content_size.saturating_add(flag.size());
+                content_size = content_size.saturating_add(serial_number.size());
+                content_size = content_size.saturating_add(xev.size());
             }
             Request::Geometry {
                 input_method_id,
                 input_context_id,
             } => {
-                70u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
             }
             Request::GetIcValues {
                 input_method_id,
                 input_context_id,
                 ic_attributes,
             } => {
-                56u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
-                ((ic_attributes.iter().map(|e| e.size()).sum::<usize>() + 0 + 2 - 2 - 0) as u16)
-                    .write(writer);
-                for elem in ic_attributes.iter() {
-                    elem.write(writer);
-                }
-                writer.write_pad4();
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
+                content_size = content_size.saturating_add(with_pad4(
+                    usize::saturating_add(
+                        ic_attributes
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                        0 + 2,
+                    ) - 0,
+                ));
             }
             Request::GetIcValuesReply {
                 input_method_id,
                 input_context_id,
                 ic_attributes,
             } => {
-                57u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
-                ((ic_attributes.iter().map(|e| e.size()).sum::<usize>() + 2 + 2 - 2 - 2) as u16)
-                    .write(writer);
-                0u16.write(writer);
-                for elem in ic_attributes.iter() {
-                    elem.write(writer);
-                }
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
+                content_size = content_size.saturating_add(usize::saturating_add(
+                    ic_attributes
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    2 + 2,
+                ));
             }
             Request::GetImValues {
                 input_method_id,
                 im_attributes,
             } => {
-                44u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                ((im_attributes.iter().map(|e| e.size()).sum::<usize>() + 0 + 2 - 2 - 0) as u16)
-                    .write(writer);
-                for elem in im_attributes.iter() {
-                    elem.write(writer);
-                }
-                writer.write_pad4();
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(usize::saturating_add(
+                    with_pad4(
+                        usize::saturating_add(
+                            im_attributes
+                                .iter()
+                                .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                            0 + 2,
+                        ) - 2,
+                    ),
+                    2,
+                ));
             }
             Request::GetImValuesReply {
                 input_method_id,
                 im_attributes,
             } => {
-                45u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                ((im_attributes.iter().map(|e| e.size()).sum::<usize>() + 0 + 2 - 2 - 0) as u16)
-                    .write(writer);
-                for elem in im_attributes.iter() {
-                    elem.write(writer);
-                }
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(usize::saturating_add(
+                    im_attributes
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 2,
+                ));
             }
             Request::Open { locale } => {
-                30u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                (locale.len() as u8).write(writer);
-                writer.write(locale.as_bytes());
-                writer.write_pad4();
+                content_size = content_size.saturating_add(with_pad4(locale.len() + 1 + 0 - 0));
             }
             Request::OpenReply {
                 input_method_id,
                 im_attrs,
                 ic_attrs,
             } => {
-                31u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                ((im_attrs.iter().map(|e| e.size()).sum::<usize>() + 0 + 2 - 2 - 0) as u16)
-                    .write(writer);
-                for elem in im_attrs.iter() {
-                    elem.write(writer);
-                }
-                ((ic_attrs.iter().map(|e| e.size()).sum::<usize>() + 2 + 2 - 2 - 2) as u16)
-                    .write(writer);
-                0u16.write(writer);
-                for elem in ic_attrs.iter() {
-                    elem.write(writer);
-                }
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(usize::saturating_add(
+                    im_attrs
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 2,
+                ));
+                content_size = content_size.saturating_add(usize::saturating_add(
+                    ic_attrs
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    2 + 2,
+                ));
             }
             Request::PreeditCaret {
                 input_method_id,
@@ -2456,36 +4410,27 @@ impl XimWrite for Request {
                 direction,
                 style,
             } => {
-                76u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
-                position.write(writer);
-                direction.write(writer);
-                style.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
+                content_size = content_size.saturating_add(position.size());
+                content_size = content_size.saturating_add(direction.size());
+                content_size = content_size.saturating_add(style.size());
             }
             Request::PreeditCaretReply {
                 input_method_id,
                 input_context_id,
                 position,
             } => {
-                77u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
-                position.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
+                content_size = content_size.saturating_add(position.size());
             }
             Request::PreeditDone {
                 input_method_id,
                 input_context_id,
             } => {
-                78u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
             }
             Request::PreeditDraw {
                 input_method_id,
@@ -2497,134 +4442,109 @@ impl XimWrite for Request {
                 preedit_string,
                 feedbacks,
             } => {
-                75u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
-                caret.write(writer);
-                chg_first.write(writer);
-                chg_length.write(writer);
-                status.write(writer);
-                (preedit_string.len() as u16).write(writer);
-                writer.write(&preedit_string);
-                writer.write_pad4();
-                ((feedbacks.iter().map(|e| e.size()).sum::<usize>() + 2 + 2 - 2 - 2) as u16)
-                    .write(writer);
-                0u16.write(writer);
-                for elem in feedbacks.iter() {
-                    elem.write(writer);
-                }
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
+                content_size = content_size.saturating_add(caret.size());
+                content_size = content_size.saturating_add(chg_first.size());
+                content_size = content_size.saturating_add(chg_length.size());
+                content_size = content_size.saturating_add(status.size());
+                content_size = content_size.saturating_add(with_pad4(preedit_string.len() + 2 - 0));
+                content_size = content_size.saturating_add(usize::saturating_add(
+                    feedbacks
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    2 + 2,
+                ));
             }
             Request::PreeditStart {
                 input_method_id,
                 input_context_id,
             } => {
-                73u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
             }
             Request::PreeditStartReply {
                 input_method_id,
                 input_context_id,
                 return_value,
             } => {
-                74u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
-                return_value.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
+                content_size = content_size.saturating_add(return_value.size());
             }
             Request::PreeditState {
                 input_method_id,
                 input_context_id,
                 state,
             } => {
-                82u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
-                state.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
+                content_size = content_size.saturating_add(state.size());
             }
             Request::QueryExtension {
                 input_method_id,
                 extensions,
             } => {
-                40u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                ((extensions.iter().map(|e| e.len() + 1 + 0).sum::<usize>() + 0 + 2 - 2 - 0)
-                    as u16)
-                    .write(writer);
-                for elem in extensions.iter() {
-                    (elem.len() as u8).write(writer);
-                    writer.write(elem.as_bytes());
-                }
-                writer.write_pad4();
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(usize::saturating_add(
+                    with_pad4(
+                        usize::saturating_add(
+                            extensions
+                                .iter()
+                                .fold(0usize, |acc, e| usize::saturating_add(acc, e.len() + 1 + 0)),
+                            0 + 2,
+                        ) - 2,
+                    ),
+                    2,
+                ));
             }
             Request::QueryExtensionReply {
                 input_method_id,
                 extensions,
-            } => {
-                41u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                ((extensions.iter().map(|e| e.size()).sum::<usize>() + 0 + 2 - 2 - 0) as u16)
-                    .write(writer);
-                for elem in extensions.iter() {
-                    elem.write(writer);
-                }
+            } => {
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(usize::saturating_add(
+                    extensions
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 2,
+                ));
             }
             Request::RegisterTriggerKeys {
                 input_method_id,
                 on_keys,
                 off_keys,
             } => {
-                34u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                writer.write(&[0u8; 2]);
-                ((on_keys.iter().map(|e| e.size()).sum::<usize>() + 0 + 4 - 4 - 0) as u32)
-                    .write(writer);
-                for elem in on_keys.iter() {
-                    elem.write(writer);
-                }
-                ((off_keys.iter().map(|e| e.size()).sum::<usize>() + 0 + 4 - 4 - 0) as u32)
-                    .write(writer);
-                for elem in off_keys.iter() {
-                    elem.write(writer);
-                }
+                content_size =
+                    content_size.saturating_add(usize::saturating_add(input_method_id.size(), 2));
+                content_size = content_size.saturating_add(usize::saturating_add(
+                    on_keys
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 4,
+                ));
+                content_size = content_size.saturating_add(usize::saturating_add(
+                    off_keys
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 4,
+                ));
             }
             Request::ResetIc {
                 input_method_id,
                 input_context_id,
             } => {
-                64u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
             }
             Request::ResetIcReply {
                 input_method_id,
                 input_context_id,
                 preedit_string,
             } => {
-                65u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
-                (preedit_string.len() as u16).write(writer);
-                writer.write(&preedit_string);
-                writer.write_pad4();
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
+                content_size = content_size.saturating_add(with_pad4(preedit_string.len() + 2 - 0));
             }
             Request::SetEventMask {
                 input_method_id,
@@ -2632,133 +4552,93 @@ impl XimWrite for Request {
                 forward_event_mask,
                 synchronous_event_mask,
             } => {
-                37u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
-                forward_event_mask.write(writer);
-                synchronous_event_mask.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
+                content_size = content_size.saturating_add(forward_event_mask.size());
+                content_size = content_size.saturating_add(synchronous_event_mask.size());
             }
             Request::SetIcFocus {
                 input_method_id,
                 input_context_id,
             } => {
-                58u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
             }
             Request::SetIcValues {
                 input_method_id,
                 input_context_id,
                 ic_attributes,
             } => {
-                54u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
-                ((ic_attributes.iter().map(|e| e.size()).sum::<usize>() + 2 + 2 - 2 - 2) as u16)
-                    .write(writer);
-                0u16.write(writer);
-                for elem in ic_attributes.iter() {
-                    elem.write(writer);
-                }
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
+                content_size = content_size.saturating_add(usize::saturating_add(
+                    ic_attributes
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    2 + 2,
+                ));
             }
             Request::SetIcValuesReply {
                 input_method_id,
                 input_context_id,
             } => {
-                55u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
             }
             Request::SetImValues {
                 input_method_id,
                 attributes,
             } => {
-                42u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                ((attributes.iter().map(|e| e.size()).sum::<usize>() + 0 + 2 - 2 - 0) as u16)
-                    .write(writer);
-                for elem in attributes.iter() {
-                    elem.write(writer);
-                }
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(usize::saturating_add(
+                    attributes
+                        .iter()
+                        .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                    0 + 2,
+                ));
             }
             Request::SetImValuesReply { input_method_id } => {
-                43u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                writer.write(&[0u8; 2]);
+                content_size =
+                    content_size.saturating_add(usize::saturating_add(input_method_id.size(), 2));
             }
             Request::StatusDone {
                 input_method_id,
                 input_context_id,
             } => {
-                81u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
             }
             Request::StatusDraw {
                 input_method_id,
                 input_context_id,
                 content,
             } => {
-                80u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
-                content.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
+                content_size = content_size.saturating_add(content.size());
             }
             Request::StatusStart {
                 input_method_id,
                 input_context_id,
             } => {
-                79u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
-            }
-            Request::StrConversion {} => {
-                71u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-            }
-            Request::StrConversionReply {} => {
-                72u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
             }
+            Request::StrConversion {} => {}
+            Request::StrConversionReply {} => {}
             Request::Sync {
                 input_method_id,
                 input_context_id,
             } => {
-                61u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
             }
             Request::SyncReply {
                 input_method_id,
                 input_context_id,
             } => {
-                62u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
             }
             Request::TriggerNotify {
                 input_method_id,
@@ -2767,59 +4647,81 @@ impl XimWrite for Request {
                 index,
                 event_mask,
             } => {
-                35u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
-                flag.write(writer);
-                index.write(writer);
-                event_mask.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
+                content_size = content_size.saturating_add(flag.size());
+                content_size = content_size.saturating_add(index.size());
+                content_size = content_size.saturating_add(event_mask.size());
             }
             Request::TriggerNotifyReply {
                 input_method_id,
                 input_context_id,
             } => {
-                36u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
             }
             Request::UnsetIcFocus {
                 input_method_id,
                 input_context_id,
             } => {
-                59u8.write(writer);
-                0u8.write(writer);
-                (((self.size() - 4) / 4) as u16).write(writer);
-                input_method_id.write(writer);
-                input_context_id.write(writer);
+                content_size = content_size.saturating_add(input_method_id.size());
+                content_size = content_size.saturating_add(input_context_id.size());
             }
         }
+        content_size.saturating_add(4)
     }
-    fn size(&self) -> usize {
-        let mut content_size = 0;
+    fn write_vectored<'a>(&'a self, writer: &mut VectoredWriter<'a>) {
         match self {
-            Request::AuthNext {} => {}
-            Request::AuthNg {} => {}
-            Request::AuthReply {} => {}
-            Request::AuthRequired {} => {}
-            Request::AuthSetup {} => {}
+            Request::AuthNext {} => {
+                writer.copy(&12u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+            }
+            Request::AuthNg {} => {
+                writer.copy(&14u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+            }
+            Request::AuthReply {} => {
+                writer.copy(&11u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+            }
+            Request::AuthRequired {} => {
+                writer.copy(&10u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+            }
+            Request::AuthSetup {} => {
+                writer.copy(&13u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+            }
             Request::Close { input_method_id } => {
-                content_size += input_method_id.size() + 2;
+                writer.copy(&32u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                writer.write_zeros(2);
             }
             Request::CloseReply { input_method_id } => {
-                content_size += input_method_id.size() + 2;
+                writer.copy(&33u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                writer.write_zeros(2);
             }
             Request::Commit {
                 input_method_id,
                 input_context_id,
                 data,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
-                content_size += data.size();
+                writer.copy(&63u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
+                data.write_vectored(writer);
             }
             Request::Connect {
                 endian,
@@ -2827,77 +4729,150 @@ impl XimWrite for Request {
                 client_minor_protocol_version,
                 client_auth_protocol_names,
             } => {
-                content_size += endian.size() + 1;
-                content_size += client_major_protocol_version.size();
-                content_size += client_minor_protocol_version.size();
-                content_size += client_auth_protocol_names
-                    .iter()
-                    .map(|e| with_pad4(e.len() + 2 + 0 - 0))
-                    .sum::<usize>()
-                    + 0
-                    + 2;
+                writer.copy(&1u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                endian.write_vectored(writer);
+                writer.write_zeros(1);
+                client_major_protocol_version.write_vectored(writer);
+                client_minor_protocol_version.write_vectored(writer);
+                writer.copy(
+                    &((usize::saturating_add(
+                        client_auth_protocol_names.iter().fold(0usize, |acc, e| {
+                            usize::saturating_add(acc, with_pad4(e.len() + 2 + 0 - 0))
+                        }),
+                        0 + 2,
+                    ) - 2
+                        - 0) as u16),
+                );
+                for elem in client_auth_protocol_names.iter() {
+                    writer.copy(&(elem.len() as u16));
+                    writer.borrow(elem.as_bytes());
+                    writer.write_pad4();
+                }
             }
             Request::ConnectReply {
                 server_major_protocol_version,
                 server_minor_protocol_version,
             } => {
-                content_size += server_major_protocol_version.size();
-                content_size += server_minor_protocol_version.size();
+                writer.copy(&2u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                server_major_protocol_version.write_vectored(writer);
+                server_minor_protocol_version.write_vectored(writer);
             }
             Request::CreateIc {
                 input_method_id,
                 ic_attributes,
             } => {
-                content_size += input_method_id.size();
-                content_size += ic_attributes.iter().map(|e| e.size()).sum::<usize>() + 0 + 2;
+                writer.copy(&50u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                writer.copy(
+                    &((usize::saturating_add(
+                        ic_attributes
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                        0 + 2,
+                    ) - 2
+                        - 0) as u16),
+                );
+                for elem in ic_attributes.iter() {
+                    elem.write_vectored(writer);
+                }
             }
             Request::CreateIcReply {
                 input_method_id,
                 input_context_id,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
+                writer.copy(&51u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
             }
             Request::DestroyIc {
                 input_method_id,
                 input_context_id,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
+                writer.copy(&52u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
             }
             Request::DestroyIcReply {
                 input_method_id,
                 input_context_id,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
+                writer.copy(&53u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
+            }
+            Request::Disconnect {} => {
+                writer.copy(&3u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+            }
+            Request::DisconnectReply {} => {
+                writer.copy(&4u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
             }
-            Request::Disconnect {} => {}
-            Request::DisconnectReply {} => {}
             Request::EncodingNegotiation {
                 input_method_id,
                 encodings,
                 encoding_infos,
             } => {
-                content_size += input_method_id.size();
-                content_size +=
-                    with_pad4(encodings.iter().map(|e| e.len() + 1 + 0).sum::<usize>() + 0 + 2 - 2)
-                        + 2;
-                content_size += encoding_infos
-                    .iter()
-                    .map(|e| with_pad4(e.len() + 2 + 0 - 0))
-                    .sum::<usize>()
-                    + 2
-                    + 2;
+                writer.copy(&38u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                writer.copy(
+                    &((usize::saturating_add(
+                        encodings
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.len() + 1 + 0)),
+                        0 + 2,
+                    ) - 2
+                        - 0) as u16),
+                );
+                for elem in encodings.iter() {
+                    writer.copy(&(elem.len() as u8));
+                    writer.borrow(elem.as_bytes());
+                }
+                writer.write_pad4();
+                writer.copy(
+                    &((usize::saturating_add(
+                        encoding_infos.iter().fold(0usize, |acc, e| {
+                            usize::saturating_add(acc, with_pad4(e.len() + 2 + 0 - 0))
+                        }),
+                        2 + 2,
+                    ) - 2
+                        - 2) as u16),
+                );
+                writer.write_zeros(2);
+                for elem in encoding_infos.iter() {
+                    writer.copy(&(elem.len() as u16));
+                    writer.borrow(elem.as_bytes());
+                    writer.write_pad4();
+                }
             }
             Request::EncodingNegotiationReply {
                 input_method_id,
                 category,
                 index,
             } => {
-                content_size += input_method_id.size();
-                content_size += category.size();
-                content_size += index.size() + 2;
+                writer.copy(&39u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                category.write_vectored(writer);
+                index.write_vectored(writer);
+                writer.write_zeros(2);
             }
             Request::Error {
                 input_method_id,
@@ -2906,11 +4881,17 @@ impl XimWrite for Request {
                 code,
                 detail,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
-                content_size += flag.size();
-                content_size += code.size();
-                content_size += with_pad4(detail.len() + 2 + 2 - 0);
+                writer.copy(&20u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
+                flag.write_vectored(writer);
+                code.write_vectored(writer);
+                writer.copy(&(detail.len() as u16));
+                writer.write_zeros(2);
+                writer.borrow(detail.as_bytes());
+                writer.write_pad4();
             }
             Request::ForwardEvent {
                 input_method_id,
@@ -2919,65 +4900,158 @@ impl XimWrite for Request {
                 serial_number,
                 xev,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
-                content_size += flag.size();
-                content_size += serial_number.size();
-                content_size += xev.size();
+                writer.copy(&60u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
+                flag.write_vectored(writer);
+                serial_number.write_vectored(writer);
+                xev.write_vectored(writer);
             }
             Request::Geometry {
                 input_method_id,
                 input_context_id,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
+                writer.copy(&70u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
             }
             Request::GetIcValues {
                 input_method_id,
                 input_context_id,
                 ic_attributes,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
-                content_size +=
-                    with_pad4(ic_attributes.iter().map(|e| e.size()).sum::<usize>() + 0 + 2 - 0);
+                writer.copy(&56u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
+                writer.copy(
+                    &((usize::saturating_add(
+                        ic_attributes
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                        0 + 2,
+                    ) - 2
+                        - 0) as u16),
+                );
+                for elem in ic_attributes.iter() {
+                    elem.write_vectored(writer);
+                }
+                writer.write_pad4();
             }
             Request::GetIcValuesReply {
                 input_method_id,
                 input_context_id,
                 ic_attributes,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
-                content_size += ic_attributes.iter().map(|e| e.size()).sum::<usize>() + 2 + 2;
+                writer.copy(&57u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
+                writer.copy(
+                    &((usize::saturating_add(
+                        ic_attributes
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                        2 + 2,
+                    ) - 2
+                        - 2) as u16),
+                );
+                writer.write_zeros(2);
+                for elem in ic_attributes.iter() {
+                    elem.write_vectored(writer);
+                }
             }
             Request::GetImValues {
                 input_method_id,
                 im_attributes,
             } => {
-                content_size += input_method_id.size();
-                content_size +=
-                    with_pad4(im_attributes.iter().map(|e| e.size()).sum::<usize>() + 0 + 2 - 2)
-                        + 2;
+                writer.copy(&44u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                writer.copy(
+                    &((usize::saturating_add(
+                        im_attributes
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                        0 + 2,
+                    ) - 2
+                        - 0) as u16),
+                );
+                for elem in im_attributes.iter() {
+                    elem.write_vectored(writer);
+                }
+                writer.write_pad4();
             }
             Request::GetImValuesReply {
                 input_method_id,
                 im_attributes,
             } => {
-                content_size += input_method_id.size();
-                content_size += im_attributes.iter().map(|e| e.size()).sum::<usize>() + 0 + 2;
+                writer.copy(&45u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                writer.copy(
+                    &((usize::saturating_add(
+                        im_attributes
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                        0 + 2,
+                    ) - 2
+                        - 0) as u16),
+                );
+                for elem in im_attributes.iter() {
+                    elem.write_vectored(writer);
+                }
             }
             Request::Open { locale } => {
-                content_size += with_pad4(locale.len() + 1 + 0 - 0);
+                writer.copy(&30u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                writer.copy(&(locale.len() as u8));
+                writer.borrow(locale.as_bytes());
+                writer.write_pad4();
             }
             Request::OpenReply {
                 input_method_id,
                 im_attrs,
                 ic_attrs,
             } => {
-                content_size += input_method_id.size();
-                content_size += im_attrs.iter().map(|e| e.size()).sum::<usize>() + 0 + 2;
-                content_size += ic_attrs.iter().map(|e| e.size()).sum::<usize>() + 2 + 2;
+                writer.copy(&31u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                writer.copy(
+                    &((usize::saturating_add(
+                        im_attrs
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                        0 + 2,
+                    ) - 2
+                        - 0) as u16),
+                );
+                for elem in im_attrs.iter() {
+                    elem.write_vectored(writer);
+                }
+                writer.copy(
+                    &((usize::saturating_add(
+                        ic_attrs
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                        2 + 2,
+                    ) - 2
+                        - 2) as u16),
+                );
+                writer.write_zeros(2);
+                for elem in ic_attrs.iter() {
+                    elem.write_vectored(writer);
+                }
             }
             Request::PreeditCaret {
                 input_method_id,
@@ -2986,27 +5060,36 @@ impl XimWrite for Request {
                 direction,
                 style,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
-                content_size += position.size();
-                content_size += direction.size();
-                content_size += style.size();
+                writer.copy(&76u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
+                position.write_vectored(writer);
+                direction.write_vectored(writer);
+                style.write_vectored(writer);
             }
             Request::PreeditCaretReply {
                 input_method_id,
                 input_context_id,
                 position,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
-                content_size += position.size();
+                writer.copy(&77u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
+                position.write_vectored(writer);
             }
             Request::PreeditDone {
                 input_method_id,
                 input_context_id,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
+                writer.copy(&78u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
             }
             Request::PreeditDraw {
                 input_method_id,
@@ -3018,80 +5101,168 @@ impl XimWrite for Request {
                 preedit_string,
                 feedbacks,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
-                content_size += caret.size();
-                content_size += chg_first.size();
-                content_size += chg_length.size();
-                content_size += status.size();
-                content_size += with_pad4(preedit_string.len() + 2 - 0);
-                content_size += feedbacks.iter().map(|e| e.size()).sum::<usize>() + 2 + 2;
+                writer.copy(&75u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
+                caret.write_vectored(writer);
+                chg_first.write_vectored(writer);
+                chg_length.write_vectored(writer);
+                status.write_vectored(writer);
+                writer.copy(&(preedit_string.len() as u16));
+                writer.borrow(&preedit_string[..]);
+                writer.write_pad4();
+                writer.copy(
+                    &((usize::saturating_add(
+                        feedbacks
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                        2 + 2,
+                    ) - 2
+                        - 2) as u16),
+                );
+                writer.write_zeros(2);
+                for elem in feedbacks.iter() {
+                    elem.write_vectored(writer);
+                }
             }
             Request::PreeditStart {
                 input_method_id,
                 input_context_id,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
+                writer.copy(&73u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
             }
             Request::PreeditStartReply {
                 input_method_id,
                 input_context_id,
                 return_value,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
-                content_size += return_value.size();
+                writer.copy(&74u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
+                return_value.write_vectored(writer);
             }
             Request::PreeditState {
                 input_method_id,
                 input_context_id,
                 state,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
-                content_size += state.size();
+                writer.copy(&82u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
+                state.write_vectored(writer);
             }
             Request::QueryExtension {
                 input_method_id,
                 extensions,
             } => {
-                content_size += input_method_id.size();
-                content_size += with_pad4(
-                    extensions.iter().map(|e| e.len() + 1 + 0).sum::<usize>() + 0 + 2 - 0,
+                writer.copy(&40u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                writer.copy(
+                    &((usize::saturating_add(
+                        extensions
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.len() + 1 + 0)),
+                        0 + 2,
+                    ) - 2
+                        - 0) as u16),
                 );
+                for elem in extensions.iter() {
+                    writer.copy(&(elem.len() as u8));
+                    writer.borrow(elem.as_bytes());
+                }
+                writer.write_pad4();
             }
             Request::QueryExtensionReply {
                 input_method_id,
                 extensions,
             } => {
-                content_size += input_method_id.size();
-                content_size += extensions.iter().map(|e| e.size()).sum::<usize>() + 0 + 2;
+                writer.copy(&41u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                writer.copy(
+                    &((usize::saturating_add(
+                        extensions
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                        0 + 2,
+                    ) - 2
+                        - 0) as u16),
+                );
+                for elem in extensions.iter() {
+                    elem.write_vectored(writer);
+                }
             }
             Request::RegisterTriggerKeys {
                 input_method_id,
                 on_keys,
                 off_keys,
             } => {
-                content_size += input_method_id.size() + 2;
-                content_size += on_keys.iter().map(|e| e.size()).sum::<usize>() + 0 + 4;
-                content_size += off_keys.iter().map(|e| e.size()).sum::<usize>() + 0 + 4;
+                writer.copy(&34u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                writer.write_zeros(2);
+                writer.copy(
+                    &((usize::saturating_add(
+                        on_keys
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                        0 + 4,
+                    ) - 4
+                        - 0) as u32),
+                );
+                for elem in on_keys.iter() {
+                    elem.write_vectored(writer);
+                }
+                writer.copy(
+                    &((usize::saturating_add(
+                        off_keys
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                        0 + 4,
+                    ) - 4
+                        - 0) as u32),
+                );
+                for elem in off_keys.iter() {
+                    elem.write_vectored(writer);
+                }
             }
             Request::ResetIc {
                 input_method_id,
                 input_context_id,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
+                writer.copy(&64u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
             }
             Request::ResetIcReply {
                 input_method_id,
                 input_context_id,
                 preedit_string,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
-                content_size += with_pad4(preedit_string.len() + 2 - 0);
+                writer.copy(&65u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
+                writer.copy(&(preedit_string.len() as u16));
+                writer.borrow(&preedit_string[..]);
+                writer.write_pad4();
             }
             Request::SetEventMask {
                 input_method_id,
@@ -3099,82 +5270,147 @@ impl XimWrite for Request {
                 forward_event_mask,
                 synchronous_event_mask,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
-                content_size += forward_event_mask.size();
-                content_size += synchronous_event_mask.size();
+                writer.copy(&37u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
+                forward_event_mask.write_vectored(writer);
+                synchronous_event_mask.write_vectored(writer);
             }
             Request::SetIcFocus {
                 input_method_id,
                 input_context_id,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
+                writer.copy(&58u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
             }
             Request::SetIcValues {
                 input_method_id,
                 input_context_id,
                 ic_attributes,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
-                content_size += ic_attributes.iter().map(|e| e.size()).sum::<usize>() + 2 + 2;
+                writer.copy(&54u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
+                writer.copy(
+                    &((usize::saturating_add(
+                        ic_attributes
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                        2 + 2,
+                    ) - 2
+                        - 2) as u16),
+                );
+                writer.write_zeros(2);
+                for elem in ic_attributes.iter() {
+                    elem.write_vectored(writer);
+                }
             }
             Request::SetIcValuesReply {
                 input_method_id,
                 input_context_id,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
+                writer.copy(&55u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
             }
             Request::SetImValues {
                 input_method_id,
                 attributes,
             } => {
-                content_size += input_method_id.size();
-                content_size += attributes.iter().map(|e| e.size()).sum::<usize>() + 0 + 2;
+                writer.copy(&42u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                writer.copy(
+                    &((usize::saturating_add(
+                        attributes
+                            .iter()
+                            .fold(0usize, |acc, e| usize::saturating_add(acc, e.size())),
+                        0 + 2,
+                    ) - 2
+                        - 0) as u16),
+                );
+                for elem in attributes.iter() {
+                    elem.write_vectored(writer);
+                }
             }
             Request::SetImValuesReply { input_method_id } => {
-                content_size += input_method_id.size() + 2;
+                writer.copy(&43u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                writer.write_zeros(2);
             }
             Request::StatusDone {
                 input_method_id,
                 input_context_id,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
+                writer.copy(&81u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
             }
             Request::StatusDraw {
                 input_method_id,
                 input_context_id,
                 content,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
-                content_size += content.size();
+                writer.copy(&80u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
+                content.write_vectored(writer);
             }
             Request::StatusStart {
                 input_method_id,
                 input_context_id,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
+                writer.copy(&79u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
+            }
+            Request::StrConversion {} => {
+                writer.copy(&71u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+            }
+            Request::StrConversionReply {} => {
+                writer.copy(&72u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
             }
-            Request::StrConversion {} => {}
-            Request::StrConversionReply {} => {}
             Request::Sync {
                 input_method_id,
                 input_context_id,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
+                writer.copy(&61u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
             }
             Request::SyncReply {
                 input_method_id,
                 input_context_id,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
+                writer.copy(&62u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
             }
             Request::TriggerNotify {
                 input_method_id,
@@ -3183,27 +5419,279 @@ impl XimWrite for Request {
                 index,
                 event_mask,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
-                content_size += flag.size();
-                content_size += index.size();
-                content_size += event_mask.size();
+                writer.copy(&35u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
+                flag.write_vectored(writer);
+                index.write_vectored(writer);
+                event_mask.write_vectored(writer);
             }
             Request::TriggerNotifyReply {
                 input_method_id,
                 input_context_id,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
+                writer.copy(&36u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
             }
             Request::UnsetIcFocus {
                 input_method_id,
                 input_context_id,
             } => {
-                content_size += input_method_id.size();
-                content_size += input_context_id.size();
+                writer.copy(&59u8);
+                writer.copy(&0u8);
+                writer.copy(&(((self.size() - 4) / 4) as u16));
+                input_method_id.write_vectored(writer);
+                input_context_id.write_vectored(writer);
             }
         }
-        content_size + 4
+    }
+}
+impl Request {
+    /// The request `name` with every field at its default, `None` for the requests with a field without one.
+    pub fn default_for(name: &str) -> Option<Request> {
+        match name {
+            "AuthNext" => Some(Request::AuthNext {}),
+            "AuthNg" => Some(Request::AuthNg {}),
+            "AuthReply" => Some(Request::AuthReply {}),
+            "AuthRequired" => Some(Request::AuthRequired {}),
+            "AuthSetup" => Some(Request::AuthSetup {}),
+            "Close" => Some(Request::Close {
+                input_method_id: Default::default(),
+            }),
+            "CloseReply" => Some(Request::CloseReply {
+                input_method_id: Default::default(),
+            }),
+            // Commit is skipped, CommitData has no default
+            // Connect is skipped, Endian has no default
+            "ConnectReply" => Some(Request::ConnectReply {
+                server_major_protocol_version: Default::default(),
+                server_minor_protocol_version: Default::default(),
+            }),
+            "CreateIc" => Some(Request::CreateIc {
+                input_method_id: Default::default(),
+                ic_attributes: Default::default(),
+            }),
+            "CreateIcReply" => Some(Request::CreateIcReply {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+            }),
+            "DestroyIc" => Some(Request::DestroyIc {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+            }),
+            "DestroyIcReply" => Some(Request::DestroyIcReply {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+            }),
+            "Disconnect" => Some(Request::Disconnect {}),
+            "DisconnectReply" => Some(Request::DisconnectReply {}),
+            "EncodingNegotiation" => Some(Request::EncodingNegotiation {
+                input_method_id: Default::default(),
+                encodings: Default::default(),
+                encoding_infos: Default::default(),
+            }),
+            "EncodingNegotiationReply" => Some(Request::EncodingNegotiationReply {
+                input_method_id: Default::default(),
+                category: Default::default(),
+                index: Default::default(),
+            }),
+            // Error is skipped, ErrorFlag has no default
+            // ForwardEvent is skipped, ForwardEventFlag has no default
+            "Geometry" => Some(Request::Geometry {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+            }),
+            "GetIcValues" => Some(Request::GetIcValues {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+                ic_attributes: Default::default(),
+            }),
+            "GetIcValuesReply" => Some(Request::GetIcValuesReply {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+                ic_attributes: Default::default(),
+            }),
+            "GetImValues" => Some(Request::GetImValues {
+                input_method_id: Default::default(),
+                im_attributes: Default::default(),
+            }),
+            "GetImValuesReply" => Some(Request::GetImValuesReply {
+                input_method_id: Default::default(),
+                im_attributes: Default::default(),
+            }),
+            "Open" => Some(Request::Open {
+                locale: Default::default(),
+            }),
+            "OpenReply" => Some(Request::OpenReply {
+                input_method_id: Default::default(),
+                im_attrs: Default::default(),
+                ic_attrs: Default::default(),
+            }),
+            // PreeditCaret is skipped, CaretDirection has no default
+            "PreeditCaretReply" => Some(Request::PreeditCaretReply {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+                position: Default::default(),
+            }),
+            "PreeditDone" => Some(Request::PreeditDone {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+            }),
+            // PreeditDraw is skipped, PreeditDrawStatus has no default
+            "PreeditStart" => Some(Request::PreeditStart {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+            }),
+            "PreeditStartReply" => Some(Request::PreeditStartReply {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+                return_value: Default::default(),
+            }),
+            // PreeditState is skipped, PreeditStateFlag has no default
+            "QueryExtension" => Some(Request::QueryExtension {
+                input_method_id: Default::default(),
+                extensions: Default::default(),
+            }),
+            "QueryExtensionReply" => Some(Request::QueryExtensionReply {
+                input_method_id: Default::default(),
+                extensions: Default::default(),
+            }),
+            "RegisterTriggerKeys" => Some(Request::RegisterTriggerKeys {
+                input_method_id: Default::default(),
+                on_keys: Default::default(),
+                off_keys: Default::default(),
+            }),
+            "ResetIc" => Some(Request::ResetIc {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+            }),
+            "ResetIcReply" => Some(Request::ResetIcReply {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+                preedit_string: Default::default(),
+            }),
+            "SetEventMask" => Some(Request::SetEventMask {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+                forward_event_mask: Default::default(),
+                synchronous_event_mask: Default::default(),
+            }),
+            "SetIcFocus" => Some(Request::SetIcFocus {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+            }),
+            "SetIcValues" => Some(Request::SetIcValues {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+                ic_attributes: Default::default(),
+            }),
+            "SetIcValuesReply" => Some(Request::SetIcValuesReply {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+            }),
+            "SetImValues" => Some(Request::SetImValues {
+                input_method_id: Default::default(),
+                attributes: Default::default(),
+            }),
+            "SetImValuesReply" => Some(Request::SetImValuesReply {
+                input_method_id: Default::default(),
+            }),
+            "StatusDone" => Some(Request::StatusDone {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+            }),
+            // StatusDraw is skipped, StatusContent has no default
+            "StatusStart" => Some(Request::StatusStart {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+            }),
+            "StrConversion" => Some(Request::StrConversion {}),
+            "StrConversionReply" => Some(Request::StrConversionReply {}),
+            "Sync" => Some(Request::Sync {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+            }),
+            "SyncReply" => Some(Request::SyncReply {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+            }),
+            // TriggerNotify is skipped, TriggerNotifyFlag has no default
+            "TriggerNotifyReply" => Some(Request::TriggerNotifyReply {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+            }),
+            "UnsetIcFocus" => Some(Request::UnsetIcFocus {
+                input_method_id: Default::default(),
+                input_context_id: Default::default(),
+            }),
+            _ => None,
+        }
+    }
+}
+#[cfg(test)]
+#[test]
+fn default_requests_round_trip() {
+    for name in [
+        "AuthNext",
+        "AuthNg",
+        "AuthReply",
+        "AuthRequired",
+        "AuthSetup",
+        "Close",
+        "CloseReply",
+        "ConnectReply",
+        "CreateIc",
+        "CreateIcReply",
+        "DestroyIc",
+        "DestroyIcReply",
+        "Disconnect",
+        "DisconnectReply",
+        "EncodingNegotiation",
+        "EncodingNegotiationReply",
+        "Geometry",
+        "GetIcValues",
+        "GetIcValuesReply",
+        "GetImValues",
+        "GetImValuesReply",
+        "Open",
+        "OpenReply",
+        "PreeditCaretReply",
+        "PreeditDone",
+        "PreeditStart",
+        "PreeditStartReply",
+        "QueryExtension",
+        "QueryExtensionReply",
+        "RegisterTriggerKeys",
+        "ResetIc",
+        "ResetIcReply",
+        "SetEventMask",
+        "SetIcFocus",
+        "SetIcValues",
+        "SetIcValuesReply",
+        "SetImValues",
+        "SetImValuesReply",
+        "StatusDone",
+        "StatusStart",
+        "StrConversion",
+        "StrConversionReply",
+        "Sync",
+        "SyncReply",
+        "TriggerNotifyReply",
+        "UnsetIcFocus",
+    ] {
+        let req = Request::default_for(name).unwrap();
+        let mut out = alloc::vec![0; req.size()];
+        req.write(&mut Writer::new(&mut out));
+        assert_eq!(
+            Request::read(&mut Reader::new(&out)).unwrap(),
+            req,
+            "{}",
+            name
+        );
     }
 }